@@ -0,0 +1,56 @@
+//! Generates the manifests of the asset folders, which list their files for
+//! builds that can't scan folders (see `src/manifest.rs`).
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Asset folders with the extensions of their listed files.
+const FOLDERS: [(&str, &[&str]); 2] = [("textures", &["png", "anim"]), ("blocks", &["block"])];
+
+fn main() -> io::Result<()> {
+    let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    for (folder, extensions) in FOLDERS {
+        println!("cargo:rerun-if-changed=assets/{folder}");
+
+        let files = scan(&assets, folder, extensions)?;
+        // Same format as `serde_json::to_string_pretty`, the paths need no escaping
+        let mut text = String::from("[");
+        for (i, file) in files.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            text += &format!("{sep}\n  \"{file}\"");
+        }
+        text += if files.is_empty() { "]\n" } else { "\n]\n" };
+
+        // Only write changed manifests, unchanged ones keep their timestamp
+        let path = assets.join(format!("{folder}.manifest"));
+        if fs::read_to_string(&path).ok().as_deref() != Some(text.as_str()) {
+            fs::write(path, text)?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the files with one of the `extensions` in the asset `folder` and its
+/// subfolders, sorted by path like `Manifest::scan`.
+fn scan(assets: &Path, folder: &str, extensions: &[&str]) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![folder.to_string()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(assets.join(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = format!("{dir}/{name}");
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if extensions
+                .iter()
+                .any(|e| name.strip_suffix(e).is_some_and(|n| n.ends_with('.')))
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
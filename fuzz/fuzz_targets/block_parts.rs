@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    bevy_voxel_fuzz::parse_cube(data);
+    bevy_voxel_fuzz::parse_face(data);
+});
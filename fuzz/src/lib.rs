@@ -0,0 +1,46 @@
+//! The block loader of the game, built as library for the fuzz targets.
+//!
+//! The game is binary-only, so the modules of the loader are included directly.
+
+// Only the parsing is used
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use bevy::prelude::*;
+
+#[path = "../../src/block.rs"]
+mod block;
+#[path = "../../src/textures.rs"]
+mod textures;
+#[path = "../../src/util.rs"]
+mod util;
+
+use block::{BlockAsset, CubeData, FaceData};
+use textures::TileTextures;
+
+/// Stand-in for the block material of the game, used by the texture systems.
+#[derive(Default, Resource)]
+pub struct BlockMat(Handle<StandardMaterial>);
+
+/// Parses a block file and resolves it against a fixed set of textures.
+///
+/// Uses [`TileTextures::with_names`] instead of the global atlas, which is
+/// only initialized by the game's asset loading.
+pub fn parse_block(bytes: &[u8]) {
+    let textures = TileTextures::with_names(&["stone", "dirt", "grass_top", "planks"]);
+    // Errors are expected, only panics are failures
+    if let Ok(asset) = BlockAsset::parse(bytes, Path::new("fuzz.block")) {
+        let _ = asset.resolve(&textures);
+    }
+}
+
+/// Deserializes a single cube of a block file.
+pub fn parse_cube(bytes: &[u8]) {
+    let _ = serde_json::from_slice::<CubeData>(bytes);
+}
+
+/// Deserializes a single face of a block file.
+pub fn parse_face(bytes: &[u8]) {
+    let _ = serde_json::from_slice::<FaceData>(bytes);
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::anyhow;
+use bevy::prelude::*;
+
+use crate::block::BlockId;
+use crate::player::PlayerController;
+use crate::saves::SaveDir;
+use crate::world::{BlockChangedEvent, ChangeCause};
+use crate::AppState;
+
+/// File in the world folder the unlocked achievements are saved to.
+const ACHIEVEMENTS_FILE: &str = "achievements.json";
+/// Seconds a toast notification is shown.
+const TOAST_LIFETIME: f32 = 4.0;
+/// Number of placed blocks for the "Builder" achievement.
+const BUILDER_BLOCKS: usize = 100;
+
+pub struct AchievementPlugin;
+
+impl Plugin for AchievementPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Achievements>()
+            .add_systems(OnEnter(AppState::Running), load_achievements)
+            .add_systems(
+                Update,
+                (block_achievements, height_achievements).run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Achievement {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub desc: &'static str,
+    pub unlocked: bool,
+}
+
+impl Achievement {
+    const fn new(id: &'static str, name: &'static str, desc: &'static str) -> Self {
+        Self {
+            id,
+            name,
+            desc,
+            unlocked: false,
+        }
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct Achievements(pub Vec<Achievement>);
+
+impl Default for Achievements {
+    fn default() -> Self {
+        Self(vec![
+            Achievement::new("first_block", "First Block Broken", "Break a block"),
+            Achievement::new("deep_diver", "Deep Diver", "Dive below a depth of 100"),
+            Achievement::new("high_flyer", "High Flyer", "Fly above a height of 200"),
+            Achievement::new("builder", "Builder", "Place 100 blocks"),
+        ])
+    }
+}
+
+impl Achievements {
+    /// Unlocks the achievement `id`.
+    ///
+    /// Returns the achievement if it was locked before.
+    pub fn unlock(&mut self, id: &str) -> Option<&Achievement> {
+        let achievement = self.0.iter_mut().find(|a| a.id == id && !a.unlocked)?;
+        achievement.unlocked = true;
+        Some(achievement)
+    }
+
+    /// Returns the ids of the unlocked achievements.
+    pub fn unlocked(&self) -> Vec<&'static str> {
+        self.0.iter().filter(|a| a.unlocked).map(|a| a.id).collect()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let text = serde_json::to_string_pretty(&self.unlocked())?;
+        fs::write(path, text).map_err(|e| anyhow!("Failed to write {}: {e}", path.display()))
+    }
+
+    /// Unlocks the achievements saved at `path`, if the file exists.
+    pub fn load(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(anyhow!("Failed to read {}: {e}", path.display())),
+        };
+        let ids: Vec<String> = serde_json::from_str(&text)
+            .map_err(|e| anyhow!("Invalid achievements {}: {e}", path.display()))?;
+        for id in ids {
+            self.unlock(&id);
+        }
+        Ok(())
+    }
+}
+
+/// A notification that fades out over its lifetime, shown by the UI.
+#[derive(Component, Debug)]
+pub struct ToastNotification {
+    pub text: String,
+    /// Remaining time in seconds
+    pub lifetime: f32,
+}
+
+fn load_achievements(dir: Res<SaveDir>, mut achievements: ResMut<Achievements>) {
+    if let Err(e) = achievements.load(&dir.0.join(ACHIEVEMENTS_FILE)) {
+        error!("{e}");
+    }
+}
+
+/// Unlocks the achievement, notifies the player and saves the progress.
+fn unlock(cmds: &mut Commands, achievements: &mut Achievements, dir: &SaveDir, id: &str) {
+    let Some(achievement) = achievements.unlock(id) else {
+        return;
+    };
+    info!("Achievement unlocked: {}", achievement.name);
+    cmds.spawn(ToastNotification {
+        text: format!("{}\n{}", achievement.name, achievement.desc),
+        lifetime: TOAST_LIFETIME,
+    });
+    if let Err(e) = achievements.save(&dir.0.join(ACHIEVEMENTS_FILE)) {
+        error!("{e}");
+    }
+}
+
+/// Unlocks the achievements for breaking and placing blocks.
+fn block_achievements(
+    mut cmds: Commands,
+    mut events: EventReader<BlockChangedEvent>,
+    mut achievements: ResMut<Achievements>,
+    mut placed: Local<usize>,
+    dir: Res<SaveDir>,
+) {
+    for event in events.iter() {
+        if event.cause != ChangeCause::Player {
+            continue;
+        }
+        if event.new == BlockId::AIR {
+            unlock(&mut cmds, &mut achievements, &dir, "first_block");
+        } else {
+            *placed += 1;
+            if *placed >= BUILDER_BLOCKS {
+                unlock(&mut cmds, &mut achievements, &dir, "builder");
+            }
+        }
+    }
+}
+
+/// Unlocks the achievements for the player's height.
+fn height_achievements(
+    mut cmds: Commands,
+    mut achievements: ResMut<Achievements>,
+    dir: Res<SaveDir>,
+    query: Query<&Transform, (With<PlayerController>, Changed<Transform>)>,
+) {
+    let Ok(transform) = query.get_single() else {
+        return;
+    };
+    if transform.translation.y < -100.0 {
+        unlock(&mut cmds, &mut achievements, &dir, "deep_diver");
+    } else if transform.translation.y > 200.0 {
+        unlock(&mut cmds, &mut achievements, &dir, "high_flyer");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Achievements;
+
+    #[test]
+    fn save_and_load() {
+        let mut achievements = Achievements::default();
+        assert!(achievements.unlock("builder").is_some());
+        assert!(achievements.unlock("builder").is_none());
+        assert!(achievements.unlock("unknown").is_none());
+
+        let path = std::env::temp_dir().join("bevy-voxel-achievements.json");
+        achievements.save(&path).unwrap();
+        let mut loaded = Achievements::default();
+        loaded.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unlocked(), vec!["builder"]);
+
+        let mut missing = Achievements::default();
+        missing.load(&path).unwrap();
+        assert!(missing.unlocked().is_empty());
+    }
+}
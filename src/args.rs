@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use bevy::render::settings::Backends;
+
+/// Command line arguments.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Args {
+    /// Folder containing the `textures` and `blocks` folders
+    pub assets: String,
+    /// Folder containing the saved worlds and the clipboard
+    pub world: PathBuf,
+    /// Run the meshing benchmark instead of the game
+    pub bench_meshing: bool,
+    /// Run the terrain generation benchmark instead of the game
+    pub bench_generation: bool,
+    /// Graphics backend, defaults to the `WGPU_BACKEND` environment variable
+    /// or the primary backends of the platform
+    pub backend: Option<Backend>,
+}
+
+/// Graphics backends that can be selected with `--backend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Vulkan,
+    Gl,
+    Dx12,
+    Metal,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vulkan" => Ok(Self::Vulkan),
+            "gl" => Ok(Self::Gl),
+            "dx12" => Ok(Self::Dx12),
+            "metal" => Ok(Self::Metal),
+            _ => Err(format!(
+                "Unknown backend '{s}', expected vulkan, gl, dx12 or metal"
+            )),
+        }
+    }
+}
+
+impl From<Backend> for Backends {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Vulkan => Backends::VULKAN,
+            Backend::Gl => Backends::GL,
+            Backend::Dx12 => Backends::DX12,
+            Backend::Metal => Backends::METAL,
+        }
+    }
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            assets: "assets".into(),
+            world: ".".into(),
+            bench_meshing: false,
+            bench_generation: false,
+            backend: None,
+        }
+    }
+}
+
+impl Args {
+    pub const USAGE: &str =
+        "Usage: bevy-voxel [--assets <dir>] [--world <save-path>] [--backend <vulkan|gl|dx12|metal>] [--bench-meshing] [--bench-generation]";
+
+    /// Parses the arguments, excluding the program name.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut result = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for '{arg}'"));
+            match arg.as_str() {
+                "--assets" => result.assets = value()?,
+                "--world" => result.world = value()?.into(),
+                "--backend" => result.backend = Some(value()?.parse()?),
+                "--bench-meshing" => result.bench_meshing = true,
+                "--bench-generation" => result.bench_generation = true,
+                _ => return Err(format!("Unknown argument '{arg}'")),
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Args, Backend};
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parse_args() {
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+
+        let args = parse(&["--world", "saves/a", "--assets", "packs/b"]).unwrap();
+        assert_eq!(args.assets, "packs/b");
+        assert_eq!(args.world.to_str(), Some("saves/a"));
+
+        assert!(parse(&["--bench-meshing"]).unwrap().bench_meshing);
+        assert!(parse(&["--bench-generation"]).unwrap().bench_generation);
+        assert_eq!(
+            parse(&["--backend", "gl"]).unwrap().backend,
+            Some(Backend::Gl)
+        );
+        assert!(parse(&["--backend", "opengl"]).is_err());
+        assert!(parse(&["--assets"]).is_err());
+        assert!(parse(&["--foo"]).is_err());
+    }
+}
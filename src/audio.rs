@@ -0,0 +1,260 @@
+use std::path::Path;
+
+use bevy::asset::LoadState;
+use bevy::audio::Volume;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::block::{blocks, Block, BlockId};
+use crate::player::PlayerController;
+use crate::settings;
+use crate::world::{BlockChangedEvent, ChangeCause};
+use crate::AppState;
+
+/// Scales positions so that sounds fade out after about 32 blocks.
+const SPATIAL_SCALE: f32 = 1.0 / 32.0;
+/// Distance between the ears of the listener in blocks.
+const EAR_GAP: f32 = 0.4;
+/// Maximum relative pitch change of the sound effects.
+const PITCH_VARIATION: f32 = 0.1;
+/// Tag and footstep group used for blocks without specific sounds.
+const DEFAULT_SOUNDS: &str = "default";
+
+pub struct VoxelAudioPlugin;
+
+impl Plugin for VoxelAudioPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = match settings::load_section("audio") {
+            Ok(settings) => settings.unwrap_or_default(),
+            Err(e) => {
+                warn!("Using the default audio settings: {e}");
+                AudioSettings::default()
+            }
+        };
+        app.insert_resource(settings)
+            .init_resource::<SoundBank>()
+            .init_resource::<SoundLoading>()
+            .add_systems(OnEnter(AppState::LoadSounds), load_sounds)
+            .add_systems(Update, check_sounds.run_if(in_state(AppState::LoadSounds)))
+            .add_systems(
+                Update,
+                (apply_volume, block_sounds).run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+/// Volumes from 0 to 1, stored in the `[audio]` section of the settings file.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct AudioSettings {
+    pub master: f32,
+    pub effects: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            effects: 1.0,
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn save(&self) {
+        if let Err(e) = settings::save_section("audio", self) {
+            error!("{e}");
+        }
+    }
+}
+
+/// Sounds for the block tags and footstep groups.
+///
+/// The sounds are loaded from `sounds/break/<tag>/` and `sounds/footstep/<group>/`.
+/// Blocks without matching sounds use the "default" ones.
+/// The sound files referenced by the blocks take precedence.
+#[derive(Resource, Debug, Default)]
+pub struct SoundBank {
+    pub break_sounds: HashMap<String, Vec<Handle<AudioSource>>>,
+    pub footstep_bank: HashMap<String, Vec<Handle<AudioSource>>>,
+    /// Sound files referenced by the blocks
+    pub files: HashMap<String, Handle<AudioSource>>,
+}
+
+impl SoundBank {
+    fn file(&self, path: &Option<String>) -> Option<&Handle<AudioSource>> {
+        self.files.get(path.as_ref()?)
+    }
+
+    /// Returns a break sound for the `block`.
+    fn break_sound(&self, block: &Block) -> Option<&Handle<AudioSource>> {
+        if let Some(sound) = self.file(&block.sounds.break_sound) {
+            return Some(sound);
+        }
+        let handles = block
+            .tags
+            .iter()
+            .find_map(|t| self.break_sounds.get(t))
+            .or_else(|| self.break_sounds.get(DEFAULT_SOUNDS))?;
+        handles.choose(&mut rand::thread_rng())
+    }
+
+    /// Returns the place sound for the `block`.
+    fn place_sound(&self, block: &Block) -> Option<&Handle<AudioSource>> {
+        self.file(&block.sounds.place)
+    }
+
+    /// Returns a footstep sound for walking on the `block`.
+    pub fn footstep_sound(&self, block: &Block) -> Option<&Handle<AudioSource>> {
+        if let Some(sound) = self.file(&block.sounds.step) {
+            return Some(sound);
+        }
+        let handles = self
+            .footstep_bank
+            .get(&block.footstep_group)
+            .or_else(|| self.footstep_bank.get(DEFAULT_SOUNDS))?;
+        handles.choose(&mut rand::thread_rng())
+    }
+}
+
+#[derive(Resource, Default)]
+struct SoundLoading {
+    folder: Vec<HandleUntyped>,
+    files: Vec<(String, Handle<AudioSource>)>,
+}
+
+/// Start loading the sound folder and the sounds referenced by the blocks.
+fn load_sounds(
+    mut state: ResMut<NextState<AppState>>,
+    mut loading: ResMut<SoundLoading>,
+    asset_server: Res<AssetServer>,
+) {
+    match asset_server.load_folder("sounds") {
+        Ok(handles) => loading.folder = handles,
+        Err(e) => info!("No sound folder loaded: {e}"),
+    }
+
+    let blocks = blocks().read().unwrap();
+    let mut paths: Vec<&str> = blocks.values().flat_map(|b| b.sounds.paths()).collect();
+    paths.sort_unstable();
+    paths.dedup();
+    loading.files = paths
+        .into_iter()
+        .map(|p| (p.to_string(), asset_server.load(p)))
+        .collect();
+
+    if loading.folder.is_empty() && loading.files.is_empty() {
+        state.set(AppState::WorldSelect);
+    }
+}
+
+/// Wait for the sounds and sort them into the sound bank.
+///
+/// Sounds are optional, missing files are only reported once.
+fn check_sounds(
+    mut state: ResMut<NextState<AppState>>,
+    mut loading: ResMut<SoundLoading>,
+    mut bank: ResMut<SoundBank>,
+    asset_server: Res<AssetServer>,
+) {
+    let folder = loading.folder.iter().map(|h| h.id());
+    let files = loading.files.iter().map(|(_, h)| h.id());
+    if folder
+        .chain(files)
+        .any(|id| asset_server.get_load_state(id) == LoadState::Loading)
+    {
+        return;
+    }
+
+    for (path, handle) in std::mem::take(&mut loading.files) {
+        if asset_server.get_load_state(&handle) == LoadState::Loaded {
+            bank.files.insert(path, handle);
+        } else {
+            warn!("Failed to load the sound {path}");
+        }
+    }
+
+    for handle in std::mem::take(&mut loading.folder) {
+        let Some(path) = asset_server.get_handle_path(&handle) else {
+            continue;
+        };
+        if asset_server.get_load_state(&handle) != LoadState::Loaded {
+            warn!("Failed to load the sound {}", path.path().display());
+            continue;
+        }
+        let mut parts = path.path().iter().skip(1).map(Path::new);
+        let (Some(kind), Some(tag), Some(_file)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let sounds = match kind.to_str() {
+            Some("break") => &mut bank.break_sounds,
+            Some("footstep") => &mut bank.footstep_bank,
+            _ => continue,
+        };
+        sounds
+            .entry(tag.to_string_lossy().into_owned())
+            .or_default()
+            .push(handle.typed());
+    }
+
+    state.set(AppState::WorldSelect);
+}
+
+fn apply_volume(settings: Res<AudioSettings>, mut volume: ResMut<GlobalVolume>) {
+    if settings.is_changed() {
+        *volume = GlobalVolume::new(settings.master);
+    }
+}
+
+/// Spawn a sound effect at `pos` heard by the `listener`, with a slightly random pitch.
+pub fn play_at(
+    cmds: &mut Commands,
+    sound: Handle<AudioSource>,
+    settings: &AudioSettings,
+    listener: &Transform,
+    pos: Vec3,
+) {
+    let listener = Transform {
+        translation: listener.translation * SPATIAL_SCALE,
+        ..*listener
+    };
+    let pitch = rand::thread_rng().gen_range(1.0 - PITCH_VARIATION..=1.0 + PITCH_VARIATION);
+    cmds.spawn(SpatialAudioBundle {
+        source: sound,
+        settings: PlaybackSettings::DESPAWN
+            .with_volume(Volume::new_relative(settings.effects))
+            .with_speed(pitch),
+        spatial: SpatialSettings::new(listener, EAR_GAP * SPATIAL_SCALE, pos * SPATIAL_SCALE),
+    });
+}
+
+/// Play the break and place sounds of the blocks changed by the player.
+fn block_sounds(
+    mut cmds: Commands,
+    mut events: EventReader<BlockChangedEvent>,
+    bank: Res<SoundBank>,
+    settings: Res<AudioSettings>,
+    query: Query<&Transform, With<PlayerController>>,
+) {
+    let Ok(listener) = query.get_single() else {
+        return;
+    };
+    let blocks = blocks().read().unwrap();
+    for event in events.iter() {
+        if event.cause != ChangeCause::Player {
+            continue;
+        }
+        let sound = if event.new == BlockId::AIR {
+            blocks.get(&event.old).and_then(|b| bank.break_sound(b))
+        } else {
+            blocks.get(&event.new).and_then(|b| bank.place_sound(b))
+        };
+        if let Some(sound) = sound {
+            let pos = event.pos.as_vec3() + 0.5;
+            play_at(&mut cmds, sound.clone(), &settings, listener, pos);
+        }
+    }
+}
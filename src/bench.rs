@@ -0,0 +1,297 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::utils::HashMap;
+
+use crate::block::{Block, BlockId, Cube, Face};
+use crate::chunk::{Border, Chunk};
+use crate::generation::{generate_chunk, NoiseParam, WorldGen};
+use crate::textures::TileTextures;
+use crate::util::for_uvec3;
+use crate::world::VoxelWorld;
+
+/// Number of meshed chunks per pattern.
+const ITERATIONS: u32 = 100;
+/// Number of generated chunks per case.
+const GEN_ITERATIONS: u32 = 20;
+
+/// Deterministic chunk contents for benchmarking.
+#[derive(Debug, Clone, Copy)]
+pub enum Pattern {
+    /// Only air
+    Air,
+    /// Completely filled
+    Solid,
+    /// Completely filled and surrounded by filled neighbors
+    Enclosed,
+    /// Alternating blocks, the worst case for face culling
+    Checkerboard,
+    /// Sphere in the center of the chunk
+    Sphere,
+    /// Flat ground filling the lower half
+    Flat,
+    /// Surface chunk of the default world generation
+    Terrain,
+}
+
+impl Pattern {
+    pub fn all() -> [Self; 7] {
+        [
+            Self::Air,
+            Self::Solid,
+            Self::Enclosed,
+            Self::Checkerboard,
+            Self::Sphere,
+            Self::Flat,
+            Self::Terrain,
+        ]
+    }
+
+    /// Returns the borders of the neighbors.
+    pub fn borders(self) -> [Border; 6] {
+        match self {
+            Self::Enclosed => [Border::full(); 6],
+            _ => [Border::new(); 6],
+        }
+    }
+
+    /// Creates a chunk of stone and air with this pattern.
+    pub fn chunk(self) -> Chunk {
+        if let Self::Terrain = self {
+            return terrain_chunk();
+        }
+        let mut chunk = Chunk::new(BlockId::AIR);
+        let center = Vec3::splat(Chunk::SIZE as f32 / 2.0);
+        let radius = Chunk::SIZE as f32 / 2.0 - 2.0;
+        for_uvec3(UVec3::ZERO, Chunk::MAX, |pos| {
+            let filled = match self {
+                Self::Air | Self::Terrain => false,
+                Self::Solid | Self::Enclosed => true,
+                Self::Checkerboard => (pos.x + pos.y + pos.z) % 2 == 0,
+                Self::Sphere => (pos.as_vec3() + 0.5).distance(center) < radius,
+                Self::Flat => pos.y < Chunk::SIZE as u32 / 2,
+            };
+            if filled {
+                chunk[pos] = BlockId::STONE;
+            }
+        });
+        chunk
+    }
+}
+
+/// Returns the chunk containing the surface at the world origin, with all
+/// blocks except air replaced by stone.
+fn terrain_chunk() -> Chunk {
+    let column = WorldGen::default().generate_column(IVec2::ZERO);
+    let y = surface_chunk_y(&column);
+    let mut chunk = column
+        .into_iter()
+        .find_map(|(cy, chunk)| (cy == y).then_some(chunk))
+        .unwrap_or_else(|| Chunk::new(BlockId::AIR));
+    for_uvec3(UVec3::ZERO, Chunk::MAX, |pos| {
+        if chunk[pos] != BlockId::AIR {
+            chunk[pos] = BlockId::STONE;
+        }
+    });
+    chunk
+}
+
+/// Returns the y index of the chunk containing the surface of the column.
+fn surface_chunk_y(column: &[(i32, Chunk)]) -> i32 {
+    let surface = VoxelWorld::find_surface_y(column, UVec2::ZERO).unwrap_or_default();
+    surface.div_euclid(Chunk::SIZE as i32)
+}
+
+/// Chunks of the default world generation at the world origin.
+#[derive(Debug, Clone, Copy)]
+pub enum GenCase {
+    /// Chunk containing the terrain surface
+    Surface,
+    /// Chunk below the terrain, returned early as stone
+    Underground,
+    /// Chunk above the terrain, returned early as air
+    Air,
+    /// Surface chunk with 10 octaves in every noise layer, the worst case
+    HighOctaves,
+}
+
+impl GenCase {
+    pub fn all() -> [Self; 4] {
+        [
+            Self::Surface,
+            Self::Underground,
+            Self::Air,
+            Self::HighOctaves,
+        ]
+    }
+
+    /// Returns the generation settings and the chunk position of this case.
+    pub fn setup(self) -> (WorldGen, IVec3) {
+        let mut gen = WorldGen::default();
+        if let Self::HighOctaves = self {
+            gen = with_noise(gen, |p| p.octaves = 10);
+        }
+        let y = match self {
+            Self::Surface | Self::HighOctaves => surface_chunk_y(&gen.generate_column(IVec2::ZERO)),
+            Self::Underground => gen.min_occupied_y_chunk(IVec2::ZERO) - 1,
+            Self::Air => gen.max_occupied_y_chunk(IVec2::ZERO) + 1,
+        };
+        (gen, IVec3::new(0, y, 0))
+    }
+}
+
+/// Applies `f` to the parameters of all noise layers.
+fn with_noise(mut gen: WorldGen, f: impl Fn(&mut NoiseParam)) -> WorldGen {
+    for layer in &mut gen.layers {
+        f(&mut layer.param);
+    }
+    gen
+}
+
+/// Returns the average duration of `f` over `iterations` runs.
+fn measure(iterations: u32, mut f: impl FnMut()) -> std::time::Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    start.elapsed() / iterations
+}
+
+/// Air and a fully culled stone block, independent of the block assets.
+pub fn blocks(textures: &TileTextures) -> HashMap<BlockId, Block> {
+    let stone = textures.id("stone").unwrap_or_else(|| textures.missing());
+    let faces = [(); 6].map(|_| Face {
+        texture: stone,
+        cull: true,
+        tint: Face::NO_TINT,
+    });
+
+    let mut blocks = HashMap::new();
+    blocks.insert(BlockId::AIR, Block::default());
+    blocks.insert(
+        BlockId::STONE,
+        Block {
+            opaque: true,
+            solid_faces: [true; 6],
+            cubes: vec![Cube {
+                min: UVec3::ZERO,
+                max: Cube::MAX,
+                faces,
+            }],
+            ..default()
+        },
+    );
+    blocks
+}
+
+/// Meshes each pattern and prints the time per chunk and the throughput.
+///
+/// This neither requires the bevy app nor the loaded assets.
+pub fn run() {
+    let textures = TileTextures::with_names(&["stone"]);
+    let blocks = blocks(&textures);
+
+    for pattern in Pattern::all() {
+        let chunk = pattern.chunk();
+        let borders = pattern.borders();
+        let start = Instant::now();
+        let mut faces = 0;
+        for _ in 0..ITERATIONS {
+            let mesh = black_box(chunk.mesh(borders, &blocks, &textures));
+            faces = mesh.indices().map_or(0, Indices::len) / 6;
+        }
+        let elapsed = start.elapsed() / ITERATIONS;
+        println!(
+            "{pattern:?}: {} ns/chunk, {:.0} chunks/s ({faces} faces)",
+            elapsed.as_nanos(),
+            1.0 / elapsed.as_secs_f64()
+        );
+    }
+}
+
+/// Generates each case and the column at the world origin and prints the
+/// time per chunk.
+///
+/// Afterwards the surface chunk is generated with varying octaves and
+/// frequencies of all noise layers, showing how much of the cost is spent sampling the noise.
+pub fn run_generation() {
+    for case in GenCase::all() {
+        let (gen, pos) = case.setup();
+        let elapsed = measure(GEN_ITERATIONS, || {
+            black_box(generate_chunk(pos, &gen));
+        });
+        println!(
+            "{case:?} {pos}: {} us/chunk, {:.0} chunks/s",
+            elapsed.as_micros(),
+            1.0 / elapsed.as_secs_f64()
+        );
+    }
+
+    let gen = WorldGen::default();
+    let chunks = gen.generate_column(IVec2::ZERO).len();
+    let elapsed = measure(GEN_ITERATIONS, || {
+        black_box(gen.generate_column(IVec2::ZERO));
+    });
+    println!(
+        "Column: {} us/column ({chunks} chunks)",
+        elapsed.as_micros()
+    );
+
+    let (default, pos) = GenCase::Surface.setup();
+    for octaves in [1, 2, 4, 6, 8, 10] {
+        let gen = with_noise(default.clone(), |p| p.octaves = octaves);
+        let elapsed = measure(GEN_ITERATIONS, || {
+            black_box(generate_chunk(pos, &gen));
+        });
+        println!("Octaves {octaves}: {} us/chunk", elapsed.as_micros());
+    }
+    for scale in [0.25, 0.5, 1.0, 2.0, 4.0] {
+        let gen = with_noise(default.clone(), |p| p.frequency *= scale);
+        let elapsed = measure(GEN_ITERATIONS, || {
+            black_box(generate_chunk(pos, &gen));
+        });
+        println!("Frequency x{scale}: {} us/chunk", elapsed.as_micros());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::render::mesh::Indices;
+
+    use super::{blocks, GenCase, Pattern};
+    use crate::block::BlockId;
+    use crate::chunk::Chunk;
+    use crate::textures::TileTextures;
+
+    #[test]
+    fn pattern_faces() {
+        let textures = TileTextures::with_names(&["stone"]);
+        let blocks = blocks(&textures);
+        let faces = |pattern: Pattern| {
+            let mesh = pattern.chunk().mesh(pattern.borders(), &blocks, &textures);
+            mesh.indices().map_or(0, Indices::len) / 6
+        };
+
+        assert_eq!(faces(Pattern::Air), 0);
+        assert_eq!(faces(Pattern::Solid), 6 * Chunk::SIZE * Chunk::SIZE);
+        assert_eq!(faces(Pattern::Enclosed), 0);
+        assert_eq!(faces(Pattern::Flat), 4 * Chunk::SIZE * Chunk::SIZE);
+        assert!(faces(Pattern::Terrain) > 0);
+        assert_eq!(faces(Pattern::Checkerboard), 6 * Chunk::SIZE.pow(3) / 2);
+        assert!(faces(Pattern::Sphere) > 0);
+    }
+
+    #[test]
+    fn gen_cases() {
+        let trivial = |case: GenCase| {
+            let (gen, pos) = case.setup();
+            gen.trivial_chunk(pos)
+        };
+        assert_eq!(trivial(GenCase::Surface), None);
+        assert_eq!(trivial(GenCase::HighOctaves), None);
+        assert_eq!(trivial(GenCase::Underground), Some(BlockId::STONE));
+        assert_eq!(trivial(GenCase::Air), Some(BlockId::AIR));
+    }
+}
@@ -1,54 +1,154 @@
+use std::fmt;
 use std::ops::{Index, IndexMut};
+use std::path::Path;
 use std::sync::{OnceLock, RwLock};
 
+use anyhow::anyhow;
 use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
 use bevy::prelude::*;
 use bevy::reflect::{TypePath, TypeUuid};
 use bevy::render::mesh::{Indices, PrimitiveTopology};
 use bevy::utils::HashMap;
-use serde::Deserialize;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
 
 use crate::textures::{TileTextureId, TileTextures};
-use crate::util::Direction;
+use crate::util::{for_uvec3, Direction};
 
-/// Id of a block. This is also used by the asset server to load the blocks
-/// before storing them in a shared map.
-#[derive(Debug, Clone, Copy, TypeUuid, TypePath, Deserialize, PartialEq, Eq, Hash)]
-#[uuid = "fd6772fe-c8b7-4e89-b1f8-4af6faa57627"]
+/// Id of a block.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Hash)]
 pub struct BlockId(pub u8);
 
+impl BlockId {
+    pub const AIR: Self = Self(0);
+    pub const STONE: Self = Self(1);
+    pub const DIRT: Self = Self(2);
+    pub const GRASS: Self = Self(3);
+    pub const WATER: Self = Self(4);
+    pub const FIRE: Self = Self(5);
+}
+
+/// Maximum size of a multi-voxel block along each axis.
+pub const MAX_BLOCK_SIZE: u32 = 4;
+
 static BLOCKS: OnceLock<RwLock<HashMap<BlockId, Block>>> = OnceLock::new();
 
+/// The shared block registry, built by [`build_registry`] once all blocks are loaded.
 pub fn blocks<'a>() -> &'a RwLock<HashMap<BlockId, Block>> {
     BLOCKS.get_or_init(default)
 }
 
 /// Block occupying a specific coordinate.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Block {
+    /// File name of the block without the extension, shown in the UI.
+    pub name: String,
     /// If this block fills its coordinate.
     /// Allowing adjascent faces to be culled during rendering.
     pub opaque: bool,
     /// Cubes that define the mesh of this block.
     pub cubes: Vec<Cube>,
+    /// Sides of the voxel that are fully covered by the cubes of an opaque block,
+    /// hiding the adjacent faces of the neighbors.
+    pub solid_faces: [bool; 6],
+    /// Tags describing the behavior of this block, like "flammable".
+    pub tags: Vec<String>,
+    /// Probability per fire tick that a neighboring fire ignites this block.
+    pub ignite_chance: f32,
+    /// Number of fire ticks until a fire block extinguishes.
+    pub max_age: u8,
+    /// Emitted light level from 0 to 15.
+    pub light_emission: u8,
+    /// Color of the emitted light.
+    pub emissive: Color,
+    /// Seconds of mining until the block breaks in survival mode, zero breaks instantly.
+    pub hardness: f32,
+    /// Group of the sounds played when walking on this block, like "stone" or "wood".
+    pub footstep_group: String,
+    /// Sound files overriding the sounds of the tags and footstep group.
+    pub sounds: BlockSounds,
+    /// Number of voxels the block extends beyond its origin in the positive
+    /// directions, zero for blocks occupying a single voxel.
+    ///
+    /// The other voxels of a multi-voxel block contain the same block id and
+    /// their offset to the origin as metadata (see [`part_meta`]).
+    pub extent: UVec3,
+    /// If placed blocks store per-instance data, like the color of a lamp
+    /// (see [`BlockEntityData`](crate::block_entity::BlockEntityData)).
+    pub has_entity: bool,
+}
+
+/// Returns the metadata of the voxel at `offset` from the origin of a multi-voxel block.
+pub fn part_meta(offset: UVec3) -> u8 {
+    debug_assert!(offset.cmplt(UVec3::splat(MAX_BLOCK_SIZE)).all());
+    (offset.x | offset.y << 2 | offset.z << 4) as u8
+}
+
+/// Returns the offset from the origin of a multi-voxel block encoded in `meta`.
+pub fn part_offset(meta: u8) -> UVec3 {
+    let meta = meta as u32;
+    UVec3::new(meta & 3, meta >> 2 & 3, meta >> 4 & 3)
+}
+
+/// Paths of the sounds of a block, relative to the assets folder.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BlockSounds {
+    #[serde(rename = "break")]
+    pub break_sound: Option<String>,
+    pub place: Option<String>,
+    pub step: Option<String>,
+}
+
+impl BlockSounds {
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        [&self.break_sound, &self.place, &self.step]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
 }
 
 impl Block {
+    /// Returns if the block has the given `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Returns if the block spans multiple voxels.
+    pub fn is_multi_voxel(&self) -> bool {
+        self.extent != UVec3::ZERO
+    }
+
+    /// Returns the offsets of the voxels of this block from its origin,
+    /// starting with the origin.
+    pub fn voxels(&self) -> Vec<UVec3> {
+        let mut voxels = Vec::new();
+        for_uvec3(UVec3::ZERO, self.extent + 1, |p| voxels.push(p));
+        voxels
+    }
+
     /// Generate the complete mesh for this block.
     pub fn mesh(&self) -> Mesh {
         let mut positions = Vec::with_capacity(24);
         let mut normals = Vec::with_capacity(24);
         let mut uvs = Vec::with_capacity(24);
+        let mut tangents = Vec::with_capacity(24);
+        let mut colors = Vec::with_capacity(24);
         let mut indices = Vec::new();
 
+        let textures = TileTextures::get();
         for cube in &self.cubes {
             cube.mesh(
                 Vec3::ZERO,
                 [false; 6],
+                textures,
                 &mut indices,
                 &mut positions,
                 &mut normals,
                 &mut uvs,
+                &mut tangents,
+                &mut colors,
             );
         }
 
@@ -56,6 +156,8 @@ impl Block {
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
         mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
         mesh.set_indices(Some(Indices::U32(indices)));
         mesh
     }
@@ -70,7 +172,47 @@ pub struct Cube {
 }
 
 impl Cube {
-    const MAX: UVec3 = UVec3::splat(16);
+    pub const MAX: UVec3 = UVec3::splat(16);
+
+    /// Returns if a cube from `min` to `max` reaches the side of the voxel in direction `d`.
+    pub fn touches_side(min: UVec3, max: UVec3, d: Direction) -> bool {
+        let v = IVec3::from(d);
+        let axis = if v.x != 0 {
+            0
+        } else if v.y != 0 {
+            1
+        } else {
+            2
+        };
+        if v[axis] > 0 {
+            max[axis] == Self::MAX[axis]
+        } else {
+            min[axis] == 0
+        }
+    }
+
+    /// Returns the sides of the voxel that are fully covered by the `cubes`.
+    pub fn covered_sides(cubes: &[Cube]) -> [bool; 6] {
+        const N: usize = Cube::MAX.x as usize;
+        Direction::all().map(|d| {
+            let v = IVec3::from(d);
+            let (u, w) = if v.x != 0 {
+                (1, 2)
+            } else if v.y != 0 {
+                (0, 2)
+            } else {
+                (0, 1)
+            };
+            let mut covered = [[false; N]; N];
+            for cube in cubes.iter().filter(|c| Self::touches_side(c.min, c.max, d)) {
+                let (min, max) = (cube.min.min(Cube::MAX), cube.max.min(Cube::MAX));
+                for row in &mut covered[min[u] as usize..max[u] as usize] {
+                    row[min[w] as usize..max[w] as usize].fill(true);
+                }
+            }
+            covered.iter().flatten().all(|c| *c)
+        })
+    }
 
     fn minf(&self) -> Vec3 {
         self.min.as_vec3() / Self::MAX.as_vec3()
@@ -80,14 +222,22 @@ impl Cube {
     }
 
     /// Generate the mesh for the cube.
+    ///
+    /// The tangents point along the u axis of the face textures, with the
+    /// handedness in w, as the faces are axis-aligned.
+    /// The vertex colors are the tints of the faces.
+    #[allow(clippy::too_many_arguments)]
     pub fn mesh(
         &self,
         pos: Vec3,
         occupied: [bool; 6],
+        textures: &TileTextures,
         indices: &mut Vec<u32>,
         positions: &mut Vec<[f32; 3]>,
         normals: &mut Vec<[f32; 3]>,
         uvs: &mut Vec<[f32; 2]>,
+        tangents: &mut Vec<[f32; 4]>,
+        colors: &mut Vec<[f32; 4]>,
     ) {
         let r_p = &[
             Vec3::new(-0.5, -0.5, -0.5),
@@ -106,7 +256,7 @@ impl Cube {
 
         for d in Direction::all() {
             let face = &self.faces[d as usize];
-            if !(face.cull == Some(d) && occupied[d as usize]) {
+            if !(face.cull && occupied[d as usize]) {
                 let rot = Quat::from(d);
                 for p in r_p {
                     // Rotate and normalize to (0, 1)
@@ -117,10 +267,17 @@ impl Cube {
                     positions.push(p.into());
                 }
 
-                normals.extend_from_slice(&[Vec3::from(d).into(); 4]);
+                let normal = Vec3::from(d);
+                normals.extend_from_slice(&[normal.into(); 4]);
+
+                // u and v decrease along x and y of the unrotated face
+                let (tangent, bitangent) = (rot * Vec3::NEG_X, rot * Vec3::NEG_Y);
+                let handedness = normal.cross(tangent).dot(bitangent).signum();
+                tangents.extend_from_slice(&[tangent.extend(handedness).into(); 4]);
 
-                let uv = TileTextures::get().uv(face.texture);
+                let uv = textures.uv(face.texture);
                 uvs.extend_from_slice(&r_uvs.map(|r_uv| (uv.0 + r_uv * (uv.1 - uv.0)).into()));
+                colors.extend_from_slice(&[Vec3::from(face.tint).extend(1.0).into(); 4]);
 
                 let j = indices.len() as u32 / 6 * 4;
                 indices.extend_from_slice(&[j, j + 1, j + 2, j, j + 2, j + 3]);
@@ -147,43 +304,455 @@ impl IndexMut<Direction> for Cube {
 pub struct Face {
     /// Id of the face's texture.
     pub texture: TileTextureId,
-    /// If the block in the direction is occupied this face is not rendered.
-    pub cull: Option<Direction>,
+    /// If the neighboring block in the direction of this face is occupied,
+    /// this face is not rendered.
+    pub cull: bool,
+    /// Color multiplied with the texture, e.g. for grayscale textures.
+    pub tint: [f32; 3],
+}
+
+impl Face {
+    /// The tint of faces without one.
+    pub const NO_TINT: [f32; 3] = [1.0; 3];
 }
 
 /// Deserializer for the block json format.
+///
+/// Crate visible for the fuzz targets, which include this module.
 #[derive(Debug, Deserialize)]
-struct BlockData {
+pub(crate) struct BlockData {
     id: BlockId,
     #[serde(default)]
     cubes: Vec<CubeData>,
     #[serde(default)]
     opaque: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    ignite_chance: f32,
+    #[serde(default)]
+    max_age: u8,
+    #[serde(default)]
+    light_emission: u8,
+    /// Defaults to white
+    emissive: Option<[f32; 3]>,
+    #[serde(default)]
+    hardness: f32,
+    /// Defaults to "stone" for opaque blocks
+    footstep_group: Option<String>,
+    #[serde(default)]
+    sounds: BlockSounds,
+    /// Number of voxels along each axis, the cubes may extend into the other voxels
+    #[serde(default = "block_size")]
+    size: UVec3,
+    #[serde(default)]
+    has_entity: bool,
+}
+
+fn block_size() -> UVec3 {
+    UVec3::ONE
 }
 
 /// Deserializer for the block json format.
 #[derive(Debug, Deserialize)]
-struct CubeData {
+pub(crate) struct CubeData {
     #[serde(default)]
     min: UVec3,
     #[serde(default = "cube_max")]
     max: UVec3,
-    faces: [FaceData; 6],
+    faces: FacesData,
+    /// Culling of the faces that do not specify it themselves
+    #[serde(default)]
+    cull: CullPolicy,
 }
 
 fn cube_max() -> UVec3 {
     Cube::MAX
 }
 
-/// Deserializer for the block json format.
-#[derive(Debug, Deserialize)]
-struct FaceData {
+/// Faces of a cube in the block json format.
+///
+/// Either an array of six faces (-x, -y, -z, +x, +y, +z) or an object like
+/// `{ "top": "grass_top", "bottom": "dirt", "sides": "grass_side" }`.
+// Only used while parsing, so the size does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+enum FacesData {
+    List([FaceData; 6]),
+    Map(FaceMap),
+}
+
+impl<'de> Deserialize<'de> for FacesData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FacesVisitor;
+
+        impl<'de> Visitor<'de> for FacesVisitor {
+            type Value = FacesData;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an array of six faces or an object with faces")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Deserialize::deserialize(SeqAccessDeserializer::new(seq)).map(FacesData::List)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                Deserialize::deserialize(MapAccessDeserializer::new(map)).map(FacesData::Map)
+            }
+        }
+
+        deserializer.deserialize_any(FacesVisitor)
+    }
+}
+
+impl FacesData {
+    /// Returns the faces in the order of [`Direction::all`].
+    fn resolve(self) -> Result<[FaceData; 6], Direction> {
+        match self {
+            Self::List(faces) => Ok(faces),
+            Self::Map(map) => map.resolve(),
+        }
+    }
+}
+
+/// Faces by group or direction, the directions take precedence over the groups
+/// and the groups over `all`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaceMap {
+    all: Option<FaceData>,
+    /// The horizontal faces
+    sides: Option<FaceData>,
+    top: Option<FaceData>,
+    bottom: Option<FaceData>,
+    #[serde(rename = "-x")]
+    neg_x: Option<FaceData>,
+    #[serde(rename = "-y")]
+    neg_y: Option<FaceData>,
+    #[serde(rename = "-z")]
+    neg_z: Option<FaceData>,
+    #[serde(rename = "+x")]
+    pos_x: Option<FaceData>,
+    #[serde(rename = "+y")]
+    pos_y: Option<FaceData>,
+    #[serde(rename = "+z")]
+    pos_z: Option<FaceData>,
+}
+
+impl FaceMap {
+    /// Returns the faces in the order of [`Direction::all`] or the first
+    /// direction without a face.
+    fn resolve(self) -> Result<[FaceData; 6], Direction> {
+        let Self {
+            all,
+            sides,
+            top,
+            bottom,
+            neg_x,
+            neg_y,
+            neg_z,
+            pos_x,
+            pos_y,
+            pos_z,
+        } = self;
+        let sides = sides.or(all.clone());
+        let faces = [
+            (Direction::NegX, neg_x.or(sides.clone())),
+            (Direction::NegY, neg_y.or(bottom).or(all.clone())),
+            (Direction::NegZ, neg_z.or(sides.clone())),
+            (Direction::PosX, pos_x.or(sides.clone())),
+            (Direction::PosY, pos_y.or(top).or(all)),
+            (Direction::PosZ, pos_z.or(sides)),
+        ];
+        let mut result = Vec::with_capacity(6);
+        for (d, face) in faces {
+            result.push(face.ok_or(d)?);
+        }
+        Ok(result.try_into().expect("cubes have six faces"))
+    }
+}
+
+/// Deserializer for the block json format, also accepts only the texture name.
+#[derive(Debug, Clone)]
+pub(crate) struct FaceData {
     texture: String,
-    cull: Option<Direction>,
+    cull: Option<CullData>,
+    tint: Option<TintData>,
+}
+
+impl<'de> Deserialize<'de> for FaceData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Texture(String),
+            Face {
+                texture: String,
+                cull: Option<CullData>,
+                tint: Option<TintData>,
+            },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Texture(texture) => Self {
+                texture,
+                cull: None,
+                tint: None,
+            },
+            Repr::Face {
+                texture,
+                cull,
+                tint,
+            } => Self {
+                texture,
+                cull,
+                tint,
+            },
+        })
+    }
+}
+
+/// Tint of a face in the block json format, either as linear rgb values
+/// from 0 to 1 or as sRGB hex string like `"#88cc44"`.
+///
+/// The tint is stored linear, like the vertex colors it ends up in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TintData([f32; 3]);
+
+impl<'de> Deserialize<'de> for TintData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Rgb([f32; 3]),
+            Hex(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Rgb(rgb) => Ok(Self(rgb)),
+            Repr::Hex(hex) => {
+                let digits = hex.strip_prefix('#').unwrap_or(&hex);
+                let color = (digits.len() == 6)
+                    .then(|| Color::hex(digits).ok())
+                    .flatten()
+                    .ok_or_else(|| serde::de::Error::custom(format!("invalid tint '{hex}'")))?;
+                let [r, g, b, _] = color.as_linear_rgba_f32();
+                Ok(Self([r, g, b]))
+            }
+        }
+    }
+}
+
+/// Culling of a face in the block json format.
+///
+/// The direction of a face is implied by its index (-x, -y, -z, +x, +y, +z),
+/// so `"cull": true` replaced the former `"cull": "<direction>"`.
+/// Directions are still accepted, but only cull if they match the face.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum CullData {
+    Enabled(bool),
+    /// Deprecated
+    Direction(Direction),
+}
+
+/// Default culling of the faces of a cube.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CullPolicy {
+    /// Faces are always rendered
+    #[default]
+    None,
+    /// Faces on the sides of the voxel are culled toward their own direction
+    Auto,
+}
+
+/// A block file as loaded by the asset server, its textures are resolved by
+/// [`build_registry`] once all blocks are loaded.
+#[derive(Debug, TypeUuid, TypePath)]
+#[uuid = "fd6772fe-c8b7-4e89-b1f8-4af6faa57627"]
+pub struct BlockAsset {
+    pub id: BlockId,
+    /// File name without the extension
+    pub name: String,
+    path: String,
+    data: BlockData,
+}
+
+impl BlockAsset {
+    /// Parses a block file without resolving its textures.
+    pub(crate) fn parse(bytes: &[u8], path: &Path) -> Result<Self, anyhow::Error> {
+        let path_str = path.display().to_string();
+        let data: BlockData = serde_json::from_slice(bytes)
+            .map_err(|e| anyhow!("Invalid block file {path_str}: {e}"))?;
+        Ok(Self {
+            id: data.id,
+            name: path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            path: path_str,
+            data,
+        })
+    }
+
+    /// Validates the block and resolves its textures.
+    ///
+    /// All problems of the file are reported together, including suggestions
+    /// for unknown texture names.
+    pub fn resolve(&self, texture_map: &TileTextures) -> Result<Block, anyhow::Error> {
+        let data = &self.data;
+        let size = data.size;
+        if size.cmplt(UVec3::ONE).any() || size.cmpgt(UVec3::splat(MAX_BLOCK_SIZE)).any() {
+            return Err(anyhow!(
+                "Invalid block size {size} in {}, expected 1 to {MAX_BLOCK_SIZE} voxels per axis",
+                self.path
+            ));
+        }
+
+        let mut errors = Vec::new();
+        if data.opaque && data.cubes.is_empty() {
+            errors.push("opaque blocks need at least one cube".to_string());
+        }
+
+        let mut cubes = Vec::with_capacity(data.cubes.len());
+        for (i, c) in data.cubes.iter().enumerate() {
+            let limit = Cube::MAX * size;
+            if c.min.cmpgt(c.max).any() {
+                errors.push(format!(
+                    "cube {i}: min {} is greater than max {}",
+                    c.min, c.max
+                ));
+            }
+            if c.max.cmpgt(limit).any() {
+                errors.push(format!(
+                    "cube {i}: max {} is outside of the block (0 to {limit})",
+                    c.max
+                ));
+            }
+            let face_data = match c.faces.clone().resolve() {
+                Ok(faces) => faces,
+                Err(d) => {
+                    errors.push(format!("cube {i}: missing face {d:?}"));
+                    continue;
+                }
+            };
+            let mut faces = Vec::with_capacity(6);
+            for (d, f) in Direction::all().into_iter().zip(face_data) {
+                let texture = texture_map.id(&f.texture).unwrap_or_else(|| {
+                    let suggestion = match texture_map.closest(&f.texture) {
+                        Some(name) => format!(", did you mean '{name}'?"),
+                        None => String::new(),
+                    };
+                    errors.push(format!(
+                        "cube {i} face {d:?}: unknown texture '{}'{suggestion}",
+                        f.texture
+                    ));
+                    texture_map.missing()
+                });
+                let cull = match f.cull {
+                    None => c.cull == CullPolicy::Auto && Cube::touches_side(c.min, c.max, d),
+                    Some(CullData::Enabled(cull)) => cull,
+                    Some(CullData::Direction(cull)) if cull == d => true,
+                    Some(CullData::Direction(cull)) => {
+                        warn!(
+                            "Cull direction {cull:?} does not match face {d:?} in {}: cube {i}",
+                            self.path
+                        );
+                        false
+                    }
+                };
+                let tint = f.tint.map_or(Face::NO_TINT, |t| t.0);
+                faces.push(Face {
+                    texture,
+                    cull,
+                    tint,
+                });
+            }
+            cubes.push(Cube {
+                min: c.min,
+                max: c.max,
+                faces: faces.try_into().expect("cubes have six faces"),
+            });
+        }
+
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "Invalid block file {}:\n  {}",
+                self.path,
+                errors.join("\n  ")
+            ));
+        }
+
+        let solid_faces = if data.opaque {
+            Cube::covered_sides(&cubes)
+        } else {
+            [false; 6]
+        };
+        Ok(Block {
+            name: self.name.clone(),
+            opaque: data.opaque,
+            cubes,
+            solid_faces,
+            tags: data.tags.clone(),
+            ignite_chance: data.ignite_chance,
+            max_age: data.max_age,
+            light_emission: data.light_emission,
+            emissive: data
+                .emissive
+                .map_or(Color::WHITE, |[r, g, b]| Color::rgb(r, g, b)),
+            hardness: data.hardness,
+            footstep_group: data.footstep_group.clone().unwrap_or_else(|| {
+                if data.opaque {
+                    "stone".into()
+                } else {
+                    String::new()
+                }
+            }),
+            sounds: data.sounds.clone(),
+            extent: size - 1,
+            has_entity: data.has_entity,
+        })
+    }
+}
+
+/// Resolves all loaded blocks into a new block registry.
+///
+/// Assets loaded more than once from the same file are only added once,
+/// different files with the same id are reported as duplicates.
+pub fn build_registry<'a>(
+    assets: impl IntoIterator<Item = &'a BlockAsset>,
+    textures: &TileTextures,
+) -> Result<HashMap<BlockId, Block>, anyhow::Error> {
+    let mut registry = HashMap::new();
+    let mut sources: HashMap<BlockId, &str> = HashMap::new();
+    let mut errors = Vec::new();
+    for asset in assets {
+        if let Some(other) = sources.get(&asset.id) {
+            if *other != asset.path {
+                errors.push(format!(
+                    "Duplicate block id {} in {} and {}",
+                    asset.id.0, other, asset.path
+                ));
+            }
+            continue;
+        }
+        sources.insert(asset.id, &asset.path);
+        match asset.resolve(textures) {
+            Ok(block) => {
+                registry.insert(asset.id, block);
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors.join("\n")));
+    }
+    Ok(registry)
 }
 
 /// Loading all block assets.
-/// It requires all block textures to be fully loaded.
 #[derive(Default)]
 pub struct BlockLoader;
 
@@ -194,29 +763,8 @@ impl AssetLoader for BlockLoader {
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
         Box::pin(async move {
-            let block_data: BlockData = serde_json::from_slice(bytes)?;
-
-            let texture_map = TileTextures::get();
-
-            let block = Block {
-                opaque: block_data.opaque,
-                cubes: block_data
-                    .cubes
-                    .into_iter()
-                    .map(|c| Cube {
-                        min: c.min,
-                        max: c.max,
-                        faces: c.faces.map(|f| Face {
-                            texture: texture_map.id(&f.texture),
-                            cull: f.cull,
-                        }),
-                    })
-                    .collect(),
-            };
-
-            load_context.set_default_asset(LoadedAsset::new(block_data.id));
-            blocks().write().unwrap().insert(block_data.id, block);
-
+            let asset = BlockAsset::parse(bytes, load_context.path())?;
+            load_context.set_default_asset(LoadedAsset::new(asset));
             Ok(())
         })
     }
@@ -225,3 +773,535 @@ impl AssetLoader for BlockLoader {
         &["block"]
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+    use bevy::render::mesh::VertexAttributeValues;
+    use bevy::utils::HashMap;
+
+    use std::path::Path;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{build_registry, Block, BlockAsset, BlockId, Cube, CubeData, Face, FaceData};
+    use crate::block_entity::BlockEntityData;
+    use crate::chunk::{Border, Chunk};
+    use crate::textures::TileTextures;
+    use crate::util::Direction;
+
+    fn parse_block(
+        bytes: &[u8],
+        path: &str,
+        textures: &TileTextures,
+    ) -> Result<(BlockId, Block), anyhow::Error> {
+        let asset = BlockAsset::parse(bytes, Path::new(path))?;
+        Ok((asset.id, asset.resolve(textures)?))
+    }
+
+    /// Number of mutated inputs per seed.
+    const FUZZ_ITERATIONS: usize = 2000;
+
+    /// Applies a random byte or token mutation to the json `bytes`.
+    fn mutate(rng: &mut StdRng, bytes: &mut Vec<u8>) {
+        const TOKENS: &[&[u8]] = &[
+            b"{",
+            b"}",
+            b"[",
+            b"]",
+            b"\"",
+            b",",
+            b":",
+            b"null",
+            b"true",
+            b"-1",
+            b"1e999",
+            b"4294967296",
+            b"\"#zzzzzz\"",
+            b"\"\\u0000\"",
+            b"\"all\"",
+            b"\"faces\"",
+            b"\"cubes\"",
+            b"\"size\"",
+            b"[0, 0, 0]",
+            b"[99, 99, 99]",
+        ];
+        let i = rng.gen_range(0..=bytes.len());
+        match rng.gen_range(0..5) {
+            0 if i < bytes.len() => bytes[i] = rng.gen(),
+            1 => {
+                let end = rng.gen_range(i..=bytes.len());
+                bytes.drain(i..end);
+            }
+            2 => bytes.truncate(i),
+            3 => {
+                let token = TOKENS[rng.gen_range(0..TOKENS.len())];
+                bytes.splice(i..i, token.iter().copied());
+            }
+            _ => {
+                let end = rng.gen_range(i..=bytes.len());
+                let copy = bytes[i..end].to_vec();
+                let at = rng.gen_range(0..=bytes.len());
+                bytes.splice(at..at, copy);
+            }
+        }
+    }
+
+    /// Calls `f` with randomly mutated versions of the `seeds`.
+    fn fuzz(seeds: &[&[u8]], mut f: impl FnMut(&[u8])) {
+        let mut rng = StdRng::seed_from_u64(0);
+        for seed in seeds {
+            for _ in 0..FUZZ_ITERATIONS {
+                let mut bytes = seed.to_vec();
+                for _ in 0..rng.gen_range(1..=4) {
+                    mutate(&mut rng, &mut bytes);
+                }
+                f(&bytes);
+            }
+        }
+    }
+
+    /// Malformed block files are reported as errors and never panic.
+    #[test]
+    fn fuzz_block_files() {
+        let textures = TileTextures::with_names(&["stone", "dirt", "grass_top", "planks"]);
+        let seeds: [&[u8]; 4] = [
+            include_bytes!("../assets/blocks/grass.block"),
+            include_bytes!("../assets/blocks/stone_stairs.block"),
+            include_bytes!("../assets/blocks/door.block"),
+            br##"{ "id": 1, "cubes": [{ "faces": [
+                "stone", { "texture": "dirt", "cull": true, "tint": "#88cc44" },
+                { "texture": "stone", "cull": "-z" }, { "texture": "planks", "tint": [1, 0.5, 0] },
+                "grass_top", "unknown"
+            ] }] }"##,
+        ];
+        fuzz(&seeds, |bytes| {
+            // Errors are expected, only panics fail the test
+            if let Ok(asset) = BlockAsset::parse(bytes, Path::new("fuzz.block")) {
+                let _ = asset.resolve(&textures);
+            }
+        });
+    }
+
+    /// Malformed cubes and faces are rejected by their deserializers.
+    #[test]
+    fn fuzz_cubes_and_faces() {
+        let cube: &[u8] = br#"{ "min": [0, 0, 0], "max": [16, 8, 16], "cull": "auto",
+            "faces": { "all": "a", "sides": "b", "+y": { "texture": "c", "cull": false } } }"#;
+        fuzz(&[cube], |bytes| {
+            let _ = serde_json::from_slice::<CubeData>(bytes);
+        });
+
+        let faces: [&[u8]; 3] = [
+            br#""a""#,
+            br##"{ "texture": "a", "cull": "+x", "tint": "#ffffff" }"##,
+            br#"{ "texture": "a", "cull": true, "tint": [0.5, 0.5, 0.5] }"#,
+        ];
+        fuzz(&faces, |bytes| {
+            let _ = serde_json::from_slice::<FaceData>(bytes);
+        });
+    }
+
+    #[test]
+    fn unknown_texture() {
+        let textures = TileTextures::with_names(&["stone", "grass_top"]);
+        let face = |t: &str| format!(r#"{{ "texture": "{t}" }}"#);
+        let faces = [
+            face("stone"),
+            face("stone"),
+            face("stone"),
+            face("ston"),
+            face("bogus"),
+            face("grass-top"),
+        ]
+        .join(",");
+        let file = format!(r#"{{ "id": 9, "cubes": [{{ "faces": [{faces}] }}] }}"#);
+
+        let err = parse_block(file.as_bytes(), "test.block", &textures)
+            .unwrap_err()
+            .to_string();
+        // All unknown textures are reported with suggestions
+        assert!(err.starts_with("Invalid block file test.block"), "{err}");
+        assert!(
+            err.contains("cube 0 face PosX: unknown texture 'ston', did you mean 'stone'?"),
+            "{err}"
+        );
+        assert!(
+            err.contains("face PosY: unknown texture 'bogus'\n"),
+            "{err}"
+        );
+        assert!(
+            err.contains("face PosZ: unknown texture 'grass-top', did you mean 'grass_top'?"),
+            "{err}"
+        );
+
+        let file = file.replace("ston\"", "stone\"").replace("bogus", "stone");
+        let file = file.replace("grass-top", "grass_top");
+        let (id, block) = parse_block(file.as_bytes(), "test.block", &textures).unwrap();
+        assert_eq!(id, BlockId(9));
+        assert_eq!(
+            block.cubes[0][Direction::NegX].texture,
+            textures.id("stone").unwrap()
+        );
+        assert_eq!(block.footstep_group, "");
+    }
+
+    #[test]
+    fn invalid_cubes() {
+        let textures = TileTextures::with_names(&["stone"]);
+        let parse = |file: &str| {
+            parse_block(file.as_bytes(), "test.block", &textures)
+                .unwrap_err()
+                .to_string()
+        };
+
+        let err = parse(r#"{ "id": 1, "opaque": true }"#);
+        assert!(
+            err.contains("opaque blocks need at least one cube"),
+            "{err}"
+        );
+
+        let err = parse(
+            r#"{ "id": 1, "cubes": [
+                { "min": [8, 0, 0], "max": [4, 16, 16], "faces": { "all": "stone" } },
+                { "max": [16, 17, 16], "faces": { "all": "stone" } },
+                { "faces": { "top": "stone" } }
+            ] }"#,
+        );
+        assert!(
+            err.contains("cube 0: min [8, 0, 0] is greater than max [4, 16, 16]"),
+            "{err}"
+        );
+        assert!(
+            err.contains("cube 1: max [16, 17, 16] is outside of the block"),
+            "{err}"
+        );
+        assert!(err.contains("cube 2: missing face NegX"), "{err}");
+
+        let err = parse(r#"{ "id": 1, "cubes": [{ "faces": [] }] }"#);
+        assert!(err.contains("invalid length 0"), "{err}");
+    }
+
+    #[test]
+    fn block_sounds() {
+        let textures = TileTextures::with_names(&["a"]);
+        let parse = |file: &str| {
+            parse_block(file.as_bytes(), "test.block", &textures)
+                .unwrap()
+                .1
+        };
+
+        let cubes = r#""cubes": [{ "faces": { "all": "a" } }]"#;
+        let block = parse(&format!(r#"{{ "id": 1, "opaque": true, {cubes} }}"#));
+        assert_eq!(block.footstep_group, "stone");
+        let block = parse(&format!(
+            r#"{{ "id": 1, "opaque": true, "footstep_group": "wood", {cubes} }}"#
+        ));
+        assert_eq!(block.footstep_group, "wood");
+
+        let block = parse(r#"{ "id": 1, "sounds": { "break": "sounds/a.ogg" } }"#);
+        assert_eq!(block.sounds.paths().collect::<Vec<_>>(), ["sounds/a.ogg"]);
+    }
+
+    #[test]
+    fn registry() {
+        let textures = TileTextures::with_names(&["a"]);
+        let file = br#"{ "id": 7, "cubes": [{ "faces": { "all": "a" } }] }"#;
+        let load = |path: &str| BlockAsset::parse(file, Path::new(path)).unwrap();
+
+        // The same file loaded twice results in one entry
+        let first = load("blocks/a.block");
+        let second = load("blocks/a.block");
+        assert_eq!(first.name, "a");
+        let registry = build_registry([&first, &second], &textures).unwrap();
+        assert_eq!(registry.len(), 1);
+        assert_eq!(registry[&BlockId(7)].name, "a");
+
+        let other = load("blocks/b.block");
+        let err = build_registry([&first, &other], &textures)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Duplicate block id 7"), "{err}");
+
+        let invalid = BlockAsset::parse(
+            br#"{ "id": 8, "cubes": [{ "faces": { "all": "b" } }] }"#,
+            Path::new("blocks/c.block"),
+        )
+        .unwrap();
+        let err = build_registry([&first, &invalid], &textures)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown texture 'b'"), "{err}");
+    }
+
+    #[test]
+    fn cull_faces() {
+        let textures = TileTextures::with_names(&["a"]);
+        let faces = [
+            r#"{ "texture": "a", "cull": true }"#,
+            r#"{ "texture": "a", "cull": false }"#,
+            r#"{ "texture": "a" }"#,
+            r#"{ "texture": "a", "cull": "+x" }"#,
+            r#"{ "texture": "a", "cull": "-x" }"#,
+            r#"{ "texture": "a", "cull": "+z" }"#,
+        ]
+        .join(",");
+        let file = format!(r#"{{ "id": 1, "cubes": [{{ "faces": [{faces}] }}] }}"#);
+        let (_, block) = parse_block(file.as_bytes(), "test.block", &textures).unwrap();
+        let cull = block.cubes[0].faces.clone().map(|f| f.cull);
+        // Mismatching legacy directions do not cull
+        assert_eq!(cull, [true, false, false, true, false, true]);
+    }
+
+    #[test]
+    fn face_shorthand() {
+        let textures = TileTextures::with_names(&["a", "b", "c", "d"]);
+        let parse = |cube: &str| {
+            let file = format!(r#"{{ "id": 1, "cubes": [{cube}] }}"#);
+            parse_block(file.as_bytes(), "test.block", &textures)
+        };
+        let texture = |t: &str| textures.id(t).unwrap();
+
+        let block = parse(
+            r#"{ "faces": { "all": "a", "sides": "b", "top": "c", "+x": { "texture": "d", "cull": false } },
+                "cull": "auto" }"#,
+        )
+        .unwrap()
+        .1;
+        let cube = &block.cubes[0];
+        // Directions override groups, which override all
+        let expected = ["b", "a", "b", "d", "c", "b"].map(texture);
+        assert_eq!(cube.faces.clone().map(|f| f.texture), expected);
+        let cull = cube.faces.clone().map(|f| f.cull);
+        assert_eq!(cull, [true, true, true, false, true, true]);
+
+        let block = parse(r#"{ "faces": { "all": "a" } }"#).unwrap().1;
+        assert!(block.cubes[0].faces.iter().all(|f| !f.cull));
+
+        let err = parse(r#"{ "faces": { "al": "a" } }"#).unwrap_err();
+        assert!(err.to_string().contains("unknown field `al`"), "{err}");
+        let err = parse(r#"{ "faces": { "sides": "a", "top": "b" } }"#).unwrap_err();
+        assert!(
+            err.to_string().contains("cube 0: missing face NegY"),
+            "{err}"
+        );
+        assert!(parse(r#"{ "faces": { "all": "a" }, "cull": "always" }"#).is_err());
+    }
+
+    #[test]
+    fn face_tint() {
+        let textures = TileTextures::with_names(&["a"]);
+        let parse = |faces: &str| {
+            let file = format!(r#"{{ "id": 1, "cubes": [{{ "faces": {faces} }}] }}"#);
+            parse_block(file.as_bytes(), "test.block", &textures)
+        };
+
+        let block = parse(
+            r##"{ "all": "a", "top": { "texture": "a", "tint": "#ff8000" },
+                "-x": { "texture": "a", "tint": [0.5, 1.0, 0.25] } }"##,
+        )
+        .unwrap()
+        .1;
+        let tints = block.cubes[0].faces.clone().map(|f| f.tint);
+        // Hex tints are sRGB and converted to linear
+        let [r, g, b, _] = Color::rgb_u8(255, 128, 0).as_linear_rgba_f32();
+        assert_eq!(tints[Direction::PosY as usize], [r, g, b]);
+        assert!(g < 0.25);
+        assert_eq!(tints[Direction::NegX as usize], [0.5, 1.0, 0.25]);
+        assert_eq!(tints[Direction::PosZ as usize], Face::NO_TINT);
+
+        // The tint ends up in the vertex colors of the face
+        let mut colors = Vec::new();
+        block.cubes[0].mesh(
+            Vec3::ZERO,
+            [false; 6],
+            &textures,
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut colors,
+        );
+        assert_eq!(colors[Direction::NegX as usize * 4], [0.5, 1.0, 0.25, 1.0]);
+
+        assert!(parse(r##"{ "all": { "texture": "a", "tint": "#12345" } }"##).is_err());
+        assert!(parse(r#"{ "all": { "texture": "a", "tint": "green" } }"#).is_err());
+    }
+
+    #[test]
+    fn partial_cubes() {
+        let textures = TileTextures::with_names(&["stone"]);
+        let parse = |file: &str| {
+            parse_block(file.as_bytes(), "test.block", &textures)
+                .unwrap()
+                .1
+        };
+        let stone = parse(
+            r#"{ "id": 1, "opaque": true, "cubes": [{ "faces": { "all": "stone" }, "cull": "auto" }] }"#,
+        );
+        let slab = parse(
+            r#"{ "id": 2, "opaque": true, "cubes": [
+                { "max": [16, 8, 16], "faces": { "all": "stone" }, "cull": "auto" }
+            ] }"#,
+        );
+        let stairs = parse(
+            r#"{ "id": 3, "opaque": true, "cubes": [
+                { "max": [16, 8, 16], "faces": { "all": "stone" }, "cull": "auto" },
+                { "min": [0, 8, 8], "faces": { "all": "stone" }, "cull": "auto" }
+            ] }"#,
+        );
+        assert_eq!(slab.solid_faces, [false, true, false, false, false, false]);
+        // The cubes of the stairs cover the back together
+        assert_eq!(stairs.solid_faces, [false, true, false, false, false, true]);
+        // The top of the slab is never culled
+        let top = &slab.cubes[0][Direction::PosY];
+        assert!(!top.cull);
+
+        let mut blocks = HashMap::new();
+        blocks.insert(BlockId::AIR, Block::default());
+        blocks.insert(BlockId(1), stone);
+        blocks.insert(BlockId(2), slab);
+
+        let faces = |chunk: &Chunk| {
+            let mesh = chunk.mesh([Border::new(); 6], &blocks, &textures);
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap().len() / 4
+        };
+        // A slab with air and a stone block above keeps its top face,
+        // and the stone block its bottom face
+        let mut chunk = Chunk::new(BlockId::AIR);
+        chunk[UVec3::new(1, 0, 1)] = BlockId(2);
+        chunk[UVec3::new(2, 0, 1)] = BlockId(2);
+        chunk[UVec3::new(1, 1, 1)] = BlockId(1);
+        assert_eq!(faces(&chunk), 3 * 6);
+
+        // Stone blocks on top of each other cull the faces in between
+        chunk[UVec3::new(2, 1, 1)] = BlockId(1);
+        chunk[UVec3::new(2, 2, 1)] = BlockId(1);
+        assert_eq!(faces(&chunk), 5 * 6 - 2 - 2);
+    }
+
+    #[test]
+    fn tangents() {
+        let textures = TileTextures::with_names(&["stone"]);
+        let (_, block) = parse_block(
+            br#"{ "id": 1, "cubes": [{ "faces": { "all": "stone" } }] }"#,
+            "stone.json",
+            &textures,
+        )
+        .unwrap();
+        let mut chunk = Chunk::new(BlockId::AIR);
+        chunk[UVec3::ONE] = BlockId(1);
+        let mut blocks = HashMap::new();
+        blocks.insert(BlockId::AIR, Block::default());
+        blocks.insert(BlockId(1), block);
+        let mesh = chunk.mesh([Border::new(); 6], &blocks, &textures);
+
+        let attribute = |id| match mesh.attribute(id).unwrap() {
+            VertexAttributeValues::Float32x2(v) => v
+                .iter()
+                .map(|v| Vec2::from(*v).extend(0.0).extend(0.0))
+                .collect(),
+            VertexAttributeValues::Float32x3(v) => {
+                v.iter().map(|v| Vec3::from(*v).extend(0.0)).collect()
+            }
+            VertexAttributeValues::Float32x4(v) => {
+                v.iter().map(|v| Vec4::from(*v)).collect::<Vec<_>>()
+            }
+            _ => unreachable!(),
+        };
+        let positions = attribute(Mesh::ATTRIBUTE_POSITION);
+        let normals = attribute(Mesh::ATTRIBUTE_NORMAL);
+        let uvs = attribute(Mesh::ATTRIBUTE_UV_0);
+        let tangents = attribute(Mesh::ATTRIBUTE_TANGENT);
+        assert_eq!(tangents.len(), 6 * 4);
+
+        for face in 0..6 {
+            let i = face * 4;
+            let normal = normals[i].truncate();
+            // The uv gradients of the first triangle
+            let (e1, e2) = (
+                positions[i + 1] - positions[i],
+                positions[i + 2] - positions[i],
+            );
+            let (d1, d2) = (uvs[i + 1] - uvs[i], uvs[i + 2] - uvs[i]);
+            let det = d1.x * d2.y - d2.x * d1.y;
+            let u = ((e1 * d2.y - e2 * d1.y) / det).truncate();
+            let v = ((e2 * d1.x - e1 * d2.x) / det).truncate();
+
+            for tangent in &tangents[i..i + 4] {
+                let (t, w) = (tangent.truncate(), tangent.w);
+                assert!((t.length() - 1.0).abs() < 1e-6, "{face}");
+                assert!(t.dot(normal).abs() < 1e-6, "{face}");
+                assert!(t.dot(u) > 0.0, "{face}");
+                assert!(
+                    w.abs() == 1.0 && (normal.cross(t) * w).dot(v) > 0.0,
+                    "{face}"
+                );
+            }
+        }
+    }
+
+    /// The triangles of every face are counter-clockwise seen from outside.
+    ///
+    /// [`Block::mesh`] requires the global texture atlas, so this meshes the
+    /// cube directly, which is what [`Block::mesh`] does for each of its cubes.
+    #[test]
+    fn face_winding_order() {
+        let textures = TileTextures::with_names(&[]);
+        let face = Face {
+            texture: textures.missing(),
+            cull: false,
+            tint: Face::NO_TINT,
+        };
+        let cube = Cube {
+            min: UVec3::ZERO,
+            max: Cube::MAX,
+            faces: [(); 6].map(|_| face.clone()),
+        };
+        let (mut indices, mut positions, mut normals) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut uvs, mut tangents, mut colors) = (Vec::new(), Vec::new(), Vec::new());
+        cube.mesh(
+            Vec3::ZERO,
+            [false; 6],
+            &textures,
+            &mut indices,
+            &mut positions,
+            &mut normals,
+            &mut uvs,
+            &mut tangents,
+            &mut colors,
+        );
+        assert_eq!(positions.len(), 6 * 4);
+        assert_eq!(indices.len(), 6 * 6);
+
+        for (face, d) in Direction::all().into_iter().enumerate() {
+            let expected = Vec3::from(d);
+            for normal in &normals[face * 4..face * 4 + 4] {
+                assert_eq!(Vec3::from(*normal), expected, "{d:?}");
+            }
+            for triangle in indices[face * 6..face * 6 + 6].chunks(3) {
+                let [v0, v1, v2] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i] as usize]));
+                let winding = (v1 - v0).cross(v2 - v0);
+                assert!(winding.dot(expected) > 0.0, "{d:?}: {winding}");
+            }
+        }
+    }
+
+    #[test]
+    fn lamp_block() {
+        let textures = TileTextures::with_names(&["sand", "stone"]);
+        let asset = BlockAsset::parse(
+            include_bytes!("../assets/blocks/lamp.block"),
+            Path::new("blocks/lamp.block"),
+        )
+        .unwrap();
+        let lamp = asset.resolve(&textures).unwrap();
+        assert!(lamp.has_entity);
+        assert!(matches!(
+            BlockEntityData::new(&lamp),
+            Some(BlockEntityData::Lamp { .. })
+        ));
+    }
+}
@@ -0,0 +1,234 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::block::{blocks, Block};
+use crate::world::{BlockChangedEvent, ChunkGenerated, VoxelWorld, WorldSet};
+use crate::AppState;
+
+/// Maximum number of lamps that emit light.
+const MAX_LIGHTS: usize = 32;
+
+/// Entity data of the blocks of a chunk, by their position within the chunk.
+pub type BlockEntities = HashMap<UVec3, BlockEntityData>;
+
+/// Per-instance data of a placed block, beyond its id and metadata.
+///
+/// Blocks declare with `has_entity` that they have data, which is created
+/// when they are placed and removed with the block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockEntityData {
+    /// Light emitting block with its own light color
+    Lamp { color: [f32; 3] },
+}
+
+impl BlockEntityData {
+    /// Returns the data of a newly placed `block`, `None` for blocks without.
+    ///
+    /// Lamps are the only entities so far, they start with the light color
+    /// of their block.
+    pub fn new(block: &Block) -> Option<Self> {
+        (block.has_entity && block.light_emission > 0).then(|| {
+            let [r, g, b, _] = block.emissive.as_rgba_f32();
+            Self::Lamp { color: [r, g, b] }
+        })
+    }
+}
+
+pub struct BlockEntityPlugin;
+
+impl Plugin for BlockEntityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LampLights>()
+            .add_systems(
+                Update,
+                (create_block_entities, update_lamp_lights)
+                    .chain()
+                    .after(WorldSet)
+                    .run_if(in_state(AppState::Running)),
+            )
+            .add_systems(OnExit(AppState::Running), clear_lamp_lights);
+    }
+}
+
+/// Point lights of the loaded lamps.
+#[derive(Default, Resource)]
+struct LampLights(HashMap<IVec3, Entity>);
+
+/// Creates the data of the placed blocks with entities.
+///
+/// The data of removed blocks is already removed by the world.
+fn create_block_entities(
+    mut world: ResMut<VoxelWorld>,
+    mut events: EventReader<BlockChangedEvent>,
+) {
+    let blocks = blocks().read().unwrap();
+    for event in events.iter() {
+        let Some(entity) = blocks.get(&event.new).and_then(BlockEntityData::new) else {
+            continue;
+        };
+        // Only the origin of multi-voxel blocks has data
+        if world.block_origin(event.pos, &blocks) == event.pos
+            && world.block_entity(event.pos).is_none()
+        {
+            world.set_block_entity(event.pos, Some(entity));
+        }
+    }
+}
+
+/// Spawns the lights of placed and loaded lamps, updates their colors
+/// and removes the lights of removed and unloaded lamps.
+fn update_lamp_lights(
+    mut cmds: Commands,
+    world: Res<VoxelWorld>,
+    mut lights: ResMut<LampLights>,
+    mut generated: EventReader<ChunkGenerated>,
+    mut changed: EventReader<BlockChangedEvent>,
+    mut point_lights: Query<&mut PointLight>,
+) {
+    lights.0.retain(|pos, entity| {
+        let Some(BlockEntityData::Lamp { color }) = world.block_entity(*pos) else {
+            if let Some(cmds) = cmds.get_entity(*entity) {
+                cmds.despawn_recursive();
+            }
+            return false;
+        };
+        let color = Color::rgb(color[0], color[1], color[2]);
+        if let Ok(mut light) = point_lights.get_mut(*entity) {
+            if light.color != color {
+                light.color = color;
+            }
+        }
+        true
+    });
+
+    let mut candidates = Vec::new();
+    for event in generated.iter() {
+        candidates.extend(world.block_entities_in(event.pos));
+    }
+    candidates.extend(changed.iter().map(|e| e.pos));
+
+    let blocks = blocks().read().unwrap();
+    for pos in candidates {
+        if lights.0.len() >= MAX_LIGHTS {
+            break;
+        }
+        let Some(BlockEntityData::Lamp { color }) = world.block_entity(pos) else {
+            continue;
+        };
+        if lights.0.contains_key(&pos) {
+            continue;
+        }
+        let Some(block) = world.get_block(pos).and_then(|b| blocks.get(&b)) else {
+            continue;
+        };
+        let entity = cmds
+            .spawn(PointLightBundle {
+                transform: Transform::from_translation(pos.as_vec3() + 0.5),
+                point_light: PointLight {
+                    intensity: 800.0 * block.light_emission as f32 / 15.0,
+                    color: Color::rgb(color[0], color[1], color[2]),
+                    range: block.light_emission as f32,
+                    ..default()
+                },
+                ..default()
+            })
+            .id();
+        lights.0.insert(pos, entity);
+    }
+}
+
+/// The lights are despawned with the other entities of the world.
+fn clear_lamp_lights(mut lights: ResMut<LampLights>) {
+    lights.0.clear();
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use super::{create_block_entities, BlockEntityData};
+    use crate::block::{blocks, Block, BlockId};
+    use crate::chunk::Chunk;
+    use crate::util::for_uvec3;
+    use crate::world::{BlockChangedEvent, ChangeCause, VoxelWorld};
+
+    #[test]
+    fn lamp_data() {
+        let lamp = Block {
+            has_entity: true,
+            light_emission: 12,
+            emissive: Color::rgb(1.0, 0.5, 0.0),
+            ..default()
+        };
+        assert_eq!(
+            BlockEntityData::new(&lamp),
+            Some(BlockEntityData::Lamp {
+                color: [1.0, 0.5, 0.0]
+            })
+        );
+        let plain = Block {
+            has_entity: false,
+            ..lamp.clone()
+        };
+        assert_eq!(BlockEntityData::new(&plain), None);
+
+        let json = serde_json::to_string(&BlockEntityData::new(&lamp).unwrap()).unwrap();
+        assert_eq!(json, r#"{"type":"lamp","color":[1.0,0.5,0.0]}"#);
+    }
+
+    #[test]
+    fn entities_follow_edits() {
+        let lamp_id = BlockId(200);
+        let lamp = Block {
+            has_entity: true,
+            light_emission: 12,
+            ..default()
+        };
+        blocks().write().unwrap().insert(lamp_id, lamp);
+
+        let mut app = App::new();
+        app.add_event::<BlockChangedEvent>()
+            .insert_resource(VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::X))
+            .add_systems(Update, create_block_entities);
+
+        let pos = IVec3::new(3, 4, 5);
+        let far = pos + IVec3::X * Chunk::SIZE as i32;
+        let mut world = app.world.resource_mut::<VoxelWorld>();
+        world.set_block(pos, lamp_id, ChangeCause::Player);
+        world.fill_box(far, far + 1, lamp_id, ChangeCause::WorldEdit);
+        world.take_modified();
+
+        // The events are dispatched by the world plugin
+        let mut placed = vec![pos];
+        for_uvec3(UVec3::ZERO, UVec3::splat(2), |p| {
+            placed.push(far + p.as_ivec3())
+        });
+        for &p in &placed {
+            app.world.send_event(BlockChangedEvent {
+                pos: p,
+                old: BlockId::AIR,
+                new: lamp_id,
+                cause: ChangeCause::Player,
+            });
+        }
+        app.update();
+
+        let mut world = app.world.resource_mut::<VoxelWorld>();
+        for &p in &placed {
+            assert!(world.block_entity(p).is_some(), "{p}");
+        }
+        assert_eq!(world.block_entities_in(IVec3::X).count(), 8);
+        // The chunks are saved with the new data
+        assert_eq!(world.take_modified().len(), 2);
+
+        // Replacing the blocks removes their data, also in bulk
+        world.set_block(pos, BlockId::STONE, ChangeCause::Player);
+        world.fill_box(far, far + 1, BlockId::AIR, ChangeCause::WorldEdit);
+        assert!(world.block_entity(pos).is_none());
+        assert_eq!(world.block_entities_in(IVec3::X).count(), 0);
+
+        blocks().write().unwrap().remove(&lamp_id);
+    }
+}
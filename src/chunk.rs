@@ -1,11 +1,14 @@
 use std::fmt;
+use std::io::{self, Read, Write};
 use std::ops::{Index, IndexMut};
 
 use bevy::prelude::*;
 use bevy::render::mesh::{Indices, PrimitiveTopology};
 use bevy::utils::HashMap;
 
-use crate::block::{blocks, Block, BlockId};
+use crate::block::{Block, BlockId};
+use crate::block_entity::{BlockEntities, BlockEntityData};
+use crate::textures::TileTextures;
 use crate::util::{for_uvec3, Direction};
 
 /// Each chunk contains a number of blocks.
@@ -14,21 +17,85 @@ use crate::util::{for_uvec3, Direction};
 pub struct Chunk {
     /// They are stored in the order: Y, Z, X (in -> out)
     blocks: Box<[[[BlockId; Chunk::SIZE]; Chunk::SIZE]; Chunk::SIZE]>,
+    /// Sparse per-block metadata (e.g. fluid levels), zero if missing
+    meta: HashMap<UVec3, u8>,
+    /// Per-instance data of the blocks with entities
+    entities: BlockEntities,
 }
 
 impl Chunk {
     pub const SIZE: usize = 32;
     pub const MAX: UVec3 = UVec3::splat(Self::SIZE as u32);
+    /// Identifies the chunk file format and version.
+    const MAGIC: &[u8; 4] = b"VXC2";
+    /// Previous version without block entities, which can still be read.
+    const MAGIC_V1: &[u8; 4] = b"VXC1";
+    /// Maximum length of the block entity json in a chunk file, which is
+    /// far more than a chunk full of lamps needs.
+    const MAX_ENTITIES_LEN: usize = 64 * Self::SIZE.pow(3);
 
     pub fn new(block: BlockId) -> Self {
         Self {
             blocks: Box::new([[[block; Chunk::SIZE]; Chunk::SIZE]; Chunk::SIZE]),
+            meta: HashMap::new(),
+            entities: BlockEntities::new(),
         }
     }
 
-    fn occupied(&self, pos: UVec3, blocks: &HashMap<BlockId, Block>) -> bool {
+    /// Returns the metadata of the block at `pos`.
+    pub fn meta(&self, pos: UVec3) -> u8 {
+        self.meta.get(&pos).copied().unwrap_or_default()
+    }
+
+    /// Sets the metadata of the block at `pos`.
+    pub fn set_meta(&mut self, pos: UVec3, meta: u8) {
+        debug_assert!(pos.cmplt(Self::MAX).all(), "{pos:?}");
+        if meta == 0 {
+            self.meta.remove(&pos);
+        } else {
+            self.meta.insert(pos, meta);
+        }
+    }
+
+    /// Returns the entity data of the block at `pos`.
+    pub fn entity(&self, pos: UVec3) -> Option<&BlockEntityData> {
+        self.entities.get(&pos)
+    }
+
+    /// Sets or removes the entity data of the block at `pos`.
+    pub fn set_entity(&mut self, pos: UVec3, entity: Option<BlockEntityData>) {
+        debug_assert!(pos.cmplt(Self::MAX).all(), "{pos:?}");
+        if let Some(entity) = entity {
+            self.entities.insert(pos, entity);
+        } else {
+            self.entities.remove(&pos);
+        }
+    }
+
+    /// Returns the entity data of all blocks of the chunk.
+    pub fn entities(&self) -> impl Iterator<Item = (UVec3, &BlockEntityData)> {
+        self.entities.iter().map(|(pos, e)| (*pos, e))
+    }
+
+    /// Serializes the block entities as json sorted by position,
+    /// empty if there are none.
+    fn entities_json(&self) -> Vec<u8> {
+        if self.entities.is_empty() {
+            return Vec::new();
+        }
+        let mut entities = self
+            .entities
+            .iter()
+            .map(|(pos, e)| (pos.to_array(), e))
+            .collect::<Vec<_>>();
+        entities.sort_unstable_by_key(|(pos, _)| *pos);
+        serde_json::to_vec(&entities).expect("block entities are serializable")
+    }
+
+    /// Returns if the block at `pos` covers its side in direction `d`.
+    fn occupied(&self, pos: UVec3, d: Direction, blocks: &HashMap<BlockId, Block>) -> bool {
         debug_assert!(pos.cmplt(Self::MAX).all(), "{pos:?}");
-        blocks[&self[pos]].opaque
+        blocks[&self[pos]].solid_faces[d as usize]
     }
 
     pub fn border(&self, dir: Direction, blocks: &HashMap<BlockId, Block>) -> Border {
@@ -36,7 +103,7 @@ impl Chunk {
         for y in 0..Self::SIZE as u32 {
             for x in 0..Self::SIZE as u32 {
                 let pos = Self::from_surface(dir, UVec2::new(x, y));
-                if self.occupied(pos, blocks) {
+                if self.occupied(pos, dir, blocks) {
                     border.set_occupied(UVec2::new(x, y));
                 }
             }
@@ -46,19 +113,30 @@ impl Chunk {
 
     /// Computes a single mesh over all blocks.
     /// Not visible faces are excluded.
-    pub fn mesh(&self, borders: [Border; 6]) -> Mesh {
+    pub fn mesh(
+        &self,
+        borders: [Border; 6],
+        blocks: &HashMap<BlockId, Block>,
+        textures: &TileTextures,
+    ) -> Mesh {
         let mut positions = Vec::with_capacity(24);
         let mut normals = Vec::with_capacity(24);
         let mut uvs = Vec::with_capacity(24);
+        let mut tangents = Vec::with_capacity(24);
+        let mut colors = Vec::with_capacity(24);
         let mut indices = Vec::new();
 
-        let blocks = blocks().read().unwrap();
-
         for_uvec3(UVec3::ZERO, Self::MAX, |pos| {
+            let block = &blocks[&self[pos]];
+            // The origin of a multi-voxel block contains its whole geometry
+            if block.is_multi_voxel() && self.meta(pos) != 0 {
+                return;
+            }
+
             let occupied = Direction::all().map(|d| {
                 let p = pos.as_ivec3() + IVec3::from(d);
                 if p.cmpge(IVec3::ZERO).all() && p.cmplt(Self::MAX.as_ivec3()).all() {
-                    self.occupied(p.as_uvec3(), &blocks)
+                    self.occupied(p.as_uvec3(), d.inverse(), blocks)
                 } else {
                     // Check neighbors if out of bounds
                     let p = (p + Self::MAX.as_ivec3()).as_uvec3() % Self::MAX;
@@ -68,15 +146,17 @@ impl Chunk {
             });
 
             if !occupied.iter().all(|b| *b) {
-                let block = &blocks[&self[pos]];
                 for cube in &block.cubes {
                     cube.mesh(
                         pos.as_vec3(),
                         occupied,
+                        textures,
                         &mut indices,
                         &mut positions,
                         &mut normals,
                         &mut uvs,
+                        &mut tangents,
+                        &mut colors,
                     );
                 }
             }
@@ -86,10 +166,181 @@ impl Chunk {
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
         mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
         mesh.set_indices(Some(Indices::U32(indices)));
         mesh
     }
 
+    /// Computes which faces of the chunk are connected through its non-opaque blocks.
+    pub fn connectivity(&self, blocks: &HashMap<BlockId, Block>) -> Connectivity {
+        let index =
+            |p: UVec3| (p.x as usize * Self::SIZE + p.z as usize) * Self::SIZE + p.y as usize;
+        let mut visited = vec![false; Self::SIZE.pow(3)];
+        let mut stack = Vec::new();
+        let mut connectivity = Connectivity::NONE;
+
+        for_uvec3(UVec3::ZERO, Self::MAX, |start| {
+            if visited[index(start)] || blocks[&self[start]].opaque {
+                return;
+            }
+            // Flood fill the open cells, collecting the faces they touch
+            let mut faces = Vec::with_capacity(6);
+            visited[index(start)] = true;
+            stack.push(start);
+            while let Some(p) = stack.pop() {
+                for d in Direction::all() {
+                    let n = p.as_ivec3() + IVec3::from(d);
+                    if n.cmplt(IVec3::ZERO).any() || n.cmpge(Self::MAX.as_ivec3()).any() {
+                        if !faces.contains(&d) {
+                            faces.push(d);
+                        }
+                        continue;
+                    }
+                    let n = n.as_uvec3();
+                    if !visited[index(n)] && !blocks[&self[n]].opaque {
+                        visited[index(n)] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+            for &a in &faces {
+                for &b in &faces {
+                    connectivity.connect(a, b);
+                }
+            }
+        });
+        connectivity
+    }
+
+    /// Returns a hash of the blocks, their metadata and entities.
+    ///
+    /// Unlike the std hashers, the hash is the same across runs and
+    /// platforms (64 bit FNV-1a), so that it can be compared with stored hashes.
+    pub fn content_hash(&self) -> u64 {
+        const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+        let mut hash = OFFSET;
+        let mut write = |byte: u8| hash = (hash ^ byte as u64).wrapping_mul(PRIME);
+
+        for_uvec3(UVec3::ZERO, Self::MAX, |pos| write(self[pos].0));
+        // The metadata map has no stable order
+        let mut meta = self.meta.iter().collect::<Vec<_>>();
+        meta.sort_unstable_by_key(|(pos, _)| pos.to_array());
+        for (pos, meta) in meta {
+            for byte in [pos.x as u8, pos.y as u8, pos.z as u8, *meta] {
+                write(byte);
+            }
+        }
+        for byte in self.entities_json() {
+            write(byte);
+        }
+        hash
+    }
+
+    /// Writes the chunk in a small binary format.
+    ///
+    /// The format consists of the magic bytes, the block ids as one byte each
+    /// in the [`for_uvec3`] order, the number of metadata entries as little endian
+    /// `u32` and the entries as four bytes (x, y, z, metadata).
+    /// The block entities follow as little endian `u32` length and json.
+    pub fn write(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(Self::MAGIC)?;
+        let mut ids = Vec::with_capacity(Self::SIZE.pow(3));
+        for_uvec3(UVec3::ZERO, Self::MAX, |pos| ids.push(self[pos].0));
+        w.write_all(&ids)?;
+
+        w.write_all(&(self.meta.len() as u32).to_le_bytes())?;
+        for (pos, meta) in &self.meta {
+            w.write_all(&[pos.x as u8, pos.y as u8, pos.z as u8, *meta])?;
+        }
+
+        let entities = self.entities_json();
+        w.write_all(&(entities.len() as u32).to_le_bytes())?;
+        w.write_all(&entities)?;
+        Ok(())
+    }
+
+    /// Reads a chunk written by [`Chunk::write`], also in the previous
+    /// version without block entities.
+    ///
+    /// The block ids are not checked, see [`Chunk::remove_unknown`].
+    pub fn read(mut r: impl Read) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != Self::MAGIC && &magic != Self::MAGIC_V1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a chunk file",
+            ));
+        }
+        let mut ids = vec![0; Self::SIZE.pow(3)];
+        r.read_exact(&mut ids)?;
+        let mut chunk = Self::new(BlockId::AIR);
+        let mut ids = ids.into_iter();
+        for_uvec3(UVec3::ZERO, Self::MAX, |pos| {
+            chunk[pos] = BlockId(ids.next().unwrap_or_default())
+        });
+
+        let mut len = [0; 4];
+        r.read_exact(&mut len)?;
+        for _ in 0..u32::from_le_bytes(len) {
+            let mut entry = [0; 4];
+            r.read_exact(&mut entry)?;
+            let pos = UVec3::new(entry[0] as _, entry[1] as _, entry[2] as _);
+            if !pos.cmplt(Self::MAX).all() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid metadata",
+                ));
+            }
+            chunk.set_meta(pos, entry[3]);
+        }
+
+        if &magic == Self::MAGIC {
+            r.read_exact(&mut len)?;
+            let len = u32::from_le_bytes(len) as usize;
+            if len > Self::MAX_ENTITIES_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid entities",
+                ));
+            }
+            let mut json = vec![0; len];
+            r.read_exact(&mut json)?;
+            if !json.is_empty() {
+                let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid entities");
+                let entities: Vec<([u32; 3], BlockEntityData)> =
+                    serde_json::from_slice(&json).map_err(|_| invalid())?;
+                for (pos, entity) in entities {
+                    let pos = UVec3::from_array(pos);
+                    if !pos.cmplt(Self::MAX).all() {
+                        return Err(invalid());
+                    }
+                    chunk.set_entity(pos, Some(entity));
+                }
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Replaces the blocks that are missing in `blocks` with air, together
+    /// with their metadata and entities, and returns their number.
+    ///
+    /// Saved chunks can contain blocks that have been removed from the assets.
+    pub fn remove_unknown(&mut self, blocks: &HashMap<BlockId, Block>) -> usize {
+        let mut removed = 0;
+        for_uvec3(UVec3::ZERO, Self::MAX, |pos| {
+            if !blocks.contains_key(&self[pos]) {
+                self[pos] = BlockId::AIR;
+                self.set_meta(pos, 0);
+                self.set_entity(pos, None);
+                removed += 1;
+            }
+        });
+        removed
+    }
+
     fn from_surface(d: Direction, v: UVec2) -> UVec3 {
         let center = (Self::MAX.as_vec3() - 1.0) / 2.0;
         let pos = Vec3::new(v.x as _, v.y as _, 0.0);
@@ -122,6 +373,24 @@ impl IndexMut<UVec3> for Chunk {
     }
 }
 
+/// Symmetric matrix of the chunk faces that are connected through the
+/// non-opaque blocks of a chunk, used for occlusion culling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connectivity(u64);
+
+impl Connectivity {
+    pub const NONE: Self = Self(0);
+    /// Used for chunks whose connectivity is not known yet.
+    pub const ALL: Self = Self((1 << 36) - 1);
+
+    pub fn connected(self, a: Direction, b: Direction) -> bool {
+        self.0 & 1 << (a as usize * 6 + b as usize) != 0
+    }
+    fn connect(&mut self, a: Direction, b: Direction) {
+        self.0 |= 1 << (a as usize * 6 + b as usize) | 1 << (b as usize * 6 + a as usize);
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Border([u8; Chunk::SIZE * Chunk::SIZE / 8]);
 
@@ -129,6 +398,10 @@ impl Border {
     pub fn new() -> Self {
         Self([0; Chunk::SIZE * Chunk::SIZE / 8])
     }
+    /// Returns a border where every block is occupied.
+    pub fn full() -> Self {
+        Self([u8::MAX; Chunk::SIZE * Chunk::SIZE / 8])
+    }
     fn occupied(self, p: UVec2) -> bool {
         debug_assert!(p.cmplt(Chunk::SIZE as u32 * UVec2::ONE).all());
         let i = p.x + p.y * Chunk::SIZE as u32;
@@ -166,8 +439,10 @@ mod test {
     use bevy::prelude::*;
     use bevy::utils::HashMap;
 
-    use super::Chunk;
+    use super::{Border, Chunk, Connectivity};
     use crate::block::{Block, BlockId};
+    use crate::block_entity::BlockEntityData;
+    use crate::generation::{generate_chunk, WorldGen};
     use crate::util::Direction;
 
     #[test]
@@ -178,6 +453,7 @@ mod test {
             Block {
                 cubes: Vec::new(),
                 opaque: false,
+                ..Default::default()
             },
         );
         blocks.insert(
@@ -185,6 +461,8 @@ mod test {
             Block {
                 cubes: Vec::new(),
                 opaque: true,
+                solid_faces: [true; 6],
+                ..Default::default()
             },
         );
 
@@ -213,11 +491,192 @@ mod test {
             for y in 0..Chunk::SIZE as u32 {
                 for x in 0..Chunk::SIZE as u32 {
                     let p = UVec2::new(x, y);
-                    if !(border.occupied(p) == (p == p2)) {
+                    if border.occupied(p) != (p == p2) {
                         eprintln!("invalid {p}");
                     }
                 }
             }
         }
     }
+
+    /// All positions on a chunk side.
+    fn surface() -> impl Iterator<Item = UVec2> {
+        let size = Chunk::SIZE as u32;
+        (0..size).flat_map(move |y| (0..size).map(move |x| UVec2::new(x, y)))
+    }
+
+    #[test]
+    fn border_round_trip() {
+        for p in surface() {
+            let mut border = Border::new();
+            border.set_occupied(p);
+            assert!(border.occupied(p), "{p}");
+            // Setting a cell twice keeps it set
+            border.set_occupied(p);
+            assert!(border.occupied(p), "{p}");
+        }
+    }
+
+    #[test]
+    fn border_no_cross_contamination() {
+        for p in surface() {
+            let mut border = Border::new();
+            border.set_occupied(p);
+            for q in surface() {
+                assert_eq!(border.occupied(q), p == q, "set {p}, read {q}");
+            }
+        }
+    }
+
+    #[test]
+    fn surface_round_trip() {
+        for d in Direction::all() {
+            for v in surface() {
+                let p = Chunk::from_surface(d, v);
+                assert!(p.cmplt(Chunk::MAX).all(), "{d:?} {v} -> {p}");
+                assert_eq!(Chunk::to_surface(d, p), v, "{d:?} {p}");
+            }
+        }
+    }
+
+    #[test]
+    fn connectivity() {
+        let mut blocks = HashMap::new();
+        blocks.insert(BlockId(0), Block::default());
+        blocks.insert(
+            BlockId(1),
+            Block {
+                opaque: true,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            Chunk::new(BlockId(0)).connectivity(&blocks),
+            Connectivity::ALL
+        );
+        assert_eq!(
+            Chunk::new(BlockId(1)).connectivity(&blocks),
+            Connectivity::NONE
+        );
+
+        // A horizontal wall separates the top from the bottom
+        let mut chunk = Chunk::new(BlockId(0));
+        for x in 0..Chunk::SIZE as u32 {
+            for z in 0..Chunk::SIZE as u32 {
+                chunk[UVec3::new(x, 16, z)] = BlockId(1);
+            }
+        }
+        let connectivity = chunk.connectivity(&blocks);
+        assert!(!connectivity.connected(Direction::NegY, Direction::PosY));
+        assert!(connectivity.connected(Direction::NegY, Direction::PosX));
+        assert!(connectivity.connected(Direction::PosY, Direction::NegZ));
+        assert!(connectivity.connected(Direction::NegX, Direction::PosX));
+
+        // A single hole connects both sides
+        chunk[UVec3::new(3, 16, 7)] = BlockId(0);
+        let connectivity = chunk.connectivity(&blocks);
+        assert!(connectivity.connected(Direction::PosY, Direction::NegY));
+        assert_eq!(connectivity, Connectivity::ALL);
+    }
+
+    #[test]
+    fn content_hash() {
+        // The same in every run, unlike the std hashers
+        assert_eq!(
+            Chunk::new(BlockId::AIR).content_hash(),
+            0x8f69_55bf_94ec_2325
+        );
+
+        let gen = WorldGen::default();
+        let pos = IVec3::new(0, 0, 0);
+        let generated = generate_chunk(pos, &gen).content_hash();
+        let mut chunk = generate_chunk(pos, &gen);
+        assert_eq!(chunk.content_hash(), generated);
+
+        let p = UVec3::new(1, 2, 3);
+        let old = chunk[p];
+        chunk[p] = if old == BlockId::STONE {
+            BlockId::AIR
+        } else {
+            BlockId::STONE
+        };
+        assert_ne!(chunk.content_hash(), generated);
+        chunk[p] = old;
+        assert_eq!(chunk.content_hash(), generated);
+        chunk.set_meta(p, 3);
+        assert_ne!(chunk.content_hash(), generated);
+
+        // Independent of the order the metadata was set in
+        let mut a = Chunk::new(BlockId::AIR);
+        let mut b = Chunk::new(BlockId::AIR);
+        for (i, pos) in [UVec3::X, UVec3::Y, UVec3::Z].into_iter().enumerate() {
+            a.set_meta(pos, i as u8 + 1);
+        }
+        for (i, pos) in [UVec3::X, UVec3::Y, UVec3::Z].into_iter().enumerate().rev() {
+            b.set_meta(pos, i as u8 + 1);
+        }
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert_ne!(a.content_hash(), Chunk::new(BlockId::AIR).content_hash());
+    }
+
+    #[test]
+    fn entities_round_trip() {
+        let lamp = BlockEntityData::Lamp {
+            color: [0.2, 0.4, 1.0],
+        };
+        let mut chunk = Chunk::new(BlockId::AIR);
+        let pos = UVec3::new(31, 0, 7);
+        chunk[pos] = BlockId::STONE;
+        chunk.set_meta(pos, 3);
+        chunk.set_entity(pos, Some(lamp.clone()));
+        let hash = chunk.content_hash();
+
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        let read = Chunk::read(&bytes[..]).unwrap();
+        assert_eq!(read.entity(pos), Some(&lamp));
+        assert_eq!(read.entities().count(), 1);
+        assert_eq!(read.content_hash(), hash);
+
+        chunk.set_entity(pos, None);
+        assert_ne!(chunk.content_hash(), hash);
+
+        // Chunks of the previous version have no entities
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        bytes[..4].copy_from_slice(b"VXC1");
+        bytes.truncate(bytes.len() - 4);
+        let read = Chunk::read(&bytes[..]).unwrap();
+        assert_eq!(read[pos], BlockId::STONE);
+        assert_eq!(read.meta(pos), 3);
+        assert_eq!(read.entities().count(), 0);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let mut chunk = Chunk::new(BlockId::AIR);
+        let pos = UVec3::new(1, 2, 3);
+        chunk[pos] = BlockId(200);
+        chunk.set_meta(pos, 5);
+        chunk[UVec3::ZERO] = BlockId::STONE;
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+
+        // Removed blocks are read and replaced afterwards
+        let mut read = Chunk::read(&bytes[..]).unwrap();
+        let mut blocks = HashMap::new();
+        blocks.insert(BlockId::AIR, Block::default());
+        blocks.insert(BlockId::STONE, Block::default());
+        assert_eq!(read.remove_unknown(&blocks), 1);
+        assert_eq!(read[pos], BlockId::AIR);
+        assert_eq!(read.meta(pos), 0);
+        assert_eq!(read[UVec3::ZERO], BlockId::STONE);
+
+        // The entity length is checked before allocating
+        let len = bytes.len();
+        bytes[len - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = Chunk::read(&bytes[..]).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
@@ -0,0 +1,387 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::args::Args;
+use crate::block::BlockId;
+use crate::interaction::{update_target, TargetedBlock};
+use crate::world::{ChangeCause, ChunkGenerated, VoxelWorld, WorldSet};
+use crate::AppState;
+
+/// File in the world folder the clipboard is saved to and loaded from.
+const CLIPBOARD_FILE: &str = "clipboard.vxs";
+/// Identifies the clipboard file format and version.
+const MAGIC: &[u8; 4] = b"VXS1";
+
+pub struct ClipboardPlugin;
+
+impl Plugin for ClipboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .init_resource::<Clipboard>()
+            .init_resource::<PendingPastes>()
+            .add_systems(
+                Update,
+                (
+                    select_corners,
+                    copy_paste,
+                    save_load,
+                    draw_selection,
+                    apply_pending_pastes.after(WorldSet),
+                )
+                    .chain()
+                    .after(update_target)
+                    .run_if(in_state(AppState::Running)),
+            )
+            .add_systems(OnExit(AppState::Running), clear_pending_pastes);
+    }
+}
+
+/// Region selected by two corner blocks (inclusive).
+#[derive(Resource, Debug, Default)]
+pub struct Selection {
+    pub a: Option<IVec3>,
+    pub b: Option<IVec3>,
+}
+
+impl Selection {
+    /// Returns the minimum and maximum block of the selection, if both corners are set.
+    pub fn bounds(&self) -> Option<(IVec3, IVec3)> {
+        let (a, b) = (self.a?, self.b?);
+        Some((a.min(b), a.max(b)))
+    }
+}
+
+/// The copied blocks.
+#[derive(Resource, Debug, Default)]
+pub struct Clipboard(pub Option<Schematic>);
+
+/// A box of blocks, stored in x, y, z order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schematic {
+    pub size: UVec3,
+    pub blocks: Vec<BlockId>,
+}
+
+impl Schematic {
+    /// Copies the blocks between `min` and `max` (inclusive).
+    ///
+    /// Returns `None` if a part of the region is not loaded.
+    pub fn copy(world: &VoxelWorld, min: IVec3, max: IVec3) -> Option<Self> {
+        let size = (max - min + 1).as_uvec3();
+        let mut blocks = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    blocks.push(world.get_block(IVec3::new(x, y, z))?);
+                }
+            }
+        }
+        Some(Self { size, blocks })
+    }
+
+    fn index(&self, pos: UVec3) -> usize {
+        (pos.x * self.size.y * self.size.z + pos.y * self.size.z + pos.z) as usize
+    }
+
+    /// Returns the blocks together with their offsets from the minimum corner.
+    pub fn iter(&self) -> impl Iterator<Item = (UVec3, BlockId)> + '_ {
+        let size = self.size;
+        (0..size.x)
+            .flat_map(move |x| (0..size.y).flat_map(move |y| (0..size.z).map(move |z| (x, y, z))))
+            .map(|(x, y, z)| UVec3::new(x, y, z))
+            .zip(self.blocks.iter().copied())
+    }
+
+    /// Rotates the schematic by 90° around the y axis.
+    pub fn rotate(&self) -> Self {
+        let size = UVec3::new(self.size.z, self.size.y, self.size.x);
+        let mut rotated = Self {
+            size,
+            blocks: vec![BlockId::AIR; self.blocks.len()],
+        };
+        for (pos, block) in self.iter() {
+            let i = rotated.index(UVec3::new(self.size.z - 1 - pos.z, pos.y, pos.x));
+            rotated.blocks[i] = block;
+        }
+        rotated
+    }
+
+    /// Pastes the non-air blocks with the minimum corner at `anchor`.
+    ///
+    /// Returns the blocks in chunks that are not loaded, which have to be
+    /// placed once their chunks are generated.
+    pub fn paste(&self, world: &mut VoxelWorld, anchor: IVec3) -> Vec<(IVec3, BlockId)> {
+        let mut unloaded = Vec::new();
+        for (pos, block) in self.iter() {
+            let pos = anchor + pos.as_ivec3();
+            if block != BlockId::AIR && world.set_block(pos, block, ChangeCause::Player).is_none() {
+                unloaded.push((pos, block));
+            }
+        }
+        unloaded
+    }
+
+    /// Writes the schematic in a small binary format.
+    ///
+    /// The format consists of the magic bytes, the size as three little endian
+    /// `u32` and the block ids as one byte each.
+    pub fn write(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        for v in self.size.to_array() {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        let ids: Vec<u8> = self.blocks.iter().map(|b| b.0).collect();
+        w.write_all(&ids)
+    }
+
+    /// Reads a schematic written by [`Schematic::write`].
+    pub fn read(mut r: impl Read) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a schematic file",
+            ));
+        }
+        let mut size = [0; 3];
+        for v in &mut size {
+            let mut bytes = [0; 4];
+            r.read_exact(&mut bytes)?;
+            *v = u32::from_le_bytes(bytes);
+        }
+        let size = UVec3::from_array(size);
+        let len = size.x as u64 * size.y as u64 * size.z as u64;
+
+        let mut ids = Vec::new();
+        r.take(len).read_to_end(&mut ids)?;
+        if ids.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Self {
+            size,
+            blocks: ids.into_iter().map(BlockId).collect(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Pasted blocks waiting for their chunks to be generated.
+#[derive(Resource, Debug, Default)]
+pub struct PendingPastes(HashMap<IVec3, Vec<(IVec3, BlockId)>>);
+
+impl PendingPastes {
+    pub fn push(&mut self, edits: impl IntoIterator<Item = (IVec3, BlockId)>) {
+        for (pos, block) in edits {
+            let chunk = VoxelWorld::chunk_of_block(pos);
+            self.0.entry(chunk).or_default().push((pos, block));
+        }
+    }
+
+    /// Number of chunks with pending blocks.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Places the pending pasted blocks of the newly generated chunks.
+fn apply_pending_pastes(
+    mut generated: EventReader<ChunkGenerated>,
+    mut pending: ResMut<PendingPastes>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    for event in generated.iter() {
+        if let Some(edits) = pending.0.remove(&event.pos) {
+            for (pos, block) in edits {
+                world.set_block(pos, block, ChangeCause::Player);
+            }
+        }
+    }
+}
+
+fn clear_pending_pastes(mut pending: ResMut<PendingPastes>) {
+    pending.0.clear();
+}
+
+/// Set the selection corners to the targeted block with Q and E.
+fn select_corners(
+    key: Res<Input<KeyCode>>,
+    target: Res<TargetedBlock>,
+    mut selection: ResMut<Selection>,
+) {
+    let corner_a = key.just_pressed(KeyCode::Q);
+    let corner_b = key.just_pressed(KeyCode::E);
+    if !(corner_a || corner_b) {
+        return;
+    }
+    let Some(target) = target.0 else {
+        return;
+    };
+    if corner_a {
+        selection.a = Some(target.hit);
+    } else {
+        selection.b = Some(target.hit);
+    }
+}
+
+/// Copy the selection with Ctrl+C, rotate the clipboard with R
+/// and paste it at the targeted block with Ctrl+V.
+///
+/// Blocks pasted into chunks that are not generated yet are placed once they are.
+fn copy_paste(
+    key: Res<Input<KeyCode>>,
+    selection: Res<Selection>,
+    mut clipboard: ResMut<Clipboard>,
+    mut pending: ResMut<PendingPastes>,
+    target: Res<TargetedBlock>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    if key.just_pressed(KeyCode::R) {
+        if let Some(schematic) = &mut clipboard.0 {
+            *schematic = schematic.rotate();
+        }
+    }
+
+    if !key.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    if key.just_pressed(KeyCode::C) {
+        let Some((min, max)) = selection.bounds() else {
+            return;
+        };
+        match Schematic::copy(&world, min, max) {
+            Some(schematic) => clipboard.0 = Some(schematic),
+            None => warn!("Cannot copy a selection that is not fully loaded"),
+        }
+    } else if key.just_pressed(KeyCode::V) {
+        let Some(schematic) = &clipboard.0 else {
+            return;
+        };
+        let Some(target) = target.0 else {
+            return;
+        };
+        let unloaded = schematic.paste(&mut world, target.hit);
+        if !unloaded.is_empty() {
+            pending.push(unloaded);
+            info!(
+                "Pasting into {} chunks once they are generated",
+                pending.len()
+            );
+        }
+    }
+}
+
+/// Save the clipboard with F5 and load it with F9.
+fn save_load(key: Res<Input<KeyCode>>, args: Res<Args>, mut clipboard: ResMut<Clipboard>) {
+    let path = args.world.join(CLIPBOARD_FILE);
+    if key.just_pressed(KeyCode::F5) {
+        if let Some(schematic) = &clipboard.0 {
+            match schematic.save(&path) {
+                Ok(()) => info!("Saved clipboard to {}", path.display()),
+                Err(e) => error!("Failed to save {}: {e}", path.display()),
+            }
+        }
+    } else if key.just_pressed(KeyCode::F9) {
+        match Schematic::load(&path) {
+            Ok(schematic) => clipboard.0 = Some(schematic),
+            Err(e) => error!("Failed to load {}: {e}", path.display()),
+        }
+    }
+}
+
+/// Outline the selected region.
+fn draw_selection(selection: Res<Selection>, mut gizmos: Gizmos) {
+    let Some((min, max)) = selection.bounds() else {
+        return;
+    };
+    let min = min.as_vec3();
+    let max = max.as_vec3() + 1.0;
+    gizmos.cuboid(
+        Transform::from_translation((min + max) / 2.0).with_scale(max - min),
+        Color::YELLOW,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::{IVec3, UVec3};
+
+    use super::{PendingPastes, Schematic};
+    use crate::block::BlockId;
+    use crate::chunk::Chunk;
+    use crate::world::VoxelWorld;
+
+    fn schematic() -> Schematic {
+        let size = UVec3::new(3, 2, 1);
+        Schematic {
+            size,
+            blocks: (0..6).map(BlockId).collect(),
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let schematic = schematic();
+        let mut bytes = Vec::new();
+        schematic.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 12 + 6);
+        assert_eq!(Schematic::read(bytes.as_slice()).unwrap(), schematic);
+
+        // Truncated and foreign files are rejected
+        assert!(Schematic::read(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Schematic::read(&b"PNG\0\0\0\0\0"[..]).is_err());
+    }
+
+    #[test]
+    fn rotate() {
+        let schematic = schematic();
+        let rotated = schematic.rotate();
+        assert_eq!(rotated.size, UVec3::new(1, 2, 3));
+        assert_ne!(rotated, schematic);
+
+        let full = rotated.rotate().rotate().rotate();
+        assert_eq!(full, schematic);
+    }
+
+    #[test]
+    fn paste_unloaded() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::ZERO);
+        // Spans the loaded chunk and its unloaded neighbor in x
+        let anchor = IVec3::new(Chunk::SIZE as i32 - 2, 0, 0);
+        let schematic = Schematic {
+            size: UVec3::new(4, 1, 1),
+            blocks: vec![BlockId(1), BlockId::AIR, BlockId(2), BlockId(3)],
+        };
+        let unloaded = schematic.paste(&mut world, anchor);
+        assert_eq!(world.get_block(anchor), Some(BlockId(1)));
+        assert_eq!(world.get_block(anchor + IVec3::X), Some(BlockId::AIR));
+        assert_eq!(
+            unloaded,
+            [
+                (anchor + IVec3::new(2, 0, 0), BlockId(2)),
+                (anchor + IVec3::new(3, 0, 0), BlockId(3))
+            ]
+        );
+
+        let mut pending = PendingPastes::default();
+        pending.push(unloaded);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending.0[&IVec3::X].len(), 2);
+    }
+}
@@ -0,0 +1,244 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+
+use crate::args::Args;
+use crate::block::blocks;
+use crate::clipboard::Selection;
+use crate::player::PlayerController;
+use crate::textures::TileTextures;
+use crate::world::VoxelWorld;
+use crate::AppState;
+
+/// Folder in the world folder the exported meshes are written to.
+const EXPORT_DIR: &str = "export";
+/// Name of the exported mesh, material and texture atlas files.
+const EXPORT_NAME: &str = "terrain";
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, export.run_if(in_state(AppState::Running)));
+    }
+}
+
+/// Writes the `meshes`, translated by their offsets, as a single Wavefront OBJ
+/// using the material `material` from the library `mtllib`.
+pub fn write_obj(
+    w: &mut impl Write,
+    meshes: &[(Vec3, Mesh)],
+    mtllib: &str,
+    material: &str,
+) -> io::Result<()> {
+    writeln!(w, "mtllib {mtllib}")?;
+    writeln!(w, "usemtl {material}")?;
+
+    let mut offset = 1;
+    for (translation, mesh) in meshes {
+        let (
+            Some(VertexAttributeValues::Float32x3(positions)),
+            Some(VertexAttributeValues::Float32x3(normals)),
+            Some(VertexAttributeValues::Float32x2(uvs)),
+        ) = (
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+            mesh.attribute(Mesh::ATTRIBUTE_UV_0),
+        )
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Mesh without positions, normals or uvs",
+            ));
+        };
+
+        for p in positions {
+            let p = Vec3::from(*p) + *translation;
+            writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        for n in normals {
+            writeln!(w, "vn {} {} {}", n[0], n[1], n[2])?;
+        }
+        // The texture origin is at the bottom left in OBJ
+        for uv in uvs {
+            writeln!(w, "vt {} {}", uv[0], 1.0 - uv[1])?;
+        }
+
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+        for face in indices.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]].map(|i| i + offset);
+            writeln!(w, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        offset += positions.len();
+    }
+    Ok(())
+}
+
+/// Writes the material library referencing the texture atlas `texture`.
+fn write_mtl(w: &mut impl Write, material: &str, texture: &str) -> io::Result<()> {
+    writeln!(w, "newmtl {material}")?;
+    writeln!(w, "Kd 1 1 1")?;
+    writeln!(w, "map_Kd {texture}")
+}
+
+/// Saves the base level of the texture atlas as png.
+fn save_atlas(image: &Image, path: &Path) -> Result<(), anyhow::Error> {
+    let size = image.size().as_uvec2();
+    // Skip the mipmaps following the base level
+    let len = (size.x * size.y * 4) as usize;
+    let data = image
+        .data
+        .get(..len)
+        .ok_or_else(|| anyhow::anyhow!("The texture atlas is not rgba8"))?;
+    image::save_buffer(path, data, size.x, size.y, image::ColorType::Rgba8)?;
+    Ok(())
+}
+
+/// Exports the meshes of the `chunks` with the texture atlas into `dir`.
+fn export_chunks(
+    world: &VoxelWorld,
+    chunks: impl IntoIterator<Item = IVec3>,
+    atlas: &Image,
+    dir: &Path,
+) -> Result<usize, anyhow::Error> {
+    let blocks = blocks().read().unwrap();
+    let textures = TileTextures::get();
+    let meshes = chunks
+        .into_iter()
+        .filter_map(|pos| {
+            let mesh = world.mesh_chunk(pos, &blocks, textures)?;
+            Some((VoxelWorld::world_pos(pos), mesh))
+        })
+        .collect::<Vec<_>>();
+
+    fs::create_dir_all(dir)?;
+    let (obj, mtl, png) = (
+        format!("{EXPORT_NAME}.obj"),
+        format!("{EXPORT_NAME}.mtl"),
+        format!("{EXPORT_NAME}.png"),
+    );
+    let mut w = BufWriter::new(File::create(dir.join(&obj))?);
+    write_obj(&mut w, &meshes, &mtl, EXPORT_NAME)?;
+    w.flush()?;
+    let mut w = BufWriter::new(File::create(dir.join(&mtl))?);
+    write_mtl(&mut w, EXPORT_NAME, &png)?;
+    w.flush()?;
+    save_atlas(atlas, &dir.join(png))?;
+    Ok(meshes.len())
+}
+
+/// Export the chunks of the selection, or the player's chunk without one, with F6.
+fn export(
+    key: Res<Input<KeyCode>>,
+    args: Res<Args>,
+    world: Res<VoxelWorld>,
+    selection: Res<Selection>,
+    images: Res<Assets<Image>>,
+    query: Query<&Transform, With<PlayerController>>,
+) {
+    if !key.just_pressed(KeyCode::F6) {
+        return;
+    }
+    let (min, max) = match selection.bounds() {
+        Some((min, max)) => (
+            VoxelWorld::chunk_of_block(min),
+            VoxelWorld::chunk_of_block(max),
+        ),
+        None => {
+            let chunk = VoxelWorld::chunk_pos(query.single().translation);
+            (chunk, chunk)
+        }
+    };
+    let Some(atlas) = images.get(&TileTextures::get().image()) else {
+        return;
+    };
+
+    let mut chunks = Vec::new();
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                chunks.push(IVec3::new(x, y, z));
+            }
+        }
+    }
+    let dir = args.world.join(EXPORT_DIR);
+    match export_chunks(&world, chunks, atlas, &dir) {
+        Ok(n) => info!("Exported {n} chunks to {}", dir.display()),
+        Err(e) => error!("Failed to export to {}: {e}", dir.display()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+    use bevy::utils::HashMap;
+
+    use super::write_obj;
+    use crate::block::{Block, BlockId, Cube, Face};
+    use crate::chunk::{Border, Chunk};
+    use crate::textures::TileTextures;
+
+    #[test]
+    fn obj_format() {
+        let textures = TileTextures::with_names(&["stone"]);
+        let face = Face {
+            texture: textures.missing(),
+            cull: true,
+            tint: Face::NO_TINT,
+        };
+        let mut blocks = HashMap::new();
+        blocks.insert(BlockId::AIR, Block::default());
+        blocks.insert(
+            BlockId::STONE,
+            Block {
+                opaque: true,
+                solid_faces: [true; 6],
+                cubes: vec![Cube {
+                    min: UVec3::ZERO,
+                    max: Cube::MAX,
+                    faces: [(); 6].map(|_| face.clone()),
+                }],
+                ..default()
+            },
+        );
+        let mut chunk = Chunk::new(BlockId::AIR);
+        chunk[UVec3::ZERO] = BlockId::STONE;
+        let mesh = chunk.mesh([Border::new(); 6], &blocks, &textures);
+
+        let mut out = Vec::new();
+        let meshes = [(Vec3::ZERO, mesh.clone()), (Vec3::X * 32.0, mesh)];
+        write_obj(&mut out, &meshes, "terrain.mtl", "terrain").unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let count = |prefix: &str| text.lines().filter(|l| l.starts_with(prefix)).count();
+        assert_eq!(count("v "), 2 * 24);
+        assert_eq!(count("vn "), 2 * 24);
+        assert_eq!(count("vt "), 2 * 24);
+        assert_eq!(count("f "), 2 * 12);
+        assert!(text.starts_with("mtllib terrain.mtl\nusemtl terrain\n"));
+
+        // One-based indices referencing the vertices of their own mesh
+        let indices = text
+            .lines()
+            .filter_map(|l| l.strip_prefix("f "))
+            .flat_map(|l| {
+                l.split(' ')
+                    .map(|v| v.split('/').next().unwrap().parse().unwrap())
+            })
+            .collect::<Vec<usize>>();
+        assert_eq!(indices.iter().min(), Some(&1));
+        assert_eq!(indices.iter().max(), Some(&48));
+        assert!(indices[..36].iter().all(|i| *i <= 24));
+        let x = text
+            .lines()
+            .filter_map(|l| l.strip_prefix("v "))
+            .map(|l| l.split(' ').next().unwrap().parse::<f32>().unwrap())
+            .fold(f32::MIN, f32::max);
+        assert_eq!(x, 33.0);
+    }
+}
@@ -0,0 +1,220 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use rand::Rng;
+
+use crate::block::{blocks, BlockId};
+use crate::health::DamageEvent;
+use crate::player::{PlayerController, EYE_HEIGHT};
+use crate::util::Direction;
+use crate::world::{BlockChangedEvent, ChangeCause, VoxelWorld};
+use crate::AppState;
+
+/// Interval between two fire updates in seconds.
+const TICK: f32 = 0.5;
+/// Maximum number of fire blocks updated per tick.
+const BUDGET: usize = 256;
+/// Maximum number of fires that emit light.
+const MAX_LIGHTS: usize = 16;
+/// Damage per fire tick to a player standing in fire.
+const FIRE_DAMAGE: f32 = 1.0;
+
+pub struct FirePlugin;
+
+impl Plugin for FirePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FireActive>()
+            .init_resource::<FireLights>()
+            .insert_resource(FireTimer(Timer::from_seconds(TICK, TimerMode::Repeating)))
+            .add_systems(
+                Update,
+                (
+                    activate_fires,
+                    update_fires,
+                    burn_player,
+                    update_fire_lights,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+/// Positions of the burning fire blocks.
+#[derive(Default, Resource)]
+pub struct FireActive(pub HashSet<IVec3>);
+
+#[derive(Resource)]
+struct FireTimer(Timer);
+
+/// Point lights of the burning fires.
+#[derive(Default, Resource)]
+pub struct FireLights(HashMap<IVec3, Entity>);
+
+/// Tracks new fires, extinguishes fires next to water, and lets fires without support fall.
+fn activate_fires(
+    mut world: ResMut<VoxelWorld>,
+    mut events: EventReader<BlockChangedEvent>,
+    mut active: ResMut<FireActive>,
+) {
+    let events: Vec<BlockChangedEvent> = events.iter().copied().collect();
+    for event in events {
+        let neighbors = Direction::all().map(|d| event.pos + IVec3::from(d));
+        match event.new {
+            BlockId::FIRE => {
+                let wet = neighbors
+                    .iter()
+                    .any(|p| world.get_block(*p) == Some(BlockId::WATER));
+                if wet {
+                    world.set_block(event.pos, BlockId::AIR, ChangeCause::Simulation);
+                } else {
+                    active.0.insert(event.pos);
+                }
+            }
+            BlockId::WATER => {
+                for p in neighbors {
+                    if world.get_block(p) == Some(BlockId::FIRE) {
+                        world.set_block(p, BlockId::AIR, ChangeCause::Simulation);
+                    }
+                }
+            }
+            // The fire above might have lost its support
+            BlockId::AIR if world.get_block(event.pos + IVec3::Y) == Some(BlockId::FIRE) => {
+                active.0.insert(event.pos + IVec3::Y);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Damages the player standing in fire once per fire tick.
+fn burn_player(
+    timer: Res<FireTimer>,
+    world: Res<VoxelWorld>,
+    mut damage: EventWriter<DamageEvent>,
+    query: Query<(Entity, &Transform), With<PlayerController>>,
+) {
+    if !timer.0.just_finished() {
+        return;
+    }
+    for (entity, transform) in &query {
+        let feet = (transform.translation - Vec3::Y * EYE_HEIGHT)
+            .floor()
+            .as_ivec3();
+        let head = transform.translation.floor().as_ivec3();
+        if [feet, head]
+            .iter()
+            .any(|p| world.get_block(*p) == Some(BlockId::FIRE))
+        {
+            damage.send(DamageEvent {
+                entity,
+                amount: FIRE_DAMAGE,
+            });
+        }
+    }
+}
+
+/// Ages the active fires and spreads them to flammable neighbors.
+///
+/// The age of a fire is stored in its block metadata.
+fn update_fires(
+    time: Res<Time>,
+    mut timer: ResMut<FireTimer>,
+    mut active: ResMut<FireActive>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let blocks = blocks().read().unwrap();
+    let Some(max_age) = blocks.get(&BlockId::FIRE).map(|b| b.max_age) else {
+        return;
+    };
+    let mut rng = rand::thread_rng();
+
+    let batch: Vec<IVec3> = active.0.iter().take(BUDGET).copied().collect();
+    for pos in batch {
+        active.0.remove(&pos);
+        if world.get_block(pos) != Some(BlockId::FIRE) {
+            continue;
+        }
+        let age = world.get_meta(pos).unwrap_or_default().saturating_add(1);
+
+        // Fire requires a supporting block, otherwise it falls
+        match world.get_block(pos - IVec3::Y) {
+            Some(BlockId::AIR) => {
+                world.set_block(pos, BlockId::AIR, ChangeCause::Simulation);
+                world.set_block_meta(pos - IVec3::Y, BlockId::FIRE, age, ChangeCause::Simulation);
+                continue;
+            }
+            None => {
+                active.0.insert(pos);
+                continue;
+            }
+            _ => {}
+        }
+
+        if age > max_age {
+            world.set_block(pos, BlockId::AIR, ChangeCause::Simulation);
+            continue;
+        }
+        world.set_block_meta(pos, BlockId::FIRE, age, ChangeCause::Simulation);
+        active.0.insert(pos);
+
+        for d in Direction::all() {
+            let p = pos + IVec3::from(d);
+            let Some(block) = world.get_block(p).and_then(|id| blocks.get(&id)) else {
+                continue;
+            };
+            if block.has_tag("flammable") && rng.gen::<f32>() < block.ignite_chance {
+                world.set_block(p, BlockId::FIRE, ChangeCause::Simulation);
+            }
+        }
+    }
+}
+
+/// Spawns point lights for the burning fires and removes the extinguished ones.
+fn update_fire_lights(
+    mut cmds: Commands,
+    world: Res<VoxelWorld>,
+    active: Res<FireActive>,
+    mut lights: ResMut<FireLights>,
+) {
+    lights.0.retain(|pos, entity| {
+        let burning = world.get_block(*pos) == Some(BlockId::FIRE);
+        if !burning {
+            cmds.entity(*entity).despawn();
+        }
+        burning
+    });
+
+    let blocks = blocks().read().unwrap();
+    let Some(emission) = blocks.get(&BlockId::FIRE).map(|b| b.light_emission) else {
+        return;
+    };
+    if emission == 0 {
+        return;
+    }
+
+    for pos in &active.0 {
+        if lights.0.len() >= MAX_LIGHTS {
+            break;
+        }
+        if lights.0.contains_key(pos) {
+            continue;
+        }
+        let entity = cmds
+            .spawn(PointLightBundle {
+                transform: Transform::from_translation(pos.as_vec3() + 0.5),
+                point_light: PointLight {
+                    intensity: 800.0 * emission as f32 / 15.0,
+                    color: Color::ORANGE_RED,
+                    range: emission as f32,
+                    ..default()
+                },
+                ..default()
+            })
+            .id();
+        lights.0.insert(*pos, entity);
+    }
+}
@@ -0,0 +1,281 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::block::{blocks, BlockId};
+use crate::util::Direction;
+use crate::world::{BlockChangedEvent, ChangeCause, VoxelWorld};
+use crate::AppState;
+
+/// Interval between two fluid updates in seconds.
+const TICK: f32 = 0.2;
+/// Metadata flag of water that is fed from above.
+///
+/// Otherwise the metadata stores the horizontal distance to the feeding water,
+/// which is zero for source blocks.
+const FALLING: u8 = 0x80;
+
+const HORIZONTAL: [IVec3; 4] = [IVec3::NEG_X, IVec3::NEG_Z, IVec3::X, IVec3::Z];
+
+pub struct FluidPlugin;
+
+impl Plugin for FluidPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FluidActive>()
+            .init_resource::<FluidSettings>()
+            .insert_resource(FluidTimer(Timer::from_seconds(TICK, TimerMode::Repeating)))
+            .add_systems(
+                Update,
+                (activate_fluids, update_fluids)
+                    .chain()
+                    .run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct FluidSettings {
+    /// Maximum horizontal distance water flows from its source
+    pub spread_distance: u8,
+    /// Maximum number of fluid blocks updated per tick
+    pub budget: usize,
+}
+
+impl Default for FluidSettings {
+    fn default() -> Self {
+        Self {
+            spread_distance: 7,
+            budget: 256,
+        }
+    }
+}
+
+/// Positions of the fluid blocks that have to be updated, grouped by chunk.
+///
+/// Chunks without active fluids have no entry.
+#[derive(Default, Resource)]
+pub struct FluidActive(HashMap<IVec3, HashSet<IVec3>>);
+
+impl FluidActive {
+    pub fn insert(&mut self, pos: IVec3) {
+        let chunk = VoxelWorld::chunk_of_block(pos);
+        self.0.entry(chunk).or_default().insert(pos);
+    }
+
+    /// Returns the number of active fluid blocks.
+    pub fn len(&self) -> usize {
+        self.0.values().map(HashSet::len).sum()
+    }
+
+    /// Removes and returns up to `budget` active positions.
+    fn take(&mut self, budget: usize) -> Vec<IVec3> {
+        let mut batch = Vec::with_capacity(budget.min(self.len()));
+        self.0.retain(|_, active| {
+            let n = (budget - batch.len()).min(active.len());
+            let start = batch.len();
+            batch.extend(active.iter().take(n).copied());
+            for pos in &batch[start..] {
+                active.remove(pos);
+            }
+            !active.is_empty()
+        });
+        batch
+    }
+
+    /// Activates the water at and around `pos`.
+    fn activate_around(&mut self, world: &VoxelWorld, pos: IVec3) {
+        let neighbors = Direction::all().map(|d| pos + IVec3::from(d));
+        for p in [pos].into_iter().chain(neighbors) {
+            if world.get_block(p) == Some(BlockId::WATER) {
+                self.insert(p);
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+struct FluidTimer(Timer);
+
+/// Activates the fluid blocks at and around changed blocks.
+fn activate_fluids(
+    world: Res<VoxelWorld>,
+    mut events: EventReader<BlockChangedEvent>,
+    mut active: ResMut<FluidActive>,
+) {
+    let blocks = blocks().read().unwrap();
+    for event in events.iter() {
+        let neighbors = Direction::all().map(|d| event.pos + IVec3::from(d));
+        for pos in [event.pos].into_iter().chain(neighbors) {
+            let fluid = world
+                .get_block(pos)
+                .and_then(|id| blocks.get(&id))
+                .is_some_and(|b| b.has_tag("fluid"));
+            if fluid {
+                active.insert(pos);
+            }
+        }
+    }
+}
+
+/// Spreads and drains the active fluid blocks, limited by the budget.
+fn update_fluids(
+    time: Res<Time>,
+    settings: Res<FluidSettings>,
+    mut timer: ResMut<FluidTimer>,
+    mut active: ResMut<FluidActive>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    for pos in active.take(settings.budget) {
+        update_fluid(&mut world, &mut active, &settings, pos);
+    }
+}
+
+/// Returns the distance of the water at `pos` to its feeding water.
+fn water_distance(world: &VoxelWorld, pos: IVec3) -> Option<u8> {
+    if world.get_block(pos) != Some(BlockId::WATER) {
+        return None;
+    }
+    let meta = world.get_meta(pos)?;
+    Some(if meta & FALLING != 0 { 0 } else { meta })
+}
+
+/// Updates the water level at `pos` and spreads it into the neighboring air.
+///
+/// Water without a feeding neighbor drains away.
+/// Water falls down first, then prefers neighbors with air below them,
+/// and otherwise spreads evenly until it reaches the spread distance.
+fn update_fluid(
+    world: &mut VoxelWorld,
+    active: &mut FluidActive,
+    settings: &FluidSettings,
+    pos: IVec3,
+) {
+    let Some(mut distance) = water_distance(world, pos) else {
+        return;
+    };
+    let meta = world.get_meta(pos).unwrap_or_default();
+
+    // Check if the water is still fed
+    if meta & FALLING != 0 {
+        if water_distance(world, pos + IVec3::Y).is_none() {
+            drain(world, active, pos);
+            return;
+        }
+    } else if meta != 0 {
+        let level = HORIZONTAL
+            .iter()
+            .filter_map(|d| water_distance(world, pos + *d))
+            .min()
+            .map(|d| d + 1);
+        match level {
+            Some(level) if level <= settings.spread_distance => {
+                if level != meta {
+                    world.set_block_meta(pos, BlockId::WATER, level, ChangeCause::Simulation);
+                    active.activate_around(world, pos);
+                }
+                distance = level;
+            }
+            _ => {
+                drain(world, active, pos);
+                return;
+            }
+        }
+    }
+
+    let air = |p: IVec3| world.get_block(p) == Some(BlockId::AIR);
+    let targets = if air(pos - IVec3::Y) {
+        vec![(pos - IVec3::Y, FALLING)]
+    } else if distance < settings.spread_distance {
+        let free: Vec<IVec3> = HORIZONTAL
+            .map(|d| pos + d)
+            .into_iter()
+            .filter(|p| air(*p))
+            .collect();
+        let downhill: Vec<IVec3> = free
+            .iter()
+            .copied()
+            .filter(|p| air(*p - IVec3::Y))
+            .collect();
+        let targets = if downhill.is_empty() { free } else { downhill };
+        targets.into_iter().map(|p| (p, distance + 1)).collect()
+    } else {
+        Vec::new()
+    };
+
+    for (target, meta) in targets {
+        if world
+            .set_block_meta(target, BlockId::WATER, meta, ChangeCause::Simulation)
+            .is_some()
+        {
+            active.insert(target);
+        }
+    }
+}
+
+/// Removes the water at `pos` and updates the water around it.
+fn drain(world: &mut VoxelWorld, active: &mut FluidActive, pos: IVec3) {
+    world.set_block(pos, BlockId::AIR, ChangeCause::Simulation);
+    active.activate_around(world, pos);
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::IVec3;
+
+    use super::{update_fluid, FluidActive, FluidSettings};
+    use crate::block::BlockId;
+    use crate::world::{ChangeCause, VoxelWorld};
+
+    /// Updates the fluids until they settle and returns the number of water blocks.
+    fn settle(world: &mut VoxelWorld, active: &mut FluidActive, settings: &FluidSettings) -> usize {
+        for _ in 0..1000 {
+            if active.len() == 0 {
+                break;
+            }
+            for pos in active.take(settings.budget) {
+                update_fluid(world, active, settings, pos);
+            }
+        }
+        assert_eq!(active.len(), 0);
+
+        let mut count = 0;
+        for x in -16..16 {
+            for y in 0..16 {
+                for z in -16..16 {
+                    count +=
+                        (world.get_block(IVec3::new(x, y, z)) == Some(BlockId::WATER)) as usize;
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn spread_and_drain() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::new(-1, 0, -1), IVec3::new(0, 0, 0));
+        let floor = (IVec3::new(-32, 0, -32), IVec3::new(31, 0, 31));
+        world.fill_box(floor.0, floor.1, BlockId::STONE, ChangeCause::Player);
+
+        let settings = FluidSettings {
+            spread_distance: 3,
+            budget: 16,
+        };
+        let mut active = FluidActive::default();
+
+        let source = IVec3::new(0, 4, 0);
+        world.set_block(source, BlockId::WATER, ChangeCause::Player);
+        active.insert(source);
+        let count = settle(&mut world, &mut active, &settings);
+        // Falling column and a diamond with radius 3 on the floor
+        assert_eq!(count, 1 + 2 + 25);
+        assert_eq!(world.get_meta(IVec3::new(3, 1, 0)), Some(3));
+        assert_eq!(world.get_block(IVec3::new(4, 1, 0)), Some(BlockId::AIR));
+
+        world.set_block(source, BlockId::AIR, ChangeCause::Player);
+        active.activate_around(&world, source);
+        assert_eq!(settle(&mut world, &mut active, &settings), 0);
+    }
+}
@@ -1,19 +1,53 @@
-use std::cell::RefCell;
 use std::f32::consts::PI;
-use std::ops::Range;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use anyhow::anyhow;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::utils::{BoxedFuture, HashSet};
 use noise::{MultiFractal, NoiseFn, RidgedMulti, Simplex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::block::BlockId;
 use crate::chunk::Chunk;
-use crate::util::{for_uvec3, RangeExt};
+use crate::util::{chunk_rng, for_uvec3, Direction, RangeExt};
 
-const MIN_HEIGHT: isize = -128;
-const MAX_HEIGHT: isize = 128;
+/// Default world height in chunks.
+const VERTICAL_CHUNKS: Range<i32> = -4..4;
+/// Maximum distance of the world top and bottom from zero in chunks.
+pub const MAX_VERTICAL_CHUNKS: i32 = 16;
 const DIRT_HEIGHT: usize = 2;
+/// Margin around the chunk in which the erosion particles move.
+const EROSION_MARGIN: i32 = 8;
+/// Maximum number of steps of an erosion particle.
+const EROSION_STEPS: usize = 32;
+/// How much a particle keeps its direction instead of following the slope.
+const EROSION_INERTIA: f32 = 0.1;
+/// Sediment capacity of a particle relative to its slope, speed and water.
+const EROSION_CAPACITY: f32 = 4.0;
+/// Minimum slope for the sediment capacity, so that flat particles still erode.
+const EROSION_MIN_SLOPE: f32 = 0.01;
+/// Maximum number of blocks of an underground lake.
+const MAX_LAKE_SIZE: usize = 512;
+/// Radius decrease per block above the island top, giving it a flat dome.
+const ISLAND_DOME_SLOPE: f32 = 4.0;
+/// How much the noise frays the outline of the islands, relative to their radius.
+const ISLAND_ROUGHNESS: f32 = 0.3;
+/// Frequency of the noise fraying the islands.
+const ISLAND_FREQUENCY: f64 = 1.0 / 16.0;
+/// Maximum number of blocks the map preview scans down for the surface.
+const PREVIEW_DEPTH: i32 = 1024;
+/// Distance in blocks between the pixels of the noise preview.
+pub const NOISE_PREVIEW_SCALE: i32 = 4;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NoiseParam {
     pub octaves: usize,
     pub frequency: f32,
@@ -22,74 +56,853 @@ pub struct NoiseParam {
     pub attenuation: f32,
 }
 
+impl NoiseParam {
+    /// Returns the min and max value of the ridged multifractal noise.
+    ///
+    /// Each octave is at most the previous one divided by the attenuation,
+    /// scaled by the persistence.
+    fn bounds(&self) -> (f32, f32) {
+        let scale = 2.0 - 0.5_f32.powi(self.octaves as i32 - 1);
+        if scale <= 0.0 {
+            return (f32::NEG_INFINITY, f32::INFINITY);
+        }
+        let (mut min, mut max) = (0.0, 0.0);
+        let mut octave = 1.0_f32;
+        for x in 0..self.octaves {
+            if x > 0 && self.attenuation > 0.0 {
+                octave = (octave / self.attenuation).min(1.0);
+            }
+            let v = octave * self.persistence.powi(x as i32);
+            min += v.min(0.0);
+            max += v.max(0.0);
+        }
+        (min * 2.0 / scale - 1.0, max * 2.0 / scale - 1.0)
+    }
+}
+
+/// How a noise layer is combined with the layers before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayerOp {
+    /// Adds the scaled layer
+    Add,
+    /// Scales the previous layers by one plus the scaled layer
+    Multiply,
+    /// Takes the maximum of the previous layers and the scaled layer
+    Max,
+}
+
+impl LayerOp {
+    pub const ALL: [Self; 3] = [Self::Add, Self::Multiply, Self::Max];
+
+    fn apply(self, acc: f32, v: f32) -> f32 {
+        match self {
+            Self::Add => acc + v,
+            Self::Multiply => acc * (1.0 + v),
+            Self::Max => acc.max(v),
+        }
+    }
+
+    /// Applies the operation to the value ranges of `acc` and `v`.
+    fn apply_bounds(self, acc: (f32, f32), v: (f32, f32)) -> (f32, f32) {
+        match self {
+            Self::Add | Self::Max => (self.apply(acc.0, v.0), self.apply(acc.1, v.1)),
+            Self::Multiply => {
+                let corners = [
+                    self.apply(acc.0, v.0),
+                    self.apply(acc.0, v.1),
+                    self.apply(acc.1, v.0),
+                    self.apply(acc.1, v.1),
+                ];
+                (
+                    corners.into_iter().fold(f32::INFINITY, f32::min),
+                    corners.into_iter().fold(f32::NEG_INFINITY, f32::max),
+                )
+            }
+        }
+    }
+}
+
+/// A named 3d noise, like continents, hills or ridges.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseLayer {
+    pub name: String,
+    pub param: NoiseParam,
+    /// Seed of the noise, so that layers with the same parameters differ
+    pub seed: u32,
+    pub op: LayerOp,
+    pub strength: f32,
+}
+
+impl NoiseLayer {
+    fn new(name: &str, param: NoiseParam, seed: u32, op: LayerOp, strength: f32) -> Self {
+        Self {
+            name: name.into(),
+            param,
+            seed,
+            op,
+            strength,
+        }
+    }
+}
+
+/// What the map preview shows of the terrain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PreviewMode {
+    /// Height of the terrain surface
+    #[default]
+    Surface,
+    /// Horizontal slice of the terrain at the given height
+    Slice(i32),
+}
+
+/// The source of the terrain.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum WorldGenKind {
+    /// Terrain from the 3d noise layers
+    #[default]
+    Noise,
+    /// Terrain heights from the brightness of a grayscale png image
+    Heightmap {
+        path: PathBuf,
+        /// Heights of the black and white pixels
+        #[serde(with = "unbounded_range")]
+        height_range: Range<f32>,
+        /// Width of the image in blocks, defaults to one block per pixel
+        #[serde(default)]
+        world_width: Option<f32>,
+    },
+}
+
+/// Terrain floating above the main terrain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FloatingIslandsParams {
+    /// Lowest block of the islands
+    pub min_height: f32,
+    /// Highest block of the islands
+    pub max_height: f32,
+    /// Fraction of the island cells that contain an island, from 0 to 1
+    pub island_density: f32,
+    /// Radius of the island tops
+    pub island_radius: Range<f32>,
+    /// Radius decrease per block below the island top,
+    /// zero gives cylinders reaching down to the min height
+    pub underside_taper: f32,
+}
+
+impl Default for FloatingIslandsParams {
+    fn default() -> Self {
+        Self {
+            min_height: 64.0,
+            max_height: 128.0,
+            island_density: 0.3,
+            island_radius: 8.0..24.0,
+            underside_taper: 0.5,
+        }
+    }
+}
+
+/// A decoded grayscale heightmap image.
+#[derive(Clone)]
+pub struct HeightmapImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Arc<Vec<u8>>,
+}
+
+impl fmt::Debug for HeightmapImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HeightmapImage({}x{})", self.width, self.height)
+    }
+}
+
+impl HeightmapImage {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let image = image::open(path)
+            .map_err(|e| anyhow!("Failed to load the heightmap {}: {e}", path.display()))?
+            .into_luma8();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            pixels: Arc::new(image.into_raw()),
+        })
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> f32 {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.pixels[x + y * self.width as usize] as f32 / u8::MAX as f32
+    }
+
+    /// Returns the bilinear interpolated brightness (0 to 1) at the pixel
+    /// coordinates `p`, clamped to the image borders.
+    pub fn sample(&self, p: Vec2) -> f32 {
+        let p = p - 0.5;
+        let (x, y) = (p.x.floor() as i64, p.y.floor() as i64);
+        let f = p - p.floor();
+        let top = self.pixel(x, y) * (1.0 - f.x) + self.pixel(x + 1, y) * f.x;
+        let bottom = self.pixel(x, y + 1) * (1.0 - f.x) + self.pixel(x + 1, y + 1) * f.x;
+        top * (1.0 - f.y) + bottom * f.y
+    }
+}
+
 /// World generation parameters
-#[derive(Debug, Resource, Clone)]
+///
+/// Terrain presets are loaded from `.worldgen` json files.
+#[derive(Debug, Resource, Clone, Serialize, Deserialize, TypeUuid, TypePath)]
+#[uuid = "3f6c1d2a-5b8e-4c71-9a0d-7e2f4b6c8a91"]
 pub struct WorldGen {
-    /// Base 3d noise
-    pub base: NoiseParam,
+    /// Seed of the world, added to the seeds of the layers
+    #[serde(default)]
+    pub seed: u32,
+    #[serde(default)]
+    pub kind: WorldGenKind,
+    /// The image of the heightmap kind, see [`WorldGen::load_heightmap`]
+    #[serde(skip)]
+    pub heightmap: Option<HeightmapImage>,
+    /// Stack of 3d noise layers, combined in order
+    pub layers: Vec<NoiseLayer>,
+    #[serde(with = "unbounded_range")]
     pub base_limit: Range<f32>,
-    pub base_strength: f32,
     /// Cave 3d noise
+    #[allow(unused)]
+    #[serde(with = "unbounded_range")]
     pub cave_limit: Range<f32>,
 
-    /// The min/max height of the world
-    pub height: Range<f32>,
+    /// The chunk y indices of the world, chunks above are air and below stone
+    #[serde(default = "default_vertical_chunks")]
+    pub vertical_chunks: Range<i32>,
     /// How deep is the dirt generated (distance to air)
     pub dirt_height: usize,
     /// Height range in which grass and dirt are generated
     pub dirt_range: Range<isize>,
+    /// Enclosed voids below this height are filled with water
+    #[serde(default)]
+    pub sea_level: i32,
+
+    /// Iterations of the hydraulic erosion, zero disables it
+    #[serde(default)]
+    pub erosion_passes: u32,
+    /// Fraction of the free sediment capacity eroded per step
+    #[serde(default = "default_erosion_rate")]
+    pub erosion_rate: f32,
+    /// Fraction of the excess sediment deposited per step
+    #[serde(default = "default_deposition_rate")]
+    pub deposition_rate: f32,
+    /// Fraction of the water evaporating per step
+    #[serde(default = "default_evaporation")]
+    pub evaporation: f32,
+
+    /// Additional islands above the terrain, disabled if none
+    #[serde(default)]
+    pub floating_islands: Option<FloatingIslandsParams>,
+}
+
+fn default_vertical_chunks() -> Range<i32> {
+    VERTICAL_CHUNKS
+}
+fn default_erosion_rate() -> f32 {
+    0.3
+}
+fn default_deposition_rate() -> f32 {
+    0.3
+}
+fn default_evaporation() -> f32 {
+    0.02
 }
 
 impl Default for WorldGen {
     fn default() -> Self {
         WorldGen {
-            base: NoiseParam {
-                octaves: 6,
-                frequency: 0.01,
-                lacunarity: PI * 2.0 / 3.0,
-                persistence: 1.0,
-                attenuation: 2.0,
-            },
+            seed: 0,
+            kind: WorldGenKind::Noise,
+            heightmap: None,
+            layers: vec![
+                NoiseLayer::new(
+                    "Hills",
+                    NoiseParam {
+                        octaves: 6,
+                        frequency: 0.01,
+                        lacunarity: PI * 2.0 / 3.0,
+                        persistence: 1.0,
+                        attenuation: 2.0,
+                    },
+                    0,
+                    LayerOp::Add,
+                    0.4,
+                ),
+                NoiseLayer::new(
+                    "Continents",
+                    NoiseParam {
+                        octaves: 3,
+                        frequency: 0.002,
+                        lacunarity: 2.0,
+                        persistence: 0.5,
+                        attenuation: 2.0,
+                    },
+                    1,
+                    LayerOp::Add,
+                    0.25,
+                ),
+                NoiseLayer::new(
+                    "Erosion",
+                    NoiseParam {
+                        octaves: 2,
+                        frequency: 0.005,
+                        lacunarity: 2.0,
+                        persistence: 0.5,
+                        attenuation: 2.0,
+                    },
+                    2,
+                    LayerOp::Multiply,
+                    0.3,
+                ),
+            ],
             base_limit: -f32::INFINITY..0.5,
-            base_strength: 0.4,
             cave_limit: -0.1..0.1,
-            height: MIN_HEIGHT as _..MAX_HEIGHT as _,
+            vertical_chunks: VERTICAL_CHUNKS,
             dirt_height: DIRT_HEIGHT,
-            dirt_range: MIN_HEIGHT / 2..MAX_HEIGHT / 2,
+            dirt_range: -64..64,
+            sea_level: 0,
+            erosion_passes: 0,
+            erosion_rate: default_erosion_rate(),
+            deposition_rate: default_deposition_rate(),
+            evaporation: default_evaporation(),
+            floating_islands: None,
         }
     }
 }
 
+impl WorldGen {
+    /// Returns the block height range of the world.
+    pub fn height(&self) -> Range<f32> {
+        let size = Chunk::SIZE as f32;
+        self.vertical_chunks.start as f32 * size..self.vertical_chunks.end as f32 * size
+    }
+
+    /// Returns the chunk y indices that are loaded, the world height and
+    /// one chunk around it for the borders of the outermost chunks.
+    pub fn loaded_chunks(&self) -> RangeInclusive<i32> {
+        self.vertical_chunks.start - 1..=self.vertical_chunks.end
+    }
+
+    /// Clamps the settings that depend on the world height into it.
+    ///
+    /// Returns a warning for every clamped setting.
+    pub fn clamp_to_height(&mut self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.vertical_chunks.is_empty() {
+            self.vertical_chunks.end = self.vertical_chunks.start + 1;
+            warnings.push(format!(
+                "The world has to be at least one chunk high, using {:?}",
+                self.vertical_chunks
+            ));
+        }
+        let height = self.height();
+        let (min, max) = (height.start as i32, height.end as i32);
+
+        let dirt = self.dirt_range.start.clamp(min as _, max as _)
+            ..self.dirt_range.end.clamp(min as _, max as _);
+        if dirt != self.dirt_range {
+            warnings.push(format!(
+                "The dirt range {:?} exceeds the world height, using {dirt:?}",
+                self.dirt_range
+            ));
+            self.dirt_range = dirt;
+        }
+        let sea_level = self.sea_level.clamp(min, max);
+        if sea_level != self.sea_level {
+            warnings.push(format!(
+                "The sea level {} exceeds the world height, using {sea_level}",
+                self.sea_level
+            ));
+            self.sea_level = sea_level;
+        }
+        warnings
+    }
+
+    /// Returns the highest chunk y index of the column `xz`
+    /// that may contain terrain, without the floating islands.
+    pub fn max_occupied_y_chunk(&self, xz: IVec2) -> i32 {
+        let size = Chunk::SIZE as f32;
+        match &self.kind {
+            WorldGenKind::Noise => {
+                let (clip_air, clip_stone) = self.noise_clip();
+                // The noise plus the height offset exceeds the base limit
+                let (min, _) = self.noise_bounds();
+                let t = self.base_limit.end - min;
+                let y_air = match t {
+                    _ if t <= 0.0 => f32::NEG_INFINITY,
+                    _ if t > 1.0 => f32::INFINITY,
+                    _ => self.height().lerp(t) + 1.0,
+                };
+                let noise_max = ((y_air / size).ceil() as i32).saturating_sub(1);
+                clip_air.min(noise_max.max(clip_stone - 1))
+            }
+            WorldGenKind::Heightmap { .. } => {
+                let heights = self.heightmap_heights(xz);
+                (heights.iter().max().unwrap() - 1)
+                    .div_euclid(Chunk::SIZE as i32)
+                    .clamp(self.vertical_chunks.start - 1, self.vertical_chunks.end - 1)
+            }
+        }
+    }
+
+    /// Returns the lowest chunk y index of the column `xz`
+    /// that may contain anything else than stone.
+    pub fn min_occupied_y_chunk(&self, xz: IVec2) -> i32 {
+        let size = Chunk::SIZE as f32;
+        let dirt = self.dirt_height.max(1) as i32;
+        match &self.kind {
+            WorldGenKind::Noise => {
+                let (clip_air, clip_stone) = self.noise_clip();
+                // The noise plus the height offset stays within the base limit
+                let noise_min = if self.base_limit.start == f32::NEG_INFINITY {
+                    let (_, max) = self.noise_bounds();
+                    let t = self.base_limit.end - max;
+                    let y_stone = match t {
+                        _ if t <= 0.0 => f32::NEG_INFINITY,
+                        _ if t > 1.0 => f32::INFINITY,
+                        _ => self.height().lerp(t) - 1.0,
+                    };
+                    // Keep the dirt below the surface
+                    ((y_stone - (Chunk::SIZE as i32 - 1 + dirt) as f32) / size).ceil() as i32
+                } else {
+                    i32::MIN
+                };
+                clip_stone.max(noise_min.min(clip_air.saturating_add(1)))
+            }
+            WorldGenKind::Heightmap { .. } => {
+                let heights = self.heightmap_heights(xz);
+                (heights.iter().min().unwrap() - dirt)
+                    .div_euclid(Chunk::SIZE as i32)
+                    .clamp(self.vertical_chunks.start, self.vertical_chunks.end)
+            }
+        }
+    }
+
+    /// Returns the block of the chunk at `pos` if it is completely air or stone,
+    /// without generating it.
+    pub fn trivial_chunk(&self, pos: IVec3) -> Option<BlockId> {
+        let islands = self.floating_islands.as_ref();
+        if pos.y >= self.vertical_chunks.end {
+            Some(BlockId::AIR)
+        } else if pos.y < self.vertical_chunks.start {
+            Some(BlockId::STONE)
+        } else if pos.y > self.max_occupied_y_chunk(pos.xz())
+            && !islands.is_some_and(|i| FloatingIslands::overlaps(pos, i))
+        {
+            Some(BlockId::AIR)
+        } else if pos.y < self.min_occupied_y_chunk(pos.xz()) {
+            Some(BlockId::STONE)
+        } else {
+            None
+        }
+    }
+
+    /// Generates the chunks of the column `xz` from bottom to top together
+    /// with their chunk y index.
+    ///
+    /// The column spans the chunks within the world height that may contain
+    /// anything else than stone or air, including the floating islands.
+    pub fn generate_column(&self, xz: IVec2) -> Vec<(i32, Chunk)> {
+        let bottom = self.min_occupied_y_chunk(xz);
+        let mut top = self.max_occupied_y_chunk(xz);
+        if let Some(islands) = &self.floating_islands {
+            top = top.max((islands.max_height / Chunk::SIZE as f32).floor() as i32);
+        }
+        let top = top.min(self.vertical_chunks.end - 1);
+        (bottom..=top)
+            .map(|y| (y, generate_chunk(IVec3::new(xz.x, y, xz.y), self)))
+            .collect()
+    }
+
+    /// Chunks above the first index are air and below the second stone,
+    /// regardless of the noise.
+    fn noise_clip(&self) -> (i32, i32) {
+        (self.vertical_chunks.end - 1, self.vertical_chunks.start)
+    }
+
+    /// Returns the min and max value of the combined noise layers.
+    fn noise_bounds(&self) -> (f32, f32) {
+        self.layers
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |acc, (i, layer)| {
+                let (min, max) = layer.param.bounds();
+                let (a, b) = (layer.strength * min, layer.strength * max);
+                let v = (a.min(b), a.max(b));
+                if i == 0 {
+                    v
+                } else {
+                    layer.op.apply_bounds(acc, v)
+                }
+            })
+    }
+
+    /// Returns the terrain heights of the heightmap kind for the chunk column `xz`.
+    fn heightmap_heights(&self, xz: IVec2) -> Vec<i32> {
+        let b_pos = xz * Chunk::SIZE as i32;
+        let mut heights = Vec::with_capacity(Chunk::SIZE * Chunk::SIZE);
+        for z in 0..Chunk::SIZE as i32 {
+            for x in 0..Chunk::SIZE as i32 {
+                heights.push(self.heightmap_height(b_pos + IVec2::new(x, z)));
+            }
+        }
+        heights
+    }
+
+    /// Returns the terrain height of the heightmap kind at the block column `xz`.
+    ///
+    /// The image is centered at the origin, positions outside of it repeat its border.
+    fn heightmap_height(&self, xz: IVec2) -> i32 {
+        let WorldGenKind::Heightmap {
+            height_range,
+            world_width,
+            ..
+        } = &self.kind
+        else {
+            return 0;
+        };
+        let h = match &self.heightmap {
+            Some(image) => {
+                let scale = image.width as f32 / world_width.unwrap_or(image.width as f32);
+                let world = xz.as_vec2() + 0.5;
+                let center = Vec2::new(image.width as f32, image.height as f32) / 2.0;
+                height_range.lerp(image.sample(world * scale + center))
+            }
+            None => height_range.start,
+        };
+        h.round() as i32
+    }
+
+    /// Samples a top-down map of `resolution`² pixels over `size`² blocks
+    /// centered at the block column `center`, in rows along the x axis.
+    ///
+    /// The pixels contain the surface height or, for slices, how deep the
+    /// sample is within the terrain, being positive for solid blocks.
+    /// Neither the erosion nor the floating islands are included.
+    pub fn preview(
+        &self,
+        center: IVec2,
+        size: u32,
+        resolution: u32,
+        mode: PreviewMode,
+    ) -> Vec<f32> {
+        let layers = Layers::new(&self.layers, self.seed);
+        let height = self.height();
+        let density = |p: IVec3| {
+            let v = layers.get(p) + height.lerp_inv(p.y as _);
+            (v - self.base_limit.start).min(self.base_limit.end - v)
+        };
+        let step = size as f32 / resolution as f32;
+        let mut pixels = Vec::with_capacity((resolution * resolution) as usize);
+        for row in 0..resolution {
+            for col in 0..resolution {
+                let offset = (Vec2::new(col as f32, row as f32) + 0.5) * step - size as f32 / 2.0;
+                let xz = center + offset.floor().as_ivec2();
+                pixels.push(match (&self.kind, mode) {
+                    (WorldGenKind::Noise, PreviewMode::Surface) => {
+                        self.noise_surface(xz, density) as f32
+                    }
+                    (WorldGenKind::Noise, PreviewMode::Slice(y)) => {
+                        density(IVec3::new(xz.x, y, xz.y))
+                    }
+                    (WorldGenKind::Heightmap { .. }, PreviewMode::Surface) => {
+                        (self.heightmap_height(xz) - 1) as f32
+                    }
+                    (WorldGenKind::Heightmap { .. }, PreviewMode::Slice(y)) => {
+                        (self.heightmap_height(xz) - y) as f32
+                    }
+                });
+            }
+        }
+        pixels
+    }
+
+    /// Returns the terrain noise of the seeded `layers` at `p` including the
+    /// height offset, the block is solid if it is within the base limit.
+    fn noise_value(&self, layers: &Layers, p: IVec3) -> f32 {
+        layers.get(p) + self.height().lerp_inv(p.y as _)
+    }
+
+    /// Scans the column `xz` down for the highest solid block of the noise terrain.
+    fn noise_surface(&self, xz: IVec2, density: impl Fn(IVec3) -> f32) -> i32 {
+        let size = Chunk::SIZE as i32;
+        let bottom = self.min_occupied_y_chunk(xz).saturating_mul(size);
+        let top = (self.max_occupied_y_chunk(xz).saturating_add(1))
+            .saturating_mul(size)
+            .min(bottom.saturating_add(PREVIEW_DEPTH));
+        (bottom..top)
+            .rev()
+            .find(|&y| density(IVec3::new(xz.x, y, xz.y)) > 0.0)
+            .unwrap_or(bottom)
+    }
+
+    /// Loads the image of the heightmap kind, so that it is only read once.
+    pub fn load_heightmap(&mut self) -> Result<(), anyhow::Error> {
+        self.heightmap = match &self.kind {
+            WorldGenKind::Noise => None,
+            WorldGenKind::Heightmap { path, .. } => Some(HeightmapImage::load(path)?),
+        };
+        Ok(())
+    }
+}
+
+/// (De)serializes float ranges, storing infinite bounds as `±f32::MAX`,
+/// which json can not represent.
+mod unbounded_range {
+    use std::ops::Range;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    fn to_finite(v: f32) -> f32 {
+        v.clamp(-f32::MAX, f32::MAX)
+    }
+    fn from_finite(v: f32) -> f32 {
+        if v.abs() == f32::MAX {
+            v.signum() * f32::INFINITY
+        } else {
+            v
+        }
+    }
+
+    pub fn serialize<S: Serializer>(range: &Range<f32>, s: S) -> Result<S::Ok, S::Error> {
+        (to_finite(range.start)..to_finite(range.end)).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Range<f32>, D::Error> {
+        let range = Range::<f32>::deserialize(d)?;
+        Ok(from_finite(range.start)..from_finite(range.end))
+    }
+}
+
+/// Loads terrain presets from `.worldgen` json files.
+#[derive(Default)]
+pub struct WorldGenLoader;
+
+impl AssetLoader for WorldGenLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let mut gen: WorldGen = serde_json::from_slice(bytes).map_err(|e| {
+                let path = load_context.path().display();
+                anyhow::anyhow!("Invalid world generation file {path}: {e}")
+            })?;
+            for warning in gen.clamp_to_height() {
+                warn!("{}: {warning}", load_context.path().display());
+            }
+            load_context.set_default_asset(LoadedAsset::new(gen));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["worldgen"]
+    }
+}
+
+/// The terrain presets in the `worldgen` asset folder.
+#[derive(Resource, Default)]
+pub struct WorldGenPresets(pub Vec<Handle<WorldGen>>);
+
+pub fn load_presets(mut presets: ResMut<WorldGenPresets>, asset_server: Res<AssetServer>) {
+    match asset_server.load_folder("worldgen") {
+        Ok(handles) => presets.0 = handles.into_iter().map(|h| h.typed()).collect(),
+        Err(e) => warn!("No world generation presets loaded: {e}"),
+    }
+}
+
+/// Samples the terrain noise of a `size`² grid at `(x, 0, z) * NOISE_PREVIEW_SCALE`.
+///
+/// Returns the noise values in rows along x.
+pub fn preview_noise(gen: &WorldGen, size: u32) -> Vec<f32> {
+    let layers = Layers::new(&gen.layers, gen.seed);
+    let mut values = Vec::with_capacity((size * size) as usize);
+    for z in 0..size as i32 {
+        for x in 0..size as i32 {
+            values.push(gen.noise_value(&layers, IVec3::new(x, 0, z) * NOISE_PREVIEW_SCALE));
+        }
+    }
+    values
+}
+
+/// Colors the sampled noise `values` of the preview.
+///
+/// Returns RGBA pixels, colored from blue over green to red between the
+/// lowest and highest possible value of the noise layers.
+pub fn noise_preview_image(gen: &WorldGen, values: &[f32]) -> Vec<u8> {
+    // The height offset of the sampled layer
+    let offset = gen.height().lerp_inv(0.0);
+    let (min, max) = match gen.noise_bounds() {
+        (min, max) if min.is_finite() && max.is_finite() && min < max => (min, max),
+        _ => (-1.0, 1.0),
+    };
+    let gradient = [
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+    ];
+
+    let mut pixels = Vec::with_capacity(values.len() * 4);
+    for &v in values {
+        let t = ((v - offset - min) / (max - min)).clamp(0.0, 1.0) * 2.0;
+        let color = if t < 1.0 {
+            gradient[0].lerp(gradient[1], t)
+        } else {
+            gradient[1].lerp(gradient[2], t - 1.0)
+        };
+        let [r, g, b] = (color * 255.0).to_array().map(|c| c as u8);
+        pixels.extend([r, g, b, 255]);
+    }
+    pixels
+}
+
 /// Generate a new chunk at this position with the given noise configuration.
 pub fn generate_chunk(pos: IVec3, gen: &WorldGen) -> Chunk {
-    if pos.y > (gen.height.end / Chunk::SIZE as f32).ceil() as i32 {
-        // air
-        return Chunk::new(BlockId(0));
-    } else if pos.y < ((gen.height.start - 1.0) / Chunk::SIZE as f32).floor() as i32 {
-        // stone
-        return Chunk::new(BlockId(1));
+    if let Some(block) = gen.trivial_chunk(pos) {
+        return Chunk::new(block);
+    }
+    let mut chunk = match &gen.kind {
+        WorldGenKind::Noise => generate_noise_chunk(pos, gen),
+        WorldGenKind::Heightmap { .. } => generate_heightmap_chunk(pos, gen),
+    };
+    fill_lakes(&mut chunk, pos, gen.sea_level);
+    chunk
+}
+
+/// Fills the enclosed voids below the sea level with water.
+///
+/// Only the blocks of the chunk are known, so voids touching its border,
+/// reaching the sea level or exceeding the maximum lake size are left empty.
+/// The fluid simulation fills them from adjacent water.
+fn fill_lakes(chunk: &mut Chunk, pos: IVec3, sea_level: i32) {
+    let bottom = pos.y * Chunk::SIZE as i32;
+    if bottom >= sea_level {
+        return;
     }
+    let index = |p: UVec3| (p.x + Chunk::MAX.x * (p.y + Chunk::MAX.y * p.z)) as usize;
+    // Blocks of the voids that are not enclosed
+    let mut open = vec![false; Chunk::SIZE * Chunk::SIZE * Chunk::SIZE];
+    let mut region = Vec::with_capacity(MAX_LAKE_SIZE);
+    let mut queued = HashSet::with_capacity(MAX_LAKE_SIZE);
 
-    let mut chunk = Chunk::new(BlockId(0));
+    for_uvec3(UVec3::ZERO, Chunk::MAX, |start| {
+        if chunk[start] != BlockId::AIR
+            || open[index(start)]
+            || bottom + start.y as i32 >= sea_level
+        {
+            return;
+        }
 
+        region.clear();
+        queued.clear();
+        queued.insert(start);
+        region.push(start);
+        let mut enclosed = true;
+        let mut i = 0;
+        while i < region.len() {
+            let p = region[i];
+            i += 1;
+            if bottom + p.y as i32 >= sea_level
+                || p.cmpeq(UVec3::ZERO).any()
+                || p.cmpeq(Chunk::MAX - 1).any()
+                || open[index(p)]
+                || region.len() > MAX_LAKE_SIZE
+            {
+                enclosed = false;
+                break;
+            }
+            for d in Direction::all() {
+                let n = (p.as_ivec3() + IVec3::from(d)).as_uvec3();
+                if chunk[n] == BlockId::AIR && queued.insert(n) {
+                    region.push(n);
+                }
+            }
+        }
+
+        for &p in &region {
+            if enclosed {
+                chunk[p] = BlockId::WATER;
+            } else {
+                open[index(p)] = true;
+            }
+        }
+    });
+}
+
+fn generate_noise_chunk(pos: IVec3, gen: &WorldGen) -> Chunk {
     let b_pos = pos * Chunk::SIZE as i32;
 
-    let solid = RigedSimplex::new(&gen.base)
-        .map(|p, v| gen.base_strength * v + gen.height.lerp_inv(p.y as _));
+    // Sample the noise once per voxel, including the blocks above the chunk for the dirt pass
+    // and the margin around the chunk for the erosion
+    let above = gen.dirt_height.max(1) as u32;
+    let margin = if gen.erosion_passes > 0 {
+        EROSION_MARGIN
+    } else {
+        0
+    };
+    let start = b_pos - IVec3::new(margin, 0, margin);
+    let size = Chunk::MAX + UVec3::new(2 * margin as u32, above, 2 * margin as u32);
+    let height = gen.height();
+    let noise = Layers::new(&gen.layers, gen.seed)
+        .map(|p, v| v + height.lerp_inv(p.y as _))
+        .generate(start, size);
+    let base = |p: IVec3| gen.base_limit.contains(&noise.get(p));
+
+    let eroded = (gen.erosion_passes > 0).then(|| erode_surface(pos, gen, base));
+    fill_chunk(pos, gen, |p: IVec3| {
+        if let Some(eroded) = &eroded {
+            let local = (p - b_pos).as_uvec3();
+            match eroded[(local.x + local.z * Chunk::SIZE as u32) as usize] {
+                Some((old, new)) if (new..old).contains(&p.y) => return false,
+                Some((old, new)) if (old..new).contains(&p.y) => return true,
+                _ => {}
+            }
+        }
+        base(p)
+    })
+}
+
+/// Generates the terrain below the heights of the heightmap image.
+fn generate_heightmap_chunk(pos: IVec3, gen: &WorldGen) -> Chunk {
+    let b_pos = pos * Chunk::SIZE as i32;
+    let heights = gen.heightmap_heights(pos.xz());
+    fill_chunk(pos, gen, |p: IVec3| {
+        let local = p - b_pos;
+        p.y < heights[(local.x + local.z * Chunk::SIZE as i32) as usize]
+    })
+}
+
+/// Fills the solid blocks and floating islands with stone,
+/// and dirt and grass below their surface.
+///
+/// `solid` has to support the blocks up to the dirt height above the chunk.
+fn fill_chunk(pos: IVec3, gen: &WorldGen, terrain: impl Fn(IVec3) -> bool) -> Chunk {
+    let mut chunk = Chunk::new(BlockId::AIR);
+    let b_pos = pos * Chunk::SIZE as i32;
+
+    let islands = (gen.floating_islands.as_ref())
+        .filter(|i| FloatingIslands::overlaps(pos, i))
+        .map(|i| FloatingIslands::new(pos, gen.seed, i));
+    let island = |p: IVec3| islands.as_ref().is_some_and(|i| i.contains(p));
+    let solid = |p: IVec3| terrain(p) || island(p);
 
     for_uvec3(UVec3::ZERO, Chunk::MAX, |p| {
         let gp = p.as_ivec3() + b_pos;
 
-        if gen.base_limit.contains(&solid.get(gp)) {
-            // Dirt and grass
-            if gen.dirt_range.contains(&(gp.y as isize)) {
-                if !gen.base_limit.contains(&solid.get(gp + IVec3::Y)) {
-                    chunk[p] = BlockId(3);
+        if solid(gp) {
+            // Dirt and grass, islands are covered regardless of their height
+            if gen.dirt_range.contains(&(gp.y as isize)) || island(gp) {
+                if !solid(gp + IVec3::Y) {
+                    chunk[p] = BlockId::GRASS;
                     return;
                 } else {
                     for i in 2..=gen.dirt_height as i32 {
-                        if !gen.base_limit.contains(&solid.get(gp + i * IVec3::Y)) {
-                            chunk[p] = BlockId(2);
+                        if !solid(gp + i * IVec3::Y) {
+                            chunk[p] = BlockId::DIRT;
                             return;
                         }
                     }
@@ -97,12 +910,251 @@ pub fn generate_chunk(pos: IVec3, gen: &WorldGen) -> Chunk {
             }
 
             // Or Stone...
-            chunk[p] = BlockId(1);
+            chunk[p] = BlockId::STONE;
         }
     });
     chunk
 }
 
+/// The floating islands around a chunk.
+///
+/// The xz plane is divided into cells, each containing at most one island.
+/// The islands are placed so that they never cross their cell.
+struct FloatingIslands<'a> {
+    params: &'a FloatingIslandsParams,
+    noise: Simplex,
+    /// Center of the top and radius of the islands
+    islands: Vec<(Vec3, f32)>,
+}
+
+impl<'a> FloatingIslands<'a> {
+    /// Collects the islands of the cells overlapping the chunk at `pos`.
+    fn new(pos: IVec3, seed: u32, params: &'a FloatingIslandsParams) -> Self {
+        let r_max = params.island_radius.end.max(1.0);
+        let cell = Self::cell_size(params);
+        let b_pos = pos * Chunk::SIZE as i32;
+        let start = b_pos.xz().div_euclid(IVec2::splat(cell));
+        let end = (b_pos.xz() + Chunk::SIZE as i32 - 1).div_euclid(IVec2::splat(cell));
+
+        let mut islands = Vec::new();
+        for cz in start.y..=end.y {
+            for cx in start.x..=end.x {
+                let mut rng = chunk_rng(seed, IVec3::new(cx, 0, cz));
+                if !rng.gen_bool(params.island_density.clamp(0.0, 1.0) as f64) {
+                    continue;
+                }
+                // Keep the frayed outline within the cell
+                let jitter = r_max / 2.0;
+                let center = (IVec2::new(cx, cz) * cell).as_vec2()
+                    + cell as f32 / 2.0
+                    + Vec2::new(
+                        rng.gen_range(-jitter..=jitter),
+                        rng.gen_range(-jitter..=jitter),
+                    );
+                let top =
+                    rng.gen_range(params.min_height..=params.max_height.max(params.min_height));
+                let radius = rng.gen_range(params.island_radius.start.min(r_max)..=r_max);
+                islands.push((Vec3::new(center.x, top, center.y), radius));
+            }
+        }
+        Self {
+            params,
+            noise: Simplex::new(seed.wrapping_add(1)),
+            islands,
+        }
+    }
+
+    fn cell_size(params: &FloatingIslandsParams) -> i32 {
+        (4.0 * params.island_radius.end.max(1.0)).ceil() as i32
+    }
+
+    /// Whether the chunk at `pos` is within the height of the islands.
+    fn overlaps(pos: IVec3, params: &FloatingIslandsParams) -> bool {
+        let bottom = (pos.y * Chunk::SIZE as i32) as f32;
+        let top = bottom + Chunk::SIZE as f32;
+        bottom <= params.max_height && params.min_height < top
+    }
+
+    fn contains(&self, p: IVec3) -> bool {
+        let y = p.y as f32;
+        if y < self.params.min_height || y > self.params.max_height {
+            return false;
+        }
+        let pf = p.as_vec3() + 0.5;
+        self.islands.iter().any(|&(center, radius)| {
+            // Cone like underside below the top and a flat dome above
+            let dy = center.y - y;
+            let radius = if dy >= 0.0 {
+                radius - dy * self.params.underside_taper
+            } else {
+                radius + dy * ISLAND_DOME_SLOPE
+            };
+            if radius <= 0.0 {
+                return false;
+            }
+            let dist = pf.xz().distance(center.xz());
+            if dist > radius * (1.0 + ISLAND_ROUGHNESS) {
+                return false;
+            }
+            let fray = self
+                .noise
+                .get((pf.as_dvec3() * ISLAND_FREQUENCY).to_array()) as f32;
+            dist < radius * (1.0 + ISLAND_ROUGHNESS * fray)
+        })
+    }
+}
+
+/// Erodes the terrain surface of the chunk at `pos` and its margin.
+///
+/// Returns the previous and new surface height of each column of the chunk
+/// (in xz order) that has been changed.
+/// Only the surfaces within the chunk are changed, so that every surface is
+/// eroded by a single chunk.
+fn erode_surface(
+    pos: IVec3,
+    gen: &WorldGen,
+    base: impl Fn(IVec3) -> bool,
+) -> Vec<Option<(i32, i32)>> {
+    let b_pos = pos * Chunk::SIZE as i32;
+    let bottom = b_pos.y;
+    let top = bottom + Chunk::SIZE as i32;
+    let above = top + gen.dirt_height.max(1) as i32;
+
+    // Height of the first air block above the highest surface of each column
+    let size = Chunk::SIZE + 2 * EROSION_MARGIN as usize;
+    let mut owned = vec![false; size * size];
+    let mut map = HeightMap::new(size);
+    for z in 0..size {
+        for x in 0..size {
+            let column =
+                b_pos + IVec3::new(x as i32 - EROSION_MARGIN, 0, z as i32 - EROSION_MARGIN);
+            let mut height = bottom;
+            for y in (bottom..above).rev() {
+                if base(IVec3::new(column.x, y, column.z)) {
+                    height = y + 1;
+                    break;
+                }
+            }
+            owned[x + z * size] = height > bottom && height <= top;
+            map.heights[x + z * size] = height as f32;
+        }
+    }
+
+    let mut eroded = map.clone();
+    eroded.erode(gen, &mut chunk_rng(gen.seed, pos));
+
+    let mut columns = Vec::with_capacity(Chunk::SIZE * Chunk::SIZE);
+    for z in 0..Chunk::SIZE {
+        for x in 0..Chunk::SIZE {
+            let i = x + EROSION_MARGIN as usize + (z + EROSION_MARGIN as usize) * size;
+            let old = map.heights[i] as i32;
+            let new = (eroded.heights[i].round() as i32).clamp(bottom, top);
+            columns.push((owned[i] && old != new).then_some((old, new)));
+        }
+    }
+    columns
+}
+
+/// Heights of the terrain surface in a square area, in xz order.
+#[derive(Clone)]
+struct HeightMap {
+    size: usize,
+    heights: Vec<f32>,
+}
+
+impl HeightMap {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            heights: vec![0.0; size * size],
+        }
+    }
+
+    fn index(&self, x: usize, z: usize) -> usize {
+        x + z * self.size
+    }
+
+    /// Returns the bilinear interpolated height and gradient at `p`.
+    fn sample(&self, p: Vec2) -> (f32, Vec2) {
+        let (x, z) = (p.x as usize, p.y as usize);
+        let f = p - p.floor();
+        let h00 = self.heights[self.index(x, z)];
+        let h10 = self.heights[self.index(x + 1, z)];
+        let h01 = self.heights[self.index(x, z + 1)];
+        let h11 = self.heights[self.index(x + 1, z + 1)];
+        let height =
+            (h00 * (1.0 - f.x) + h10 * f.x) * (1.0 - f.y) + (h01 * (1.0 - f.x) + h11 * f.x) * f.y;
+        let gradient = Vec2::new(
+            (h10 - h00) * (1.0 - f.y) + (h11 - h01) * f.y,
+            (h01 - h00) * (1.0 - f.x) + (h11 - h10) * f.x,
+        );
+        (height, gradient)
+    }
+
+    /// Adds `amount` to the four heights around `p`, weighted by their distance.
+    fn add(&mut self, p: Vec2, amount: f32) {
+        let (x, z) = (p.x as usize, p.y as usize);
+        let f = p - p.floor();
+        for (dx, dz, w) in [
+            (0, 0, (1.0 - f.x) * (1.0 - f.y)),
+            (1, 0, f.x * (1.0 - f.y)),
+            (0, 1, (1.0 - f.x) * f.y),
+            (1, 1, f.x * f.y),
+        ] {
+            let i = self.index(x + dx, z + dz);
+            self.heights[i] += amount * w;
+        }
+    }
+
+    /// Simplified particle based hydraulic erosion.
+    ///
+    /// Each pass drops a water particle per two positions, which flows downhill,
+    /// erodes the steep slopes and deposits the sediment where the slope flattens.
+    fn erode(&mut self, gen: &WorldGen, rng: &mut impl Rng) {
+        let max = (self.size - 1) as f32;
+        for _ in 0..gen.erosion_passes {
+            for _ in 0..self.size * self.size / 2 {
+                let mut p = Vec2::new(rng.gen_range(0.0..max), rng.gen_range(0.0..max));
+                let mut dir = Vec2::ZERO;
+                let mut speed = 1.0;
+                let mut water = 1.0;
+                let mut sediment = 0.0;
+
+                for _ in 0..EROSION_STEPS {
+                    let (height, gradient) = self.sample(p);
+                    dir = (dir * EROSION_INERTIA - gradient * (1.0 - EROSION_INERTIA))
+                        .normalize_or_zero();
+                    let next = p + dir;
+                    if dir == Vec2::ZERO || next.min_element() < 0.0 || next.max_element() >= max {
+                        break;
+                    }
+
+                    let dh = self.sample(next).0 - height;
+                    let capacity = (-dh).max(EROSION_MIN_SLOPE) * speed * water * EROSION_CAPACITY;
+                    if dh > 0.0 || sediment > capacity {
+                        // Fill pits and deposit the excess sediment
+                        let amount = if dh > 0.0 {
+                            dh.min(sediment)
+                        } else {
+                            (sediment - capacity) * gen.deposition_rate
+                        };
+                        sediment -= amount;
+                        self.add(p, amount);
+                    } else {
+                        let amount = ((capacity - sediment) * gen.erosion_rate).min(-dh);
+                        sediment += amount;
+                        self.add(p, -amount);
+                    }
+
+                    speed = (speed * speed - dh).max(0.0).sqrt();
+                    water *= 1.0 - gen.evaporation;
+                    p = next;
+                }
+            }
+        }
+    }
+}
+
 /// 3D Noise
 trait Noise3D: Sized {
     fn get(&self, p: IVec3) -> f32;
@@ -110,13 +1162,7 @@ trait Noise3D: Sized {
     fn map<F: Fn(IVec3, f32) -> f32>(self, f: F) -> Map<Self, F> {
         Map { noise: self, f }
     }
-    fn map_mut<F: FnMut(IVec3, f32) -> f32>(self, f: F) -> MapMut<Self, F> {
-        MapMut {
-            noise: self,
-            f: RefCell::new(f),
-        }
-    }
-    fn generate(self, start: IVec3, size: usize) -> Generated {
+    fn generate(self, start: IVec3, size: UVec3) -> Generated {
         Generated::new(self, start, size)
     }
 }
@@ -128,8 +1174,8 @@ struct RigedSimplex {
 }
 
 impl RigedSimplex {
-    fn new(param: &NoiseParam) -> Self {
-        let inner = RidgedMulti::<Simplex>::new(0)
+    fn new(param: &NoiseParam, seed: u32) -> Self {
+        let inner = RidgedMulti::<Simplex>::new(seed)
             .set_octaves(param.octaves)
             .set_frequency(param.frequency as _)
             .set_lacunarity(param.lacunarity as _)
@@ -145,45 +1191,66 @@ impl Noise3D for RigedSimplex {
     }
 }
 
-/// Postprocesses the noise output with f
-struct Map<N: Noise3D, F: Fn(IVec3, f32) -> f32> {
-    noise: N,
-    f: F,
+/// The noise layers combined into a single field
+struct Layers {
+    layers: Vec<(RigedSimplex, LayerOp, f32)>,
 }
 
-impl<N: Noise3D, F: Fn(IVec3, f32) -> f32> Noise3D for Map<N, F> {
-    fn get(&self, index: IVec3) -> f32 {
-        (&self.f)(index, self.noise.get(index))
+impl Layers {
+    fn new(layers: &[NoiseLayer], seed: u32) -> Self {
+        let layers = layers
+            .iter()
+            .map(|l| {
+                let noise = RigedSimplex::new(&l.param, seed.wrapping_add(l.seed));
+                (noise, l.op, l.strength)
+            })
+            .collect();
+        Self { layers }
+    }
+}
+
+impl Noise3D for Layers {
+    /// The operation of the first layer is applied to zero.
+    fn get(&self, p: IVec3) -> f32 {
+        self.layers
+            .iter()
+            .enumerate()
+            .fold(0.0, |acc, (i, (noise, op, strength))| {
+                let v = strength * noise.get(p);
+                if i == 0 {
+                    v
+                } else {
+                    op.apply(acc, v)
+                }
+            })
     }
 }
 
 /// Postprocesses the noise output with f
-struct MapMut<N: Noise3D, F: FnMut(IVec3, f32) -> f32> {
+struct Map<N: Noise3D, F: Fn(IVec3, f32) -> f32> {
     noise: N,
-    /// Yes a little bit ugly...
-    f: RefCell<F>,
+    f: F,
 }
 
-impl<N: Noise3D, F: FnMut(IVec3, f32) -> f32> Noise3D for MapMut<N, F> {
+impl<N: Noise3D, F: Fn(IVec3, f32) -> f32> Noise3D for Map<N, F> {
     fn get(&self, index: IVec3) -> f32 {
-        (self.f.borrow_mut())(index, self.noise.get(index))
+        (self.f)(index, self.noise.get(index))
     }
 }
 
-/// Pregenerates the noise value for a 3D cube
+/// Pregenerates the noise values for a 3D box
 struct Generated {
     /// Data in yzx order
     data: Vec<f32>,
     start: IVec3,
-    size: usize,
+    size: UVec3,
 }
 
 impl Generated {
-    fn new(noise: impl Noise3D, start: IVec3, size: usize) -> Self {
-        let mut data = Vec::with_capacity(size * size * size);
-        for_uvec3(UVec3::ZERO, UVec3::splat(size as _), |p| {
-            let p = start + p.as_ivec3();
-            data.push(noise.get([p.y, p.z, p.x].into()) as _);
+    fn new(noise: impl Noise3D, start: IVec3, size: UVec3) -> Self {
+        let mut data = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for_uvec3(UVec3::ZERO, size, |p| {
+            data.push(noise.get(start + p.as_ivec3()));
         });
         Self { data, start, size }
     }
@@ -192,8 +1259,436 @@ impl Generated {
 impl Noise3D for Generated {
     fn get(&self, p: IVec3) -> f32 {
         let p = p - self.start;
-        assert!(p.min_element() >= 0 && p.max_element() < self.size as i32);
-        let i = p.y as usize + self.size * (p.z as usize + self.size * p.x as usize);
-        self.data[i]
+        assert!(p.cmpge(IVec3::ZERO).all() && p.cmplt(self.size.as_ivec3()).all());
+        let p = p.as_uvec3();
+        let i = p.y + self.size.y * (p.z + self.size.z * p.x);
+        self.data[i as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use bevy::math::Vec3Swizzles;
+    use bevy::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::{
+        fill_lakes, generate_chunk, generate_noise_chunk, noise_preview_image, preview_noise,
+        FloatingIslandsParams, HeightMap, HeightmapImage, LayerOp, Layers, Noise3D, PreviewMode,
+        RigedSimplex, WorldGen, WorldGenKind, NOISE_PREVIEW_SCALE,
+    };
+    use crate::block::BlockId;
+    use crate::chunk::Chunk;
+    use crate::util::{for_uvec3, RangeExt};
+    use crate::world::VoxelWorld;
+
+    #[test]
+    fn serialize_round_trip() {
+        let gen = WorldGen::default();
+        let json = serde_json::to_string(&gen).unwrap();
+        let loaded: WorldGen = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.base_limit, gen.base_limit);
+        assert_eq!(loaded.base_limit.start, f32::NEG_INFINITY);
+        assert_eq!(loaded.vertical_chunks, gen.vertical_chunks);
+        assert_eq!(loaded.layers.len(), gen.layers.len());
+        assert_eq!(loaded.layers[2].op, LayerOp::Multiply);
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+
+        let preset = include_str!("../assets/worldgen/default.worldgen");
+        let preset: WorldGen = serde_json::from_str(preset).unwrap();
+        assert_eq!(serde_json::to_string(&preset).unwrap(), json);
+    }
+
+    #[test]
+    fn generated_matches_noise() {
+        let noise = RigedSimplex::new(&WorldGen::default().layers[0].param, 0);
+        let start = IVec3::new(-3, 5, 7);
+        let size = UVec3::new(2, 5, 3);
+        let generated = noise.clone().generate(start, size);
+
+        for x in 0..size.x as i32 {
+            for y in 0..size.y as i32 {
+                for z in 0..size.z as i32 {
+                    let p = start + IVec3::new(x, y, z);
+                    assert_eq!(generated.get(p), noise.get(p), "{p}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn combine_layers() {
+        let mut layers = WorldGen::default().layers;
+        let p = IVec3::new(12, -7, 30);
+        let values: Vec<f32> = layers
+            .iter()
+            .map(|l| l.strength * RigedSimplex::new(&l.param, l.seed).get(p))
+            .collect();
+
+        for op in LayerOp::ALL {
+            layers[1].op = op;
+            layers[2].op = op;
+            let expected = op.apply(op.apply(values[0], values[1]), values[2]);
+            assert_eq!(Layers::new(&layers, 0).get(p), expected, "{op:?}");
+        }
+        assert_eq!(Layers::new(&layers[..1], 0).get(p), values[0]);
+    }
+
+    #[test]
+    fn erode_bowl() {
+        let mut map = HeightMap::new(16);
+        let center = Vec2::splat(7.5);
+        for z in 0..16 {
+            for x in 0..16 {
+                let d = Vec2::new(x as f32, z as f32).distance(center);
+                map.heights[x + z * 16] = d;
+            }
+        }
+        let rim = map.heights[0];
+        let bottom = map.heights[7 + 7 * 16];
+
+        let gen = WorldGen {
+            erosion_passes: 4,
+            ..Default::default()
+        };
+        map.erode(&gen, &mut StdRng::seed_from_u64(1));
+        assert!(map.heights.iter().all(|h| h.is_finite()));
+        // The slopes are eroded and the sediment is deposited at the bottom
+        assert!(map.heights[7 + 7 * 16] > bottom);
+        let highest = map.heights.iter().copied().fold(f32::MIN, f32::max);
+        assert!(highest < rim, "{highest}");
+    }
+
+    #[test]
+    fn erosion_changes_surface() {
+        let gen = WorldGen::default();
+        let eroded = WorldGen {
+            erosion_passes: 2,
+            ..gen.clone()
+        };
+        let mut changed = 0;
+        for y in -2..=2 {
+            let pos = IVec3::new(0, y, 0);
+            let (a, b) = (generate_chunk(pos, &gen), generate_chunk(pos, &eroded));
+            for_uvec3(UVec3::ZERO, Chunk::MAX, |p| {
+                changed += (a[p] != b[p]) as usize
+            });
+
+            // Erosion is deterministic
+            let c = generate_chunk(pos, &eroded);
+            for_uvec3(UVec3::ZERO, Chunk::MAX, |p| assert_eq!(b[p], c[p]));
+        }
+        assert!(changed > 0);
+    }
+
+    #[test]
+    fn heightmap_terrain() {
+        let json =
+            r#"{ "Heightmap": { "path": "h.png", "height_range": { "start": 0, "end": 32 } } }"#;
+        let kind: WorldGenKind = serde_json::from_str(json).unwrap();
+        let WorldGenKind::Heightmap { path, .. } = &kind else {
+            panic!("{kind:?}");
+        };
+        assert_eq!(path.to_str(), Some("h.png"));
+
+        // Black on the negative and white on the positive x side
+        let gen = WorldGen {
+            kind: WorldGenKind::Heightmap {
+                path: "h.png".into(),
+                height_range: 0.0..32.0,
+                world_width: Some(64.0),
+            },
+            heightmap: Some(HeightmapImage {
+                width: 2,
+                height: 1,
+                pixels: Arc::new(vec![0, 255]),
+            }),
+            ..Default::default()
+        };
+        let chunk = generate_chunk(IVec3::ZERO, &gen);
+        assert_eq!(chunk[UVec3::new(31, 31, 0)], BlockId::GRASS);
+        assert_eq!(chunk[UVec3::new(31, 30, 0)], BlockId::DIRT);
+        assert_eq!(chunk[UVec3::new(31, 0, 5)], BlockId::STONE);
+        let chunk = generate_chunk(IVec3::new(-1, 0, 0), &gen);
+        assert!((0..Chunk::SIZE as u32).all(|y| chunk[UVec3::new(0, y, 0)] == BlockId::AIR));
+
+        // The bilinear interpolation between the pixels
+        let image = gen.heightmap.unwrap();
+        assert_eq!(image.sample(Vec2::new(1.0, 0.5)), 0.5);
+        assert_eq!(image.sample(Vec2::new(-5.0, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn floating_islands() {
+        // The flat terrain is far below the islands
+        let gen = WorldGen {
+            vertical_chunks: -8..4,
+            layers: Vec::new(),
+            floating_islands: Some(FloatingIslandsParams {
+                min_height: 64.0,
+                max_height: 95.0,
+                island_density: 1.0,
+                island_radius: 8.0..8.0,
+                underside_taper: 1.0,
+            }),
+            ..Default::default()
+        };
+        for y in [1, 3] {
+            let chunk = generate_chunk(IVec3::new(0, y, 0), &gen);
+            for_uvec3(UVec3::ZERO, Chunk::MAX, |p| {
+                assert_eq!(chunk[p], BlockId::AIR, "chunk {y} {p}");
+            });
+        }
+
+        // Solid blocks per layer
+        let chunk = generate_chunk(IVec3::new(0, 2, 0), &gen);
+        let mut layers = [0; Chunk::SIZE];
+        let mut grass = 0;
+        for_uvec3(UVec3::ZERO, Chunk::MAX, |p| {
+            if chunk[p] != BlockId::AIR {
+                layers[p.y as usize] += 1;
+            }
+            grass += (chunk[p] == BlockId::GRASS) as usize;
+        });
+        assert!(grass > 0);
+
+        // Narrow underside
+        let widest = (0..Chunk::SIZE).max_by_key(|&y| layers[y]).unwrap();
+        let bottom = (0..Chunk::SIZE).find(|&y| layers[y] > 0).unwrap();
+        assert!(bottom < widest, "{layers:?}");
+        assert!(layers[bottom] * 2 < layers[widest], "{layers:?}");
+    }
+
+    #[test]
+    fn underground_lakes() {
+        let mut chunk = Chunk::new(BlockId::STONE);
+        // Enclosed void, void at the border and void above the sea level
+        for_uvec3(UVec3::splat(4), UVec3::splat(7), |p| {
+            chunk[p] = BlockId::AIR
+        });
+        for_uvec3(UVec3::new(0, 4, 10), UVec3::new(3, 7, 13), |p| {
+            chunk[p] = BlockId::AIR
+        });
+        for_uvec3(UVec3::new(10, 20, 10), UVec3::new(13, 23, 13), |p| {
+            chunk[p] = BlockId::AIR
+        });
+        fill_lakes(&mut chunk, IVec3::ZERO, 16);
+        assert_eq!(chunk[UVec3::splat(5)], BlockId::WATER);
+        assert_eq!(chunk[UVec3::new(1, 5, 11)], BlockId::AIR);
+        assert_eq!(chunk[UVec3::new(11, 21, 11)], BlockId::AIR);
+
+        // A void crossing the sea level stays empty
+        let mut chunk = Chunk::new(BlockId::STONE);
+        for_uvec3(UVec3::new(4, 10, 4), UVec3::new(7, 20, 7), |p| {
+            chunk[p] = BlockId::AIR
+        });
+        fill_lakes(&mut chunk, IVec3::ZERO, 16);
+        assert_eq!(chunk[UVec3::new(5, 10, 5)], BlockId::AIR);
+
+        // Exceeding the maximum lake size
+        let mut chunk = Chunk::new(BlockId::STONE);
+        for_uvec3(UVec3::ONE, UVec3::splat(12), |p| chunk[p] = BlockId::AIR);
+        fill_lakes(&mut chunk, IVec3::ZERO, 16);
+        assert_eq!(chunk[UVec3::splat(5)], BlockId::AIR);
+    }
+
+    #[test]
+    fn noise_bounds() {
+        let gen = WorldGen::default();
+        let (min, max) = gen.noise_bounds();
+        let layers = Layers::new(&gen.layers, gen.seed);
+        for_uvec3(UVec3::ZERO, UVec3::splat(24), |p| {
+            let v = layers.get(p.as_ivec3() * 37 - 400);
+            assert!((min..=max).contains(&v), "{v} not in {min}..{max}");
+        });
+    }
+
+    #[test]
+    fn trivial_chunks() {
+        let gen = WorldGen::default();
+        let xz = IVec2::new(3, -2);
+        assert_eq!(
+            gen.trivial_chunk(IVec3::new(xz.x, 10, xz.y)),
+            Some(BlockId::AIR)
+        );
+        assert_eq!(
+            gen.trivial_chunk(IVec3::new(xz.x, -10, xz.y)),
+            Some(BlockId::STONE)
+        );
+
+        // Chunks outside of the world are trivial regardless of the noise
+        assert_eq!(
+            gen.trivial_chunk(IVec3::new(xz.x, gen.vertical_chunks.end, xz.y)),
+            Some(BlockId::AIR)
+        );
+        assert_eq!(
+            gen.trivial_chunk(IVec3::new(xz.x, gen.vertical_chunks.start - 1, xz.y)),
+            Some(BlockId::STONE)
+        );
+
+        // The skipped chunks within the flat world would have been generated the same
+        let gen = WorldGen {
+            layers: Vec::new(),
+            ..Default::default()
+        };
+        let (min, max) = (gen.min_occupied_y_chunk(xz), gen.max_occupied_y_chunk(xz));
+        for y in [min - 1, max + 1] {
+            let pos = IVec3::new(xz.x, y, xz.y);
+            assert!(gen.vertical_chunks.contains(&y));
+            let block = gen.trivial_chunk(pos).unwrap();
+            let chunk = generate_noise_chunk(pos, &gen);
+            for_uvec3(UVec3::ZERO, Chunk::MAX, |p| {
+                assert_eq!(chunk[p], block, "{pos} {p}")
+            });
+        }
+    }
+
+    #[test]
+    fn world_height() {
+        let xz = IVec2::new(-3, 5);
+
+        // A single chunk
+        let mut gen = WorldGen {
+            vertical_chunks: 0..1,
+            sea_level: -20,
+            dirt_range: -64..64,
+            ..Default::default()
+        };
+        assert_eq!(gen.height(), 0.0..32.0);
+        assert_eq!(gen.clamp_to_height().len(), 2);
+        assert_eq!(gen.sea_level, 0);
+        assert_eq!(gen.dirt_range, 0..32);
+        assert!(gen.clamp_to_height().is_empty());
+        let ys = gen
+            .generate_column(xz)
+            .iter()
+            .map(|(y, _)| *y)
+            .collect::<Vec<_>>();
+        assert_eq!(ys, vec![0]);
+        assert_eq!(
+            gen.trivial_chunk(IVec3::new(xz.x, 1, xz.y)),
+            Some(BlockId::AIR)
+        );
+        assert_eq!(
+            gen.trivial_chunk(IVec3::new(xz.x, -1, xz.y)),
+            Some(BlockId::STONE)
+        );
+
+        // Empty worlds become one chunk high
+        gen.vertical_chunks = 3..3;
+        assert_eq!(gen.clamp_to_height().len(), 3);
+        assert_eq!(gen.vertical_chunks, 3..4);
+
+        // A very tall world stretches the terrain
+        let gen = WorldGen {
+            vertical_chunks: -64..64,
+            ..Default::default()
+        };
+        assert_eq!(gen.height(), -2048.0..2048.0);
+        let (min, max) = (gen.min_occupied_y_chunk(xz), gen.max_occupied_y_chunk(xz));
+        let default = WorldGen::default();
+        assert!(min < default.min_occupied_y_chunk(xz));
+        assert!(max > default.max_occupied_y_chunk(xz));
+        assert!(gen.vertical_chunks.start <= min && max < gen.vertical_chunks.end);
+        assert_eq!(
+            gen.trivial_chunk(IVec3::new(xz.x, 64, xz.y)),
+            Some(BlockId::AIR)
+        );
+        assert_eq!(
+            gen.trivial_chunk(IVec3::new(xz.x, -65, xz.y)),
+            Some(BlockId::STONE)
+        );
+    }
+
+    #[test]
+    fn generate_column() {
+        let gen = WorldGen::default();
+        let xz = IVec2::new(1, 2);
+        let column = gen.generate_column(xz);
+        let ys = column.iter().map(|(y, _)| *y).collect::<Vec<_>>();
+        let expected =
+            (gen.min_occupied_y_chunk(xz)..=gen.max_occupied_y_chunk(xz)).collect::<Vec<_>>();
+        assert_eq!(ys, expected);
+        for (y, chunk) in &column {
+            let generated = generate_chunk(IVec3::new(xz.x, *y, xz.y), &gen);
+            for_uvec3(UVec3::ZERO, Chunk::MAX, |p| {
+                assert_eq!(chunk[p], generated[p])
+            });
+        }
+    }
+
+    #[test]
+    fn generation_determinism() {
+        let pos = IVec3::new(3, -2, 7);
+        let a = generate_chunk(pos, &WorldGen::default());
+        let b = generate_chunk(pos, &WorldGen::default());
+        for_uvec3(UVec3::ZERO, Chunk::MAX, |p| assert_eq!(a[p], b[p], "{p}"));
+    }
+
+    #[test]
+    fn generation_boundary_no_panic() {
+        let gen = WorldGen::default();
+        // Just outside of the world height
+        let above = generate_chunk(IVec3::new(0, gen.vertical_chunks.end + 1, 0), &gen);
+        let below = generate_chunk(IVec3::new(0, gen.vertical_chunks.start - 1, 0), &gen);
+        for_uvec3(UVec3::ZERO, Chunk::MAX, |p| {
+            assert_eq!(above[p], BlockId::AIR);
+            assert_eq!(below[p], BlockId::STONE);
+        });
+        // Far away from the origin, at the edge of the block coordinates
+        let far = IVec3::new(
+            i32::MAX / Chunk::SIZE as i32,
+            0,
+            i32::MIN / Chunk::SIZE as i32,
+        );
+        generate_chunk(far, &gen);
+        gen.generate_column(far.xz());
+    }
+
+    #[test]
+    fn map_preview() {
+        let gen = WorldGen::default();
+        let xz = IVec2::new(40, -24);
+
+        // The surface matches the generated terrain
+        let surface = gen.preview(xz, 1, 1, PreviewMode::Surface)[0] as i32;
+        let column = gen.generate_column(VoxelWorld::chunk_of_block(xz.extend(0).xzy()).xz());
+        let local = VoxelWorld::local_of_block(xz.extend(0).xzy()).xz();
+        assert_eq!(VoxelWorld::find_surface_y(&column, local), Some(surface));
+
+        let slice = gen.preview(xz, 1, 1, PreviewMode::Slice(surface))[0];
+        assert!(slice > 0.0);
+        let slice = gen.preview(xz, 1, 1, PreviewMode::Slice(surface + 1))[0];
+        assert!(slice <= 0.0);
+
+        let pixels = gen.preview(xz, 64, 16, PreviewMode::Surface);
+        assert_eq!(pixels.len(), 16 * 16);
+    }
+
+    #[test]
+    fn noise_preview() {
+        let gen = WorldGen::default();
+        let values = preview_noise(&gen, 8);
+        assert_eq!(values.len(), 8 * 8);
+        let layers = Layers::new(&gen.layers, gen.seed);
+        let v = gen.noise_value(&layers, IVec3::new(3, 0, 5) * NOISE_PREVIEW_SCALE);
+        assert_eq!(values[3 + 5 * 8], v);
+
+        let pixels = noise_preview_image(&gen, &values);
+        assert_eq!(pixels.len(), 8 * 8 * 4);
+        assert!(pixels.chunks(4).all(|p| p[3] == 255));
+
+        // Pixels are colored by the noise without the height offset
+        let (min, max) = gen.noise_bounds();
+        let t = (v - gen.height().lerp_inv(0.0) - min) / (max - min);
+        let pixel = &pixels[(3 + 5 * 8) * 4..][..4];
+        if t < 0.5 {
+            assert_eq!(pixel[0], 0);
+        } else {
+            assert_eq!(pixel[2], 0);
+        }
     }
 }
@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use rand::Rng;
+
+use crate::block::{blocks, BlockId};
+use crate::chunk::Chunk;
+use crate::world::{BlockChangedEvent, ChangeCause, VoxelWorld};
+use crate::AppState;
+
+/// Interval between two grass updates in seconds.
+const TICK: f32 = 5.0;
+/// Number of random positions sampled per chunk and tick.
+const SAMPLES: usize = 64;
+/// Minimal sky light for dirt to turn into grass.
+const SPREAD_LIGHT: u8 = 9;
+/// Grass with less sky light turns back into dirt.
+const REVERT_LIGHT: u8 = 4;
+/// Probability that grass spreads to a suitable neighbor.
+const SPREAD_CHANCE: f32 = 0.1;
+
+const HORIZONTAL: [IVec3; 4] = [IVec3::NEG_X, IVec3::NEG_Z, IVec3::X, IVec3::Z];
+
+pub struct GrassSpreadPlugin;
+
+impl Plugin for GrassSpreadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GrassCovered>()
+            .insert_resource(GrassTimer(Timer::from_seconds(TICK, TimerMode::Repeating)))
+            .add_systems(
+                Update,
+                (queue_covered_grass, update_grass)
+                    .chain()
+                    .run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct GrassTimer(Timer);
+
+/// Grass blocks below changed blocks, checked for reverting on the next tick.
+#[derive(Default, Resource)]
+pub struct GrassCovered(HashSet<IVec3>);
+
+/// Remembers the grass below changed blocks.
+fn queue_covered_grass(
+    world: Res<VoxelWorld>,
+    mut events: EventReader<BlockChangedEvent>,
+    mut covered: ResMut<GrassCovered>,
+) {
+    for event in events.iter() {
+        let below = event.pos - IVec3::Y;
+        if world.get_block(below) == Some(BlockId::GRASS) {
+            covered.0.insert(below);
+        }
+    }
+}
+
+/// Spreads grass onto lit dirt and reverts covered grass back to dirt.
+fn update_grass(
+    time: Res<Time>,
+    mut timer: ResMut<GrassTimer>,
+    mut covered: ResMut<GrassCovered>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let blocks = blocks().read().unwrap();
+    let mut rng = rand::thread_rng();
+
+    for pos in covered.0.drain() {
+        if world.get_block(pos) == Some(BlockId::GRASS)
+            && world.sky_light(pos, &blocks) < REVERT_LIGHT
+        {
+            world.set_block(pos, BlockId::DIRT, ChangeCause::Simulation);
+        }
+    }
+
+    let chunks: Vec<IVec3> = world.loaded().collect();
+    for chunk in chunks {
+        for _ in 0..SAMPLES {
+            let local = IVec3::new(
+                rng.gen_range(0..Chunk::SIZE as i32),
+                rng.gen_range(0..Chunk::SIZE as i32),
+                rng.gen_range(0..Chunk::SIZE as i32),
+            );
+            let pos = chunk * Chunk::SIZE as i32 + local;
+            if world.get_block(pos) != Some(BlockId::GRASS) {
+                continue;
+            }
+
+            if world.sky_light(pos, &blocks) < REVERT_LIGHT {
+                world.set_block(pos, BlockId::DIRT, ChangeCause::Simulation);
+                continue;
+            }
+
+            for d in HORIZONTAL {
+                let p = pos + d;
+                if world.get_block(p) == Some(BlockId::DIRT)
+                    && world.sky_light(p, &blocks) >= SPREAD_LIGHT
+                    && rng.gen::<f32>() < SPREAD_CHANCE
+                {
+                    world.set_block(p, BlockId::GRASS, ChangeCause::Simulation);
+                }
+            }
+        }
+    }
+}
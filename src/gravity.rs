@@ -0,0 +1,77 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+use crate::block::{blocks, BlockId};
+use crate::world::{BlockChangedEvent, ChangeCause, VoxelWorld};
+use crate::AppState;
+
+/// Interval between two falling steps in seconds.
+const TICK: f32 = 0.05;
+
+pub struct BlockGravityPlugin;
+
+impl Plugin for BlockGravityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FallingBlocks>()
+            .insert_resource(GravityTimer(Timer::from_seconds(
+                TICK,
+                TimerMode::Repeating,
+            )))
+            .add_systems(
+                Update,
+                (activate_falling, update_falling)
+                    .chain()
+                    .run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+/// Positions of blocks with the "gravity" tag that might fall.
+#[derive(Default, Resource)]
+pub struct FallingBlocks(pub HashSet<IVec3>);
+
+#[derive(Resource)]
+struct GravityTimer(Timer);
+
+/// Checks the changed blocks and the blocks above them.
+fn activate_falling(
+    world: Res<VoxelWorld>,
+    mut events: EventReader<BlockChangedEvent>,
+    mut falling: ResMut<FallingBlocks>,
+) {
+    let blocks = blocks().read().unwrap();
+    for event in events.iter() {
+        for pos in [event.pos, event.pos + IVec3::Y] {
+            let gravity = world
+                .get_block(pos)
+                .and_then(|id| blocks.get(&id))
+                .is_some_and(|b| b.has_tag("gravity"));
+            if gravity {
+                falling.0.insert(pos);
+            }
+        }
+    }
+}
+
+/// Moves the unsupported blocks down by one.
+fn update_falling(
+    time: Res<Time>,
+    mut timer: ResMut<GravityTimer>,
+    mut falling: ResMut<FallingBlocks>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    for pos in std::mem::take(&mut falling.0) {
+        let below = pos - IVec3::Y;
+        if world.get_block(below) != Some(BlockId::AIR) {
+            continue;
+        }
+        if let Some(block) = world.set_block(pos, BlockId::AIR, ChangeCause::Simulation) {
+            // The change events activate the block at its new position
+            world.set_block(below, block, ChangeCause::Simulation);
+        }
+    }
+}
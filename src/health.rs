@@ -0,0 +1,125 @@
+use bevy::prelude::*;
+
+use crate::player::{GameMode, PlayerController, SpawnPoint};
+use crate::AppState;
+
+/// Seconds the death screen is shown before the player respawns.
+const RESPAWN_DELAY: f32 = 2.0;
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>()
+            .add_event::<PlayerDeathEvent>()
+            .add_systems(
+                Update,
+                (apply_damage, respawn)
+                    .chain()
+                    .run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+/// Deals damage to the player `entity`, sent by the fall and fire damage.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct DamageEvent {
+    pub entity: Entity,
+    pub amount: f32,
+}
+
+/// Sent when the health of the player drops to zero.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PlayerDeathEvent {
+    pub entity: Entity,
+}
+
+/// Marks a dead player until it respawns.
+#[derive(Component, Debug)]
+pub struct Dead(pub Timer);
+
+/// Reduces the health of the damaged players, only players in survival mode are vulnerable.
+fn apply_damage(
+    mut cmds: Commands,
+    mut damage: EventReader<DamageEvent>,
+    mut deaths: EventWriter<PlayerDeathEvent>,
+    mut query: Query<&mut PlayerController, Without<Dead>>,
+) {
+    for event in damage.iter() {
+        let Ok(mut controller) = query.get_mut(event.entity) else {
+            continue;
+        };
+        if controller.mode != GameMode::Survival || controller.health <= 0.0 {
+            continue;
+        }
+        controller.health = (controller.health - event.amount).max(0.0);
+        if controller.health <= 0.0 {
+            info!("The player died");
+            deaths.send(PlayerDeathEvent {
+                entity: event.entity,
+            });
+            cmds.entity(event.entity)
+                .insert(Dead(Timer::from_seconds(RESPAWN_DELAY, TimerMode::Once)));
+        }
+    }
+}
+
+/// Respawns the dead players at the spawn point with full health.
+fn respawn(
+    mut cmds: Commands,
+    time: Res<Time>,
+    spawn: Res<SpawnPoint>,
+    mut query: Query<(Entity, &mut Dead, &mut PlayerController, &mut Transform)>,
+) {
+    for (entity, mut dead, mut controller, mut transform) in &mut query {
+        if dead.0.tick(time.delta()).finished() {
+            transform.translation = spawn.0;
+            controller.health = controller.max_health;
+            cmds.entity(entity).remove::<Dead>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use super::{apply_damage, DamageEvent, Dead, PlayerDeathEvent};
+    use crate::player::{GameMode, PlayerController};
+
+    #[test]
+    fn damage_and_death() {
+        let mut app = App::new();
+        app.add_event::<DamageEvent>()
+            .add_event::<PlayerDeathEvent>()
+            .add_systems(Update, apply_damage);
+
+        let mut spawn = |mode| {
+            let controller = PlayerController {
+                mode,
+                ..Default::default()
+            };
+            app.world.spawn(controller).id()
+        };
+        let survival = spawn(GameMode::Survival);
+        let creative = spawn(GameMode::Creative);
+
+        for (entity, amount) in [(survival, 5.0), (creative, 100.0)] {
+            app.world.send_event(DamageEvent { entity, amount });
+        }
+        app.update();
+        let health = |app: &App, e| app.world.get::<PlayerController>(e).unwrap().health;
+        assert_eq!(health(&app, survival), 15.0);
+        assert_eq!(health(&app, creative), 20.0);
+
+        app.world.send_event(DamageEvent {
+            entity: survival,
+            amount: 100.0,
+        });
+        app.update();
+        assert_eq!(health(&app, survival), 0.0);
+        assert!(app.world.get::<Dead>(survival).is_some());
+        let deaths = app.world.resource::<Events<PlayerDeathEvent>>();
+        assert_eq!(deaths.len(), 1);
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::block::BlockId;
+use crate::world::{BlockChangedEvent, ChangeCause, VoxelWorld};
+use crate::AppState;
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditHistory>().add_systems(
+            Update,
+            (record_edits, undo_redo)
+                .chain()
+                .run_if(in_state(AppState::Running)),
+        );
+    }
+}
+
+/// A single reversible block change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edit {
+    pub pos: IVec3,
+    pub old: BlockId,
+    pub new: BlockId,
+}
+
+impl Edit {
+    /// Returns the edit reverting this one.
+    pub fn inverse(self) -> Self {
+        Self {
+            pos: self.pos,
+            old: self.new,
+            new: self.old,
+        }
+    }
+}
+
+/// Bounded history of the player's block edits.
+///
+/// The edits are grouped into strokes, which are undone and redone as a whole.
+/// A stroke contains all edits made while a mouse button was held.
+#[derive(Resource, Debug)]
+pub struct EditHistory {
+    /// Maximum number of strokes that can be undone
+    pub depth: usize,
+    undo: VecDeque<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    /// The stroke that is currently recorded
+    stroke: Vec<Edit>,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+impl EditHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            stroke: Vec::new(),
+        }
+    }
+
+    /// Adds an edit to the current stroke.
+    pub fn record(&mut self, edit: Edit) {
+        self.stroke.push(edit);
+    }
+
+    /// Finishes the current stroke, making it a single undo step.
+    /// New strokes discard the redo history.
+    pub fn end_stroke(&mut self) {
+        if !self.stroke.is_empty() {
+            let stroke = std::mem::take(&mut self.stroke);
+            self.push_undo(stroke);
+            self.redo.clear();
+        }
+    }
+
+    /// Returns the edits reverting the last stroke, in the order they have to be applied.
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        self.end_stroke();
+        let stroke = self.undo.pop_back()?;
+        let inverse = stroke.iter().rev().map(|e| e.inverse()).collect();
+        self.redo.push(stroke);
+        Some(inverse)
+    }
+
+    /// Returns the edits reapplying the last undone stroke.
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        self.end_stroke();
+        let stroke = self.redo.pop()?;
+        self.push_undo(stroke.clone());
+        Some(stroke)
+    }
+
+    /// Forgets all strokes, keeping the depth.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.stroke.clear();
+    }
+
+    /// Number of strokes that can be undone.
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Number of strokes that can be redone.
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    fn push_undo(&mut self, stroke: Vec<Edit>) {
+        self.undo.push_back(stroke);
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+}
+
+/// Records the player's edits, including bulk edits, and ends the stroke when no mouse button is held.
+fn record_edits(
+    mouse: Res<Input<MouseButton>>,
+    mut events: EventReader<BlockChangedEvent>,
+    mut history: ResMut<EditHistory>,
+) {
+    for event in events.iter() {
+        if matches!(event.cause, ChangeCause::Player | ChangeCause::WorldEdit) {
+            history.record(Edit {
+                pos: event.pos,
+                old: event.old,
+                new: event.new,
+            });
+        }
+    }
+    if !mouse.any_pressed([MouseButton::Left, MouseButton::Middle]) {
+        history.end_stroke();
+    }
+}
+
+/// Undo with Ctrl+Z and redo with Ctrl+Y.
+///
+/// Edits in chunks that have been unloaded in the meantime are dropped with a warning.
+fn undo_redo(
+    key: Res<Input<KeyCode>>,
+    mut history: ResMut<EditHistory>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    if !key.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+
+    let edits = if key.just_pressed(KeyCode::Z) {
+        history.undo()
+    } else if key.just_pressed(KeyCode::Y) {
+        history.redo()
+    } else {
+        None
+    };
+
+    for edit in edits.into_iter().flatten() {
+        if world
+            .set_block(edit.pos, edit.new, ChangeCause::History)
+            .is_none()
+        {
+            warn!("Dropped edit in unloaded chunk at {}", edit.pos);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{Edit, EditHistory};
+    use crate::block::BlockId;
+    use crate::world::{ChangeCause, VoxelWorld};
+
+    fn edit(x: i32, new: u8) -> Edit {
+        Edit {
+            pos: IVec3::new(x, 0, 0),
+            old: BlockId::AIR,
+            new: BlockId(new),
+        }
+    }
+
+    #[test]
+    fn grouping() {
+        let mut history = EditHistory::new(8);
+        history.record(edit(0, 1));
+        history.record(edit(1, 1));
+        history.record(edit(2, 1));
+        history.end_stroke();
+        history.record(edit(3, 2));
+        history.end_stroke();
+        // Empty strokes are ignored
+        history.end_stroke();
+        assert_eq!(history.undo_len(), 2);
+
+        assert_eq!(history.undo(), Some(vec![edit(3, 2).inverse()]));
+        assert_eq!(
+            history.undo(),
+            Some(vec![
+                edit(2, 1).inverse(),
+                edit(1, 1).inverse(),
+                edit(0, 1).inverse()
+            ])
+        );
+        assert_eq!(history.undo(), None);
+    }
+
+    #[test]
+    fn interleaved() {
+        let mut history = EditHistory::new(8);
+        history.record(edit(0, 1));
+        history.end_stroke();
+        history.record(edit(1, 1));
+        history.end_stroke();
+
+        assert_eq!(history.undo(), Some(vec![edit(1, 1).inverse()]));
+        assert_eq!(history.redo(), Some(vec![edit(1, 1)]));
+        assert_eq!(history.redo(), None);
+        assert_eq!(history.undo(), Some(vec![edit(1, 1).inverse()]));
+        assert_eq!(history.undo(), Some(vec![edit(0, 1).inverse()]));
+        assert_eq!(history.redo(), Some(vec![edit(0, 1)]));
+
+        // A new stroke discards the redo history
+        history.record(edit(2, 3));
+        history.end_stroke();
+        assert_eq!(history.redo_len(), 0);
+        assert_eq!(history.redo(), None);
+        assert_eq!(history.undo(), Some(vec![edit(2, 3).inverse()]));
+        assert_eq!(history.undo(), Some(vec![edit(0, 1).inverse()]));
+        assert_eq!(history.undo(), None);
+    }
+
+    #[test]
+    fn depth() {
+        let mut history = EditHistory::new(2);
+        for i in 0..4 {
+            history.record(edit(i, 1));
+            history.end_stroke();
+        }
+        assert_eq!(history.undo_len(), 2);
+        assert_eq!(history.undo(), Some(vec![edit(3, 1).inverse()]));
+        assert_eq!(history.undo(), Some(vec![edit(2, 1).inverse()]));
+        assert_eq!(history.undo(), None);
+    }
+
+    #[test]
+    fn restore_world() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::NEG_ONE, IVec3::ZERO);
+        let mut history = EditHistory::new(64);
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let blocks = |world: &VoxelWorld| {
+            (-2..2)
+                .flat_map(|x| (-2..2).flat_map(move |y| (-2..2).map(move |z| IVec3::new(x, y, z))))
+                .map(|p| world.get_block(p))
+                .collect::<Vec<_>>()
+        };
+
+        // Edits around the chunk corner, some overwriting previous edits
+        let apply = |world: &mut VoxelWorld, edits: Vec<Edit>| {
+            for edit in edits {
+                let old = world.set_block(edit.pos, edit.new, ChangeCause::History);
+                assert_eq!(old, Some(edit.old));
+            }
+        };
+        for _ in 0..32 {
+            let pos = IVec3::from_array([(); 3].map(|_| rng.gen_range(-2..2)));
+            let new = BlockId(rng.gen_range(1..4));
+            let old = world.set_block(pos, new, ChangeCause::Player).unwrap();
+            history.record(Edit { pos, old, new });
+            history.end_stroke();
+        }
+        let edited = blocks(&world);
+
+        while let Some(edits) = history.undo() {
+            apply(&mut world, edits);
+        }
+        assert!(blocks(&world).iter().all(|b| *b == Some(BlockId::AIR)));
+
+        while let Some(edits) = history.redo() {
+            apply(&mut world, edits);
+        }
+        assert_eq!(blocks(&world), edited);
+    }
+}
@@ -0,0 +1,329 @@
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+use crate::block::{blocks, BlockId};
+use crate::player::{GameMode, MouseLook, PlayerController, PlayerSettings};
+use crate::world::{ChangeCause, VoxelWorld};
+use crate::AppState;
+
+/// Default distance of the blocks the player can interact with.
+pub const REACH: f32 = 8.0;
+
+pub struct InteractionPlugin;
+
+impl Plugin for InteractionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Brush>()
+            .init_resource::<TargetedBlock>()
+            .add_systems(
+                Update,
+                (update_target, draw_target, select_block, interact)
+                    .chain()
+                    .run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+/// The block the player is looking at within reach, shared by all systems
+/// interacting with it.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TargetedBlock(pub Option<Target>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    /// The block that was hit
+    pub hit: IVec3,
+    /// The position in front of the face that was hit, where blocks are placed
+    pub front: IVec3,
+}
+
+/// Casts the ray of the player once per frame, before the block edits.
+pub fn update_target(
+    world: Res<VoxelWorld>,
+    settings: Res<PlayerSettings>,
+    mut target: ResMut<TargetedBlock>,
+    query: Query<&Transform, With<PlayerController>>,
+) {
+    let Ok(transform) = query.get_single() else {
+        return;
+    };
+    let new = world
+        .raycast(transform.translation, transform.forward(), settings.reach)
+        .map(|(hit, front)| Target { hit, front });
+    target.set_if_neq(TargetedBlock(new));
+}
+
+/// Outline the targeted block.
+fn draw_target(target: Res<TargetedBlock>, mut gizmos: Gizmos) {
+    if let Some(target) = target.0 {
+        gizmos.cuboid(
+            Transform::from_translation(target.hit.as_vec3() + 0.5).with_scale(Vec3::splat(1.01)),
+            Color::BLACK,
+        );
+    }
+}
+
+/// Shape of the brush around the targeted block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    Cube,
+    Sphere,
+}
+
+impl BrushShape {
+    pub const ALL: [Self; 2] = [Self::Cube, Self::Sphere];
+
+    /// Returns whether the block at `offset` from the center is within the brush.
+    pub fn contains(self, offset: IVec3, radius: i32) -> bool {
+        match self {
+            Self::Cube => offset.abs().max_element() <= radius,
+            Self::Sphere => offset.length_squared() <= radius * radius,
+        }
+    }
+}
+
+/// Edits all blocks within a radius at once instead of single blocks.
+///
+/// Breaking clears the blocks and placing fills the air with the selected
+/// block. All blocks are changed in the same frame, so that every affected
+/// chunk is only remeshed once.
+#[derive(Resource, Debug)]
+pub struct Brush {
+    pub enabled: bool,
+    pub shape: BrushShape,
+    pub radius: i32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shape: BrushShape::Sphere,
+            radius: 2,
+        }
+    }
+}
+
+impl Brush {
+    /// Replaces the blocks within the brush around `center` with `block`.
+    /// Placing only replaces air, clearing only solid blocks.
+    /// Returns the number of changed blocks.
+    pub fn apply(&self, world: &mut VoxelWorld, center: IVec3, block: BlockId) -> usize {
+        let radius = IVec3::splat(self.radius.max(0));
+        world.edit_box(
+            center - radius,
+            center + radius,
+            ChangeCause::Player,
+            |pos, old| {
+                let replace = if block == BlockId::AIR {
+                    old != BlockId::AIR
+                } else {
+                    old == BlockId::AIR
+                };
+                (replace && self.shape.contains(pos - center, self.radius)).then_some(block)
+            },
+        )
+    }
+}
+
+/// The blocks the player can choose from for placing.
+#[derive(Component, Debug, Default)]
+pub struct Hotbar {
+    pub blocks: Vec<BlockId>,
+    pub selected: usize,
+}
+
+impl Hotbar {
+    /// Creates a hotbar containing all loaded blocks except air.
+    pub fn with_all_blocks() -> Self {
+        let mut blocks: Vec<BlockId> = blocks()
+            .read()
+            .unwrap()
+            .keys()
+            .copied()
+            .filter(|b| *b != BlockId::AIR)
+            .collect();
+        blocks.sort_unstable_by_key(|b| b.0);
+        Self {
+            blocks,
+            selected: 0,
+        }
+    }
+
+    /// Returns the currently selected block.
+    pub fn selected(&self) -> Option<BlockId> {
+        self.blocks.get(self.selected).copied()
+    }
+}
+
+/// Select the hotbar slot with the number keys or the mouse wheel.
+fn select_block(
+    key: Res<Input<KeyCode>>,
+    mut wheel: EventReader<MouseWheel>,
+    mut query: Query<&mut Hotbar>,
+) {
+    const KEYS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+
+    let scroll: f32 = wheel.iter().map(|e| e.y).sum();
+    let Ok(mut hotbar) = query.get_single_mut() else {
+        return;
+    };
+    let len = hotbar.blocks.len();
+    if len == 0 {
+        return;
+    }
+
+    for (i, k) in KEYS.iter().enumerate().take(len) {
+        if key.just_pressed(*k) {
+            hotbar.selected = i;
+        }
+    }
+    if scroll != 0.0 {
+        let offset = -scroll.signum() as isize;
+        hotbar.selected = (hotbar.selected as isize + offset).rem_euclid(len as isize) as usize;
+    }
+}
+
+/// Break blocks with the left and place blocks with the middle mouse button,
+/// or the right one while the mouselook is toggled.
+///
+/// In survival mode the left button has to be held for the hardness of the block.
+/// In creative mode the enabled brush edits all blocks within its radius.
+#[allow(clippy::too_many_arguments)]
+fn interact(
+    mouse: Res<Input<MouseButton>>,
+    mouselook: Res<MouseLook>,
+    brush: Res<Brush>,
+    time: Res<Time>,
+    mut egui_context: EguiContexts,
+    target: Res<TargetedBlock>,
+    mut world: ResMut<VoxelWorld>,
+    mut mining: Local<Option<(IVec3, f32)>>,
+    query: Query<(&Hotbar, &PlayerController)>,
+) {
+    let (hotbar, controller) = query.single();
+    let breaking = match controller.mode {
+        GameMode::Creative => mouse.just_pressed(MouseButton::Left),
+        GameMode::Survival => mouse.pressed(MouseButton::Left),
+        GameMode::Spectator => return,
+    };
+    let placing = mouse.just_pressed(MouseButton::Middle)
+        || (mouselook.sticky && mouse.just_pressed(MouseButton::Right));
+    if !(breaking || placing) || egui_context.ctx_mut().is_pointer_over_area() {
+        *mining = None;
+        return;
+    }
+
+    let Some(Target { hit, front }) = target.0 else {
+        *mining = None;
+        return;
+    };
+
+    let blocks = blocks().read().unwrap();
+    if brush.enabled && controller.mode == GameMode::Creative {
+        // Multi-voxel blocks can't be painted
+        match (breaking, hotbar.selected()) {
+            (true, _) => {
+                brush.apply(&mut world, hit, BlockId::AIR);
+            }
+            (false, Some(id)) if blocks.get(&id).is_some_and(|b| !b.is_multi_voxel()) => {
+                brush.apply(&mut world, front, id);
+            }
+            _ => {}
+        }
+        return;
+    }
+
+    if breaking {
+        if controller.mode == GameMode::Survival {
+            let hardness = world
+                .get_block(hit)
+                .and_then(|b| blocks.get(&b).map(|b| b.hardness))
+                .unwrap_or_default();
+            let progress = match *mining {
+                Some((pos, progress)) if pos == hit => progress + time.delta_seconds(),
+                _ => time.delta_seconds(),
+            };
+            if progress < hardness {
+                *mining = Some((hit, progress));
+                return;
+            }
+        }
+        *mining = None;
+        world.break_block(hit, &blocks, ChangeCause::Player);
+    } else if let Some(id) = hotbar.selected() {
+        if let Some(block) = blocks.get(&id) {
+            world.place_block(front, id, block, ChangeCause::Player);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use super::{Brush, BrushShape};
+    use crate::block::BlockId;
+    use crate::world::{ChangeCause, VoxelWorld};
+
+    #[test]
+    fn brush_shapes() {
+        let count = |shape: BrushShape, radius: i32| {
+            let r = radius + 1;
+            let mut n = 0;
+            for x in -r..=r {
+                for y in -r..=r {
+                    for z in -r..=r {
+                        n += shape.contains(IVec3::new(x, y, z), radius) as usize;
+                    }
+                }
+            }
+            n
+        };
+        assert_eq!(count(BrushShape::Cube, 0), 1);
+        assert_eq!(count(BrushShape::Sphere, 0), 1);
+        assert_eq!(count(BrushShape::Cube, 2), 125);
+        assert_eq!(count(BrushShape::Sphere, 1), 7);
+        assert_eq!(count(BrushShape::Sphere, 2), 33);
+    }
+
+    #[test]
+    fn brush_across_chunks() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::NEG_ONE, IVec3::ZERO);
+        let brush = Brush {
+            enabled: true,
+            shape: BrushShape::Sphere,
+            radius: 2,
+        };
+        // Centered at the corner of eight chunks
+        assert_eq!(brush.apply(&mut world, IVec3::ZERO, BlockId::STONE), 33);
+        assert_eq!(world.get_block(IVec3::new(-2, 0, 0)), Some(BlockId::STONE));
+        assert_eq!(world.get_block(IVec3::new(-2, -1, 0)), Some(BlockId::AIR));
+        // Filling only replaces air
+        world.set_block(IVec3::new(0, 3, 0), BlockId::STONE, ChangeCause::Player);
+        assert!(brush.apply(&mut world, IVec3::new(0, 1, 0), BlockId::DIRT) > 0);
+        assert_eq!(world.get_block(IVec3::new(0, 3, 0)), Some(BlockId::STONE));
+        assert_eq!(world.get_block(IVec3::ZERO), Some(BlockId::STONE));
+        assert_eq!(world.get_block(IVec3::new(1, 2, 0)), Some(BlockId::DIRT));
+
+        let cube = Brush {
+            shape: BrushShape::Cube,
+            radius: 3,
+            ..brush
+        };
+        assert!(cube.apply(&mut world, IVec3::ZERO, BlockId::AIR) > 33);
+        assert_eq!(cube.apply(&mut world, IVec3::ZERO, BlockId::AIR), 0);
+        assert_eq!(world.get_block(IVec3::new(0, 3, 0)), Some(BlockId::AIR));
+    }
+}
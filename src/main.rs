@@ -1,119 +1,445 @@
+#[cfg(not(target_arch = "wasm32"))]
 use bevy::core_pipeline::experimental::taa::TemporalAntiAliasPlugin;
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
+use bevy::pbr::wireframe::WireframePlugin;
+use std::path::Path;
+
+use bevy::asset::{HandleId, LoadState};
 use bevy::prelude::*;
-use bevy::{asset::LoadState, pbr::DirectionalLightShadowMap};
+use bevy::render::settings::WgpuSettings;
+use bevy::render::RenderPlugin;
+use bevy::utils::HashMap;
 
+mod achievements;
+mod args;
+mod audio;
+mod bench;
 mod block;
+mod block_entity;
 mod chunk;
+mod clipboard;
+mod export;
+mod fire;
+mod fluid;
 mod generation;
+mod grass;
+mod gravity;
+mod health;
+mod history;
+mod interaction;
+mod manifest;
+mod particles;
 mod player;
+mod saves;
+mod settings;
+mod sky;
+mod storage;
 mod textures;
 mod ui;
 mod util;
 mod world;
 
+use achievements::AchievementPlugin;
+use args::Args;
+use audio::VoxelAudioPlugin;
 use bevy_egui::EguiPlugin;
-use block::{BlockId, BlockLoader};
+use block::{build_registry, BlockAsset, BlockLoader};
+use block_entity::BlockEntityPlugin;
 use chunk::Chunk;
-use generation::WorldGen;
+use clipboard::ClipboardPlugin;
+use export::ExportPlugin;
+use fire::FirePlugin;
+use fluid::FluidPlugin;
+use generation::{load_presets, WorldGen, WorldGenLoader, WorldGenPresets};
+use grass::GrassSpreadPlugin;
+use gravity::BlockGravityPlugin;
+use health::HealthPlugin;
+use history::HistoryPlugin;
+use interaction::InteractionPlugin;
+use manifest::{Manifest, ManifestLoader};
+use particles::ParticlePlugin;
 use player::PlayerMovementPlugin;
-use textures::TileTextures;
+use saves::SavesPlugin;
+use sky::SkyPlugin;
+use textures::{
+    animate_textures, apply_filtering, folder_progress, AnimationClock, FilterSettings, FolderLoad,
+    TextureAnimation, TextureAnimationLoader, TileTextures,
+};
 use ui::UIPlugin;
 use world::{ChunkCenter, WorldPlugin};
 
 use crate::block::blocks;
 
 fn main() {
-    App::new()
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{}", Args::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.bench_meshing {
+        bench::run();
+        return;
+    }
+    if args.bench_generation {
+        bench::run_generation();
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    exit_on_graphics_failure();
+
+    let mut wgpu_settings = WgpuSettings::default();
+    if let Some(backend) = args.backend {
+        wgpu_settings.backends = Some(backend.into());
+    }
+
+    let mut app = App::new();
+    app.insert_resource(args.clone())
         .init_resource::<ImageLoading>()
         .init_resource::<BlockLoading>()
         .init_resource::<BlockMat>()
         .init_resource::<WorldGen>()
-        .insert_resource(DirectionalLightShadowMap { size: 4096 })
-        .add_plugins((DefaultPlugins, TemporalAntiAliasPlugin))
-        .add_plugins(FrameTimeDiagnosticsPlugin::default())
+        .init_resource::<WorldGenPresets>()
+        .init_resource::<FilterSettings>()
+        .init_resource::<AnimationClock>()
+        .add_plugins(
+            DefaultPlugins
+                .set(AssetPlugin {
+                    asset_folder: args.assets,
+                    ..default()
+                })
+                .set(RenderPlugin { wgpu_settings })
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        // Fill the browser window on the web
+                        fit_canvas_to_parent: true,
+                        ..default()
+                    }),
+                    ..default()
+                }),
+        )
+        .add_plugins((FrameTimeDiagnosticsPlugin, WireframePlugin))
         .add_plugins(EguiPlugin)
-        .add_asset::<BlockId>()
+        .add_asset::<BlockAsset>()
         .init_asset_loader::<BlockLoader>()
+        .add_asset::<WorldGen>()
+        .init_asset_loader::<WorldGenLoader>()
+        .add_asset::<TextureAnimation>()
+        .init_asset_loader::<TextureAnimationLoader>()
+        .add_asset::<Manifest>()
+        .init_asset_loader::<ManifestLoader>()
+        .add_systems(Startup, load_presets)
         .add_state::<AppState>()
         .add_systems(OnEnter(AppState::LoadTextures), load_textures)
         .add_systems(
             Update,
             check_textures.run_if(in_state(AppState::LoadTextures)),
         )
-        .add_systems(OnExit(AppState::LoadTextures), build_textures)
         .add_systems(OnEnter(AppState::LoadBlocks), load_blocks)
         .add_systems(Update, check_blocks.run_if(in_state(AppState::LoadBlocks)))
         .add_systems(OnEnter(AppState::Running), setup)
+        .add_systems(
+            Update,
+            (
+                apply_filtering.run_if(resource_changed::<FilterSettings>()),
+                animate_textures,
+            )
+                .run_if(in_state(AppState::Running)),
+        )
         // .add_systems(OnEnter(AppState::Running), debug_gizmos)
         .add_plugins(PlayerMovementPlugin)
         .add_plugins(WorldPlugin)
-        .add_plugins(UIPlugin)
-        .run();
+        .add_plugins(SkyPlugin)
+        .add_plugins(FluidPlugin)
+        .add_plugins(FirePlugin)
+        .add_plugins(BlockEntityPlugin)
+        .add_plugins(GrassSpreadPlugin)
+        .add_plugins(BlockGravityPlugin)
+        .add_plugins(HealthPlugin)
+        .add_plugins(InteractionPlugin)
+        .add_plugins(HistoryPlugin)
+        .add_plugins(ClipboardPlugin)
+        .add_plugins(ExportPlugin)
+        .add_plugins(ParticlePlugin)
+        .add_plugins(VoxelAudioPlugin)
+        .add_plugins(AchievementPlugin)
+        .add_plugins(SavesPlugin)
+        .add_plugins(UIPlugin);
+    // WebGL2 does not support TAA
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_plugins(TemporalAntiAliasPlugin);
+    app.run();
+}
+
+/// Panic messages of a graphics surface or adapter that cannot be used.
+#[cfg(not(target_arch = "wasm32"))]
+const GRAPHICS_FAILURES: [&str; 3] = [
+    "Surface::configure",
+    "invalid surface",
+    "Unable to find a GPU",
+];
+
+/// Exits with an actionable message if the graphics can't be initialized.
+///
+/// Without this, the panic of the render thread is followed by a cascade of
+/// panics in the systems waiting for it. The running chunk saves are
+/// finished before exiting. Other panics are reported as usual.
+#[cfg(not(target_arch = "wasm32"))]
+fn exit_on_graphics_failure() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    static FAILED: AtomicBool = AtomicBool::new(false);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or_default();
+        if !GRAPHICS_FAILURES.iter().any(|f| message.contains(f)) {
+            default_hook(info);
+            return;
+        }
+        // Concurrent graphics failures are reported once, all of them wait
+        if !FAILED.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "Failed to initialize the graphics: {message}\n\n\
+                Try another backend with `--backend <vulkan|gl|dx12|metal>` \
+                or the `WGPU_BACKEND` environment variable, e.g. `WGPU_BACKEND=vulkan`.\n\
+                On Wayland, `WINIT_UNIX_BACKEND=x11` switches to X11."
+            );
+        }
+        storage::wait_for_saves(Duration::from_secs(10));
+        std::process::exit(1);
+    }));
 }
 
 /// The different asset loading states of the app.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, States)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 enum AppState {
     #[default]
     LoadTextures,
     LoadBlocks,
+    LoadSounds,
+    /// Choosing or creating the world to play
+    WorldSelect,
     Running,
+    /// Loading failed with the given message
+    Error(String),
+}
+
+impl States for AppState {
+    type Iter = std::array::IntoIter<AppState, 6>;
+
+    fn variants() -> Self::Iter {
+        [
+            AppState::LoadTextures,
+            AppState::LoadBlocks,
+            AppState::LoadSounds,
+            AppState::WorldSelect,
+            AppState::Running,
+            AppState::Error(String::new()),
+        ]
+        .into_iter()
+    }
+}
+
+/// Returns the paths of all handles that failed to load.
+fn failed_paths(handles: impl IntoIterator<Item = HandleId>, asset_server: &AssetServer) -> String {
+    handle_paths(
+        handles
+            .into_iter()
+            .filter(|h| asset_server.get_load_state(*h) == LoadState::Failed),
+        asset_server,
+    )
+}
+
+/// Returns the comma separated paths of the `handles`.
+fn handle_paths(handles: impl IntoIterator<Item = HandleId>, asset_server: &AssetServer) -> String {
+    handles
+        .into_iter()
+        .filter_map(|h| asset_server.get_handle_path(h))
+        .map(|p| p.path().display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
+/// Seconds after which assets that are still loading are reported as stuck.
+const LOAD_TIMEOUT: f32 = 30.0;
+
+/// Extensions of the files listed in the textures manifest.
+const TEXTURE_EXTENSIONS: &[&str] = &["png", "anim"];
+
 #[derive(Default, Resource)]
-struct ImageLoading(Vec<HandleUntyped>);
+struct ImageLoading {
+    /// Manifest listing the texture files, `None` once they are requested
+    manifest: Option<Handle<Manifest>>,
+    images: Vec<Handle<Image>>,
+    /// Animations are named after their texture (`water.png.anim`)
+    animations: Vec<Handle<TextureAnimation>>,
+}
 
-/// Load all block textures
+/// Load the manifest of the block textures
 fn load_textures(mut loading: ResMut<ImageLoading>, asset_server: Res<AssetServer>) {
-    loading.0 = asset_server.load_folder("textures").unwrap();
+    loading.manifest = Some(asset_server.load(Manifest::path("textures")));
 }
 
-/// Wait for the block texture loading
+/// Wait for the block texture loading and create the combined block texture atlas
+///
+/// Files that fail to load are skipped with a warning.
+#[allow(clippy::too_many_arguments)]
 fn check_textures(
     mut state: ResMut<NextState<AppState>>,
-    loading: Res<ImageLoading>,
+    mut images: ResMut<Assets<Image>>,
+    mut loading: ResMut<ImageLoading>,
     asset_server: Res<AssetServer>,
+    manifests: Res<Assets<Manifest>>,
+    animation_assets: Res<Assets<TextureAnimation>>,
+    filter: Res<FilterSettings>,
+    args: Res<Args>,
+    time: Res<Time>,
+    mut waited: Local<f32>,
 ) {
-    if let LoadState::Loaded = asset_server.get_group_load_state(loading.0.iter().map(|h| h.id())) {
-        state.set(AppState::LoadBlocks)
+    if let Some(manifest) = loading.manifest.clone() {
+        let files = manifest::folder_files(
+            "textures",
+            TEXTURE_EXTENSIONS,
+            &manifest,
+            &asset_server,
+            &manifests,
+            &args.assets,
+        );
+        match files {
+            Some(Ok(files)) => {
+                for file in files {
+                    if file.ends_with(".anim") {
+                        loading.animations.push(asset_server.load(file));
+                    } else {
+                        loading.images.push(asset_server.load(file));
+                    }
+                }
+                loading.manifest = None;
+            }
+            Some(Err(e)) => state.set(AppState::Error(e)),
+            None => {}
+        }
+        return;
     }
-}
 
-/// Create the combined block texture atlas
-fn build_textures(
-    mut images: ResMut<Assets<Image>>,
-    loading: Res<ImageLoading>,
-    asset_server: Res<AssetServer>,
-) {
-    TileTextures::build(
-        &loading
-            .0
-            .iter()
-            .map(|t| t.clone_weak().typed())
-            .collect::<Vec<_>>(),
-        &asset_server,
-        &mut images,
-    )
-    .unwrap();
+    let files = (loading.images.iter().map(|h| h.id()))
+        .chain(loading.animations.iter().map(|h| h.id()))
+        .map(|id| (id, asset_server.get_load_state(id)));
+    match folder_progress(files) {
+        FolderLoad::Done { failed, .. } => {
+            if !failed.is_empty() {
+                warn!(
+                    "Skipping the textures that failed to load: {}",
+                    handle_paths(failed, &asset_server)
+                );
+            }
+            let mut animations = HashMap::new();
+            for handle in &loading.animations {
+                let Some(path) = asset_server.get_handle_path(handle) else {
+                    continue;
+                };
+                let name = path
+                    .path()
+                    .file_stem()
+                    .map(Path::new)
+                    .and_then(Path::file_stem);
+                if let (Some(name), Some(animation)) = (name, animation_assets.get(handle)) {
+                    animations.insert(name.to_string_lossy().into_owned(), animation.clone());
+                }
+            }
+            let handles = (loading.images.iter())
+                .filter(|h| asset_server.get_load_state(*h) == LoadState::Loaded)
+                .map(Handle::clone_weak)
+                .collect::<Vec<_>>();
+            if handles.is_empty() {
+                state.set(AppState::Error(
+                    "The textures folder contains no images".into(),
+                ));
+                return;
+            }
+            match TileTextures::build(&handles, &asset_server, &mut images, &animations, &filter) {
+                Ok(()) => state.set(AppState::LoadBlocks),
+                Err(e) => state.set(AppState::Error(format!(
+                    "Failed to build the texture atlas: {e}"
+                ))),
+            }
+        }
+        FolderLoad::Loading(pending) => {
+            *waited += time.delta_seconds();
+            if *waited > LOAD_TIMEOUT {
+                state.set(AppState::Error(format!(
+                    "Loading the textures did not finish after {LOAD_TIMEOUT} seconds: {}",
+                    handle_paths(pending, &asset_server)
+                )));
+            }
+        }
+    }
 }
 
 #[derive(Default, Resource)]
-struct BlockLoading(Vec<HandleUntyped>);
+struct BlockLoading {
+    /// Manifest listing the block files, `None` once they are requested
+    manifest: Option<Handle<Manifest>>,
+    blocks: Vec<Handle<BlockAsset>>,
+}
 
-/// Load the block meshes.
+/// Load the manifest of the block meshes.
 fn load_blocks(mut loading: ResMut<BlockLoading>, asset_server: Res<AssetServer>) {
-    loading.0 = asset_server.load_folder("blocks").unwrap();
+    loading.manifest = Some(asset_server.load(Manifest::path("blocks")));
 }
 
-/// Wait for the block meshes.
+/// Wait for the block meshes and build the block registry.
 fn check_blocks(
     mut state: ResMut<NextState<AppState>>,
-    loading: Res<BlockLoading>,
+    mut loading: ResMut<BlockLoading>,
     asset_server: Res<AssetServer>,
+    manifests: Res<Assets<Manifest>>,
+    block_assets: Res<Assets<BlockAsset>>,
+    args: Res<Args>,
 ) {
-    if let LoadState::Loaded = asset_server.get_group_load_state(loading.0.iter().map(|h| h.id())) {
-        state.set(AppState::Running)
+    if let Some(manifest) = loading.manifest.clone() {
+        let files = manifest::folder_files(
+            "blocks",
+            &["block"],
+            &manifest,
+            &asset_server,
+            &manifests,
+            &args.assets,
+        );
+        match files {
+            Some(Ok(files)) => {
+                loading.blocks = files.into_iter().map(|f| asset_server.load(f)).collect();
+                loading.manifest = None;
+            }
+            Some(Err(e)) => state.set(AppState::Error(e)),
+            None => {}
+        }
+        return;
+    }
+
+    match asset_server.get_group_load_state(loading.blocks.iter().map(|h| h.id())) {
+        LoadState::Loaded => {
+            let assets = loading.blocks.iter().filter_map(|h| block_assets.get(h));
+            match build_registry(assets, TileTextures::get()) {
+                Ok(registry) => {
+                    *blocks().write().unwrap() = registry;
+                    state.set(AppState::LoadSounds);
+                }
+                Err(e) => state.set(AppState::Error(format!("Failed to load the blocks:\n{e}"))),
+            }
+        }
+        LoadState::Failed => state.set(AppState::Error(format!(
+            "Failed to load the blocks (see the log for details): {}",
+            failed_paths(loading.blocks.iter().map(|h| h.id()), &asset_server)
+        ))),
+        _ => {}
     }
 }
 
@@ -125,11 +451,14 @@ fn setup(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     loading: Res<BlockLoading>,
-    block_ids: Res<Assets<BlockId>>,
+    block_assets: Res<Assets<BlockAsset>>,
 ) {
     // The combined block material
+    let textures = TileTextures::get();
     let block_mat = materials.add(StandardMaterial {
-        base_color_texture: Some(TileTextures::get().image()),
+        base_color_texture: Some(textures.image()),
+        normal_map_texture: textures.normal_map(),
+        metallic_roughness_texture: textures.metallic_roughness(),
         metallic: 0.05,
         perceptual_roughness: 1.0,
         reflectance: 0.1,
@@ -138,23 +467,33 @@ fn setup(
     cmds.insert_resource(BlockMat(block_mat.clone()));
 
     // Spawn all available blocks
-    for (i, handle) in loading.0.iter().enumerate() {
-        let block_id = block_ids.get(&handle.typed_weak()).unwrap();
-        let blocks = blocks().read().unwrap();
-        cmds.spawn(PbrBundle {
-            mesh: meshes.add(blocks[block_id].mesh()),
-            material: block_mat.clone(),
-            transform: Transform::from_xyz(2.0 + 2.0 * i as f32, 0.0, 0.0),
-            ..default()
-        });
+    let blocks = blocks().read().unwrap();
+    for (i, handle) in loading.blocks.iter().enumerate() {
+        let Some((asset, block)) = block_assets
+            .get(handle)
+            .and_then(|asset| Some((asset, blocks.get(&asset.id)?)))
+        else {
+            continue;
+        };
+        cmds.spawn((
+            Name::new(asset.name.clone()),
+            PbrBundle {
+                mesh: meshes.add(block.mesh()),
+                material: block_mat.clone(),
+                transform: Transform::from_xyz(2.0 + 2.0 * i as f32, 0.0, 0.0),
+                ..default()
+            },
+        ));
     }
 
     // Quad displaying the generated block texture atlas
+    let mut quad = Mesh::from(shape::Quad {
+        size: Vec2::new(4.0, 4.0),
+        flip: false,
+    });
+    textures.add_tangents(&mut quad);
     cmds.spawn(PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Quad {
-            size: Vec2::new(4.0, 4.0),
-            flip: false,
-        })),
+        mesh: meshes.add(quad),
         material: block_mat,
         transform: Transform::from_xyz(0.0, 1.5, -2.0),
         ..default()
@@ -0,0 +1,175 @@
+#[cfg(not(feature = "manifest-only"))]
+use std::fs;
+#[cfg(not(feature = "manifest-only"))]
+use std::path::Path;
+
+use anyhow::anyhow;
+#[cfg(not(feature = "manifest-only"))]
+use bevy::asset::FileAssetIo;
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+
+/// List of the files in an asset folder, stored next to the folder
+/// (`textures.manifest` for `textures`).
+///
+/// Requesting the listed files works where folders can't be scanned,
+/// like packaged or web builds. The manifests are generated by the build script.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TypeUuid, TypePath)]
+#[serde(transparent)]
+#[uuid = "6b0e4d2c-9a17-4f35-b8e1-3c5d7f9a2e64"]
+pub struct Manifest {
+    /// Paths relative to the assets folder
+    pub files: Vec<String>,
+}
+
+impl Manifest {
+    /// Asset path of the manifest of `folder`.
+    pub fn path(folder: &str) -> String {
+        format!("{folder}.manifest")
+    }
+
+    /// Lists the files with one of the `extensions` in the asset `folder`
+    /// and its subfolders, sorted by path.
+    #[cfg(not(feature = "manifest-only"))]
+    pub fn scan(assets: &Path, folder: &str, extensions: &[&str]) -> Result<Self, anyhow::Error> {
+        let mut files = Vec::new();
+        let mut dirs = vec![folder.to_string()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(assets.join(&dir))? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let path = format!("{dir}/{name}");
+                if entry.file_type()?.is_dir() {
+                    dirs.push(path);
+                } else if extensions
+                    .iter()
+                    .any(|e| name.strip_suffix(e).is_some_and(|n| n.ends_with('.')))
+                {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort_unstable();
+        Ok(Self { files })
+    }
+}
+
+#[derive(Default)]
+pub struct ManifestLoader;
+
+impl AssetLoader for ManifestLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let manifest: Manifest = serde_json::from_slice(bytes).map_err(|e| {
+                let path = load_context.path().display();
+                anyhow!("Invalid manifest {path}: {e}")
+            })?;
+            load_context.set_default_asset(LoadedAsset::new(manifest));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["manifest"]
+    }
+}
+
+/// Returns the files of the asset `folder` from its loaded `manifest`,
+/// `None` while the manifest is still loading.
+///
+/// Native builds also scan the folder, so that files added since the build
+/// are loaded as well.
+pub fn folder_files(
+    folder: &str,
+    extensions: &[&str],
+    manifest: &Handle<Manifest>,
+    asset_server: &AssetServer,
+    manifests: &Assets<Manifest>,
+    assets: &str,
+) -> Option<Result<Vec<String>, String>> {
+    let manifest = match asset_server.get_load_state(manifest) {
+        LoadState::Loaded => manifests.get(manifest).cloned(),
+        LoadState::Failed | LoadState::Unloaded => None,
+        LoadState::NotLoaded | LoadState::Loading => return None,
+    };
+    let manifest = scan_folder(manifest, folder, extensions, assets);
+    Some(
+        manifest
+            .map(|m| m.files)
+            .ok_or_else(|| format!("Failed to load {}", Manifest::path(folder))),
+    )
+}
+
+#[cfg(not(feature = "manifest-only"))]
+fn scan_folder(
+    manifest: Option<Manifest>,
+    folder: &str,
+    extensions: &[&str],
+    assets: &str,
+) -> Option<Manifest> {
+    let assets = FileAssetIo::get_base_path().join(assets);
+    match Manifest::scan(&assets, folder, extensions) {
+        Ok(scanned) => {
+            if manifest.as_ref() != Some(&scanned) {
+                info!(
+                    "{} is outdated, rebuild to update it",
+                    Manifest::path(folder)
+                );
+            }
+            Some(scanned)
+        }
+        Err(e) => {
+            warn!("Failed to scan the {folder} folder: {e}");
+            manifest
+        }
+    }
+}
+
+/// Only the manifests are used, the filesystem is never accessed directly.
+#[cfg(feature = "manifest-only")]
+fn scan_folder(manifest: Option<Manifest>, _: &str, _: &[&str], _: &str) -> Option<Manifest> {
+    manifest
+}
+
+#[cfg(test)]
+mod test {
+    use super::Manifest;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest: Manifest =
+            serde_json::from_str(r#"["blocks/air.block", "blocks/dirt.block"]"#).unwrap();
+        assert_eq!(manifest.files, ["blocks/air.block", "blocks/dirt.block"]);
+        assert!(serde_json::from_str::<Manifest>(r#"{"files": []}"#).is_err());
+    }
+
+    /// The shipped manifests list all files of their folders.
+    #[cfg(not(feature = "manifest-only"))]
+    #[test]
+    fn manifests_up_to_date() {
+        let assets = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        for (folder, extensions, text) in [
+            (
+                "textures",
+                &["png", "anim"][..],
+                include_str!("../assets/textures.manifest"),
+            ),
+            (
+                "blocks",
+                &["block"],
+                include_str!("../assets/blocks.manifest"),
+            ),
+        ] {
+            let manifest: Manifest = serde_json::from_str(text).unwrap();
+            let scanned = Manifest::scan(&assets, folder, extensions).unwrap();
+            assert_eq!(manifest, scanned, "{folder}");
+        }
+    }
+}
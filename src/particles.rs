@@ -0,0 +1,120 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::block::{blocks, Block, BlockId, Cube};
+use crate::world::{BlockChangedEvent, ChangeCause};
+use crate::{AppState, BlockMat};
+
+/// Number of particles spawned for a broken block.
+const PARTICLE_COUNT: usize = 8;
+const PARTICLE_SIZE: f32 = 0.15;
+/// Lifetime of the particles in seconds.
+const PARTICLE_LIFETIME: f32 = 1.0;
+const GRAVITY: f32 = 9.81;
+
+pub struct ParticlePlugin;
+
+impl Plugin for ParticlePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ParticleMeshes>().add_systems(
+            Update,
+            (spawn_particles, update_particles).run_if(in_state(AppState::Running)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct Particle {
+    velocity: Vec3,
+    lifetime: Timer,
+}
+
+/// Particle meshes of the blocks, created on first use.
+#[derive(Default, Resource)]
+struct ParticleMeshes(HashMap<BlockId, Handle<Mesh>>);
+
+/// Spawn a burst of particles for every block destroyed by the player.
+fn spawn_particles(
+    mut cmds: Commands,
+    mut events: EventReader<BlockChangedEvent>,
+    mut particle_meshes: ResMut<ParticleMeshes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    block_mat: Res<BlockMat>,
+) {
+    for event in events.iter() {
+        if event.cause != ChangeCause::Player
+            || event.new != BlockId::AIR
+            || event.old == BlockId::AIR
+        {
+            continue;
+        }
+
+        let mesh = match particle_meshes.0.get(&event.old) {
+            Some(mesh) => mesh.clone(),
+            None => {
+                let blocks = blocks().read().unwrap();
+                let Some(mesh) = blocks.get(&event.old).and_then(particle_mesh) else {
+                    continue;
+                };
+                let mesh = meshes.add(mesh);
+                particle_meshes.0.insert(event.old, mesh.clone());
+                mesh
+            }
+        };
+
+        let center = event.pos.as_vec3() + Vec3::splat(0.5 - PARTICLE_SIZE / 2.0);
+        for i in 0..PARTICLE_COUNT {
+            // Spread the particles evenly around the block
+            let angle = i as f32 / PARTICLE_COUNT as f32 * TAU;
+            let velocity = Vec3::new(angle.cos(), 2.0 + (i % 3) as f32, angle.sin()) * 1.5;
+            cmds.spawn((
+                Particle {
+                    velocity,
+                    lifetime: Timer::from_seconds(PARTICLE_LIFETIME, TimerMode::Once),
+                },
+                PbrBundle {
+                    mesh: mesh.clone(),
+                    material: block_mat.0.clone(),
+                    transform: Transform::from_translation(center)
+                        .with_scale(Vec3::splat(PARTICLE_SIZE)),
+                    ..default()
+                },
+            ));
+        }
+    }
+}
+
+/// A small cube textured like the first face of the `block`.
+fn particle_mesh(block: &Block) -> Option<Mesh> {
+    let face = block.cubes.first()?.faces[0].clone();
+    let cube = Cube {
+        min: UVec3::ZERO,
+        max: Cube::MAX,
+        faces: [(); 6].map(|_| face.clone()),
+    };
+    Some(
+        Block {
+            cubes: vec![cube],
+            ..block.clone()
+        }
+        .mesh(),
+    )
+}
+
+/// Move the particles and despawn them at the end of their lifetime.
+fn update_particles(
+    mut cmds: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Particle, &mut Transform)>,
+) {
+    for (entity, mut particle, mut transform) in query.iter_mut() {
+        if particle.lifetime.tick(time.delta()).finished() {
+            cmds.entity(entity).despawn();
+            continue;
+        }
+        particle.velocity.y -= GRAVITY * time.delta_seconds();
+        transform.translation += particle.velocity * time.delta_seconds();
+    }
+}
@@ -1,57 +1,281 @@
-use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI, TAU};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, TAU};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
 
-use bevy::core_pipeline::experimental::taa::TemporalAntiAliasBundle;
+use anyhow::anyhow;
+
+use bevy::core_pipeline::experimental::taa::{TemporalAntiAliasBundle, TemporalAntiAliasSettings};
 use bevy::core_pipeline::fxaa::Fxaa;
 use bevy::input::mouse::MouseMotion;
-use bevy::pbr::{CascadeShadowConfigBuilder, ScreenSpaceAmbientOcclusionBundle};
+use bevy::math::Vec3Swizzles;
+use bevy::pbr::{
+    FogSettings, ScreenSpaceAmbientOcclusionBundle, ScreenSpaceAmbientOcclusionSettings,
+};
 use bevy::prelude::*;
-use bevy::render::camera::Projection;
+use bevy::render::camera::{Projection, TemporalJitter};
 use bevy::window::{CursorGrabMode, PrimaryWindow, WindowMode};
+use serde::{Deserialize, Serialize};
 
+use crate::audio::{play_at, AudioSettings, SoundBank};
+use crate::block::{blocks, Block, BlockId};
 use crate::chunk::Chunk;
-use crate::util::RangeExt;
+use crate::generation::WorldGen;
+use crate::health::{DamageEvent, Dead, PlayerDeathEvent};
+use crate::interaction::{Hotbar, REACH};
+use crate::saves::SaveDir;
+use crate::sky::Sun;
+use crate::storage::is_missing;
+use crate::util::{Direction, RangeExt};
+use crate::world::VoxelWorld;
 use crate::AppState;
 
+/// File in the world folder the player data is saved to.
+const PLAYER_FILE: &str = "player.json";
+/// Distance between the camera and the ground.
+pub const EYE_HEIGHT: f32 = 1.6;
+/// Column the player spawns and respawns in, on top of the terrain.
+/// The position itself is used if the column is empty.
+pub const SPAWN_POINT: Vec3 = Vec3::new(0.0, 0.0, 2.0);
+/// Fall height without damage.
+const SAFE_FALL: f32 = 3.0;
+/// Damage per block fallen beyond the safe fall height.
+const FALL_DAMAGE: f32 = 1.0;
+/// Factor of the movement speed in spectator mode.
+const SPECTATOR_SPEED: f32 = 3.0;
+/// Distance the camera keeps from solid blocks with soft clipping.
+const CLIP_RADIUS: f32 = 0.2;
+/// Lower corner of the survival player's body relative to the eyes.
+const BODY_MIN: Vec3 = Vec3::new(-0.3, -EYE_HEIGHT, -0.3);
+/// Upper corner of the survival player's body relative to the eyes.
+const BODY_MAX: Vec3 = Vec3::new(0.3, 0.2, 0.3);
+/// Longest distance the body moves at once, shorter than a block so that
+/// fast movements can't pass through it.
+const MAX_BODY_STEP: f32 = 0.5;
+/// Range of the near clip plane.
+const NEAR_RANGE: RangeInclusive<f32> = 0.01..=1.0;
+/// Maximum distance of the far clip plane.
+const MAX_FAR: f32 = 10_000.0;
+/// Range of the vertical field of view in degrees.
+pub const FOV_RANGE: RangeInclusive<f32> = 60.0..=120.0;
+/// Field of view in degrees added while moving fast.
+const SPRINT_FOV: f32 = 5.0;
+/// Fraction of the flying speed above which the sprint field of view is added.
+const SPRINT_THRESHOLD: f32 = 0.8;
+/// Rate at which the sprint field of view fades in and out.
+const SPRINT_FOV_RATE: f32 = 8.0;
+/// On the web all tasks run on the main thread and WebGL2 lacks some features.
+const WEB: bool = cfg!(target_arch = "wasm32");
+/// Toggles the sticky mouselook.
+const MOUSELOOK_KEY: KeyCode = KeyCode::F;
+
 pub struct PlayerMovementPlugin;
 
 impl Plugin for PlayerMovementPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<PlayerSettings>()
+            .init_resource::<MouseLook>()
             .add_systems(OnEnter(AppState::Running), setup)
             .add_systems(
                 Update,
-                (windowing, player_movement, move_lights)
+                (
+                    windowing,
+                    (apply_projection, apply_antialiasing).run_if(settings_changed),
+                    player_movement,
+                    sprint_fov,
+                    move_lights,
+                    hide_light,
+                    update_carried_light,
+                    footsteps,
+                    save_player,
+                    stop_on_death,
+                )
                     .chain()
                     .run_if(in_state(AppState::Running)),
             );
     }
 }
 
-#[derive(Default, Component)]
+/// Whether the cursor is grabbed and the mouse rotates the camera.
+#[derive(Resource, Debug, Default)]
+pub struct MouseLook {
+    pub active: bool,
+    /// Toggled by a key instead of holding the right mouse button,
+    /// which is then free for placing blocks
+    pub sticky: bool,
+    /// The browser has granted the pointer lock, only used on the web
+    pub locked: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameMode {
+    /// Flying and instant block breaking
+    #[default]
+    Creative,
+    /// Gravity and block breaking depending on the block hardness
+    Survival,
+    /// Fast flying through all geometry, without interaction or damage
+    Spectator,
+}
+
+/// Antialiasing of the camera.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AaMode {
+    Off,
+    #[cfg_attr(target_arch = "wasm32", default)]
+    Fxaa,
+    /// Temporal antialiasing, the smoothest but ghosts during fast movement
+    #[cfg_attr(not(target_arch = "wasm32"), default)]
+    Taa,
+    /// 4x multisampling, which disables the ambient occlusion
+    Msaa,
+}
+
+impl AaMode {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub const ALL: [Self; 4] = [Self::Off, Self::Fxaa, Self::Taa, Self::Msaa];
+    /// WebGL2 does not support TAA
+    #[cfg(target_arch = "wasm32")]
+    pub const ALL: [Self; 3] = [Self::Off, Self::Fxaa, Self::Msaa];
+}
+
+#[derive(Component)]
 pub struct PlayerController {
+    pub mode: GameMode,
+    /// Mode before switching to spectator mode
+    pub previous_mode: GameMode,
     pub yaw: f32,
     pub pitch: f32,
     pub time: f32,
     pub velocity: Vec3,
+    /// Distance walked since the last footstep
+    pub step_distance: f32,
+    pub health: f32,
+    pub max_health: f32,
+    /// Highest position of the current fall
+    pub fall_start: Option<f32>,
+    /// Standing on the ground, never set while flying
+    pub grounded: bool,
+    /// Light of the selected hotbar block, a child of the camera
+    pub carried_light_entity: Option<Entity>,
+    /// Field of view in degrees currently added while sprinting
+    pub fov_boost: f32,
+}
+
+impl Default for PlayerController {
+    fn default() -> Self {
+        Self {
+            mode: GameMode::default(),
+            previous_mode: GameMode::default(),
+            yaw: 0.0,
+            pitch: 0.0,
+            time: 0.0,
+            velocity: Vec3::ZERO,
+            step_distance: 0.0,
+            health: 20.0,
+            max_health: 20.0,
+            fall_start: None,
+            grounded: false,
+            carried_light_entity: None,
+            fov_boost: 0.0,
+        }
+    }
+}
+
+impl PlayerController {
+    /// Switches to spectator mode or back to the previous mode.
+    pub fn toggle_spectator(&mut self) {
+        if self.mode == GameMode::Spectator {
+            self.mode = self.previous_mode;
+        } else {
+            self.previous_mode = self.mode;
+            self.mode = GameMode::Spectator;
+        }
+    }
 }
 
 #[derive(Resource)]
 pub struct PlayerSettings {
-    pub view_distance: usize,
+    /// Chunk distance in which chunks are generated and kept loaded
+    pub simulation_distance: usize,
+    /// Chunk distance in which chunks are meshed and shown, at most the simulation distance
+    pub render_distance: usize,
+    /// Flying speed in creative and spectator mode
     pub m_speed: f32,
     pub m_acceleration: f32,
     pub m_deceleration: f32,
+    /// Rotation speed of the camera
     pub r_speed: f32,
+    /// Factor of the raw mouse movement, to adjust for the mouse DPI
+    pub mouse_sensitivity: f32,
+    /// Vertical field of view in degrees
+    pub fov_degrees: f32,
+    /// Downwards acceleration in survival mode
+    pub gravity: f32,
+    /// Height of a jump in blocks, independent of the gravity
+    pub jump_height: f32,
+    /// Walking speed in survival mode
+    pub walk_speed: f32,
+    /// Factor of the acceleration while in the air
+    pub air_control: f32,
+    /// Maximum falling speed
+    pub terminal_velocity: f32,
+    /// Maximum number of concurrent chunk generation tasks
+    pub max_generation_tasks: usize,
+    /// Maximum number of concurrent chunk mesh tasks
+    pub max_mesh_tasks: usize,
+    /// Maximum number of chunk meshes uploaded per frame
+    pub max_mesh_uploads: usize,
+    /// Animate newly meshed chunks instead of popping them in
+    pub chunk_fade_in: bool,
+    /// Chunk tasks that take longer than this many milliseconds log a warning
+    pub slow_task_threshold: f32,
+    /// Walking distance between two footstep sounds
+    pub footstep_interval: f32,
+    /// Maximum distance of the blocks the player can interact with
+    pub reach: f32,
+    /// Keeps the camera out of solid blocks while flying in creative mode
+    pub soft_clip: bool,
+    /// Seconds ahead the player position is predicted to prioritize the
+    /// generation of the chunks it moves towards, 0 to disable
+    pub generation_lookahead: f32,
+    /// Distance of the near clip plane
+    pub near: f32,
+    /// Distance of the far clip plane, `None` to include all rendered chunks
+    pub far: Option<f32>,
+    pub antialiasing: AaMode,
 }
 
 impl Default for PlayerSettings {
     fn default() -> Self {
         Self {
-            view_distance: 6,
+            simulation_distance: if WEB { 4 } else { 8 },
+            render_distance: if WEB { 3 } else { 6 },
             m_speed: 35.0,
             m_acceleration: 4.0,
             m_deceleration: 10.0,
             r_speed: 0.5,
+            mouse_sensitivity: 1.0,
+            fov_degrees: 90.0,
+            gravity: 30.0,
+            jump_height: 1.7,
+            walk_speed: 8.0,
+            air_control: 0.3,
+            terminal_velocity: 60.0,
+            max_generation_tasks: if WEB { 2 } else { 256 },
+            max_mesh_tasks: std::thread::available_parallelism()
+                .map_or(1, |n| n.get().saturating_sub(1))
+                .max(1),
+            max_mesh_uploads: 16,
+            chunk_fade_in: true,
+            slow_task_threshold: 50.0,
+            footstep_interval: 2.0,
+            reach: REACH,
+            soft_clip: true,
+            generation_lookahead: 1.5,
+            near: 0.1,
+            far: None,
+            antialiasing: AaMode::default(),
         }
     }
 }
@@ -61,43 +285,96 @@ impl Default for PlayerSettings {
 #[derive(Default, Component)]
 struct PlayerLight;
 
+/// Position the player spawns and respawns at, found when entering the world.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SpawnPoint(pub Vec3);
+
+impl SpawnPoint {
+    /// Places the [`SPAWN_POINT`] on top of the generated terrain.
+    fn find(gen: &WorldGen) -> Self {
+        let block = SPAWN_POINT.floor().as_ivec3();
+        let column = gen.generate_column(VoxelWorld::chunk_of_block(block).xz());
+        match VoxelWorld::find_surface_y(&column, VoxelWorld::local_of_block(block).xz()) {
+            Some(y) => Self(Vec3::new(
+                SPAWN_POINT.x,
+                y as f32 + 1.0 + EYE_HEIGHT,
+                SPAWN_POINT.z,
+            )),
+            None => Self(SPAWN_POINT),
+        }
+    }
+}
+
+/// The player data saved in the world folder.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PlayerSave {
+    mode: GameMode,
+}
+
+impl PlayerSave {
+    fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow!("Invalid player data {}: {e}", path.display())),
+            Err(e) if is_missing(&e) => Ok(Self::default()),
+            Err(e) => Err(anyhow!("Failed to read {}: {e}", path.display())),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text).map_err(|e| anyhow!("Failed to write {}: {e}", path.display()))
+    }
+}
+
 /// Create the player
-fn setup(mut cmds: Commands) {
-    cmds.spawn((
+fn setup(mut cmds: Commands, dir: Res<SaveDir>, gen: Res<WorldGen>, settings: Res<PlayerSettings>) {
+    let save = PlayerSave::load(&dir.0.join(PLAYER_FILE)).unwrap_or_else(|e| {
+        error!("{e}");
+        PlayerSave::default()
+    });
+    let spawn = SpawnPoint::find(&gen);
+    cmds.insert_resource(spawn);
+    let (near, far) = settings.clip_planes();
+
+    let mut camera = cmds.spawn((
         Camera3dBundle {
             projection: Projection::Perspective(PerspectiveProjection {
-                fov: PI / 2.0,
+                fov: settings.fov(0.0),
+                near,
+                far,
                 ..default()
             }),
-            transform: Transform::from_xyz(0.0, 0.0, 2.0).looking_at(Vec3::ZERO, Vec3::Y),
+            transform: Transform::from_translation(spawn.0).looking_at(spawn.0 - Vec3::Z, Vec3::Y),
             ..default()
         },
-        PlayerController::default(),
-        Fxaa::default(),
-    ))
-    .insert(ScreenSpaceAmbientOcclusionBundle::default())
-    .insert(TemporalAntiAliasBundle::default());
-
-    // directional 'sun' light
-    const HALF_SIZE: f32 = Chunk::SIZE as f32 * 8.0;
-    cmds.spawn(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            shadows_enabled: true,
+        PlayerController {
+            mode: save.mode,
             ..default()
         },
-        cascade_shadow_config: CascadeShadowConfigBuilder {
-            first_cascade_far_bound: 8.0,
-            maximum_distance: HALF_SIZE,
-            overlap_proportion: 0.4,
-            ..default()
-        }
-        .into(),
-        transform: Transform {
-            rotation: Quat::from_euler(EulerRot::YXZ, FRAC_PI_4, -FRAC_PI_4, 0.0),
+        Hotbar::with_all_blocks(),
+        FogSettings::default(),
+    ));
+    // WebGL2 lacks the compute shaders of the ambient occlusion
+    if !WEB {
+        camera.insert(ScreenSpaceAmbientOcclusionBundle::default());
+    }
+
+    // directional 'sun' light, the shadows are configured by the sky
+    cmds.spawn((
+        Sun,
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
+                ..default()
+            },
+            transform: Transform {
+                rotation: Quat::from_euler(EulerRot::YXZ, FRAC_PI_4, -FRAC_PI_4, 0.0),
+                ..default()
+            },
             ..default()
         },
-        ..default()
-    });
+    ));
 
     cmds.spawn((
         PlayerLight,
@@ -115,24 +392,170 @@ fn setup(mut cmds: Commands) {
     ));
 }
 
+impl PlayerSettings {
+    /// Initial vertical speed of a jump reaching the jump height.
+    pub fn jump_speed(&self) -> f32 {
+        (2.0 * self.gravity.max(0.0) * self.jump_height.max(0.0)).sqrt()
+    }
+
+    /// Near and far clip plane distances, clamped to valid ranges.
+    ///
+    /// By default, the far plane reaches the farthest corner of the rendered chunks.
+    pub fn clip_planes(&self) -> (f32, f32) {
+        let near = self.near.clamp(*NEAR_RANGE.start(), *NEAR_RANGE.end());
+        let far = self.far.unwrap_or_else(|| {
+            (self.render_distance + 1) as f32 * Chunk::SIZE as f32 * 3.0f32.sqrt()
+        });
+        (near, far.clamp(near + 1.0, MAX_FAR))
+    }
+
+    /// Vertical field of view in radians with the additional `boost` in degrees.
+    pub fn fov(&self, boost: f32) -> f32 {
+        let fov = self.fov_degrees.clamp(*FOV_RANGE.start(), *FOV_RANGE.end());
+        (fov + boost).to_radians()
+    }
+}
+
+/// Whether the settings have to be applied, because they changed or the
+/// camera has been spawned.
+fn settings_changed(
+    settings: Res<PlayerSettings>,
+    added: Query<(), Added<PlayerController>>,
+) -> bool {
+    settings.is_changed() || !added.is_empty()
+}
+
+/// Applies the clip planes and field of view of the settings to the camera.
+fn apply_projection(
+    settings: Res<PlayerSettings>,
+    mut query: Query<(&mut Projection, &PlayerController)>,
+) {
+    let (near, far) = settings.clip_planes();
+    for (mut projection, controller) in &mut query {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.near = near;
+            perspective.far = far;
+            perspective.fov = settings.fov(controller.fov_boost);
+        }
+    }
+}
+
+/// Returns the sprint field of view after `delta` seconds, fading towards
+/// [`SPRINT_FOV`] while moving faster than the threshold and back to zero otherwise.
+fn sprint_boost(boost: f32, speed: f32, max_speed: f32, delta: f32) -> f32 {
+    let target = if speed > SPRINT_THRESHOLD * max_speed {
+        SPRINT_FOV
+    } else {
+        0.0
+    };
+    let boost = (boost..target).lerp((delta * SPRINT_FOV_RATE).min(1.0));
+    // Snap to the target instead of approaching it forever
+    if (boost - target).abs() < 0.01 {
+        target
+    } else {
+        boost
+    }
+}
+
+/// Widens the field of view while moving fast.
+fn sprint_fov(
+    time: Res<Time>,
+    settings: Res<PlayerSettings>,
+    mut query: Query<(&mut Projection, &mut PlayerController)>,
+) {
+    for (mut projection, mut controller) in &mut query {
+        let speed = controller.velocity.length();
+        let boost = sprint_boost(
+            controller.fov_boost,
+            speed,
+            settings.m_speed,
+            time.delta_seconds(),
+        );
+        if boost == controller.fov_boost {
+            continue;
+        }
+        controller.fov_boost = boost;
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = settings.fov(boost);
+        }
+    }
+}
+
+/// Adds the components of the selected antialiasing to the camera and
+/// removes the others.
+///
+/// The ambient occlusion and TAA don't support multisampling.
+fn apply_antialiasing(
+    mut cmds: Commands,
+    settings: Res<PlayerSettings>,
+    mut msaa: ResMut<Msaa>,
+    query: Query<
+        (
+            Entity,
+            Has<Fxaa>,
+            Has<TemporalAntiAliasSettings>,
+            Has<ScreenSpaceAmbientOcclusionSettings>,
+        ),
+        With<PlayerController>,
+    >,
+) {
+    let mode = settings.antialiasing;
+    for (entity, fxaa, taa, ssao) in &query {
+        let mut camera = cmds.entity(entity);
+        if mode == AaMode::Fxaa && !fxaa {
+            camera.insert(Fxaa::default());
+        } else if mode != AaMode::Fxaa && fxaa {
+            camera.remove::<Fxaa>();
+        }
+        if mode == AaMode::Taa && !taa {
+            camera.insert(TemporalAntiAliasBundle::default());
+        } else if mode != AaMode::Taa && taa {
+            // The jitter would shake the image without the accumulation
+            camera.remove::<(TemporalAntiAliasSettings, TemporalJitter)>();
+        }
+        if mode == AaMode::Msaa && ssao {
+            camera.remove::<ScreenSpaceAmbientOcclusionSettings>();
+        } else if mode != AaMode::Msaa && !ssao && !WEB {
+            camera.insert(ScreenSpaceAmbientOcclusionSettings::default());
+        }
+    }
+    msaa.set_if_neq(if mode == AaMode::Msaa {
+        Msaa::Sample4
+    } else {
+        Msaa::Off
+    });
+}
+
 /// Handle player movement and rotation
+///
+/// In creative mode the player flies, in survival mode gravity pulls the player
+/// onto the ground, space jumps and falls deal damage.
+/// In spectator mode the player flies faster and through all blocks, in creative
+/// mode optionally only through air.
+#[allow(clippy::too_many_arguments)]
 fn player_movement(
     key: Res<Input<KeyCode>>,
-    mouse: Res<Input<MouseButton>>,
+    mouselook: Res<MouseLook>,
     mut mouse_move: EventReader<MouseMotion>,
     time: Res<Time>,
     settings: Res<PlayerSettings>,
-    mut query: Query<(&mut Transform, &mut PlayerController)>,
+    world: Res<VoxelWorld>,
+    gen: Res<WorldGen>,
+    mut damage: EventWriter<DamageEvent>,
+    mut query: Query<(Entity, &mut Transform, &mut PlayerController), Without<Dead>>,
 ) {
-    let (mut transform, mut movement) = query.single_mut();
+    let Ok((entity, mut transform, mut movement)) = query.get_single_mut() else {
+        return;
+    };
 
     // Rotate the player via the mouse move event
-    if mouse.pressed(MouseButton::Right) {
+    if mouselook.active {
         if let Some(rotation) = mouse_move.iter().map(|m| m.delta).reduce(|a, e| a + e) {
-            let new_pitch = (movement.pitch + rotation.y * time.delta_seconds() * settings.r_speed)
-                .clamp(-FRAC_PI_2, FRAC_PI_2);
-            let new_yaw =
-                (movement.yaw + rotation.x * time.delta_seconds() * settings.r_speed) % TAU;
+            let (new_pitch, new_yaw) = look_angles(
+                movement.pitch,
+                movement.yaw,
+                rotation * settings.mouse_sensitivity * time.delta_seconds() * settings.r_speed,
+            );
 
             movement.pitch = new_pitch;
             movement.yaw = new_yaw;
@@ -142,10 +565,22 @@ fn player_movement(
         }
     }
 
+    let flying = movement.mode != GameMode::Survival;
+    let speed = match movement.mode {
+        GameMode::Creative => settings.m_speed,
+        GameMode::Survival => settings.walk_speed,
+        GameMode::Spectator => settings.m_speed * SPECTATOR_SPEED,
+    };
+
     // Get the movement direction from the user input
     let dir = Vec3::new(
         key.pressed(KeyCode::D) as i32 as f32 - key.pressed(KeyCode::A) as i32 as f32,
-        key.pressed(KeyCode::Space) as i32 as f32 - key.pressed(KeyCode::ShiftLeft) as i32 as f32,
+        if flying {
+            key.pressed(KeyCode::Space) as i32 as f32
+                - key.pressed(KeyCode::ShiftLeft) as i32 as f32
+        } else {
+            0.0
+        },
         key.pressed(KeyCode::S) as i32 as f32 - key.pressed(KeyCode::W) as i32 as f32,
     )
     .clamp_length_max(1.0);
@@ -168,16 +603,245 @@ fn player_movement(
     };
 
     // Update the new player position
-    if actively_moving || movement.velocity.length_squared() > f32::EPSILON {
-        let velocity = movement.velocity.lerp(
-            Quat::from_axis_angle(-Vec3::Y, movement.yaw) * dir * settings.m_speed,
-            time.delta_seconds() * boost,
+    if flying {
+        movement.grounded = false;
+        movement.fall_start = None;
+        let soft_clip = settings.soft_clip && movement.mode == GameMode::Creative;
+        let blocks = blocks().read().unwrap();
+        let solid = |p: IVec3| {
+            soft_clip
+                && world
+                    .get_block(p)
+                    .is_some_and(|b| blocks.get(&b).is_some_and(|b| b.opaque))
+        };
+        if actively_moving || movement.velocity.length_squared() > f32::EPSILON {
+            let velocity = movement.velocity.lerp(
+                Quat::from_axis_angle(-Vec3::Y, movement.yaw) * dir * speed,
+                time.delta_seconds() * boost,
+            );
+            transform.translation = clip_movement(
+                transform.translation,
+                velocity * time.delta_seconds(),
+                solid,
+            );
+            movement.velocity = velocity;
+        }
+        // Blocks may have been placed at or generated around the camera
+        if let Some(pos) = push_out(transform.translation, solid) {
+            transform.translation = pos;
+        }
+    } else {
+        let blocks = blocks().read().unwrap();
+        // Returns `None` for unloaded chunks within the world height,
+        // above and below it the chunks are never loaded
+        let solid = |pos: IVec3| {
+            let Some(block) = world.get_block(pos) else {
+                let y = VoxelWorld::chunk_of_block(pos).y;
+                let loaded = gen.loaded_chunks();
+                return (!loaded.contains(&y)).then_some(y < *loaded.start());
+            };
+            Some(blocks.get(&block).is_some_and(|b| b.opaque))
+        };
+        // Wait for the chunks below to be loaded
+        let Some(below) = body_collides(transform.translation - Vec3::Y * 0.01, &solid) else {
+            return;
+        };
+
+        let control = if movement.grounded {
+            1.0
+        } else {
+            settings.air_control
+        };
+        let mut velocity = movement.velocity.lerp(
+            Quat::from_axis_angle(-Vec3::Y, movement.yaw) * dir * speed,
+            (time.delta_seconds() * boost * control).min(1.0),
         );
-        transform.translation += velocity * time.delta_seconds();
+        velocity.y = if below && movement.velocity.y <= 0.0 {
+            if key.pressed(KeyCode::Space) {
+                settings.jump_speed()
+            } else {
+                0.0
+            }
+        } else {
+            (movement.velocity.y - settings.gravity * time.delta_seconds())
+                .max(-settings.terminal_velocity)
+        };
+        // Unloaded chunks block the movement until they have been generated
+        let Some((next, blocked)) = move_body(
+            transform.translation,
+            velocity * time.delta_seconds(),
+            &solid,
+        ) else {
+            movement.velocity = Vec3::ZERO;
+            return;
+        };
+        transform.translation = next;
+        // Stop at the walls, floors and ceilings that were hit
+        for axis in 0..3 {
+            if blocked[axis] {
+                velocity[axis] = 0.0;
+            }
+        }
+
+        // Track the highest point of the fall and deal damage when landing
+        let y = transform.translation.y;
+        if velocity.y != 0.0 {
+            movement.fall_start = Some(movement.fall_start.map_or(y, |start| start.max(y)));
+        } else if let Some(start) = movement.fall_start.take() {
+            let amount = (start - y - SAFE_FALL).max(0.0) * FALL_DAMAGE;
+            if amount > 0.0 {
+                damage.send(DamageEvent { entity, amount });
+            }
+        }
+        movement.grounded = velocity.y == 0.0;
         movement.velocity = velocity;
     }
 }
 
+/// Returns the pitch and yaw after rotating by `delta` (yaw, pitch) radians.
+///
+/// The pitch is clamped to straight up or down and the yaw wraps around within
+/// one non-negative turn, also when turning left from zero.
+fn look_angles(pitch: f32, yaw: f32, delta: Vec2) -> (f32, f32) {
+    let pitch = (pitch + delta.y).clamp(-FRAC_PI_2, FRAC_PI_2);
+    let yaw = (yaw + delta.x).rem_euclid(TAU);
+    debug_assert!(pitch.is_finite() && yaw.is_finite());
+    (pitch, yaw)
+}
+
+/// Moves `pos` by `delta` one axis at a time, skipping the axes that would
+/// bring it closer than [`CLIP_RADIUS`] to a `solid` block.
+///
+/// Skipping single axes lets the camera slide along walls.
+fn clip_movement(pos: Vec3, delta: Vec3, solid: impl Fn(IVec3) -> bool) -> Vec3 {
+    let mut pos = pos;
+    for axis in 0..3 {
+        if delta[axis] == 0.0 {
+            continue;
+        }
+        let mut next = pos;
+        next[axis] += delta[axis];
+        let mut probe = next;
+        probe[axis] += delta[axis].signum() * CLIP_RADIUS;
+        if !solid(probe.floor().as_ivec3()) {
+            pos = next;
+        }
+    }
+    pos
+}
+
+/// Returns if the survival player's body with the eyes at `pos` overlaps a
+/// `solid` block, or `None` if it overlaps an unloaded one.
+fn body_collides(pos: Vec3, solid: &impl Fn(IVec3) -> Option<bool>) -> Option<bool> {
+    // Touching a face is no overlap
+    let min = (pos + BODY_MIN + 1e-4).floor().as_ivec3();
+    let max = (pos + BODY_MAX - 1e-4).floor().as_ivec3();
+    let mut collides = false;
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                collides |= solid(IVec3::new(x, y, z))?;
+            }
+        }
+    }
+    Some(collides)
+}
+
+/// Moves the survival player's eyes at `pos` by `delta` one axis at a time,
+/// stopping the axes that hit `solid` blocks at their faces.
+///
+/// Long movements are split into steps of [`MAX_BODY_STEP`].
+/// Returns the new position and the blocked axes, or `None` if the body
+/// would enter an unloaded block.
+fn move_body(
+    pos: Vec3,
+    delta: Vec3,
+    solid: &impl Fn(IVec3) -> Option<bool>,
+) -> Option<(Vec3, [bool; 3])> {
+    let steps = (delta.abs().max_element() / MAX_BODY_STEP).ceil().max(1.0);
+    let step = delta / steps;
+    let mut pos = pos;
+    let mut blocked = [false; 3];
+    for _ in 0..steps as u32 {
+        for axis in 0..3 {
+            if step[axis] == 0.0 || blocked[axis] {
+                continue;
+            }
+            let mut next = pos;
+            next[axis] += step[axis];
+            if body_collides(next, solid)? {
+                // Touch the face of the block that was hit
+                next[axis] = if step[axis] > 0.0 {
+                    (next[axis] + BODY_MAX[axis] - 1e-4).floor() - BODY_MAX[axis]
+                } else {
+                    (next[axis] + BODY_MIN[axis] + 1e-4).floor() + 1.0 - BODY_MIN[axis]
+                };
+                blocked[axis] = true;
+                if body_collides(next, solid)? {
+                    continue;
+                }
+            }
+            pos = next;
+        }
+    }
+    Some((pos, blocked))
+}
+
+/// Returns the closest position outside of the `solid` block containing `pos`,
+/// through one of its free neighbors, or `None` if `pos` is not inside a solid block.
+fn push_out(pos: Vec3, solid: impl Fn(IVec3) -> bool) -> Option<Vec3> {
+    let block = pos.floor().as_ivec3();
+    if !solid(block) {
+        return None;
+    }
+    let local = pos - block.as_vec3();
+    Direction::all()
+        .into_iter()
+        .filter(|&d| !solid(block + IVec3::from(d)))
+        .map(|d| {
+            let dir = Vec3::from(d);
+            let along = local.dot(dir.abs());
+            let dist = if dir.max_element() > 0.0 {
+                1.0 - along
+            } else {
+                along
+            };
+            (dist, dir)
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(dist, dir)| pos + dir * (dist + CLIP_RADIUS))
+}
+
+/// Stop the movement of the players that died.
+fn stop_on_death(
+    mut deaths: EventReader<PlayerDeathEvent>,
+    mut query: Query<&mut PlayerController>,
+) {
+    for event in deaths.iter() {
+        if let Ok(mut controller) = query.get_mut(event.entity) {
+            controller.velocity = Vec3::ZERO;
+            controller.time = 0.0;
+            controller.step_distance = 0.0;
+            controller.fall_start = None;
+        }
+    }
+}
+
+/// Save the player data when the game mode changes.
+fn save_player(
+    dir: Res<SaveDir>,
+    mut saved: Local<Option<GameMode>>,
+    query: Query<&PlayerController>,
+) {
+    let mode = query.single().mode;
+    if saved.replace(mode).is_some_and(|m| m != mode) {
+        let save = PlayerSave { mode };
+        if let Err(e) = save.save(&dir.0.join(PLAYER_FILE)) {
+            error!("{e}");
+        }
+    }
+}
+
 // Maybe only update directional light pos when entering new chunk?
 fn move_lights(
     player: Query<&Transform, (With<PlayerController>, Changed<GlobalTransform>)>,
@@ -190,30 +854,380 @@ fn move_lights(
     }
 }
 
+/// Hide the player light in spectator mode.
+fn hide_light(
+    player: Query<&PlayerController, Changed<PlayerController>>,
+    mut lights: Query<&mut Visibility, With<PlayerLight>>,
+) {
+    if let Ok(controller) = player.get_single() {
+        let visibility = if controller.mode == GameMode::Spectator {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        for mut v in &mut lights {
+            v.set_if_neq(visibility);
+        }
+    }
+}
+
 /// Update the window on mouse lock / fullscreen
 fn windowing(
     key: Res<Input<KeyCode>>,
     mouse: Res<Input<MouseButton>>,
+    mut mouselook: ResMut<MouseLook>,
     mut windows: Query<&mut Window, With<PrimaryWindow>>,
 ) {
     let mut window = windows.single_mut();
 
-    if mouse.just_pressed(MouseButton::Right) {
-        window.cursor.visible = false;
-        window.cursor.grab_mode = CursorGrabMode::Locked;
-    }
-    if mouse.just_released(MouseButton::Right) {
-        window.cursor.visible = true;
-        window.cursor.grab_mode = CursorGrabMode::None;
+    // Either toggle the mouselook or hold the right mouse button
+    let active = if key.just_pressed(MOUSELOOK_KEY) {
+        mouselook.sticky = !mouselook.sticky;
+        mouselook.sticky
+    } else if mouselook.sticky {
+        !key.just_pressed(KeyCode::Escape) && !pointer_lock_lost(&mut mouselook)
+    } else {
+        mouse.pressed(MouseButton::Right)
+    };
+    mouselook.sticky &= active;
+    if active != mouselook.active {
+        mouselook.active = active;
+        mouselook.locked = false;
+        window.cursor.visible = !active;
+        window.cursor.grab_mode = if active {
+            CursorGrabMode::Locked
+        } else {
+            CursorGrabMode::None
+        };
     }
 
     if key.just_pressed(KeyCode::F11)
         || (key.pressed(KeyCode::AltLeft) && key.just_pressed(KeyCode::Return))
     {
-        window.mode = if window.mode != WindowMode::Fullscreen {
-            WindowMode::Fullscreen
-        } else {
-            WindowMode::Windowed
+        window.mode = next_window_mode(window.mode);
+    }
+}
+
+/// Returns the next mode of the fullscreen toggle.
+///
+/// Cycles windowed → borderless → exclusive fullscreen, as the exclusive
+/// fullscreen has issues with multiple monitors and window switching.
+pub fn next_window_mode(mode: WindowMode) -> WindowMode {
+    match mode {
+        WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+        WindowMode::BorderlessFullscreen => WindowMode::Fullscreen,
+        WindowMode::Fullscreen | WindowMode::SizedFullscreen => WindowMode::Windowed,
+    }
+}
+
+/// Returns whether the browser released the pointer lock, which it does on
+/// escape without passing the key on.
+#[cfg(target_arch = "wasm32")]
+fn pointer_lock_lost(mouselook: &mut MouseLook) -> bool {
+    let locked = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.pointer_lock_element())
+        .is_some();
+    // The lock is granted asynchronously after the request
+    let lost = mouselook.locked && !locked;
+    mouselook.locked = locked;
+    lost
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pointer_lock_lost(_: &mut MouseLook) -> bool {
+    false
+}
+
+/// Returns the light of the `block` when held in the hotbar, if it emits light.
+fn carried_light(block: Option<&Block>) -> Option<PointLight> {
+    let block = block.filter(|b| b.light_emission > 0)?;
+    Some(PointLight {
+        intensity: 800.0 * block.light_emission as f32 / 15.0,
+        color: block.emissive,
+        range: block.light_emission as f32,
+        shadows_enabled: false,
+        ..default()
+    })
+}
+
+/// Replaces the carried light when the selected hotbar block changes.
+fn update_carried_light(
+    mut cmds: Commands,
+    mut query: Query<(Entity, &Hotbar, &mut PlayerController), Changed<Hotbar>>,
+) {
+    let blocks = blocks().read().unwrap();
+    for (camera, hotbar, mut controller) in &mut query {
+        if let Some(light) = controller.carried_light_entity.take() {
+            cmds.entity(light).despawn_recursive();
+        }
+        let block = hotbar.selected().and_then(|id| blocks.get(&id));
+        if let Some(point_light) = carried_light(block) {
+            let light = cmds
+                .spawn(PointLightBundle {
+                    // Held in the right hand
+                    transform: Transform::from_xyz(0.4, -0.3, -0.5),
+                    point_light,
+                    ..default()
+                })
+                .set_parent(camera)
+                .id();
+            controller.carried_light_entity = Some(light);
+        }
+    }
+}
+
+/// Play a footstep sound for the block below the player after each step.
+fn footsteps(
+    mut cmds: Commands,
+    time: Res<Time>,
+    settings: Res<PlayerSettings>,
+    world: Res<VoxelWorld>,
+    bank: Res<SoundBank>,
+    audio: Res<AudioSettings>,
+    mut query: Query<(&Transform, &mut PlayerController)>,
+) {
+    let (transform, mut controller) = query.single_mut();
+    let ground = transform.translation.floor().as_ivec3() - IVec3::Y;
+    let Some(block) = world.get_block(ground).filter(|b| *b != BlockId::AIR) else {
+        controller.step_distance = 0.0;
+        return;
+    };
+
+    let velocity = Vec2::new(controller.velocity.x, controller.velocity.z);
+    controller.step_distance += velocity.length() * time.delta_seconds();
+    if controller.step_distance < settings.footstep_interval {
+        return;
+    }
+    controller.step_distance = 0.0;
+
+    let blocks = blocks().read().unwrap();
+    if let Some(sound) = blocks.get(&block).and_then(|b| bank.footstep_sound(b)) {
+        let pos = ground.as_vec3() + Vec3::new(0.5, 1.0, 0.5);
+        play_at(&mut cmds, sound.clone(), &audio, transform, pos);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use std::f32::consts::{FRAC_PI_2, TAU};
+
+    use bevy::core_pipeline::experimental::taa::TemporalAntiAliasSettings;
+    use bevy::core_pipeline::fxaa::Fxaa;
+    use bevy::pbr::ScreenSpaceAmbientOcclusionSettings;
+    use bevy::window::WindowMode;
+
+    use super::{
+        apply_antialiasing, body_collides, carried_light, clip_movement, look_angles, move_body,
+        next_window_mode, push_out, settings_changed, sprint_boost, AaMode, PlayerController,
+        PlayerSettings, BODY_MAX, EYE_HEIGHT, SPRINT_FOV,
+    };
+    use crate::block::Block;
+
+    #[test]
+    fn carried_lights() {
+        let stone = Block::default();
+        assert!(carried_light(None).is_none());
+        assert!(carried_light(Some(&stone)).is_none());
+
+        let torch = Block {
+            light_emission: 15,
+            emissive: Color::ORANGE,
+            ..default()
+        };
+        let light = carried_light(Some(&torch)).unwrap();
+        assert_eq!(light.color, Color::ORANGE);
+        assert_eq!(light.range, 15.0);
+        let dim = Block {
+            light_emission: 5,
+            ..torch
         };
+        assert!(carried_light(Some(&dim)).unwrap().intensity < light.intensity);
+    }
+
+    #[test]
+    fn soft_clip() {
+        // A wall at x = 2 and the block at the origin
+        let solid = |p: IVec3| p.x == 2 || p == IVec3::ZERO;
+
+        // Slides along the wall without entering it
+        let pos = clip_movement(Vec3::new(1.5, 0.5, 0.5), Vec3::new(1.0, 0.0, 1.0), solid);
+        assert_eq!(pos, Vec3::new(1.5, 0.5, 1.5));
+        let pos = clip_movement(Vec3::new(1.5, 0.5, 0.5), Vec3::new(-0.25, 0.0, 0.0), solid);
+        assert_eq!(pos, Vec3::new(1.25, 0.5, 0.5));
+        let pos = clip_movement(Vec3::new(1.5, 0.5, 0.5), Vec3::new(-0.5, 0.0, 0.0), solid);
+        assert_eq!(pos, Vec3::new(1.5, 0.5, 0.5));
+
+        // Pushed out of the block through the closest free side
+        assert_eq!(push_out(Vec3::new(1.5, 0.5, 0.5), solid), None);
+        let pos = push_out(Vec3::new(0.5, 0.9, 0.5), solid).unwrap();
+        assert!(!solid(pos.floor().as_ivec3()));
+        assert!((pos.y - 1.2).abs() < 1e-6 && pos.x == 0.5 && pos.z == 0.5);
+        // Surrounded by solid blocks, stays in place
+        assert_eq!(push_out(Vec3::new(2.5, 0.5, 0.5), |_| true), None);
+    }
+
+    #[test]
+    fn body_collisions() {
+        // A wall at x = 2 and a thin floor at y = -1
+        let solid = |p: IVec3| Some(p.x == 2 || p.y == -1);
+        let eyes = Vec3::new(0.5, EYE_HEIGHT, 0.5);
+
+        // Walks up to the wall and slides along it
+        let (pos, blocked) = move_body(eyes, Vec3::new(3.0, 0.0, 1.0), &solid).unwrap();
+        assert!((pos.x - (2.0 - BODY_MAX.x)).abs() < 1e-4, "{pos}");
+        assert!((pos.z - 1.5).abs() < 1e-4, "{pos}");
+        assert_eq!(blocked, [true, false, false]);
+
+        // Fast falls land on the floor instead of passing through it
+        let high = eyes + Vec3::Y * 20.0;
+        let (pos, blocked) = move_body(high, Vec3::new(0.0, -40.0, 0.0), &solid).unwrap();
+        assert!((pos.y - EYE_HEIGHT).abs() < 1e-4, "{pos}");
+        assert_eq!(blocked, [false, true, false]);
+        assert_eq!(body_collides(pos, &solid), Some(false));
+        assert_eq!(body_collides(pos - Vec3::Y * 0.01, &solid), Some(true));
+
+        // Unloaded blocks stop the movement
+        let unloaded = |p: IVec3| (p.x < 2).then_some(false);
+        assert_eq!(move_body(eyes, Vec3::new(3.0, 0.0, 0.0), &unloaded), None);
+    }
+
+    #[test]
+    fn clip_planes() {
+        let settings = PlayerSettings {
+            render_distance: 3,
+            ..default()
+        };
+        let (near, far) = settings.clip_planes();
+        assert_eq!(near, 0.1);
+        assert!(far > 4.0 * 32.0 * 1.7 && far < 4.0 * 32.0 * 1.8);
+
+        let settings = PlayerSettings {
+            near: -1.0,
+            far: Some(0.0),
+            ..default()
+        };
+        let (near, far) = settings.clip_planes();
+        assert_eq!(near, 0.01);
+        assert!((far - 1.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn look_angle_ranges() {
+        assert!((-0.1f32).rem_euclid(TAU) > 0.0);
+
+        let (pitch, yaw) = look_angles(0.0, 0.0, Vec2::new(-0.1, 10.0));
+        assert_eq!(pitch, FRAC_PI_2);
+        assert!((yaw - (TAU - 0.1)).abs() < 1e-6);
+
+        let (pitch, yaw) = look_angles(-1.0, 6.0, Vec2::new(100.0, -100.0));
+        assert_eq!(pitch, -FRAC_PI_2);
+        assert!((0.0..TAU).contains(&yaw));
+    }
+
+    #[test]
+    fn antialiasing_modes() {
+        let mut app = App::new();
+        app.init_resource::<PlayerSettings>()
+            .insert_resource(Msaa::Sample4)
+            .add_systems(Update, apply_antialiasing);
+        let camera = app
+            .world
+            .spawn((
+                PlayerController::default(),
+                ScreenSpaceAmbientOcclusionSettings::default(),
+            ))
+            .id();
+
+        for mode in AaMode::ALL {
+            app.world.resource_mut::<PlayerSettings>().antialiasing = mode;
+            app.update();
+            let entity = app.world.entity(camera);
+            assert_eq!(entity.contains::<Fxaa>(), mode == AaMode::Fxaa, "{mode:?}");
+            assert_eq!(
+                entity.contains::<TemporalAntiAliasSettings>(),
+                mode == AaMode::Taa,
+                "{mode:?}"
+            );
+            assert_eq!(
+                entity.contains::<ScreenSpaceAmbientOcclusionSettings>(),
+                mode != AaMode::Msaa,
+                "{mode:?}"
+            );
+            let msaa = *app.world.resource::<Msaa>();
+            assert_eq!(msaa == Msaa::Sample4, mode == AaMode::Msaa, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn antialiasing_on_spawn() {
+        let mut app = App::new();
+        app.insert_resource(PlayerSettings {
+            antialiasing: AaMode::Fxaa,
+            ..default()
+        })
+        .insert_resource(Msaa::Sample4)
+        .add_systems(Update, apply_antialiasing.run_if(settings_changed));
+        // The settings are unchanged when the world is opened later
+        app.update();
+        app.update();
+
+        let camera = app.world.spawn(PlayerController::default()).id();
+        app.update();
+        assert!(app.world.entity(camera).contains::<Fxaa>());
+        assert!(*app.world.resource::<Msaa>() == Msaa::Off);
+    }
+
+    #[test]
+    fn sprint_fov() {
+        let settings = PlayerSettings::default();
+        assert!((settings.fov(0.0) - FRAC_PI_2).abs() < 1e-6);
+        assert!((settings.fov(SPRINT_FOV) - 95f32.to_radians()).abs() < 1e-6);
+        let settings = PlayerSettings {
+            fov_degrees: 10.0,
+            ..default()
+        };
+        assert!((settings.fov(0.0) - 60f32.to_radians()).abs() < 1e-6);
+
+        // Fades in smoothly above 80% of the speed and settles at the boost
+        let mut boost = 0.0;
+        let mut steps = 0;
+        while boost < SPRINT_FOV {
+            let next = sprint_boost(boost, 30.0, 35.0, 1.0 / 60.0);
+            assert!(next > boost && next - boost < 1.0);
+            boost = next;
+            steps += 1;
+        }
+        assert!(steps > 5 && steps < 120, "{steps}");
+        assert_eq!(sprint_boost(boost, 30.0, 35.0, 1.0 / 60.0), SPRINT_FOV);
+
+        // And fades out when slowing down
+        let next = sprint_boost(boost, 20.0, 35.0, 1.0 / 60.0);
+        assert!(next < boost && next > 0.0);
+        assert_eq!(sprint_boost(next, 0.0, 35.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn window_mode_cycle() {
+        let mut mode = WindowMode::Windowed;
+        let mut modes = Vec::new();
+        for _ in 0..3 {
+            mode = next_window_mode(mode);
+            modes.push(mode);
+        }
+        assert_eq!(
+            modes,
+            [
+                WindowMode::BorderlessFullscreen,
+                WindowMode::Fullscreen,
+                WindowMode::Windowed
+            ]
+        );
+        assert_eq!(
+            next_window_mode(WindowMode::SizedFullscreen),
+            WindowMode::Windowed
+        );
     }
 }
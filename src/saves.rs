@@ -0,0 +1,293 @@
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use serde::{Deserialize, Serialize};
+
+use crate::achievements::Achievements;
+use crate::block::blocks;
+use crate::chunk::Chunk;
+use crate::fire::{FireActive, FireLights};
+use crate::fluid::FluidActive;
+use crate::generation::{WorldGen, MAX_VERTICAL_CHUNKS};
+use crate::grass::GrassCovered;
+use crate::gravity::FallingBlocks;
+use crate::history::EditHistory;
+use crate::storage::{is_missing, queue_saves, ChunkStorage, PendingSaves};
+use crate::world::VoxelWorld;
+use crate::AppState;
+
+/// Folder in the save path containing one folder per world.
+const WORLDS_DIR: &str = "worlds";
+/// File in the world folder with the world settings.
+const WORLD_FILE: &str = "world.json";
+/// Version of the world format, worlds with a newer version are not opened.
+///
+/// 2: The world height is stored in chunks instead of blocks
+/// 3: The generation uses a portable random number generator, chunks that
+///    are generated afterwards may not line up with older ones
+const WORLD_VERSION: u32 = 3;
+/// Maximum length of a world name.
+const MAX_NAME_LEN: usize = 64;
+
+pub struct SavesPlugin;
+
+impl Plugin for SavesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnExit(AppState::Running), close_world);
+    }
+}
+
+/// Folder of the opened world, containing its settings, chunks and player data.
+#[derive(Resource, Debug, Clone)]
+pub struct SaveDir(pub PathBuf);
+
+/// The settings of a world, stored in its folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldInfo {
+    pub version: u32,
+    pub gen: WorldGen,
+}
+
+/// Returns if `name` can be used as folder name of a world.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.trim() == name
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+}
+
+/// Returns the folder of the world `name` in the save path `root`.
+pub fn world_dir(root: &Path, name: &str) -> PathBuf {
+    root.join(WORLDS_DIR).join(name)
+}
+
+/// Returns the sorted names of the worlds in the save path `root`.
+pub fn list_worlds(root: &Path) -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(root.join(WORLDS_DIR)) {
+        Ok(entries) => entries,
+        Err(e) if is_missing(&e) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.path().join(WORLD_FILE).is_file() {
+            if let Some(name) = entry.file_name().to_str().filter(|n| valid_name(n)) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort_unstable();
+    Ok(names)
+}
+
+/// Creates a new world with the generation settings `gen` and returns its folder.
+pub fn create_world(root: &Path, name: &str, gen: WorldGen) -> Result<PathBuf, anyhow::Error> {
+    if !valid_name(name) {
+        return Err(anyhow!(
+            "Invalid world name '{name}', use letters, digits, spaces, '-' and '_'"
+        ));
+    }
+    let dir = world_dir(root, name);
+    if dir.exists() {
+        return Err(anyhow!("The world '{name}' already exists"));
+    }
+    fs::create_dir_all(&dir)?;
+    write_world(&dir, gen)?;
+    Ok(dir)
+}
+
+/// Writes the settings of the world in `dir`, with the current format version.
+pub fn write_world(dir: &Path, gen: WorldGen) -> Result<(), anyhow::Error> {
+    let info = WorldInfo {
+        version: WORLD_VERSION,
+        gen,
+    };
+    fs::write(dir.join(WORLD_FILE), serde_json::to_string_pretty(&info)?)?;
+    Ok(())
+}
+
+/// Reads the settings of the world in `dir`.
+///
+/// Fails for worlds saved with a newer format version, so that they are never overwritten.
+pub fn read_world(dir: &Path) -> Result<WorldInfo, anyhow::Error> {
+    let path = dir.join(WORLD_FILE);
+    let text =
+        fs::read_to_string(&path).map_err(|e| anyhow!("Failed to read {}: {e}", path.display()))?;
+
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+    let Version { version } = serde_json::from_str(&text)
+        .map_err(|e| anyhow!("Invalid world file {}: {e}", path.display()))?;
+    if version > WORLD_VERSION {
+        return Err(anyhow!(
+            "The world was saved with the newer format version {version}, \
+             this version supports up to {WORLD_VERSION}"
+        ));
+    }
+    let mut info: serde_json::Value = serde_json::from_str(&text)?;
+    if version < 2 {
+        if let Some(gen) = info.get_mut("gen").and_then(|g| g.as_object_mut()) {
+            migrate_height(gen);
+        }
+    }
+    serde_json::from_value(info).map_err(|e| anyhow!("Invalid world file {}: {e}", path.display()))
+}
+
+/// Converts the block height range of version 1 into the chunks containing it.
+fn migrate_height(gen: &mut serde_json::Map<String, serde_json::Value>) {
+    let Some(height) = gen.remove("height") else {
+        return;
+    };
+    let Ok(height) = serde_json::from_value::<Range<f32>>(height) else {
+        return;
+    };
+    let size = Chunk::SIZE as f32;
+    let max = MAX_VERTICAL_CHUNKS as f32;
+    let start = (height.start / size).floor().clamp(-max, max) as i32;
+    let end = (height.end / size).ceil().clamp(-max, max) as i32;
+    gen.entry("vertical_chunks")
+        .or_insert_with(|| serde_json::json!({ "start": start, "end": end }));
+}
+
+pub fn delete_world(root: &Path, name: &str) -> Result<(), anyhow::Error> {
+    if !valid_name(name) {
+        return Err(anyhow!("Invalid world name '{name}'"));
+    }
+    fs::remove_dir_all(world_dir(root, name))?;
+    Ok(())
+}
+
+/// Prepares the resources of the world in `dir`.
+///
+/// The world is shown after switching to the running state.
+pub fn open_world(cmds: &mut Commands, dir: PathBuf) -> Result<(), anyhow::Error> {
+    let gen = read_world(&dir)?.gen;
+    insert_world(cmds, gen, dir)
+}
+
+/// Prepares the resources of a world that is never saved, for the web
+/// build without filesystem.
+#[cfg(target_arch = "wasm32")]
+pub fn open_unsaved_world(cmds: &mut Commands, gen: WorldGen) -> Result<(), anyhow::Error> {
+    insert_world(cmds, gen, PathBuf::new())
+}
+
+fn insert_world(cmds: &mut Commands, mut gen: WorldGen, dir: PathBuf) -> Result<(), anyhow::Error> {
+    gen.load_heightmap()?;
+    for warning in gen.clamp_to_height() {
+        warn!("{}: {warning}", dir.display());
+    }
+    info!("Open world {}", dir.display());
+    cmds.insert_resource(gen);
+    cmds.insert_resource(ChunkStorage::new(dir.join("chunks")));
+    cmds.insert_resource(SaveDir(dir));
+    let mut world = VoxelWorld::default();
+    world.set_multi_voxel_blocks(&blocks().read().unwrap());
+    cmds.insert_resource(world);
+    cmds.insert_resource(Achievements::default());
+    // Nothing of the previous world may leak into this one
+    cmds.insert_resource(FluidActive::default());
+    cmds.insert_resource(FireActive::default());
+    cmds.insert_resource(FireLights::default());
+    cmds.insert_resource(GrassCovered::default());
+    cmds.insert_resource(FallingBlocks::default());
+    cmds.add(|world: &mut World| world.resource_mut::<EditHistory>().clear());
+    Ok(())
+}
+
+/// Starts saving the modified chunks and the generation settings,
+/// which might have been edited, and removes all entities of the world.
+///
+/// The chunks are saved in the background, a chunk is not loaded again
+/// before its save has finished.
+fn close_world(
+    mut cmds: Commands,
+    mut world: ResMut<VoxelWorld>,
+    gen: Res<WorldGen>,
+    dir: Res<SaveDir>,
+    storage: Res<ChunkStorage>,
+    mut saves: ResMut<PendingSaves>,
+    entities: Query<Entity, (With<Transform>, Without<Parent>)>,
+) {
+    queue_saves(&mut world, &storage, &mut saves);
+    // The web build has no filesystem to save to
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Err(e) = write_world(&dir.0, gen.clone()) {
+        error!("Failed to save the world {}: {e}", dir.0.display());
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = (gen, dir);
+    let entities: HashSet<Entity> = world.chunk_entities().chain(&entities).collect();
+    for entity in entities {
+        cmds.entity(entity).despawn_recursive();
+    }
+    world.clear();
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{create_world, delete_world, list_worlds, read_world, valid_name, WORLD_FILE};
+    use crate::generation::WorldGen;
+
+    #[test]
+    fn names() {
+        assert!(valid_name("My World_2"));
+        assert!(!valid_name(""));
+        assert!(!valid_name(" padded "));
+        assert!(!valid_name(".."));
+        assert!(!valid_name("a/b"));
+        assert!(!valid_name(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn create_open_delete() {
+        let root = std::env::temp_dir().join("bevy-voxel-saves");
+        let _ = fs::remove_dir_all(&root);
+
+        let gen = WorldGen {
+            seed: 42,
+            ..Default::default()
+        };
+        let dir = create_world(&root, "first", gen).unwrap();
+        create_world(&root, "second", WorldGen::default()).unwrap();
+        assert!(create_world(&root, "first", WorldGen::default()).is_err());
+        assert!(create_world(&root, "../escape", WorldGen::default()).is_err());
+        assert_eq!(list_worlds(&root).unwrap(), ["first", "second"]);
+        assert_eq!(read_world(&dir).unwrap().gen.seed, 42);
+
+        // Newer worlds are not opened and stay untouched
+        let newer = r#"{"version": 1000, "gen": {}}"#;
+        fs::write(dir.join(WORLD_FILE), newer).unwrap();
+        let err = read_world(&dir).unwrap_err().to_string();
+        assert!(err.contains("1000"), "{err}");
+        assert_eq!(fs::read_to_string(dir.join(WORLD_FILE)).unwrap(), newer);
+
+        // Version 1 stored the height in blocks
+        let mut old = serde_json::to_value(WorldGen::default()).unwrap();
+        let gen = old.as_object_mut().unwrap();
+        gen.remove("vertical_chunks");
+        gen.insert(
+            "height".into(),
+            serde_json::json!({"start": -100.0, "end": 128.0}),
+        );
+        let old = serde_json::json!({"version": 1, "gen": old});
+        fs::write(dir.join(WORLD_FILE), old.to_string()).unwrap();
+        assert_eq!(read_world(&dir).unwrap().gen.vertical_chunks, -4..4);
+
+        delete_world(&root, "first").unwrap();
+        assert_eq!(list_worlds(&root).unwrap(), ["second"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
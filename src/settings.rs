@@ -0,0 +1,138 @@
+use std::fs;
+use std::io;
+
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+use crate::storage::is_missing;
+
+/// File containing the user settings, split into one section per feature.
+pub const SETTINGS_FILE: &str = "settings.toml";
+
+/// Reads the section `name` of the settings file.
+///
+/// Returns `None` if the file or the section does not exist.
+pub fn load_section<T: DeserializeOwned>(name: &str) -> Result<Option<T>, anyhow::Error> {
+    match fs::read_to_string(SETTINGS_FILE) {
+        Ok(text) => parse_section(&text, name),
+        Err(e) if is_missing(&e) => Ok(None),
+        Err(e) => Err(anyhow!("Failed to read {SETTINGS_FILE}: {e}")),
+    }
+}
+
+/// Replaces the section `name` of the settings file, keeping the other sections.
+pub fn save_section<T: Serialize>(name: &str, value: &T) -> Result<(), anyhow::Error> {
+    let text = match fs::read_to_string(SETTINGS_FILE) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(anyhow!("Failed to read {SETTINGS_FILE}: {e}")),
+    };
+    let text = replace_section(&text, name, value)?;
+    fs::write(SETTINGS_FILE, text).map_err(|e| anyhow!("Failed to write {SETTINGS_FILE}: {e}"))
+}
+
+pub fn parse_section<T: DeserializeOwned>(
+    text: &str,
+    name: &str,
+) -> Result<Option<T>, anyhow::Error> {
+    let mut table: toml::Table =
+        toml::from_str(text).map_err(|e| anyhow!("Invalid {SETTINGS_FILE}: {e}"))?;
+    table
+        .remove(name)
+        .map(|v| v.try_into())
+        .transpose()
+        .map_err(|e| anyhow!("Invalid [{name}] in {SETTINGS_FILE}: {e}"))
+}
+
+/// Sets the section `name` to `value`, keeping the comments and the
+/// formatting of the file.
+fn replace_section<T: Serialize>(
+    text: &str,
+    name: &str,
+    value: &T,
+) -> Result<String, anyhow::Error> {
+    let mut doc: DocumentMut = text
+        .parse()
+        .map_err(|e| anyhow!("Invalid {SETTINGS_FILE}: {e}"))?;
+    let new = toml_edit::ser::to_document(value)?;
+    let section = doc
+        .entry(name)
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| anyhow!("[{name}] in {SETTINGS_FILE} is not a table"))?;
+    merge(section, new.as_table());
+    Ok(doc.to_string())
+}
+
+/// Replaces the values of `table` with the ones of `new`, existing values
+/// keep their comments.
+fn merge(table: &mut dyn TableLike, new: &dyn TableLike) {
+    let removed: Vec<String> = table
+        .iter()
+        .filter(|(key, _)| !new.contains_key(key))
+        .map(|(key, _)| key.to_owned())
+        .collect();
+    for key in removed {
+        table.remove(&key);
+    }
+    for (key, item) in new.iter() {
+        match (table.get_mut(key), item) {
+            (Some(Item::Value(old)), Item::Value(value)) => {
+                let decor = old.decor().clone();
+                *old = value.clone();
+                *old.decor_mut() = decor;
+            }
+            (Some(old), item) if old.is_table_like() && item.is_table_like() => {
+                if let (Some(old), Some(item)) = (old.as_table_like_mut(), item.as_table_like()) {
+                    merge(old, item);
+                }
+            }
+            _ => {
+                table.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use super::{parse_section, replace_section};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Section {
+        value: f32,
+    }
+
+    #[test]
+    fn replace_keeps_sections() {
+        let text = "[other]\nkey = \"text\"\n\n[section]\nvalue = 1.0\n";
+        let section: Option<Section> = parse_section(text, "section").unwrap();
+        assert_eq!(section, Some(Section { value: 1.0 }));
+
+        let text = replace_section(text, "section", &Section { value: 0.5 }).unwrap();
+        let section: Option<Section> = parse_section(&text, "section").unwrap();
+        assert_eq!(section, Some(Section { value: 0.5 }));
+        assert!(text.contains("key = \"text\""));
+
+        let missing: Option<Section> = parse_section(&text, "missing").unwrap();
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn replace_keeps_comments() {
+        let text = "# Settings\n[section]\n# The value\nvalue = 1.0 # between 0 and 1\nold = 2\n";
+        let text = replace_section(text, "section", &Section { value: 0.5 }).unwrap();
+        assert_eq!(
+            text,
+            "# Settings\n[section]\n# The value\nvalue = 0.5 # between 0 and 1\n"
+        );
+
+        let text = replace_section("", "section", &Section { value: 0.5 }).unwrap();
+        let section: Option<Section> = parse_section(&text, "section").unwrap();
+        assert_eq!(section, Some(Section { value: 0.5 }));
+    }
+}
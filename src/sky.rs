@@ -0,0 +1,324 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, TAU};
+
+use bevy::pbr::{
+    CascadeShadowConfig, CascadeShadowConfigBuilder, DirectionalLightShadowMap, FogFalloff,
+    FogSettings, MAX_CASCADES_PER_LIGHT,
+};
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::chunk::Chunk;
+use crate::player::{PlayerController, PlayerSettings};
+use crate::settings;
+use crate::AppState;
+
+/// Illuminance of the sun at noon.
+const SUN_ILLUMINANCE: f32 = 100000.0;
+
+pub struct SkyPlugin;
+
+impl Plugin for SkyPlugin {
+    fn build(&self, app: &mut App) {
+        let (cycle, gradient) = match load_settings() {
+            Ok(settings) => settings,
+            Err(e) => {
+                warn!("Using the default sky: {e}");
+                default()
+            }
+        };
+        let shadows = ShadowSettings::default();
+        app.insert_resource(cycle)
+            .insert_resource(gradient)
+            .insert_resource(DirectionalLightShadowMap {
+                size: shadows.map_size,
+            })
+            .insert_resource(shadows)
+            .init_resource::<ClearColor>()
+            .add_systems(
+                Update,
+                (
+                    update_sun,
+                    update_cascades.run_if(
+                        resource_changed::<ShadowSettings>()
+                            .or_else(resource_changed::<PlayerSettings>()),
+                    ),
+                )
+                    .run_if(in_state(AppState::Running)),
+            );
+    }
+}
+
+/// Marks the directional light of the sun.
+#[derive(Component)]
+pub struct Sun;
+
+/// Time of day, advancing from midnight (0.0) over noon (0.5) to midnight (1.0).
+#[derive(Resource, Debug)]
+pub struct DayCycle {
+    pub time_of_day: f32,
+    /// Length of a full day in seconds, at least `DayCycle::MIN_LENGTH`
+    pub day_length: f32,
+}
+
+impl DayCycle {
+    /// Shortest day length in seconds, a length of zero would turn the time of day into NaN.
+    pub const MIN_LENGTH: f32 = 1.0;
+}
+
+impl Default for DayCycle {
+    fn default() -> Self {
+        Self {
+            // Matches the initial morning sun
+            time_of_day: 0.375,
+            day_length: 600.0,
+        }
+    }
+}
+
+/// Shadow quality of the sun.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct ShadowSettings {
+    /// Resolution of the shadow maps
+    pub map_size: usize,
+    pub num_cascades: usize,
+    pub first_cascade_far_bound: f32,
+    /// Shadow distance relative to the render distance
+    pub distance: f32,
+    pub overlap_proportion: f32,
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+    /// Factor of the biases when the sun is at the horizon, against the acne on grazing faces
+    pub low_sun_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            // WebGL2 is limited to a single cascade and smaller textures
+            map_size: if cfg!(target_arch = "wasm32") {
+                2048
+            } else {
+                4096
+            },
+            num_cascades: MAX_CASCADES_PER_LIGHT,
+            first_cascade_far_bound: 8.0,
+            distance: 1.0,
+            overlap_proportion: 0.4,
+            depth_bias: DirectionalLight::DEFAULT_SHADOW_DEPTH_BIAS,
+            normal_bias: DirectionalLight::DEFAULT_SHADOW_NORMAL_BIAS,
+            low_sun_bias: 3.0,
+        }
+    }
+}
+
+impl ShadowSettings {
+    /// Returns the factor of the biases for the sun `elevation`.
+    fn bias_factor(&self, elevation: f32) -> f32 {
+        let height = elevation.sin().clamp(0.0, 1.0);
+        self.low_sun_bias + (1.0 - self.low_sun_bias) * height
+    }
+}
+
+/// Sky colors over the course of a day, sorted by time of day.
+#[derive(Resource, Debug, Clone)]
+pub struct SkyColorGradient {
+    pub keyframes: Vec<(f32, Color)>,
+}
+
+impl Default for SkyColorGradient {
+    fn default() -> Self {
+        Self {
+            keyframes: vec![
+                (0.0, Color::rgb(0.02, 0.03, 0.1)),
+                (0.25, Color::rgb(0.98, 0.55, 0.5)),
+                (0.5, Color::rgb(0.53, 0.81, 0.92)),
+                (0.75, Color::rgb(1.0, 0.6, 0.25)),
+                (1.0, Color::rgb(0.02, 0.03, 0.1)),
+            ],
+        }
+    }
+}
+
+impl SkyColorGradient {
+    /// Interpolates the color at the given time of day.
+    pub fn sample(&self, t: f32) -> Color {
+        let lerp = |a: Color, b: Color, f: f32| {
+            let a = Vec4::from(a.as_linear_rgba_f32());
+            let b = Vec4::from(b.as_linear_rgba_f32());
+            let c = a.lerp(b, f);
+            Color::rgba_linear(c.x, c.y, c.z, c.w)
+        };
+
+        let i = self.keyframes.partition_point(|(k, _)| *k <= t);
+        match (self.keyframes.get(i.wrapping_sub(1)), self.keyframes.get(i)) {
+            (Some(&(t0, c0)), Some(&(t1, c1))) => lerp(c0, c1, (t - t0) / (t1 - t0)),
+            (Some(&(_, c)), None) | (None, Some(&(_, c))) => c,
+            (None, None) => Color::BLACK,
+        }
+    }
+}
+
+/// The `[sky]` section of the settings file.
+#[derive(Debug, Deserialize)]
+struct SkySettings {
+    day_length: Option<f32>,
+    /// Times of day with hex colors
+    keyframes: Option<Vec<(f32, String)>>,
+}
+
+fn load_settings() -> Result<(DayCycle, SkyColorGradient), anyhow::Error> {
+    apply_settings(settings::load_section("sky")?)
+}
+
+fn apply_settings(sky: Option<SkySettings>) -> Result<(DayCycle, SkyColorGradient), anyhow::Error> {
+    let mut cycle = DayCycle::default();
+    let mut gradient = SkyColorGradient::default();
+    let Some(sky) = sky else {
+        return Ok((cycle, gradient));
+    };
+    if let Some(day_length) = sky.day_length {
+        if day_length.is_nan() || day_length < DayCycle::MIN_LENGTH {
+            warn!(
+                "The day length {day_length} is shorter than {} seconds",
+                DayCycle::MIN_LENGTH
+            );
+        }
+        cycle.day_length = day_length.max(DayCycle::MIN_LENGTH);
+    }
+    if let Some(keyframes) = sky.keyframes {
+        gradient.keyframes = keyframes
+            .into_iter()
+            .map(|(t, hex)| {
+                Color::hex(&hex)
+                    .map(|c| (t, c))
+                    .map_err(|e| anyhow::anyhow!("Invalid sky color '{hex}': {e:?}"))
+            })
+            .collect::<Result<_, _>>()?;
+        gradient.keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+    Ok((cycle, gradient))
+}
+
+/// Advance the time of day, rotate the sun and color the sky.
+#[allow(clippy::too_many_arguments)]
+fn update_sun(
+    time: Res<Time>,
+    mut cycle: ResMut<DayCycle>,
+    gradient: Res<SkyColorGradient>,
+    settings: Res<PlayerSettings>,
+    shadows: Res<ShadowSettings>,
+    mut ambient: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+    mut sun: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+    mut fog: Query<&mut FogSettings, With<PlayerController>>,
+) {
+    let day_length = cycle.day_length.max(DayCycle::MIN_LENGTH);
+    cycle.time_of_day = (cycle.time_of_day + time.delta_seconds() / day_length).fract();
+    let color = gradient.sample(cycle.time_of_day);
+
+    // The sun rises at 0.25 and sets at 0.75
+    let elevation = cycle.time_of_day * TAU - FRAC_PI_2;
+    if let Ok((mut transform, mut light)) = sun.get_single_mut() {
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, FRAC_PI_4, -elevation, 0.0);
+        light.color = color;
+        light.illuminance = SUN_ILLUMINANCE * elevation.sin().max(0.0);
+        let bias = shadows.bias_factor(elevation);
+        light.shadow_depth_bias = shadows.depth_bias * bias;
+        light.shadow_normal_bias = shadows.normal_bias * bias;
+    }
+
+    ambient.color = color;
+    clear_color.0 = color;
+
+    if let Ok(mut fog) = fog.get_single_mut() {
+        let distance = (settings.render_distance * Chunk::SIZE) as f32;
+        fog.color = color;
+        fog.falloff = FogFalloff::Linear {
+            start: distance * 0.6,
+            end: distance,
+        };
+    }
+}
+
+/// Rebuilds the shadow cascades of the sun, which cover the render distance.
+fn update_cascades(
+    shadows: Res<ShadowSettings>,
+    settings: Res<PlayerSettings>,
+    mut shadow_map: ResMut<DirectionalLightShadowMap>,
+    mut sun: Query<&mut CascadeShadowConfig, With<Sun>>,
+) {
+    if shadow_map.size != shadows.map_size {
+        shadow_map.size = shadows.map_size;
+    }
+    let maximum_distance = (settings.render_distance * Chunk::SIZE) as f32 * shadows.distance;
+    let config = CascadeShadowConfigBuilder {
+        num_cascades: shadows.num_cascades.clamp(1, MAX_CASCADES_PER_LIGHT),
+        first_cascade_far_bound: shadows.first_cascade_far_bound.min(maximum_distance),
+        maximum_distance,
+        overlap_proportion: shadows.overlap_proportion,
+        ..default()
+    }
+    .build();
+    for mut cascades in &mut sun {
+        *cascades = config.clone();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::Color;
+
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::{apply_settings, DayCycle, ShadowSettings, SkyColorGradient};
+    use crate::settings::parse_section;
+
+    fn parse_settings(text: &str) -> Result<(DayCycle, SkyColorGradient), anyhow::Error> {
+        apply_settings(parse_section(text, "sky")?)
+    }
+
+    #[test]
+    fn sample_gradient() {
+        let gradient = SkyColorGradient {
+            keyframes: vec![(0.0, Color::BLACK), (1.0, Color::WHITE)],
+        };
+        assert_eq!(gradient.sample(0.0), Color::BLACK.as_rgba_linear());
+        assert_eq!(gradient.sample(1.0), Color::WHITE);
+        let mid = gradient.sample(0.5).as_linear_rgba_f32();
+        assert!((mid[0] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn settings() {
+        let (cycle, gradient) = parse_settings(
+            r##"
+            [sky]
+            day_length = 60.0
+            keyframes = [[1.0, "#ffffff"], [0.0, "#000000"]]
+            "##,
+        )
+        .unwrap();
+        assert_eq!(cycle.day_length, 60.0);
+        assert_eq!(gradient.keyframes.len(), 2);
+        assert_eq!(gradient.keyframes[0].0, 0.0);
+
+        assert!(parse_settings("").is_ok());
+        for day_length in ["0.0", "-5.0", "nan"] {
+            let (cycle, _) = parse_settings(&format!("[sky]\nday_length = {day_length}")).unwrap();
+            assert_eq!(cycle.day_length, DayCycle::MIN_LENGTH);
+        }
+        assert!(parse_settings("[sky]\nkeyframes = [[0.0, \"nope\"]]").is_err());
+    }
+
+    #[test]
+    fn low_sun_bias() {
+        let shadows = ShadowSettings {
+            low_sun_bias: 3.0,
+            ..Default::default()
+        };
+        assert_eq!(shadows.bias_factor(FRAC_PI_2), 1.0);
+        assert_eq!(shadows.bias_factor(0.0), 3.0);
+        assert_eq!(shadows.bias_factor(-1.0), 3.0);
+        assert!((1.0..3.0).contains(&shadows.bias_factor(0.5)));
+    }
+}
@@ -0,0 +1,375 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::tasks::{IoTaskPool, Task};
+use bevy::utils::{HashMap, HashSet};
+use futures_lite::future;
+
+use crate::chunk::Chunk;
+use crate::world::VoxelWorld;
+
+/// Whether the file of the error does not exist, which includes all files
+/// on the web without filesystem.
+pub fn is_missing(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::Unsupported
+    )
+}
+
+/// Chunk files of the world, one file per modified chunk.
+///
+/// Unmodified chunks are not saved, they are generated again.
+#[derive(Resource, Debug, Clone)]
+pub struct ChunkStorage {
+    dir: PathBuf,
+    /// Positions of the saved chunks, shared with the save tasks
+    saved: Arc<RwLock<HashSet<IVec3>>>,
+}
+
+impl ChunkStorage {
+    /// Indexes the chunk files in `dir`, so that checking for saved chunks
+    /// does not access the filesystem.
+    pub fn new(dir: PathBuf) -> Self {
+        let saved = fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| Self::parse_name(entry.ok()?.file_name().to_str()?))
+            .collect();
+        Self {
+            dir,
+            saved: Arc::new(RwLock::new(saved)),
+        }
+    }
+
+    fn path(&self, pos: IVec3) -> PathBuf {
+        self.dir
+            .join(format!("{}_{}_{}.chunk", pos.x, pos.y, pos.z))
+    }
+
+    /// Returns the position of a chunk file name, `None` for other files.
+    fn parse_name(name: &str) -> Option<IVec3> {
+        let mut coords = name.strip_suffix(".chunk")?.split('_').map(str::parse);
+        let pos = IVec3::new(
+            coords.next()?.ok()?,
+            coords.next()?.ok()?,
+            coords.next()?.ok()?,
+        );
+        coords.next().is_none().then_some(pos)
+    }
+
+    /// Loads the chunk at `pos`, returns `None` if it has not been saved.
+    pub fn load(&self, pos: IVec3) -> io::Result<Option<Chunk>> {
+        match File::open(self.path(pos)) {
+            Ok(file) => Chunk::read(BufReader::new(file)).map(Some),
+            Err(e) if is_missing(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the chunk at `pos` has been saved.
+    pub fn contains(&self, pos: IVec3) -> bool {
+        self.saved.read().unwrap().contains(&pos)
+    }
+
+    /// Saves the chunk at `pos`.
+    ///
+    /// The chunk is written to a temporary file first, which then replaces the
+    /// old file, so that an interrupted save never leaves a truncated chunk.
+    pub fn save(&self, pos: IVec3, chunk: &Chunk) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(pos);
+        let tmp = path.with_extension("tmp");
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        chunk.write(&mut w)?;
+        w.into_inner()?.sync_all()?;
+        fs::rename(tmp, path)?;
+        self.saved.write().unwrap().insert(pos);
+        Ok(())
+    }
+}
+
+/// The running save tasks of the unloaded chunks.
+///
+/// A chunk is not loaded again before its save has finished.
+#[derive(Resource, Default)]
+pub struct PendingSaves {
+    tasks: HashMap<IVec3, Task<()>>,
+    /// Chunks queued since the queue was last empty
+    started: usize,
+}
+
+impl PendingSaves {
+    /// Saves the chunk on the IO task pool.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn(&mut self, storage: &ChunkStorage, pos: IVec3, chunk: Arc<Chunk>) {
+        let storage = storage.clone();
+        // Keep the saves of a chunk in order without blocking
+        let previous = self.tasks.remove(&pos);
+        if previous.is_none() {
+            self.started += 1;
+        }
+        let running = RunningSave::new();
+        let task = IoTaskPool::get().spawn(async move {
+            let _running = running;
+            if let Some(previous) = previous {
+                previous.await;
+            }
+            if let Err(e) = storage.save(pos, &chunk) {
+                error!("Failed to save the chunk {pos}: {e}");
+            }
+        });
+        self.tasks.insert(pos, task);
+    }
+
+    /// The web build has no filesystem to save to, and its single threaded
+    /// task pool can't be blocked on to keep the saves in order.
+    #[cfg(target_arch = "wasm32")]
+    pub fn spawn(&mut self, _: &ChunkStorage, pos: IVec3, _: Arc<Chunk>) {
+        debug!("Not saving the chunk {pos} on the web");
+    }
+
+    pub fn contains(&self, pos: IVec3) -> bool {
+        self.tasks.contains_key(&pos)
+    }
+
+    /// Returns the finished and started saves while saving.
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        (!self.tasks.is_empty()).then(|| (self.started - self.tasks.len(), self.started))
+    }
+
+    /// Removes the finished saves.
+    pub fn poll(&mut self) {
+        self.tasks
+            .retain(|_, task| future::block_on(future::poll_once(task)).is_none());
+        if self.tasks.is_empty() {
+            self.started = 0;
+        }
+    }
+
+    /// Blocks until all saves have finished.
+    pub fn flush(&mut self) {
+        for (_, task) in self.tasks.drain() {
+            future::block_on(task);
+        }
+        self.started = 0;
+    }
+}
+
+/// Number of spawned saves that have not finished yet, over all
+/// [`PendingSaves`], see [`wait_for_saves`].
+#[cfg(not(target_arch = "wasm32"))]
+static RUNNING_SAVES: (std::sync::Mutex<usize>, std::sync::Condvar) =
+    (std::sync::Mutex::new(0), std::sync::Condvar::new());
+
+/// Counts a save as running until it is dropped, also if its task is cancelled.
+#[cfg(not(target_arch = "wasm32"))]
+struct RunningSave;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RunningSave {
+    fn new() -> Self {
+        *RUNNING_SAVES
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) += 1;
+        Self
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for RunningSave {
+    fn drop(&mut self) {
+        let (count, done) = &RUNNING_SAVES;
+        let mut count = count
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        *count -= 1;
+        if *count == 0 {
+            done.notify_all();
+        }
+    }
+}
+
+/// Blocks until the running saves have finished or the `timeout` has passed.
+///
+/// Unlike [`PendingSaves::flush`], this needs no access to the world,
+/// e.g. in a panic hook before exiting the process.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn wait_for_saves(timeout: std::time::Duration) {
+    let (count, done) = &RUNNING_SAVES;
+    let count = count
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let _ = done.wait_timeout_while(count, timeout, |count| *count > 0);
+}
+
+pub fn handle_saves(mut saves: ResMut<PendingSaves>) {
+    saves.poll();
+}
+
+/// Starts saving the modified chunks in the background.
+pub fn queue_saves(world: &mut VoxelWorld, storage: &ChunkStorage, saves: &mut PendingSaves) {
+    let modified = world.take_modified();
+    info!("Saving {} chunks", modified.len());
+    for (pos, chunk) in modified {
+        saves.spawn(storage, pos, chunk);
+    }
+}
+
+/// Saves the modified chunks and waits for all saves to finish.
+pub fn save_all(world: &mut VoxelWorld, storage: &ChunkStorage, saves: &mut PendingSaves) {
+    queue_saves(world, storage, saves);
+    saves.flush();
+}
+
+/// Saves the modified chunks and waits for all saves before the app exits.
+pub fn on_exit(
+    mut exit: EventReader<AppExit>,
+    mut world: ResMut<VoxelWorld>,
+    storage: Option<Res<ChunkStorage>>,
+    mut saves: ResMut<PendingSaves>,
+) {
+    if exit.is_empty() {
+        return;
+    }
+    exit.clear();
+
+    // No world has been opened
+    if let Some(storage) = storage {
+        save_all(&mut world, &storage, &mut saves);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use bevy::app::AppExit;
+    use bevy::prelude::*;
+
+    use bevy::tasks::{IoTaskPool, TaskPool};
+
+    use super::{on_exit, queue_saves, wait_for_saves, ChunkStorage, PendingSaves};
+    use crate::block::BlockId;
+    use crate::chunk::Chunk;
+    use crate::world::{ChangeCause, VoxelWorld};
+
+    #[test]
+    fn flush_on_exit() {
+        let dir = std::env::temp_dir().join("bevy-voxel-flush-on-exit");
+        let _ = fs::remove_dir_all(&dir);
+        let storage = ChunkStorage::new(dir.clone());
+
+        let mut app = App::new();
+        app.add_plugins(TaskPoolPlugin::default())
+            .add_event::<AppExit>()
+            .insert_resource(storage.clone())
+            .init_resource::<PendingSaves>()
+            .add_systems(Last, on_exit);
+
+        let max = IVec3::new(7, 0, 0);
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, max);
+        let modify = |world: &mut VoxelWorld, x: i32| {
+            let pos = IVec3::new(x * Chunk::SIZE as i32, x, 0);
+            world.set_block_meta(pos, BlockId::WATER, x as u8, ChangeCause::Player);
+        };
+
+        // The first half is still in the save queue, the rest is saved on exit
+        let mut saves = PendingSaves::default();
+        for x in 0..4 {
+            modify(&mut world, x);
+        }
+        for (pos, chunk) in world.take_modified() {
+            saves.spawn(&storage, pos, chunk);
+        }
+        for x in 4..=max.x {
+            modify(&mut world, x);
+        }
+        app.insert_resource(world).insert_resource(saves);
+
+        app.world.send_event(AppExit);
+        app.update();
+
+        for x in 0..=max.x {
+            let chunk = storage.load(IVec3::new(x, 0, 0)).unwrap().unwrap();
+            let local = UVec3::new(0, x as u32, 0);
+            assert_eq!(chunk[local], BlockId::WATER);
+            assert_eq!(chunk.meta(local), x as u8);
+        }
+        assert!(storage.load(IVec3::ONE).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saved_index() {
+        let dir = std::env::temp_dir().join("bevy-voxel-saved-index");
+        let _ = fs::remove_dir_all(&dir);
+        let storage = ChunkStorage::new(dir.clone());
+        let pos = IVec3::new(-3, 0, 12);
+        assert!(!storage.contains(pos));
+        storage.save(pos, &Chunk::new(BlockId::STONE)).unwrap();
+        assert!(storage.contains(pos));
+        assert!(!storage.contains(IVec3::ZERO));
+
+        // Existing chunk files are indexed, other files are ignored
+        fs::write(dir.join("0_0_0.tmp"), []).unwrap();
+        fs::write(dir.join("1_2.chunk"), []).unwrap();
+        let storage = ChunkStorage::new(dir.clone());
+        assert!(storage.contains(pos));
+        assert_eq!(storage.saved.read().unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_progress() {
+        let dir = std::env::temp_dir().join("bevy-voxel-save-progress");
+        let _ = fs::remove_dir_all(&dir);
+        let storage = ChunkStorage::new(dir.clone());
+        let _pool = IoTaskPool::init(TaskPool::default);
+
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::new(3, 0, 0));
+        for x in 0..4 {
+            let pos = IVec3::new(x * Chunk::SIZE as i32, 0, 0);
+            world.set_block(pos, BlockId::STONE, ChangeCause::Player);
+        }
+        let mut saves = PendingSaves::default();
+        assert_eq!(saves.progress(), None);
+        queue_saves(&mut world, &storage, &mut saves);
+        // Saving the same chunk again waits for the first save
+        world.set_block(IVec3::ONE, BlockId::DIRT, ChangeCause::Player);
+        queue_saves(&mut world, &storage, &mut saves);
+
+        // Only polling removes the finished saves
+        assert_eq!(saves.progress(), Some((0, 4)));
+        saves.flush();
+        assert_eq!(saves.progress(), None);
+        let chunk = storage.load(IVec3::ZERO).unwrap().unwrap();
+        assert_eq!(chunk[UVec3::ONE], BlockId::DIRT);
+        assert_eq!(chunk[UVec3::ZERO], BlockId::STONE);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wait_without_world() {
+        let dir = std::env::temp_dir().join("bevy-voxel-wait-for-saves");
+        let _ = fs::remove_dir_all(&dir);
+        let storage = ChunkStorage::new(dir.clone());
+        let _pool = IoTaskPool::init(TaskPool::default);
+
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::ZERO);
+        world.set_block(IVec3::ONE, BlockId::STONE, ChangeCause::Player);
+        let mut saves = PendingSaves::default();
+        queue_saves(&mut world, &storage, &mut saves);
+
+        // Finished without polling or flushing the saves
+        wait_for_saves(std::time::Duration::from_secs(10));
+        let chunk = storage.load(IVec3::ZERO).unwrap().unwrap();
+        assert_eq!(chunk[UVec3::ONE], BlockId::STONE);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
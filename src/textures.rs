@@ -2,13 +2,29 @@ use std::error::Error;
 use std::fmt;
 use std::sync::OnceLock;
 
+use anyhow::anyhow;
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
 use bevy::prelude::*;
-use bevy::render::texture::ImageSampler;
-use bevy::utils::HashMap;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::render::render_resource::{
+    Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureFormat,
+};
+use bevy::render::texture::{ImageSampler, TextureFormatPixelInfo};
+use bevy::utils::{BoxedFuture, HashMap};
+use serde::Deserialize;
+
+use crate::BlockMat;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TileTextureId(usize);
 
+/// Size of the missing texture placeholder in pixels.
+const MISSING_SIZE: u32 = 16;
+/// Flat surface normal for the tiles without normal map.
+const NEUTRAL_NORMAL: [u8; 4] = [128, 128, 255, 255];
+/// Keeps the metallic and roughness of the material for the tiles without map.
+const NEUTRAL_METALLIC_ROUGHNESS: [u8; 4] = [255, 255, 255, 255];
+
 static MAP: OnceLock<TileTextures> = OnceLock::new();
 
 /// The combined texture atlas for all of the blocks.
@@ -16,6 +32,88 @@ static MAP: OnceLock<TileTextures> = OnceLock::new();
 pub struct TileTextures {
     atlas: TextureAtlas,
     mapping: HashMap<String, TileTextureId>,
+    /// Placeholder for unknown textures
+    missing: TileTextureId,
+    /// Atlas of the `_n` normal maps with the same layout
+    normal_map: Option<Handle<Image>>,
+    /// Atlas of the `_r` metallic-roughness maps with the same layout
+    metallic_roughness: Option<Handle<Image>>,
+    animations: Vec<TileAnimation>,
+}
+
+/// Animation of a texture, loaded from a `.png.anim` json file next to the texture.
+///
+/// The texture is a vertical strip of equally sized frames.
+#[derive(Debug, Clone, Deserialize, TypeUuid, TypePath)]
+#[uuid = "a1c3e5f7-2b4d-4f68-8a0c-1e3f5a7c9b2d"]
+pub struct TextureAnimation {
+    pub frames: u32,
+    /// Seconds each frame is shown
+    pub frame_time: f32,
+}
+
+#[derive(Default)]
+pub struct TextureAnimationLoader;
+
+impl AssetLoader for TextureAnimationLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let animation: TextureAnimation = serde_json::from_slice(bytes).map_err(|e| {
+                let path = load_context.path().display();
+                anyhow!("Invalid texture animation {path}: {e}")
+            })?;
+            load_context.set_default_asset(LoadedAsset::new(animation));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["anim"]
+    }
+}
+
+/// The frames of an animated tile, which are copied into the shown tile.
+#[derive(Debug)]
+struct TileAnimation {
+    tile: TileTextureId,
+    frames: Vec<TileTextureId>,
+    frame_time: f32,
+}
+
+/// Progress of loading the files of an asset folder.
+#[derive(Debug, PartialEq)]
+pub enum FolderLoad<T> {
+    /// These files are still loading
+    Loading(Vec<T>),
+    /// All files have either loaded or failed
+    Done { loaded: Vec<T>, failed: Vec<T> },
+}
+
+/// Returns the progress of the `files` with their load states.
+///
+/// Failed files do not stop the others from loading.
+pub fn folder_progress<T>(files: impl IntoIterator<Item = (T, LoadState)>) -> FolderLoad<T> {
+    let mut loading = Vec::new();
+    let mut loaded = Vec::new();
+    let mut failed = Vec::new();
+    for (file, state) in files {
+        match state {
+            LoadState::Loaded => loaded.push(file),
+            LoadState::Failed => failed.push(file),
+            // Unloaded assets are not in use and can't be loading anymore
+            LoadState::Unloaded => failed.push(file),
+            LoadState::NotLoaded | LoadState::Loading => loading.push(file),
+        }
+    }
+    if loading.is_empty() {
+        FolderLoad::Done { loaded, failed }
+    } else {
+        FolderLoad::Loading(loading)
+    }
 }
 
 /// Error during texture atlas generation.
@@ -32,41 +130,139 @@ impl fmt::Display for TextureMapError {
 
 impl TileTextures {
     /// Build the texture atlas of the list of texture `handles`.
+    ///
+    /// Textures with the `_n` or `_r` suffix are the normal or metallic-roughness
+    /// maps of the texture without it, they are combined into separate atlases.
+    /// The frames of the `animations` (by texture name) are added as separate tiles.
+    /// Handles without a path or image, like files of other asset types, are
+    /// skipped with a warning.
     pub fn build(
         handles: &[Handle<Image>],
         asset_server: &AssetServer,
         images: &mut Assets<Image>,
+        animations: &HashMap<String, TextureAnimation>,
+        filter: &FilterSettings,
     ) -> Result<(), anyhow::Error> {
+        let mut names = Vec::with_capacity(handles.len());
+        let mut valid = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let path = asset_server.get_handle_path(handle);
+            let name = path.as_ref().and_then(|p| p.path().file_stem());
+            match name {
+                Some(name) if images.contains(handle) => {
+                    names.push(name.to_string_lossy().into_owned());
+                    valid.push(handle);
+                }
+                _ => warn!(
+                    "Skipping {} in the textures, it is not an image",
+                    path.as_ref()
+                        .map_or("an asset".into(), |p| p.path().display().to_string())
+                ),
+            }
+        }
+        let handles = valid;
+
+        // Separate the maps from the block textures
+        let mut base = Vec::new();
+        let mut normals = Vec::new();
+        let mut metallic_roughness = Vec::new();
+        for (&handle, name) in handles.iter().zip(&names) {
+            let is_base = |n: &str| n != name && names.iter().any(|b| b == n);
+            match (name.strip_suffix("_n"), name.strip_suffix("_r")) {
+                (Some(n), _) if is_base(n) => normals.push((n, handle)),
+                (_, Some(n)) if is_base(n) => metallic_roughness.push((n, handle)),
+                _ => base.push((name, handle)),
+            }
+        }
+
         let mut atlas = TextureAtlasBuilder::default();
 
-        for handle in handles {
-            let image = images.get_mut(handle).ok_or(TextureMapError)?;
-            atlas.add_texture(handle.clone_weak(), image);
+        // The shown tile of each texture and the frames of the animated ones
+        let mut tiles = Vec::with_capacity(base.len());
+        let mut frames = Vec::new();
+        for (name, handle) in &base {
+            let image = images.get(*handle).ok_or(TextureMapError)?;
+            let Some(animation) = animations.get(*name) else {
+                atlas.add_texture(handle.clone_weak(), image);
+                tiles.push((*name, handle.clone_weak()));
+                continue;
+            };
+            let strip = split_frames(image, animation.frames)
+                .map_err(|e| anyhow!("Invalid animation of {name}: {e}"))?;
+            let shown = images.add(strip[0].clone());
+            let handles = strip.into_iter().map(|f| images.add(f)).collect::<Vec<_>>();
+            for handle in [&shown].into_iter().chain(&handles) {
+                let image = images.get(handle).ok_or(TextureMapError)?;
+                atlas.add_texture(handle.clone_weak(), image);
+            }
+            frames.push((shown.clone(), handles, animation.frame_time));
+            tiles.push((*name, shown));
         }
 
+        let missing_image = missing_image();
+        let missing_handle = images.add(missing_image.clone());
+        atlas.add_texture(missing_handle.clone_weak(), &missing_image);
+
         let atlas = atlas.finish(images)?;
 
         // Texture filtering
-        let image = images.get_mut(&atlas.texture).unwrap();
-        image.sampler_descriptor = ImageSampler::nearest();
+        let image = images.get_mut(&atlas.texture).ok_or(TextureMapError)?;
+        if image.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb {
+            let size = image.size().as_uvec2();
+            let (data, levels) = generate_mips(&image.data, size, &atlas.textures, true);
+            image.data = data;
+            image.texture_descriptor.mip_level_count = levels;
+        } else {
+            warn!(
+                "No mipmaps for the texture atlas format {:?}",
+                image.texture_descriptor.format
+            );
+        }
+        image.sampler_descriptor = filter.sampler();
 
         let mut mapping = HashMap::new();
-        for handle in handles {
-            let path = asset_server
-                .get_handle_path(handle)
-                .ok_or(TextureMapError)?;
-            let name = path
-                .path()
-                .file_stem()
-                .ok_or(TextureMapError)?
-                .to_string_lossy();
-            mapping.insert(
-                name.into_owned(),
-                TileTextureId(atlas.get_texture_index(handle).unwrap()),
-            );
+        for (name, handle) in &tiles {
+            let index = atlas.get_texture_index(handle).ok_or(TextureMapError)?;
+            mapping.insert(name.to_string(), TileTextureId(index));
         }
 
-        MAP.set(TileTextures { atlas, mapping }).unwrap();
+        let tile = |handle| atlas.get_texture_index(handle).map(TileTextureId);
+        let animations = frames
+            .iter()
+            .map(|(shown, handles, frame_time)| {
+                Some(TileAnimation {
+                    tile: tile(shown)?,
+                    frames: handles.iter().map(tile).collect::<Option<_>>()?,
+                    frame_time: *frame_time,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(TextureMapError)?;
+
+        let missing = atlas
+            .get_texture_index(&missing_handle)
+            .ok_or(TextureMapError)?;
+
+        // Maps with the same tile placement as the block textures
+        let mut map_atlas = |maps: &[(&str, &Handle<Image>)], neutral| {
+            let tiles = maps
+                .iter()
+                .map(|(name, handle)| (mapping[*name], (*handle).clone_weak()))
+                .collect::<Vec<_>>();
+            (!tiles.is_empty()).then(|| map_atlas(&atlas, &tiles, images, neutral, filter))
+        };
+        let normal_map = map_atlas(&normals, NEUTRAL_NORMAL);
+        let metallic_roughness = map_atlas(&metallic_roughness, NEUTRAL_METALLIC_ROUGHNESS);
+
+        MAP.set(TileTextures {
+            atlas,
+            mapping,
+            missing: TileTextureId(missing),
+            normal_map,
+            metallic_roughness,
+            animations,
+        })
+        .map_err(|_| TextureMapError)?;
 
         Ok(())
     }
@@ -80,6 +276,26 @@ impl TileTextures {
         self.atlas.texture.clone()
     }
 
+    /// Return the combined normal map, if any texture has one.
+    pub fn normal_map(&self) -> Option<Handle<Image>> {
+        self.normal_map.clone()
+    }
+
+    /// Return the combined metallic-roughness map, if any texture has one.
+    pub fn metallic_roughness(&self) -> Option<Handle<Image>> {
+        self.metallic_roughness.clone()
+    }
+
+    /// Adds generated tangents to a `mesh` without them, which the normal map requires.
+    pub fn add_tangents(&self, mesh: &mut Mesh) {
+        if self.normal_map.is_none() || mesh.count_vertices() == 0 {
+            return;
+        }
+        if let Err(e) = mesh.generate_tangents() {
+            warn!("Failed to generate tangents: {e}");
+        }
+    }
+
     /// Return the uv coordinates for the given texture `id`.
     pub fn uv(&self, id: TileTextureId) -> (Vec2, Vec2) {
         const V2_EPS: f32 = 0.0001;
@@ -91,7 +307,597 @@ impl TileTextures {
     }
 
     /// Return the numerical id for the given texture `name`.
-    pub fn id(&self, ident: &str) -> TileTextureId {
-        self.mapping[ident]
+    pub fn id(&self, ident: &str) -> Option<TileTextureId> {
+        self.mapping.get(ident).copied()
+    }
+
+    /// Return the known texture name closest to `ident`, if any is similar enough.
+    pub fn closest(&self, ident: &str) -> Option<&str> {
+        let max_distance = (ident.chars().count() / 3).max(2);
+        self.mapping
+            .keys()
+            .map(|name| (edit_distance(ident, name), name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Return the id of the placeholder used for unknown textures.
+    pub fn missing(&self) -> TileTextureId {
+        self.missing
+    }
+}
+
+/// Filtering of the block textures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextureFiltering {
+    /// Sharp pixels, distant textures shimmer
+    Nearest,
+    /// Sharp pixels close up and blended mipmaps in the distance
+    #[default]
+    Mip,
+    /// Blended mipmaps, sharper at flat viewing angles
+    Anisotropic,
+}
+
+impl TextureFiltering {
+    pub const ALL: [Self; 3] = [Self::Nearest, Self::Mip, Self::Anisotropic];
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterSettings {
+    pub filtering: TextureFiltering,
+    /// Maximum anisotropy from 1 to 16
+    pub anisotropy: u16,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        Self {
+            filtering: TextureFiltering::default(),
+            anisotropy: 8,
+        }
+    }
+}
+
+impl FilterSettings {
+    pub fn sampler(&self) -> ImageSampler {
+        use FilterMode::{Linear, Nearest};
+        let (mag_filter, min_filter, mipmap_filter, lod_max_clamp, anisotropy_clamp) =
+            match self.filtering {
+                TextureFiltering::Nearest => (Nearest, Nearest, Nearest, 0.0, 1),
+                TextureFiltering::Mip => (Nearest, Linear, Linear, 32.0, 1),
+                // Anisotropy requires linear filtering for all modes
+                TextureFiltering::Anisotropic => {
+                    (Linear, Linear, Linear, 32.0, self.anisotropy.clamp(1, 16))
+                }
+            };
+        ImageSampler::Descriptor(SamplerDescriptor {
+            mag_filter,
+            min_filter,
+            mipmap_filter,
+            lod_max_clamp,
+            anisotropy_clamp,
+            ..default()
+        })
+    }
+}
+
+/// Updates the sampler of the texture atlas.
+pub fn apply_filtering(
+    settings: Res<FilterSettings>,
+    block_mat: Res<BlockMat>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let textures = TileTextures::get();
+    for handle in [
+        Some(textures.image()),
+        textures.normal_map(),
+        textures.metallic_roughness(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if let Some(image) = images.get_mut(&handle) {
+            image.sampler_descriptor = settings.sampler();
+        }
+    }
+    // Rebuild the bind group of the material with the new sampler
+    materials.get_mut(&block_mat.0);
+}
+
+/// Clock of the texture animations, shared by all animated tiles.
+///
+/// It only advances while the game runs and the time is not paused.
+#[derive(Resource, Debug, Default)]
+pub struct AnimationClock {
+    pub elapsed: f32,
+}
+
+/// Copies the current frames of the animated textures into their shown tiles.
+///
+/// Only the atlas image is updated, so that the chunks do not have to be remeshed.
+pub fn animate_textures(
+    time: Res<Time>,
+    mut clock: ResMut<AnimationClock>,
+    mut shown: Local<Vec<usize>>,
+    block_mat: Res<BlockMat>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let textures = TileTextures::get();
+    if textures.animations.is_empty() {
+        return;
+    }
+    clock.elapsed += time.delta_seconds();
+    shown.resize(textures.animations.len(), 0);
+
+    let changed = textures
+        .animations
+        .iter()
+        .zip(shown.iter_mut())
+        .filter_map(|(animation, shown)| {
+            let frame = (clock.elapsed / animation.frame_time.max(f32::EPSILON)) as usize
+                % animation.frames.len();
+            (frame != *shown).then(|| {
+                *shown = frame;
+                (animation.tile, animation.frames[frame])
+            })
+        })
+        .collect::<Vec<_>>();
+    if changed.is_empty() {
+        return;
+    }
+
+    let Some(image) = images.get_mut(&textures.image()) else {
+        return;
+    };
+    let atlas = &textures.atlas;
+    let size = atlas.size.as_uvec2();
+    let base_len = (size.x * size.y * 4) as usize;
+    for (tile, frame) in changed {
+        copy_tile(
+            &mut image.data[..base_len],
+            size.x,
+            atlas.textures[frame.0],
+            atlas.textures[tile.0],
+        );
+    }
+    if image.texture_descriptor.mip_level_count > 1 {
+        let (data, _) = generate_mips(&image.data[..base_len], size, &atlas.textures, true);
+        image.data = data;
+    }
+    // Rebuild the bind group of the material with the new image
+    materials.get_mut(&block_mat.0);
+}
+
+/// Copies the rgba pixels of the rect `from` to the equally sized rect `to`.
+fn copy_tile(data: &mut [u8], width: u32, from: Rect, to: Rect) {
+    let size = from.size().as_uvec2();
+    let (from, to) = (from.min.as_uvec2(), to.min.as_uvec2());
+    for y in 0..size.y {
+        let src = ((from.x + (from.y + y) * width) * 4) as usize;
+        let dst = ((to.x + (to.y + y) * width) * 4) as usize;
+        data.copy_within(src..src + size.x as usize * 4, dst);
+    }
+}
+
+/// Splits a vertical strip into `frames` equally sized images.
+fn split_frames(image: &Image, frames: u32) -> Result<Vec<Image>, anyhow::Error> {
+    let size = image.size().as_uvec2();
+    if frames == 0 || !size.y.is_multiple_of(frames) {
+        return Err(anyhow!(
+            "the height {} is not a multiple of the {frames} frames",
+            size.y
+        ));
+    }
+    let format = image.texture_descriptor.format;
+    let pixel = format.pixel_size();
+    let len = (size.x * size.y / frames) as usize * pixel;
+    Ok(image
+        .data
+        .chunks_exact(len)
+        .take(frames as usize)
+        .map(|data| {
+            Image::new(
+                Extent3d {
+                    width: size.x,
+                    height: size.y / frames,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data.to_vec(),
+                format,
+            )
+        })
+        .collect())
+}
+
+/// Creates an rgba8 atlas with the layout of `atlas` from the `tiles` images.
+///
+/// The images are scaled to their tile and the other tiles are filled with `neutral`.
+fn map_atlas(
+    atlas: &TextureAtlas,
+    tiles: &[(TileTextureId, Handle<Image>)],
+    images: &mut Assets<Image>,
+    neutral: [u8; 4],
+    filter: &FilterSettings,
+) -> Handle<Image> {
+    let size = atlas.size.as_uvec2();
+    let mut data = neutral.repeat((size.x * size.y) as usize);
+    for (id, handle) in tiles {
+        let Some(image) = images.get(handle) else {
+            continue;
+        };
+        let format = image.texture_descriptor.format;
+        if !matches!(
+            format,
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm
+        ) {
+            warn!("Unsupported texture map format {format:?}");
+            continue;
+        }
+        let rect = atlas.textures[id.0];
+        let (min, max) = (rect.min.as_uvec2(), rect.max.as_uvec2());
+        let src_size = image.size().as_uvec2();
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                // Nearest neighbor scaling
+                let src = (UVec2::new(x, y) - min) * src_size / (max - min);
+                let i = ((src.x + src.y * src_size.x) * 4) as usize;
+                let j = ((x + y * size.x) * 4) as usize;
+                data[j..j + 4].copy_from_slice(&image.data[i..i + 4]);
+            }
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+    );
+    let (data, levels) = generate_mips(&image.data, size, &atlas.textures, false);
+    image.data = data;
+    image.texture_descriptor.mip_level_count = levels;
+    image.sampler_descriptor = filter.sampler();
+    images.add(image)
+}
+
+/// Computes the mip chain of an rgba8 atlas with a box filter.
+///
+/// Every pixel only averages the pixels of the tile containing its center,
+/// so that the tiles do not bleed into each other.
+/// The colors of `srgb` images are averaged in linear space.
+/// The levels end when the smallest tile is a single pixel.
+/// Returns the data of all levels and the number of levels.
+fn generate_mips(data: &[u8], size: UVec2, tiles: &[Rect], srgb: bool) -> (Vec<u8>, u32) {
+    let Some(min_tile) = tiles
+        .iter()
+        .map(|t| t.width().min(t.height()) as u32)
+        .min()
+        .filter(|t| *t > 0)
+    else {
+        return (data.to_vec(), 1);
+    };
+    let levels = (min_tile.ilog2() + 1).min(size.max_element().ilog2() + 1);
+
+    let to_linear = |c: u8| match srgb {
+        true => Color::rgba_u8(c, 0, 0, 0).as_rgba_linear().r(),
+        false => c as f32 / 255.0,
+    };
+    let to_srgb = |c: f32| match srgb {
+        true => (Color::rgba_linear(c, 0.0, 0.0, 0.0).as_rgba().r() * 255.0).round() as u8,
+        false => (c * 255.0).round() as u8,
+    };
+
+    let mut chain = data.to_vec();
+    let mut prev = data.to_vec();
+    let mut prev_size = size;
+    for level in 1..levels {
+        let scale = (1 << level) as f32;
+        let level_size = (prev_size / 2).max(UVec2::ONE);
+        let mut next = Vec::with_capacity((level_size.x * level_size.y * 4) as usize);
+        for y in 0..level_size.y {
+            for x in 0..level_size.x {
+                // Bounds of the tile in the previous level
+                let center = (Vec2::new(x as f32, y as f32) + 0.5) * scale;
+                let (lo, hi) = match tiles
+                    .iter()
+                    .find(|t| t.min.cmple(center).all() && center.cmplt(t.max).all())
+                {
+                    Some(tile) => {
+                        let lo = (tile.min * 2.0 / scale).floor().as_uvec2();
+                        let hi = ((tile.max * 2.0 / scale).ceil() - 1.0).as_uvec2();
+                        (lo, hi.max(lo).min(prev_size - 1))
+                    }
+                    None => (UVec2::ZERO, prev_size - 1),
+                };
+
+                let mut sum = Vec4::ZERO;
+                for offset in [UVec2::ZERO, UVec2::X, UVec2::Y, UVec2::ONE] {
+                    let p = (UVec2::new(x, y) * 2 + offset).clamp(lo, hi);
+                    let i = ((p.x + p.y * prev_size.x) * 4) as usize;
+                    let [r, g, b, a] = [prev[i], prev[i + 1], prev[i + 2], prev[i + 3]];
+                    sum += Vec4::new(to_linear(r), to_linear(g), to_linear(b), a as f32 / 255.0);
+                }
+                let avg = sum / 4.0;
+                next.extend_from_slice(&[
+                    to_srgb(avg.x),
+                    to_srgb(avg.y),
+                    to_srgb(avg.z),
+                    (avg.w * 255.0).round() as u8,
+                ]);
+            }
+        }
+        chain.extend_from_slice(&next);
+        prev = next;
+        prev_size = level_size;
+    }
+    (chain, levels)
+}
+
+/// Returns the Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = prev + (ca != *cb) as usize;
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Magenta and black checkerboard, used as placeholder for unknown textures.
+fn missing_image() -> Image {
+    const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+    let half = MISSING_SIZE / 2;
+    let data = (0..MISSING_SIZE * MISSING_SIZE)
+        .flat_map(|i| {
+            let (x, y) = (i % MISSING_SIZE, i / MISSING_SIZE);
+            if (x < half) == (y < half) {
+                MAGENTA
+            } else {
+                BLACK
+            }
+        })
+        .collect();
+    Image::new(
+        Extent3d {
+            width: MISSING_SIZE,
+            height: MISSING_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+impl TileTextures {
+    /// Creates textures with the given names without loading any images.
+    ///
+    /// The textures are placed in a row of equally sized tiles, followed by the placeholder.
+    /// This is used for testing and benchmarking.
+    pub fn with_names(names: &[&str]) -> Self {
+        let len = names.len() + 1;
+        let mut atlas = TextureAtlas::new_empty(Handle::default(), Vec2::new(len as f32, 1.0));
+        for i in 0..len {
+            atlas.add_texture(Rect::new(i as f32, 0.0, i as f32 + 1.0, 1.0));
+        }
+        Self {
+            atlas,
+            mapping: names
+                .iter()
+                .enumerate()
+                .map(|(i, n)| (n.to_string(), TileTextureId(i)))
+                .collect(),
+            missing: TileTextureId(names.len()),
+            normal_map: None,
+            metallic_roughness: None,
+            animations: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use bevy::asset::LoadState;
+
+    use super::{
+        copy_tile, folder_progress, generate_mips, map_atlas, split_frames, FilterSettings,
+        FolderLoad, TileTextureId, NEUTRAL_NORMAL,
+    };
+
+    /// Creates an rgba image with the `tiles` filled with the `colors`.
+    fn atlas(size: UVec2, tiles: &[Rect], colors: &[[u8; 4]]) -> Vec<u8> {
+        let mut data = vec![0; (size.x * size.y * 4) as usize];
+        for (tile, color) in tiles.iter().zip(colors) {
+            for y in tile.min.y as u32..tile.max.y as u32 {
+                for x in tile.min.x as u32..tile.max.x as u32 {
+                    let i = ((x + y * size.x) * 4) as usize;
+                    data[i..i + 4].copy_from_slice(color);
+                }
+            }
+        }
+        data
+    }
+
+    /// Returns the pixels of the first mip level after the base image.
+    fn level_1(data: &[u8], size: UVec2) -> Vec<[u8; 4]> {
+        let start = (size.x * size.y * 4) as usize;
+        let len = ((size.x / 2) * (size.y / 2)) as usize;
+        data[start..start + len * 4]
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], c[3]])
+            .collect()
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    #[test]
+    fn mip_levels() {
+        let size = UVec2::new(64, 16);
+        let tiles = [0, 16, 32, 48].map(|x| Rect::new(x as f32, 0.0, x as f32 + 16.0, 16.0));
+        let data = atlas(size, &tiles, &[RED; 4]);
+        let (chain, levels) = generate_mips(&data, size, &tiles, true);
+        // 16, 8, 4, 2, 1
+        assert_eq!(levels, 5);
+        let pixels: u32 = (0..levels).map(|l| (size.x >> l) * (size.y >> l)).sum();
+        assert_eq!(chain.len(), pixels as usize * 4);
+    }
+
+    #[test]
+    fn mip_tiles_aligned() {
+        let size = UVec2::new(32, 16);
+        let tiles = [
+            Rect::new(0.0, 0.0, 16.0, 16.0),
+            Rect::new(16.0, 0.0, 32.0, 16.0),
+        ];
+        let data = atlas(size, &tiles, &[RED, BLUE]);
+        let (chain, _) = generate_mips(&data, size, &tiles, true);
+
+        for (i, pixel) in level_1(&chain, size).into_iter().enumerate() {
+            let x = i as u32 % (size.x / 2);
+            assert_eq!(pixel, if x < 8 { RED } else { BLUE }, "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn mip_tiles_unaligned() {
+        // The tile border is in the middle of a level 1 pixel
+        let size = UVec2::new(26, 16);
+        let tiles = [
+            Rect::new(0.0, 0.0, 11.0, 16.0),
+            Rect::new(11.0, 0.0, 26.0, 16.0),
+        ];
+        let data = atlas(size, &tiles, &[RED, BLUE]);
+        let (chain, _) = generate_mips(&data, size, &tiles, true);
+
+        for (i, pixel) in level_1(&chain, size).into_iter().enumerate() {
+            let x = i as u32 % (size.x / 2);
+            // Pixel centers at 2x + 1
+            assert_eq!(pixel, if 2 * x + 1 < 11 { RED } else { BLUE }, "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn map_atlas_layout() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_asset::<Image>();
+        let mut images = app.world.resource_mut::<Assets<Image>>();
+
+        let mut atlas = TextureAtlas::new_empty(Handle::default(), Vec2::new(8.0, 4.0));
+        atlas.add_texture(Rect::new(0.0, 0.0, 4.0, 4.0));
+        atlas.add_texture(Rect::new(4.0, 0.0, 8.0, 4.0));
+
+        // A 2x2 map for the second tile, scaled up to 4x4
+        let pixels = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], RED];
+        let map = images.add(Image::new(
+            Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            pixels.concat(),
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+        let tiles = [(TileTextureId(1), map)];
+        let handle = map_atlas(
+            &atlas,
+            &tiles,
+            &mut images,
+            NEUTRAL_NORMAL,
+            &FilterSettings::default(),
+        );
+
+        let image = images.get(&handle).unwrap();
+        assert_eq!(image.texture_descriptor.format, TextureFormat::Rgba8Unorm);
+        assert_eq!(image.texture_descriptor.mip_level_count, 3);
+        let pixel = |x: u32, y: u32| {
+            let i = ((x + y * 8) * 4) as usize;
+            [0, 1, 2, 3].map(|c| image.data[i + c])
+        };
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(pixel(x, y), NEUTRAL_NORMAL);
+                let src = (x / 2 + y / 2 * 2) as usize;
+                assert_eq!(pixel(x + 4, y), pixels[src], "{x} {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn animation_frames() {
+        // 1x3 strip of three frames
+        let strip = Image::new(
+            Extent3d {
+                width: 1,
+                height: 3,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            [RED, BLUE, [0, 255, 0, 255]].concat(),
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        assert!(split_frames(&strip, 2).is_err());
+        let frames = split_frames(&strip, 3).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].size(), Vec2::new(1.0, 1.0));
+        assert_eq!(frames[1].data, BLUE);
+
+        // Copy the right 2x2 tile of a 4x2 image to the left
+        let mut data = atlas(UVec2::new(4, 2), &[Rect::new(2.0, 0.0, 4.0, 2.0)], &[BLUE]);
+        let (left, right) = (Rect::new(0.0, 0.0, 2.0, 2.0), Rect::new(2.0, 0.0, 4.0, 2.0));
+        copy_tile(&mut data, 4, right, left);
+        assert!(data.chunks_exact(4).all(|p| p == BLUE));
+    }
+
+    #[test]
+    fn folder_with_bad_file() {
+        let mut folder = vec![
+            ("stone.png", LoadState::Loaded),
+            ("broken.png", LoadState::Failed),
+            ("dirt.png", LoadState::Loading),
+        ];
+        assert_eq!(
+            folder_progress(folder.clone()),
+            FolderLoad::Loading(vec!["dirt.png"])
+        );
+
+        folder[2].1 = LoadState::Loaded;
+        assert_eq!(
+            folder_progress(folder),
+            FolderLoad::Done {
+                loaded: vec!["stone.png", "dirt.png"],
+                failed: vec!["broken.png"],
+            }
+        );
+        assert_eq!(
+            folder_progress::<&str>([]),
+            FolderLoad::Done {
+                loaded: vec![],
+                failed: vec![],
+            }
+        );
     }
 }
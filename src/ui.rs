@@ -1,34 +1,590 @@
+use std::time::Duration;
+
+use bevy::app::AppExit;
 use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy::ecs::system::SystemParam;
+use bevy::math::Vec3Swizzles;
+use bevy::pbr::MAX_CASCADES_PER_LIGHT;
 use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::window::PrimaryWindow;
 
-use bevy_egui::egui::{RichText, Slider};
+use bevy_egui::egui::{Color32, ColorImage, DragValue, RichText, Slider, TextureHandle, Ui};
 use bevy_egui::{egui, EguiContexts};
+use crossbeam_channel::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
 
-use crate::generation::WorldGen;
-use crate::player::{PlayerController, PlayerSettings};
-use crate::world::RegenerateEvent;
+use crate::achievements::ToastNotification;
+use crate::args::Args;
+use crate::audio::AudioSettings;
+use crate::block::{blocks, BlockId};
+use crate::block_entity::BlockEntityData;
+use crate::chunk::Chunk;
+use crate::fluid::FluidSettings;
+use crate::generation::{
+    noise_preview_image, preview_noise, FloatingIslandsParams, LayerOp, PreviewMode, WorldGen,
+    WorldGenKind, WorldGenPresets, MAX_VERTICAL_CHUNKS, NOISE_PREVIEW_SCALE,
+};
+use crate::health::Dead;
+use crate::history::EditHistory;
+use crate::interaction::{Brush, BrushShape, Hotbar, TargetedBlock};
+use crate::player::{
+    next_window_mode, AaMode, GameMode, MouseLook, PlayerController, PlayerSettings, FOV_RANGE,
+};
+use crate::saves;
+use crate::settings;
+use crate::sky::ShadowSettings;
+use crate::storage::PendingSaves;
+use crate::textures::{FilterSettings, TextureFiltering};
+use crate::world::{
+    ChangeCause, ChunkMeshSize, ChunkSeedOverlay, ChunkState, ChunkStates, ChunkWireframe,
+    OcclusionCulling, RegenerateEvent, ShowChunkBoundaries, TickRate, VoxelWorld, WorldPlugin,
+    WorldStats,
+};
 use crate::{AppState, BlockMat};
 
 pub struct UIPlugin;
 
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, update.run_if(in_state(AppState::Running)));
+        let hud_settings = match settings::load_section("hud") {
+            Ok(settings) => settings.unwrap_or_default(),
+            Err(e) => {
+                warn!("Using the default HUD settings: {e}");
+                HudSettings::default()
+            }
+        };
+        app.insert_resource(hud_settings)
+            .add_systems(
+                Update,
+                (
+                    update,
+                    material,
+                    map_preview,
+                    minimap,
+                    world_stats,
+                    world_edit,
+                    show_toasts,
+                    hud,
+                    hud_overlay,
+                    debug_overlay,
+                    block_entity_popup,
+                )
+                    .run_if(in_state(AppState::Running)),
+            )
+            .add_systems(Startup, load_icons)
+            .add_systems(Update, world_select.run_if(in_state(AppState::WorldSelect)))
+            .add_systems(Update, show_error.run_if(in_error_state));
+    }
+}
+
+/// Block material and texture filtering settings
+fn material(
+    mut egui_context: EguiContexts,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    block_mat: Res<BlockMat>,
+    mut filter: ResMut<FilterSettings>,
+) {
+    egui::Window::new("Block Material").show(egui_context.ctx_mut(), |ui| {
+        if let Some(mat) = materials.get_mut(&block_mat.0) {
+            ui.add(Slider::new(&mut mat.metallic, 0.0..=1.0).text("Metallic"));
+            ui.add(Slider::new(&mut mat.perceptual_roughness, 0.0..=1.0).text("Roughness"));
+            ui.add(Slider::new(&mut mat.reflectance, 0.0..=1.0).text("Reflectance"));
+        }
+
+        // Edit a copy to only trigger change detection on changes
+        let mut settings = *filter;
+        egui::ComboBox::from_label("Filtering")
+            .selected_text(format!("{:?}", settings.filtering))
+            .show_ui(ui, |ui| {
+                for filtering in TextureFiltering::ALL {
+                    ui.selectable_value(
+                        &mut settings.filtering,
+                        filtering,
+                        format!("{filtering:?}"),
+                    );
+                }
+            });
+        if settings.filtering == TextureFiltering::Anisotropic {
+            ui.horizontal(|ui| {
+                ui.label("Anisotropy");
+                for level in [2, 4, 8, 16] {
+                    ui.selectable_value(&mut settings.anisotropy, level, format!("{level}x"));
+                }
+            });
+        }
+        if settings != *filter {
+            *filter = settings;
+        }
+    });
+}
+
+/// Pixels along each side of the map preview.
+const PREVIEW_RESOLUTION: u32 = 128;
+/// Seconds without changes before the map preview is refreshed.
+const PREVIEW_DEBOUNCE: f32 = 0.3;
+
+/// Sampled map preview with the center of its area.
+type PreviewResult = (IVec2, Vec<f32>, ColorImage);
+
+/// State of the map preview window.
+struct MapPreview {
+    mode: PreviewMode,
+    /// Blocks along each side of the previewed area
+    size: u32,
+    /// Mode and size of the last sampled or pending preview
+    sampled: (PreviewMode, u32),
+    /// Time of the last change that has not been previewed yet
+    changed: Option<f32>,
+    /// Whether a sampling task is running
+    sampling: bool,
+    /// Channel of the finished sampling tasks, which works without blocking
+    /// on the single threaded web task pool
+    results: (Sender<PreviewResult>, Receiver<PreviewResult>),
+    texture: Option<TextureHandle>,
+    center: IVec2,
+    pixels: Vec<f32>,
+    /// Last clicked position with its value
+    clicked: Option<(IVec2, f32)>,
+}
+
+impl Default for MapPreview {
+    fn default() -> Self {
+        Self {
+            mode: PreviewMode::Surface,
+            size: 512,
+            sampled: (PreviewMode::Surface, 512),
+            changed: None,
+            sampling: false,
+            results: crossbeam_channel::unbounded(),
+            texture: None,
+            center: IVec2::ZERO,
+            pixels: Vec::new(),
+            clicked: None,
+        }
+    }
+}
+
+/// Top-down map of the world generation around the player,
+/// refreshed when the settings have not changed for a moment.
+fn map_preview(
+    mut egui_context: EguiContexts,
+    time: Res<Time>,
+    gen: Res<WorldGen>,
+    player: Query<&Transform, With<PlayerController>>,
+    mut preview: Local<MapPreview>,
+) {
+    let sampled = (preview.mode, preview.size);
+    if gen.is_changed() || sampled != preview.sampled {
+        preview.sampled = sampled;
+        preview.changed = Some(time.elapsed_seconds());
+    }
+
+    if let Ok((center, pixels, image)) = preview.results.1.try_recv() {
+        preview.sampling = false;
+        preview.center = center;
+        preview.pixels = pixels;
+        match &mut preview.texture {
+            Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+            None => {
+                preview.texture = Some(egui_context.ctx_mut().load_texture(
+                    "map_preview",
+                    image,
+                    egui::TextureOptions::NEAREST,
+                ))
+            }
+        }
+    }
+    // Only one task at a time, the latest change is sampled afterwards
+    let settled = preview
+        .changed
+        .is_some_and(|t| time.elapsed_seconds() - t >= PREVIEW_DEBOUNCE);
+    if settled && !preview.sampling {
+        preview.changed = None;
+        let center = player
+            .get_single()
+            .map_or(IVec2::ZERO, |t| t.translation.xz().floor().as_ivec2());
+        let (gen, mode, size) = (gen.clone(), preview.mode, preview.size);
+        let sender = preview.results.0.clone();
+        preview.sampling = true;
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let pixels = gen.preview(center, size, PREVIEW_RESOLUTION, mode);
+                let image = preview_image(&pixels, mode);
+                // The receiver lives as long as the window state
+                let _ = sender.send((center, pixels, image));
+            })
+            .detach();
+    }
+
+    egui::Window::new("Map Preview")
+        .default_open(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            let preview = &mut *preview;
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut preview.mode, PreviewMode::Surface, "Surface");
+                let y = match preview.mode {
+                    PreviewMode::Slice(y) => y,
+                    PreviewMode::Surface => 0,
+                };
+                ui.selectable_value(&mut preview.mode, PreviewMode::Slice(y), "Slice");
+            });
+            if let PreviewMode::Slice(y) = &mut preview.mode {
+                ui.add(Slider::new(y, -256..=256).text("Slice Y"));
+            }
+            ui.add(
+                Slider::new(&mut preview.size, 64..=4096)
+                    .logarithmic(true)
+                    .text("Size"),
+            );
+            if ui.button("Refresh").clicked() {
+                preview.changed = Some(f32::NEG_INFINITY);
+            }
+            if preview.sampling {
+                ui.spinner();
+            }
+
+            if let Some(texture) = &preview.texture {
+                let image =
+                    egui::Image::new(egui::load::SizedTexture::new(texture.id(), [256.0, 256.0]))
+                        .sense(egui::Sense::click());
+                let response = ui.add(image);
+                if let Some((rel, i)) = clicked_pixel(&response, PREVIEW_RESOLUTION) {
+                    let offset = (rel - 0.5) * preview.size as f32;
+                    let xz = preview.center + offset.floor().as_ivec2();
+                    let value = preview.pixels.get(i).copied().unwrap_or_default();
+                    info!("Map preview position: x={} z={} value={value}", xz.x, xz.y);
+                    preview.clicked = Some((xz, value));
+                }
+            }
+            if let Some((xz, value)) = preview.clicked {
+                ui.label(format!("x: {} z: {} value: {value:.2}", xz.x, xz.y));
+            }
+        });
+}
+
+/// Returns the clicked position relative to the preview image of `response`
+/// and the index of the pixel of a `resolution`² preview.
+fn clicked_pixel(response: &egui::Response, resolution: u32) -> Option<(Vec2, usize)> {
+    let pos = response
+        .interact_pointer_pos()
+        .filter(|_| response.clicked())?;
+    let rel = (pos - response.rect.min) / response.rect.size();
+    let rel = Vec2::new(rel.x, rel.y);
+    let pixel = (rel * resolution as f32)
+        .as_uvec2()
+        .min(UVec2::splat(resolution - 1));
+    Some((rel, (pixel.x + pixel.y * resolution) as usize))
+}
+
+/// Colors the pixels of the map preview.
+///
+/// Surface heights range from blue over green to white, slices show the
+/// solid blocks in gray that gets brighter towards the surface.
+fn preview_image(pixels: &[f32], mode: PreviewMode) -> ColorImage {
+    let lerp = |a: [f32; 3], b: [f32; 3], t: f32| {
+        let c = Vec3::from(a).lerp(Vec3::from(b), t.clamp(0.0, 1.0)) * 255.0;
+        Color32::from_rgb(c.x as u8, c.y as u8, c.z as u8)
+    };
+    let (min, max) = pixels
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+            (min.min(*v), max.max(*v))
+        });
+    let range = (max - min).max(f32::EPSILON);
+    let colors = pixels
+        .iter()
+        .map(|&v| match mode {
+            PreviewMode::Surface => {
+                let t = (v - min) / range;
+                if t < 0.5 {
+                    lerp([0.1, 0.2, 0.6], [0.3, 0.6, 0.2], t * 2.0)
+                } else {
+                    lerp([0.3, 0.6, 0.2], [1.0, 1.0, 1.0], t * 2.0 - 1.0)
+                }
+            }
+            PreviewMode::Slice(_) if v > 0.0 => {
+                lerp([0.8, 0.8, 0.8], [0.2, 0.2, 0.2], v / max.max(f32::EPSILON))
+            }
+            PreviewMode::Slice(_) => Color32::from_rgb(20, 30, 60),
+        })
+        .collect();
+    let size = PREVIEW_RESOLUTION as usize;
+    ColorImage {
+        size: [size, size],
+        pixels: colors,
+    }
+}
+
+/// Pixels along each side of the minimap.
+const MINIMAP_SIZE: f32 = 200.0;
+
+/// Settings of the minimap window.
+struct Minimap {
+    enabled: bool,
+    /// Pixels per chunk
+    zoom: f32,
+}
+
+impl Default for Minimap {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            zoom: 8.0,
+        }
+    }
+}
+
+/// Top-down map of the chunks in the layer of the player, colored by their
+/// pipeline state, with the player as an arrow in its facing direction.
+fn minimap(
+    mut egui_context: EguiContexts,
+    chunks: ChunkStates,
+    player: Query<&Transform, With<PlayerController>>,
+    mut minimap: Local<Minimap>,
+) {
+    let Ok(transform) = player.get_single() else {
+        return;
+    };
+    let center = VoxelWorld::chunk_pos(transform.translation);
+
+    egui::Window::new("Minimap")
+        .default_open(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut minimap.enabled, "Show");
+                ui.add(Slider::new(&mut minimap.zoom, 2.0..=32.0).text("Zoom"));
+            });
+            if !minimap.enabled {
+                return;
+            }
+
+            let (response, painter) =
+                ui.allocate_painter(egui::vec2(MINIMAP_SIZE, MINIMAP_SIZE), egui::Sense::hover());
+            let rect = response.rect;
+            painter.rect_filled(rect, 0.0, Color32::from_gray(16));
+
+            // X to the right and Z downwards, centered on the player
+            let player = transform.translation.xz() / Chunk::SIZE as f32;
+            let to_screen = |p: Vec2| rect.center() + egui::vec2(p.x, p.y) * minimap.zoom;
+            let painter = painter.with_clip_rect(rect);
+            for (pos, state) in chunks.iter().filter(|(pos, _)| pos.y == center.y) {
+                let color = match state {
+                    ChunkState::Generating => Color32::from_gray(80),
+                    ChunkState::Generated => Color32::from_rgb(200, 120, 40),
+                    ChunkState::Meshing => Color32::from_rgb(220, 200, 60),
+                    ChunkState::Meshed => Color32::from_rgb(60, 160, 70),
+                };
+                let min = to_screen(pos.xz().as_vec2() - player);
+                let chunk = egui::Rect::from_min_size(min, egui::Vec2::splat(minimap.zoom));
+                painter.rect_filled(chunk.shrink(0.5), 0.0, color);
+            }
+
+            let forward = transform.forward().xz().normalize_or_zero() * 12.0;
+            painter.arrow(
+                rect.center() - egui::vec2(forward.x, forward.y) / 2.0,
+                egui::vec2(forward.x, forward.y),
+                egui::Stroke::new(2.0, Color32::WHITE),
+            );
+        });
+}
+
+/// Upper bounds of the mesh vertex count histogram buckets.
+const VERTEX_BUCKETS: [usize; 6] = [0, 1 << 8, 1 << 10, 1 << 12, 1 << 14, 1 << 16];
+
+/// Chunk pipeline throughput, task durations and the sizes of the chunk meshes.
+fn world_stats(
+    mut egui_context: EguiContexts,
+    stats: Res<WorldStats>,
+    meshes: Query<&ChunkMeshSize>,
+) {
+    let mut buckets = [0; VERTEX_BUCKETS.len() + 1];
+    let mut bytes = 0;
+    for size in &meshes {
+        let bucket = VERTEX_BUCKETS
+            .iter()
+            .position(|&max| size.vertices <= max)
+            .unwrap_or(VERTEX_BUCKETS.len());
+        buckets[bucket] += 1;
+        bytes += size.bytes;
+    }
+    let count = meshes.iter().len();
+
+    egui::Window::new("World Stats")
+        .default_open(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(format!("Generated this frame: {}", stats.generated));
+            ui.label(format!("Meshed this frame: {}", stats.meshed));
+            let ms = |d: Option<Duration>| match d {
+                Some(d) => format!("{:.2} ms", d.as_secs_f64() * 1000.0),
+                None => "-".into(),
+            };
+            ui.label(format!("Generation time: {}", ms(stats.generate_time())));
+            ui.label(format!("Meshing time: {}", ms(stats.mesh_time())));
+            ui.separator();
+
+            ui.label(format!("Chunk meshes: {count}"));
+            ui.label(format!(
+                "Mesh memory: {:.1} MiB",
+                bytes as f64 / (1024.0 * 1024.0)
+            ));
+            ui.label("Vertices per mesh");
+            let max = buckets.iter().copied().max().unwrap_or_default().max(1);
+            egui::Grid::new("vertex_histogram").show(ui, |ui| {
+                for (i, &n) in buckets.iter().enumerate() {
+                    match VERTEX_BUCKETS.get(i) {
+                        Some(0) => ui.label("empty"),
+                        Some(bound) => ui.label(format!("≤ {bound}")),
+                        None => ui.label(format!("> {}", VERTEX_BUCKETS[i - 1])),
+                    };
+                    ui.add(
+                        egui::ProgressBar::new(n as f32 / max as f32)
+                            .desired_width(120.0)
+                            .text(n.to_string()),
+                    );
+                    ui.end_row();
+                }
+            });
+        });
+}
+
+/// Pixels along each side of the noise preview.
+const NOISE_PREVIEW_SIZE: u32 = 128;
+
+/// State of the noise preview of the world generation window.
+struct NoisePreview {
+    image: Handle<Image>,
+    /// Restarted on changes, the preview is updated when it finishes
+    debounce: Timer,
+    /// Sampled noise of the pixels
+    values: Vec<f32>,
+    /// Last clicked position with its noise value and whether it is solid
+    clicked: Option<(IVec3, f32, bool)>,
+}
+
+impl Default for NoisePreview {
+    fn default() -> Self {
+        Self {
+            image: Handle::default(),
+            debounce: Timer::from_seconds(PREVIEW_DEBOUNCE, TimerMode::Once),
+            values: Vec::new(),
+            clicked: None,
+        }
+    }
+}
+
+/// Debug views of the chunks and the simulation rate.
+#[derive(SystemParam)]
+pub struct ChunkDebug<'w> {
+    culling: ResMut<'w, OcclusionCulling>,
+    boundaries: ResMut<'w, ShowChunkBoundaries>,
+    wireframe: ResMut<'w, ChunkWireframe>,
+    seed_overlay: ResMut<'w, ChunkSeedOverlay>,
+    tick_rate: ResMut<'w, TickRate>,
+}
+
+/// Local state of the world generation window.
+#[derive(SystemParam)]
+pub struct WorldGenForm<'w, 's> {
+    time: Res<'w, Time>,
+    images: ResMut<'w, Assets<Image>>,
+    heightmap: Local<'s, HeightmapForm>,
+    preview: Local<'s, NoisePreview>,
+    /// Settings that have been clamped to the world height
+    height_warnings: Local<'s, Vec<String>>,
+}
+
+impl WorldGenForm<'_, '_> {
+    /// Updates the preview image once the settings have not `changed` for a moment.
+    fn update_preview(&mut self, gen: &WorldGen, changed: bool) {
+        if changed {
+            self.preview.debounce.reset();
+        }
+        if !self
+            .preview
+            .debounce
+            .tick(self.time.delta())
+            .just_finished()
+        {
+            return;
+        }
+
+        self.preview.values = preview_noise(gen, NOISE_PREVIEW_SIZE);
+        let data = noise_preview_image(gen, &self.preview.values);
+        if let Some(image) = self.images.get_mut(&self.preview.image) {
+            image.data = data;
+        } else {
+            let size = Extent3d {
+                width: NOISE_PREVIEW_SIZE,
+                height: NOISE_PREVIEW_SIZE,
+                depth_or_array_layers: 1,
+            };
+            let image = Image::new(
+                size,
+                TextureDimension::D2,
+                data,
+                TextureFormat::Rgba8UnormSrgb,
+            );
+            self.preview.image = self.images.add(image);
+        }
+    }
+
+    /// Returns the egui texture of the preview once it has been created.
+    fn preview_texture(&self, egui_context: &mut EguiContexts) -> Option<egui::TextureId> {
+        let image = &self.preview.image;
+        (self.images.contains(image)).then(|| egui_context.add_image(image.clone_weak()))
+    }
+
+    /// Shows the preview, clicking a pixel shows its noise value.
+    fn show_preview(&mut self, ui: &mut Ui, texture: egui::TextureId, gen: &WorldGen) {
+        let size = NOISE_PREVIEW_SIZE as f32 * 2.0;
+        let image = egui::Image::new(egui::load::SizedTexture::new(texture, [size, size]))
+            .sense(egui::Sense::click());
+        let response = ui.add(image);
+        if let Some((_, i)) = clicked_pixel(&response, NOISE_PREVIEW_SIZE) {
+            let size = NOISE_PREVIEW_SIZE as usize;
+            let p = IVec3::new((i % size) as _, 0, (i / size) as _) * NOISE_PREVIEW_SCALE;
+            // The value the pixel is colored by
+            let value = self.preview.values.get(i).copied().unwrap_or_default();
+            self.preview.clicked = Some((p, value, gen.base_limit.contains(&value)));
+        }
+        if let Some((p, value, solid)) = self.preview.clicked {
+            response.on_hover_text(format!(
+                "x: {} z: {}\nnoise: {value:.4}\n{}",
+                p.x,
+                p.z,
+                if solid { "solid" } else { "air" }
+            ));
+        }
     }
 }
 
 /// UI update function
+#[allow(clippy::too_many_arguments)]
 pub fn update(
     mut egui_context: EguiContexts,
     diagnostics: Res<DiagnosticsStore>,
     mut player_settings: ResMut<PlayerSettings>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     mut noise: ResMut<WorldGen>,
-    block_mat: Res<BlockMat>,
+    presets: Res<WorldGenPresets>,
+    worldgen_assets: Res<Assets<WorldGen>>,
+    asset_server: Res<AssetServer>,
     mut events: EventWriter<RegenerateEvent>,
-    player_controller: Query<(&PlayerController, &Transform)>,
+    mut history: ResMut<EditHistory>,
+    mut fluid_settings: ResMut<FluidSettings>,
+    mut audio: ResMut<AudioSettings>,
+    mut shadows: ResMut<ShadowSettings>,
+    mut debug: ChunkDebug,
+    mut state: ResMut<NextState<AppState>>,
+    mut form: WorldGenForm,
+    (mut player_controller, mut windows): (
+        Query<(&mut PlayerController, &Transform)>,
+        Query<&mut Window, With<PrimaryWindow>>,
+    ),
 ) {
-    let (p_movement, p_transform) = player_controller.single();
+    let (mut p_movement, p_transform) = player_controller.single_mut();
+    let mut window = windows.single_mut();
 
     egui::Window::new("Settings").show(egui_context.ctx_mut(), |ui| {
         if let Some(fps) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS) {
@@ -36,17 +592,204 @@ pub fn update(
                 ui.label(format!("FPS: {avg:.3}"));
             }
         }
+        let rate = |id| {
+            diagnostics
+                .get(id)
+                .and_then(|d| d.average())
+                .unwrap_or_default()
+        };
+        ui.label(format!(
+            "Chunks/s: {:.1} generated, {:.1} meshed",
+            rate(WorldPlugin::CHUNKS_GENERATED),
+            rate(WorldPlugin::CHUNKS_MESHED)
+        ));
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut debug.culling.enabled, "Occlusion Culling");
+            ui.label(format!("Culled Chunks: {}", debug.culling.culled));
+        });
+        ui.checkbox(&mut debug.boundaries.0, "Chunk Boundaries");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut debug.wireframe.enabled, "Wireframe (F4)");
+            ui.add_enabled(
+                debug.wireframe.enabled,
+                egui::Checkbox::new(&mut debug.wireframe.targeted, "Targeted Chunk Only"),
+            );
+        });
+        ui.checkbox(&mut debug.seed_overlay.0, "Chunk Seed Colors (F7)");
+        // Only touch the resource on changes, the fixed timestep is updated on change
+        let mut tick_rate = debug.tick_rate.0;
+        if ui
+            .add(Slider::new(&mut tick_rate, TickRate::MIN..=TickRate::MAX).text("Tick Rate"))
+            .changed()
+        {
+            debug.tick_rate.0 = tick_rate;
+        }
+        if ui.button("World Selection").clicked() {
+            state.set(AppState::WorldSelect);
+        }
 
         ui.label(RichText::new("Player Settings").heading());
         ui.add(Slider::new(&mut player_settings.m_speed, 0.0..=50.0).text("M Speed"));
         ui.add(Slider::new(&mut player_settings.m_acceleration, 0.0..=10.0).text("M Acceleration"));
         ui.add(Slider::new(&mut player_settings.m_deceleration, 0.0..=10.0).text("M Deceleration"));
         ui.add(Slider::new(&mut player_settings.r_speed, 0.0..=2.0).text("R Speed"));
-        ui.add(Slider::new(&mut player_settings.view_distance, 1..=12).text("View Distance"));
+        ui.add(
+            Slider::new(&mut player_settings.mouse_sensitivity, 0.1..=5.0)
+                .logarithmic(true)
+                .text("Mouse Sensitivity"),
+        );
+        ui.add(
+            Slider::new(&mut player_settings.fov_degrees, FOV_RANGE)
+                .suffix("°")
+                .text("FOV"),
+        );
+        ui.add(Slider::new(&mut player_settings.reach, 1.0..=32.0).text("Reach"));
+        ui.checkbox(&mut player_settings.soft_clip, "Soft Clip")
+            .on_hover_text("Keep out of solid blocks while flying in creative mode");
+        ui.collapsing("Survival Movement", |ui| {
+            ui.add(Slider::new(&mut player_settings.walk_speed, 1.0..=30.0).text("Walk Speed"));
+            ui.add(Slider::new(&mut player_settings.gravity, 1.0..=100.0).text("Gravity"));
+            ui.add(Slider::new(&mut player_settings.jump_height, 0.0..=5.0).text("Jump Height"));
+            ui.add(Slider::new(&mut player_settings.air_control, 0.0..=1.0).text("Air Control"));
+            ui.add(
+                Slider::new(&mut player_settings.terminal_velocity, 10.0..=200.0)
+                    .text("Terminal Velocity"),
+            );
+        });
+        ui.add(
+            Slider::new(&mut player_settings.simulation_distance, 1..=16)
+                .text("Simulation Distance"),
+        );
+        let max_render = player_settings.simulation_distance;
+        ui.add(
+            Slider::new(&mut player_settings.render_distance, 1..=max_render)
+                .text("Render Distance"),
+        );
+        player_settings.render_distance = player_settings.render_distance.min(max_render);
+        ui.add(
+            Slider::new(&mut player_settings.near, 0.01..=1.0)
+                .logarithmic(true)
+                .text("Near Plane"),
+        );
+        ui.horizontal(|ui| {
+            let (_, auto_far) = player_settings.clip_planes();
+            let mut auto = player_settings.far.is_none();
+            if ui.checkbox(&mut auto, "Auto").changed() {
+                player_settings.far = (!auto).then_some(auto_far);
+            }
+            if let Some(far) = &mut player_settings.far {
+                ui.add(
+                    Slider::new(far, 10.0..=10_000.0)
+                        .logarithmic(true)
+                        .text("Far Plane"),
+                );
+            } else {
+                ui.label(format!("Far Plane: {auto_far:.0}"));
+            }
+        });
+        egui::ComboBox::from_label("Antialiasing")
+            .selected_text(format!("{:?}", player_settings.antialiasing))
+            .show_ui(ui, |ui| {
+                for mode in AaMode::ALL {
+                    ui.selectable_value(
+                        &mut player_settings.antialiasing,
+                        mode,
+                        format!("{mode:?}"),
+                    );
+                }
+            });
+        ui.horizontal(|ui| {
+            ui.label("Window Mode");
+            if ui
+                .button(format!("{:?}", window.mode))
+                .on_hover_text("F11 or Alt+Enter")
+                .clicked()
+            {
+                window.mode = next_window_mode(window.mode);
+            }
+        });
+        ui.add(
+            Slider::new(&mut player_settings.generation_lookahead, 0.0..=5.0)
+                .suffix(" s")
+                .text("Generation Lookahead"),
+        );
+        ui.add(
+            Slider::new(&mut player_settings.max_generation_tasks, 1..=256)
+                .logarithmic(true)
+                .text("Generation Tasks"),
+        );
+        ui.add(Slider::new(&mut player_settings.max_mesh_tasks, 1..=32).text("Mesh Tasks"));
+        ui.add(Slider::new(&mut player_settings.max_mesh_uploads, 1..=64).text("Mesh Uploads"));
+        ui.checkbox(&mut player_settings.chunk_fade_in, "Chunk Fade In");
+        ui.add(
+            Slider::new(&mut player_settings.slow_task_threshold, 1.0..=1000.0)
+                .logarithmic(true)
+                .suffix(" ms")
+                .text("Slow Task Warning"),
+        );
+        ui.add(Slider::new(&mut history.depth, 1..=256).text("Undo Depth"));
+        ui.label(format!(
+            "Undo: {} Redo: {}",
+            history.undo_len(),
+            history.redo_len()
+        ));
+
+        ui.add(Slider::new(&mut fluid_settings.spread_distance, 1..=15).text("Water Spread"));
+        ui.add(Slider::new(&mut fluid_settings.budget, 16..=4096).text("Water Budget"));
+
+        ui.separator();
+
+        ui.label(RichText::new("Shadows").heading());
+        // Edit a copy to only rebuild the cascades on changes
+        let mut s = shadows.clone();
+        egui::ComboBox::from_label("Shadow Map")
+            .selected_text(s.map_size.to_string())
+            .show_ui(ui, |ui| {
+                for size in [1024, 2048, 4096, 8192] {
+                    ui.selectable_value(&mut s.map_size, size, size.to_string());
+                }
+            });
+        ui.add(Slider::new(&mut s.num_cascades, 1..=MAX_CASCADES_PER_LIGHT).text("Cascades"));
+        ui.add(Slider::new(&mut s.first_cascade_far_bound, 1.0..=64.0).text("First Cascade"));
+        ui.add(Slider::new(&mut s.distance, 0.1..=1.0).text("Shadow Distance"));
+        ui.add(Slider::new(&mut s.overlap_proportion, 0.0..=0.9).text("Cascade Overlap"));
+        ui.add(Slider::new(&mut s.depth_bias, 0.0..=0.2).text("Depth Bias"));
+        ui.add(Slider::new(&mut s.normal_bias, 0.0..=4.0).text("Normal Bias"));
+        ui.add(Slider::new(&mut s.low_sun_bias, 1.0..=8.0).text("Low Sun Bias"));
+        if s != *shadows {
+            *shadows = s;
+        }
+
+        ui.separator();
+
+        ui.label(RichText::new("Audio").heading());
+        // Edit a copy to only apply the volumes on changes
+        let (mut master_volume, mut effects_volume) = (audio.master, audio.effects);
+        let master = ui.add(Slider::new(&mut master_volume, 0.0..=1.0).text("Master Volume"));
+        let effects = ui.add(Slider::new(&mut effects_volume, 0.0..=1.0).text("Effects Volume"));
+        if master.changed() || effects.changed() {
+            audio.master = master_volume;
+            audio.effects = effects_volume;
+        }
+        // Persist the volumes once the sliders are released
+        if [master, effects]
+            .iter()
+            .any(|r| r.drag_released() || (r.changed() && !r.dragged()))
+        {
+            audio.save();
+        }
 
         ui.separator();
 
         ui.label(RichText::new("Player Movement").heading());
+        ui.horizontal(|ui| {
+            ui.label("Game Mode");
+            ui.selectable_value(&mut p_movement.mode, GameMode::Creative, "Creative");
+            ui.selectable_value(&mut p_movement.mode, GameMode::Survival, "Survival");
+        });
+        if ui.button("Toggle Spectator").clicked() {
+            p_movement.toggle_spectator();
+        }
         ui.label(format!("Yaw: {:.2}", p_movement.yaw));
         ui.label(format!("Pitch: {:.2}", p_movement.pitch));
         ui.label(format!("Time: {:.2}", p_movement.time));
@@ -54,44 +797,844 @@ pub fn update(
         ui.label(format!("Position: {:.2?}", p_transform.translation));
     });
 
-    egui::Window::new("Block Material").show(egui_context.ctx_mut(), |ui| {
-        if let Some(mat) = materials.get_mut(&block_mat.0) {
-            ui.add(Slider::new(&mut mat.metallic, 0.0..=1.0).text("Metallic"));
-            ui.add(Slider::new(&mut mat.perceptual_roughness, 0.0..=1.0).text("Roughness"));
-            ui.add(Slider::new(&mut mat.reflectance, 0.0..=1.0).text("Reflectance"));
-        }
-    });
+    let preview_texture = form.preview_texture(&mut egui_context);
 
+    // Only edits mark the settings as changed, the previews refresh on them
+    let mut changed = false;
+    let gen = noise.bypass_change_detection();
     egui::Window::new("World Generation").show(egui_context.ctx_mut(), |ui| {
-        ui.label("Height");
-        ui.add(Slider::new(&mut noise.height.start, -8.0 * 32.0..=8.0 * 32.0).text("min"));
-        ui.add(Slider::new(&mut noise.height.end, -8.0 * 32.0..=8.0 * 32.0).text("max"));
+        ui.label("Height in Chunks");
+        let range = -MAX_VERTICAL_CHUNKS..=MAX_VERTICAL_CHUNKS;
+        let start = ui.add(Slider::new(&mut gen.vertical_chunks.start, range.clone()).text("min"));
+        let end = ui.add(Slider::new(&mut gen.vertical_chunks.end, range).text("max"));
+        if start.changed() || end.changed() {
+            changed = true;
+            let warnings = gen.clamp_to_height();
+            if !warnings.is_empty() {
+                *form.height_warnings = warnings;
+            }
+        }
+        for warning in form.height_warnings.iter() {
+            ui.colored_label(egui::Color32::YELLOW, warning);
+        }
+
+        ui.separator();
+
+        // The heightmap spans the height range above
+        ui.horizontal(|ui| {
+            ui.label("Heightmap");
+            ui.text_edit_singleline(&mut form.heightmap.path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Load Heightmap").clicked() {
+                let mut loaded = WorldGen {
+                    kind: WorldGenKind::Heightmap {
+                        path: form.heightmap.path.clone().into(),
+                        height_range: gen.height(),
+                        world_width: None,
+                    },
+                    ..gen.clone()
+                };
+                match loaded.load_heightmap() {
+                    Ok(()) => {
+                        *gen = loaded;
+                        changed = true;
+                        form.heightmap.error = None;
+                        events.send(RegenerateEvent);
+                    }
+                    Err(e) => form.heightmap.error = Some(e.to_string()),
+                }
+            }
+            if gen.kind != WorldGenKind::Noise && ui.button("Use Noise").clicked() {
+                gen.kind = WorldGenKind::Noise;
+                gen.heightmap = None;
+                changed = true;
+                events.send(RegenerateEvent);
+            }
+        });
+        if let Some(error) = &form.heightmap.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        ui.separator();
+
+        egui::ComboBox::from_label("Preset")
+            .selected_text("Select")
+            .show_ui(ui, |ui| {
+                for handle in &presets.0 {
+                    let Some(preset) = worldgen_assets.get(handle) else {
+                        continue;
+                    };
+                    let name = asset_server
+                        .get_handle_path(handle)
+                        .and_then(|p| Some(p.path().file_stem()?.to_string_lossy().into_owned()))
+                        .unwrap_or_default();
+                    if ui.selectable_label(false, name).clicked() {
+                        *gen = preset.clone();
+                        changed = true;
+                        events.send(RegenerateEvent);
+                    }
+                }
+            });
 
         ui.separator();
 
         ui.label("3D Noise");
-        ui.add(Slider::new(&mut noise.base.octaves, 1..=10).text("Octaves"));
-        ui.add(Slider::new(&mut noise.base.frequency, 0.0..=10.0).text("Frequency"));
-        ui.add(Slider::new(&mut noise.base.lacunarity, 0.0..=10.0).text("Lacunarity"));
-        ui.add(Slider::new(&mut noise.base.persistence, 0.0..=10.0).text("Persistance"));
-        ui.add(Slider::new(&mut noise.base.attenuation, 0.0..=10.0).text("Attenuation"));
-        let max = noise.base.octaves as f32;
-        ui.add(Slider::new(&mut noise.base_limit.start, -max..=max).text("Min"));
-        ui.add(Slider::new(&mut noise.base_limit.end, -max..=max).text("Max"));
-        ui.add(Slider::new(&mut noise.base_strength, 0.0..=1.0).text("Strength"));
+        for layer in &mut gen.layers {
+            ui.collapsing(layer.name.clone(), |ui| {
+                egui::ComboBox::new(&layer.name, "Operation")
+                    .selected_text(format!("{:?}", layer.op))
+                    .show_ui(ui, |ui| {
+                        for op in LayerOp::ALL {
+                            changed |= ui
+                                .selectable_value(&mut layer.op, op, format!("{op:?}"))
+                                .changed();
+                        }
+                    });
+                changed |= ui
+                    .add(Slider::new(&mut layer.strength, 0.0..=1.0).text("Strength"))
+                    .changed();
+                changed |= ui
+                    .add(DragValue::new(&mut layer.seed).prefix("Seed: "))
+                    .changed();
+                let param = &mut layer.param;
+                changed |= ui
+                    .add(Slider::new(&mut param.octaves, 1..=10).text("Octaves"))
+                    .changed();
+                changed |= ui
+                    .add(Slider::new(&mut param.frequency, 0.0..=10.0).text("Frequency"))
+                    .changed();
+                changed |= ui
+                    .add(Slider::new(&mut param.lacunarity, 0.0..=10.0).text("Lacunarity"))
+                    .changed();
+                changed |= ui
+                    .add(Slider::new(&mut param.persistence, 0.0..=10.0).text("Persistance"))
+                    .changed();
+                changed |= ui
+                    .add(Slider::new(&mut param.attenuation, 0.0..=10.0).text("Attenuation"))
+                    .changed();
+            });
+        }
+        let max = gen
+            .layers
+            .iter()
+            .map(|l| l.param.octaves)
+            .max()
+            .unwrap_or(1) as f32;
+        changed |= ui
+            .add(Slider::new(&mut gen.base_limit.start, -max..=max).text("Min"))
+            .changed();
+        changed |= ui
+            .add(Slider::new(&mut gen.base_limit.end, -max..=max).text("Max"))
+            .changed();
 
         ui.separator();
 
+        // Within the world height
+        let height = gen.height();
+        let (min, max) = (height.start as i32, height.end as i32);
         ui.label("Dirt Range");
-        ui.add(Slider::new(&mut noise.dirt_range.start, -8 * 32..=8 * 32).text("min"));
-        ui.add(Slider::new(&mut noise.dirt_range.end, -8 * 32..=8 * 32).text("max"));
+        changed |= ui
+            .add(Slider::new(&mut gen.dirt_range.start, min as isize..=max as isize).text("min"))
+            .changed();
+        changed |= ui
+            .add(Slider::new(&mut gen.dirt_range.end, min as isize..=max as isize).text("max"))
+            .changed();
+
+        changed |= ui
+            .add(Slider::new(&mut gen.dirt_height, 1..=10).text("Dirt"))
+            .changed();
+        changed |= ui
+            .add(Slider::new(&mut gen.sea_level, min..=max).text("Sea Level"))
+            .changed();
+
+        ui.separator();
 
-        ui.add(Slider::new(&mut noise.dirt_height, 1..=10).text("Dirt"));
+        ui.label("Erosion");
+        changed |= ui
+            .add(Slider::new(&mut gen.erosion_passes, 0..=8).text("Passes"))
+            .changed();
+        changed |= ui
+            .add(Slider::new(&mut gen.erosion_rate, 0.0..=1.0).text("Erosion Rate"))
+            .changed();
+        changed |= ui
+            .add(Slider::new(&mut gen.deposition_rate, 0.0..=1.0).text("Deposition Rate"))
+            .changed();
+        changed |= ui
+            .add(Slider::new(&mut gen.evaporation, 0.0..=0.5).text("Evaporation"))
+            .changed();
+
+        ui.separator();
+
+        let mut enabled = gen.floating_islands.is_some();
+        if ui.checkbox(&mut enabled, "Floating Islands").changed() {
+            gen.floating_islands = enabled.then(FloatingIslandsParams::default);
+            changed = true;
+        }
+        if let Some(islands) = &mut gen.floating_islands {
+            changed |= ui
+                .add(Slider::new(&mut islands.min_height, -8.0 * 32.0..=8.0 * 32.0).text("min"))
+                .changed();
+            changed |= ui
+                .add(Slider::new(&mut islands.max_height, -8.0 * 32.0..=8.0 * 32.0).text("max"))
+                .changed();
+            changed |= ui
+                .add(Slider::new(&mut islands.island_density, 0.0..=1.0).text("Density"))
+                .changed();
+            changed |= ui
+                .add(Slider::new(&mut islands.island_radius.start, 1.0..=64.0).text("Min Radius"))
+                .changed();
+            changed |= ui
+                .add(Slider::new(&mut islands.island_radius.end, 1.0..=64.0).text("Max Radius"))
+                .changed();
+            changed |= ui
+                .add(Slider::new(&mut islands.underside_taper, 0.0..=4.0).text("Underside Taper"))
+                .changed();
+        }
 
         ui.separator();
 
         if ui.button("Regenerate").clicked() {
+            form.height_warnings.clear();
             events.send(RegenerateEvent);
         }
+
+        ui.separator();
+
+        if let Some(texture) = preview_texture {
+            ui.collapsing("Noise Preview", |ui| form.show_preview(ui, texture, gen));
+        }
+    });
+    if changed {
+        noise.set_changed();
+    }
+    // After the edits, the next run would not see them as changed
+    form.update_preview(&noise, noise.is_changed());
+}
+
+/// Inputs of the heightmap import.
+#[derive(Default)]
+pub struct HeightmapForm {
+    path: String,
+    error: Option<String>,
+}
+
+/// Inputs of the world edit window.
+struct WorldEditForm {
+    min: IVec3,
+    max: IVec3,
+    seed: IVec3,
+    block: BlockId,
+    from: BlockId,
+    limit: usize,
+}
+
+impl Default for WorldEditForm {
+    fn default() -> Self {
+        Self {
+            min: IVec3::ZERO,
+            max: IVec3::ZERO,
+            seed: IVec3::ZERO,
+            block: BlockId::STONE,
+            from: BlockId::AIR,
+            limit: 4096,
+        }
+    }
+}
+
+/// Bulk edit operations with typed coordinates and the brush settings.
+fn world_edit(
+    mut egui_context: EguiContexts,
+    mut form: Local<WorldEditForm>,
+    mut world: ResMut<VoxelWorld>,
+    mut brush: ResMut<Brush>,
+) {
+    let mut ids: Vec<BlockId> = blocks().read().unwrap().keys().copied().collect();
+    ids.sort_unstable_by_key(|b| b.0);
+
+    egui::Window::new("World Edit")
+        .default_open(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut brush.enabled, "Brush");
+                egui::ComboBox::from_id_source("brush_shape")
+                    .selected_text(format!("{:?}", brush.shape))
+                    .show_ui(ui, |ui| {
+                        for shape in BrushShape::ALL {
+                            ui.selectable_value(&mut brush.shape, shape, format!("{shape:?}"));
+                        }
+                    });
+            });
+            ui.add(Slider::new(&mut brush.radius, 0..=8).text("Radius"));
+
+            ui.separator();
+
+            let form = &mut *form;
+            ivec3_edit(ui, "Min", &mut form.min);
+            ivec3_edit(ui, "Max", &mut form.max);
+            block_select(ui, "Block", &mut form.block, &ids);
+            block_select(ui, "Replace", &mut form.from, &ids);
+
+            let mut changed = None;
+            ui.horizontal(|ui| {
+                if ui.button("Fill").clicked() {
+                    changed = Some(world.fill_box(
+                        form.min,
+                        form.max,
+                        form.block,
+                        ChangeCause::WorldEdit,
+                    ));
+                }
+                if ui.button("Replace").clicked() {
+                    changed = Some(world.replace_in_box(
+                        form.min,
+                        form.max,
+                        form.from,
+                        form.block,
+                        ChangeCause::WorldEdit,
+                    ));
+                }
+            });
+
+            ui.separator();
+
+            ivec3_edit(ui, "Seed", &mut form.seed);
+            ui.add(Slider::new(&mut form.limit, 1..=65536).text("Limit"));
+            if ui.button("Flood Fill").clicked() {
+                changed = Some(world.flood_fill(
+                    form.seed,
+                    form.block,
+                    form.limit,
+                    ChangeCause::WorldEdit,
+                ));
+            }
+
+            if let Some(changed) = changed {
+                info!("World edit changed {changed} blocks");
+            }
+        });
+}
+
+fn ivec3_edit(ui: &mut Ui, label: &str, v: &mut IVec3) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(DragValue::new(&mut v.x).prefix("x: "));
+        ui.add(DragValue::new(&mut v.y).prefix("y: "));
+        ui.add(DragValue::new(&mut v.z).prefix("z: "));
+    });
+}
+
+fn block_select(ui: &mut Ui, label: &str, block: &mut BlockId, ids: &[BlockId]) {
+    egui::ComboBox::from_label(label)
+        .selected_text(block.0.to_string())
+        .show_ui(ui, |ui| {
+            for id in ids {
+                ui.selectable_value(block, *id, id.0.to_string());
+            }
+        });
+}
+
+/// Shows the toast notifications in the top-right corner, fading them out.
+fn show_toasts(
+    mut cmds: Commands,
+    mut egui_context: EguiContexts,
+    time: Res<Time>,
+    mut toasts: Query<(Entity, &mut ToastNotification)>,
+) {
+    if toasts.is_empty() {
+        return;
+    }
+    egui::Area::new("toasts")
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+        .interactable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            for (entity, mut toast) in &mut toasts {
+                toast.lifetime -= time.delta_seconds();
+                if toast.lifetime <= 0.0 {
+                    cmds.entity(entity).despawn();
+                    continue;
+                }
+                let alpha = (toast.lifetime.min(1.0) * 255.0) as u8;
+                egui::Frame::popup(ui.style())
+                    .multiply_with_opacity(alpha as f32 / 255.0)
+                    .show(ui, |ui| {
+                        ui.label(
+                            RichText::new(&toast.text)
+                                .color(egui::Color32::from_white_alpha(alpha)),
+                        );
+                    });
+            }
+        });
+}
+
+/// Icons of the HUD.
+#[derive(Resource)]
+struct HeartIcons {
+    full: Handle<Image>,
+    half: Handle<Image>,
+    empty: Handle<Image>,
+}
+
+fn load_icons(mut cmds: Commands, asset_server: Res<AssetServer>) {
+    cmds.insert_resource(HeartIcons {
+        full: asset_server.load("ui/heart_full.png"),
+        half: asset_server.load("ui/heart_half.png"),
+        empty: asset_server.load("ui/heart_empty.png"),
+    });
+}
+
+/// Shows the health in survival mode and the death screen.
+fn hud(
+    mut egui_context: EguiContexts,
+    icons: Res<HeartIcons>,
+    query: Query<(&PlayerController, Option<&Dead>)>,
+) {
+    let Ok((controller, dead)) = query.get_single() else {
+        return;
+    };
+    if dead.is_some() {
+        egui::Area::new("death")
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.label(
+                    RichText::new("You died")
+                        .size(48.0)
+                        .color(egui::Color32::RED),
+                );
+            });
+    }
+    if controller.mode != GameMode::Survival {
+        return;
+    }
+
+    const HEARTS: usize = 10;
+    let [full, half, empty] =
+        [&icons.full, &icons.half, &icons.empty].map(|h| egui_context.add_image(h.clone_weak()));
+    // Each heart stands for a tenth of the maximum health
+    let halves = (controller.health / controller.max_health * (2 * HEARTS) as f32).ceil() as usize;
+    egui::Area::new("health")
+        .anchor(
+            egui::Align2::CENTER_BOTTOM,
+            egui::vec2(0.0, -24.0 - HOTBAR_SLOT),
+        )
+        .interactable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                for i in 0..HEARTS {
+                    let icon = match halves.saturating_sub(2 * i) {
+                        0 => empty,
+                        1 => half,
+                        _ => full,
+                    };
+                    ui.add(egui::Image::new(egui::load::SizedTexture::new(
+                        icon,
+                        [18.0, 18.0],
+                    )));
+                }
+            });
+        });
+}
+
+/// Shape of the crosshair at the screen center.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrosshairStyle {
+    Cross,
+    Dot,
+    Circle,
+    Hidden,
+}
+
+impl CrosshairStyle {
+    pub const ALL: [Self; 4] = [Self::Cross, Self::Dot, Self::Circle, Self::Hidden];
+}
+
+/// Crosshair and HUD options, stored in the `[hud]` section of the settings file.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HudSettings {
+    pub crosshair: CrosshairStyle,
+    /// sRGB color of the crosshair
+    pub color: [u8; 3],
+    /// Show the block coordinates in the bottom left corner
+    pub coordinates: bool,
+}
+
+impl Default for HudSettings {
+    fn default() -> Self {
+        Self {
+            crosshair: CrosshairStyle::Cross,
+            color: [255; 3],
+            coordinates: false,
+        }
+    }
+}
+
+impl HudSettings {
+    pub fn save(&self) {
+        if let Err(e) = settings::save_section("hud", self) {
+            error!("{e}");
+        }
+    }
+}
+
+/// Side length of a hotbar slot in points.
+const HOTBAR_SLOT: f32 = 36.0;
+/// Number of hotbar slots shown around the selected one.
+const HOTBAR_VISIBLE: usize = 9;
+
+/// Shows the crosshair while the cursor is grabbed, the hotbar and the
+/// optional coordinates.
+///
+/// Everything is painted in egui points, so it scales with the window and
+/// the UI scale factor, and does not take any mouse input.
+/// The crosshair is hidden while an egui widget has the keyboard focus.
+fn hud_overlay(
+    mut egui_context: EguiContexts,
+    mouselook: Res<MouseLook>,
+    mut settings: ResMut<HudSettings>,
+    player: Query<(&Transform, &Hotbar), With<PlayerController>>,
+) {
+    let Ok((transform, hotbar)) = player.get_single() else {
+        return;
+    };
+    let ctx = egui_context.ctx_mut();
+
+    // Edit a copy to only trigger change detection on changes
+    let mut hud = settings.clone();
+    egui::Window::new("HUD")
+        .default_open(false)
+        .show(ctx, |ui| {
+            egui::ComboBox::from_label("Crosshair")
+                .selected_text(format!("{:?}", hud.crosshair))
+                .show_ui(ui, |ui| {
+                    for style in CrosshairStyle::ALL {
+                        ui.selectable_value(&mut hud.crosshair, style, format!("{style:?}"));
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.color_edit_button_srgb(&mut hud.color);
+                ui.label("Crosshair Color");
+            });
+            ui.checkbox(&mut hud.coordinates, "Coordinates");
+        });
+    if hud != *settings {
+        hud.save();
+        *settings = hud;
+    }
+
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    let screen = ctx.screen_rect();
+    if mouselook.active && !ctx.wants_keyboard_input() {
+        let [r, g, b] = settings.color;
+        let color = Color32::from_rgb(r, g, b);
+        let center = screen.center();
+        let stroke = egui::Stroke::new(2.0, color);
+        match settings.crosshair {
+            CrosshairStyle::Cross => {
+                painter.line_segment(
+                    [center - egui::vec2(8.0, 0.0), center + egui::vec2(8.0, 0.0)],
+                    stroke,
+                );
+                painter.line_segment(
+                    [center - egui::vec2(0.0, 8.0), center + egui::vec2(0.0, 8.0)],
+                    stroke,
+                );
+            }
+            CrosshairStyle::Dot => painter.circle_filled(center, 2.5, color),
+            CrosshairStyle::Circle => painter.circle_stroke(center, 6.0, stroke),
+            CrosshairStyle::Hidden => {}
+        }
+    }
+
+    // Slots around the selected block, which is highlighted
+    let blocks = blocks().read().unwrap();
+    let name = |id: BlockId| {
+        blocks
+            .get(&id)
+            .map_or_else(|| id.0.to_string(), |b| b.name.clone())
+    };
+    let len = hotbar.blocks.len();
+    let visible = len.min(HOTBAR_VISIBLE);
+    let first = (hotbar.selected + len).saturating_sub(visible / 2);
+    let width = visible as f32 * HOTBAR_SLOT;
+    let min = egui::pos2(
+        screen.center().x - width / 2.0,
+        screen.bottom() - 16.0 - HOTBAR_SLOT,
+    );
+    for i in 0..visible {
+        let index = (first + i) % len;
+        let slot = egui::Rect::from_min_size(
+            min + egui::vec2(i as f32 * HOTBAR_SLOT, 0.0),
+            egui::Vec2::splat(HOTBAR_SLOT),
+        )
+        .shrink(2.0);
+        painter.rect_filled(slot, 4.0, Color32::from_black_alpha(160));
+        if index == hotbar.selected {
+            painter.rect_stroke(slot, 4.0, egui::Stroke::new(2.0, Color32::WHITE));
+        }
+        painter.with_clip_rect(slot).text(
+            slot.center(),
+            egui::Align2::CENTER_CENTER,
+            name(hotbar.blocks[index]),
+            egui::FontId::proportional(10.0),
+            Color32::WHITE,
+        );
+    }
+    // The full name of the selected block above the slots
+    if let Some(selected) = hotbar.selected() {
+        painter.text(
+            egui::pos2(screen.center().x, min.y - 4.0),
+            egui::Align2::CENTER_BOTTOM,
+            name(selected),
+            egui::FontId::proportional(14.0),
+            Color32::WHITE,
+        );
+    }
+
+    if settings.coordinates {
+        let pos = transform.translation.floor().as_ivec3();
+        painter.text(
+            screen.left_bottom() + egui::vec2(8.0, -8.0),
+            egui::Align2::LEFT_BOTTOM,
+            format!("{} {} {}", pos.x, pos.y, pos.z),
+            egui::FontId::monospace(14.0),
+            Color32::WHITE,
+        );
+    }
+}
+
+/// Key that toggles the debug overlay.
+const DEBUG_OVERLAY_KEY: KeyCode = KeyCode::F3;
+
+/// Returns the compass direction of `forward`, with north towards -Z.
+fn compass(forward: Vec3) -> char {
+    let forward = forward.xz();
+    if forward.x.abs() > forward.y.abs() {
+        if forward.x > 0.0 {
+            'E'
+        } else {
+            'W'
+        }
+    } else if forward.y > 0.0 {
+        'S'
+    } else {
+        'N'
+    }
+}
+
+/// Shows the position, chunk, facing and FPS in the top left corner.
+///
+/// The predicted chunk is the one the player moves towards, its generation is
+/// prioritized.
+fn debug_overlay(
+    mut egui_context: EguiContexts,
+    key: Res<Input<KeyCode>>,
+    diagnostics: Res<DiagnosticsStore>,
+    settings: Res<PlayerSettings>,
+    player: Query<(&Transform, &PlayerController)>,
+    mut visible: Local<bool>,
+) {
+    if key.just_pressed(DEBUG_OVERLAY_KEY) {
+        *visible = !*visible;
+    }
+    if !*visible {
+        return;
+    }
+    let Ok((transform, controller)) = player.get_single() else {
+        return;
+    };
+
+    let pos = transform.translation;
+    let predicted =
+        VoxelWorld::predicted_chunk(pos, controller.velocity, settings.generation_lookahead);
+    let fps = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.average())
+        .unwrap_or_default();
+    egui::Area::new("debug_overlay")
+        .anchor(egui::Align2::LEFT_TOP, egui::vec2(8.0, 8.0))
+        .interactable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(format!("XYZ: {:.2} {:.2} {:.2}", pos.x, pos.y, pos.z));
+            ui.label(format!("Chunk: {}", VoxelWorld::chunk_pos(pos)));
+            ui.label(format!("Predicted: {predicted}"));
+            ui.label(format!("Facing: {}", compass(transform.forward())));
+            ui.label(format!("FPS: {fps:.0}"));
+        });
+}
+
+/// Edits the entity data of the targeted block, like the color of a lamp.
+fn block_entity_popup(
+    mut egui_context: EguiContexts,
+    target: Res<TargetedBlock>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    let Some(target) = target.0 else {
+        return;
+    };
+    let pos = world.block_origin(target.hit, &blocks().read().unwrap());
+    let Some(mut entity) = world.block_entity(pos).cloned() else {
+        return;
+    };
+
+    let mut changed = false;
+    egui::Window::new("Block Entity")
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8.0, -8.0))
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(format!("Block: {pos}"));
+            match &mut entity {
+                BlockEntityData::Lamp { color } => {
+                    ui.horizontal(|ui| {
+                        ui.label("Light Color");
+                        changed |= ui.color_edit_button_rgb(color).changed();
+                    });
+                }
+            }
+        });
+    if changed {
+        world.set_block_entity(pos, Some(entity));
+    }
+}
+
+/// Inputs of the world selection screen.
+#[derive(Default)]
+struct WorldSelectForm {
+    /// Names of the saved worlds, `None` if they have to be listed again
+    worlds: Option<Vec<String>>,
+    name: String,
+    seed: u32,
+    /// World that is deleted after confirmation
+    delete: Option<String>,
+    error: Option<String>,
+}
+
+/// Lists the saved worlds to open, create or delete them.
+fn world_select(
+    mut cmds: Commands,
+    mut egui_context: EguiContexts,
+    mut form: Local<WorldSelectForm>,
+    mut state: ResMut<NextState<AppState>>,
+    args: Res<Args>,
+    gen: Res<WorldGen>,
+    saves: Res<PendingSaves>,
+) {
+    let form = &mut *form;
+    let root = &args.world;
+    let worlds = form.worlds.get_or_insert_with(|| {
+        saves::list_worlds(root).unwrap_or_else(|e| {
+            error!("Failed to list the worlds: {e}");
+            Vec::new()
+        })
+    });
+
+    let mut result = Ok(());
+    let mut refresh = false;
+    egui::CentralPanel::default().show(egui_context.ctx_mut(), |ui| {
+        ui.heading("Worlds");
+        if let Some((saved, started)) = saves.progress() {
+            ui.add(
+                egui::ProgressBar::new(saved as f32 / started as f32)
+                    .text(format!("Saving chunks: {saved}/{started}")),
+            );
+        }
+        for name in worlds.iter() {
+            ui.horizontal(|ui| {
+                ui.label(name);
+                if ui.button("Open").clicked() {
+                    result = saves::open_world(&mut cmds, saves::world_dir(root, name));
+                    if result.is_ok() {
+                        state.set(AppState::Running);
+                    }
+                }
+                if form.delete.as_ref() == Some(name) {
+                    if ui.button("Confirm Delete").clicked() {
+                        result = saves::delete_world(root, name);
+                        form.delete = None;
+                        refresh = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        form.delete = None;
+                    }
+                } else if ui
+                    .add_enabled(saves.progress().is_none(), egui::Button::new("Delete"))
+                    .on_disabled_hover_text("Wait until the chunks have been saved")
+                    .clicked()
+                {
+                    form.delete = Some(name.clone());
+                }
+            });
+        }
+
+        ui.separator();
+
+        ui.heading("New World");
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut form.name);
+        });
+        ui.add(DragValue::new(&mut form.seed).prefix("Seed: "));
+        if ui.button("Create").clicked() {
+            let gen = WorldGen {
+                seed: form.seed,
+                ..gen.clone()
+            };
+            result = saves::create_world(root, &form.name, gen)
+                .and_then(|dir| saves::open_world(&mut cmds, dir));
+            if result.is_ok() {
+                form.name.clear();
+                refresh = true;
+                state.set(AppState::Running);
+            }
+        }
+        // Worlds can't be saved without filesystem
+        #[cfg(target_arch = "wasm32")]
+        if ui.button("Play Without Saving").clicked() {
+            let gen = WorldGen {
+                seed: form.seed,
+                ..gen.clone()
+            };
+            result = saves::open_unsaved_world(&mut cmds, gen);
+            if result.is_ok() {
+                state.set(AppState::Running);
+            }
+        }
+
+        if let Some(error) = &form.error {
+            ui.separator();
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    });
+
+    if let Err(e) = result {
+        error!("{e}");
+        form.error = Some(e.to_string());
+    }
+    if refresh {
+        form.worlds = None;
+    }
+}
+
+fn in_error_state(state: Res<State<AppState>>) -> bool {
+    matches!(state.get(), AppState::Error(_))
+}
+
+/// Displays the loading error instead of panicking.
+fn show_error(
+    mut egui_context: EguiContexts,
+    state: Res<State<AppState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let AppState::Error(message) = state.get() else {
+        return;
+    };
+    egui::CentralPanel::default().show(egui_context.ctx_mut(), |ui| {
+        ui.heading("Error");
+        ui.label(message);
+        ui.separator();
+        if ui.button("Quit").clicked() {
+            exit.send(AppExit);
+        }
     });
 }
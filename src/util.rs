@@ -2,6 +2,8 @@ use std::f32::consts::PI;
 use std::ops::Range;
 
 use bevy::math::{IVec3, Quat, UVec3, Vec3};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serde::Deserialize;
 
 /// 3d world direction.
@@ -34,6 +36,11 @@ impl Direction {
         ]
     }
 
+    /// Returns the direction of an axis-aligned vector, `None` for zero.
+    ///
+    /// Diagonal vectors are not rejected: the first negative component
+    /// (x, y, z) wins, otherwise the first positive one.
+    /// So `(-1, 1, 0)` is [`Direction::NegX`], but `(1, -1, 0)` is [`Direction::NegY`].
     pub fn from_ivec3(v: IVec3) -> Option<Self> {
         match v {
             _ if v.x < 0 => Some(Self::NegX),
@@ -140,10 +147,32 @@ pub fn for_uvec3(from: UVec3, to: UVec3, mut f: impl FnMut(UVec3)) {
     }
 }
 
+/// Returns a random number generator for the world `seed` and the chunk or
+/// cell at `pos`, so that the generation passes are reproducible.
+///
+/// The seed and the coordinates are hashed one after another with the
+/// SplitMix64 finalizer, so that adjacent chunks are uncorrelated.
+/// The generator is portable, its sequence does not change between
+/// platforms and versions of `rand` like the one of `StdRng` may.
+pub fn chunk_rng(seed: u32, pos: IVec3) -> impl Rng {
+    fn mix(mut z: u64) -> u64 {
+        z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    let hash = [pos.x, pos.y, pos.z]
+        .into_iter()
+        .fold(mix(seed as u64), |h, v| mix(h ^ v as u32 as u64));
+    ChaCha8Rng::seed_from_u64(hash)
+}
+
 #[cfg(test)]
 mod test {
-    use super::Direction;
+    use super::{chunk_rng, Direction};
     use bevy::prelude::*;
+    use bevy::utils::HashSet;
+    use rand::Rng;
 
     #[test]
     fn rotation() {
@@ -171,4 +200,62 @@ mod test {
         let p = Quat::from(Direction::PosY) * (pos - center) + center;
         assert_eq!(p.round(), Vec3::new(4.0, 31.0, 2.0));
     }
+
+    #[test]
+    fn chunk_rng_reproducible() {
+        let sample = |seed, pos| {
+            let mut rng = chunk_rng(seed, pos);
+            [(); 8].map(|_| rng.gen::<u32>())
+        };
+        let pos = IVec3::new(-3, 7, 12);
+        assert_eq!(sample(42, pos), sample(42, pos));
+        assert_ne!(sample(42, pos), sample(43, pos));
+        assert_ne!(sample(42, pos), sample(42, pos + IVec3::Y));
+
+        // Adjacent chunks, also around zero, give distinct and uniform values
+        let mut firsts = HashSet::new();
+        let mut sum = 0.0;
+        let mut n = 0;
+        for x in -8..8 {
+            for y in -4..4 {
+                for z in -8..8 {
+                    let mut rng = chunk_rng(1, IVec3::new(x, y, z));
+                    assert!(firsts.insert(rng.gen::<u64>()));
+                    sum += rng.gen::<f64>();
+                    n += 1;
+                }
+            }
+        }
+        let mean = sum / n as f64;
+        assert!((mean - 0.5).abs() < 0.02, "{mean}");
+    }
+
+    #[test]
+    fn from_ivec3() {
+        for d in Direction::all() {
+            assert_eq!(Direction::from_ivec3(IVec3::from(d)), Some(d));
+            // Only the sign matters
+            assert_eq!(Direction::from_ivec3(IVec3::from(d) * 5), Some(d));
+        }
+        assert_eq!(Direction::from_ivec3(IVec3::ZERO), None);
+        assert_eq!(
+            Direction::from_ivec3(IVec3::new(1, 0, 0)),
+            Some(Direction::PosX)
+        );
+
+        // Diagonals: negative components first, then in x, y, z order
+        assert_eq!(
+            Direction::from_ivec3(IVec3::new(-1, 1, 0)),
+            Some(Direction::NegX)
+        );
+        assert_eq!(
+            Direction::from_ivec3(IVec3::new(1, -1, 0)),
+            Some(Direction::NegY)
+        );
+        assert_eq!(
+            Direction::from_ivec3(IVec3::new(0, 1, 1)),
+            Some(Direction::PosY)
+        );
+        assert_eq!(Direction::from_ivec3(IVec3::ONE), Some(Direction::PosX));
+    }
 }
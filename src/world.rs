@@ -1,46 +1,709 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::Duration;
 
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics, RegisterDiagnostic};
+use bevy::ecs::query::Has;
+use bevy::ecs::system::SystemParam;
+use bevy::pbr::wireframe::Wireframe;
 use bevy::prelude::*;
-use bevy::tasks::{AsyncComputeTaskPool, Task};
-use bevy::utils::hashbrown::HashMap;
-use futures_lite::future;
+use bevy::render::primitives::{Aabb, Frustum};
+use bevy::render::render_resource::WgpuFeatures;
+use bevy::render::renderer::RenderDevice;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::utils::hashbrown::{HashMap, HashSet};
+// Unlike the std instant, this one also works on the web
+use bevy::utils::Instant;
+use crossbeam_channel::{Receiver, Sender};
+use rand::Rng;
 
-use crate::block::blocks;
-use crate::chunk::{Border, Chunk};
+use crate::block::{blocks, part_meta, part_offset, Block, BlockId, MAX_BLOCK_SIZE};
+use crate::block_entity::BlockEntityData;
+use crate::chunk::{Border, Chunk, Connectivity};
 use crate::generation::{generate_chunk, WorldGen};
+use crate::interaction::TargetedBlock;
 use crate::player::{PlayerController, PlayerSettings};
-use crate::util::Direction;
+use crate::storage::{handle_saves, on_exit, queue_saves, ChunkStorage, PendingSaves};
+use crate::textures::TileTextures;
+use crate::util::{chunk_rng, for_uvec3, Direction};
 use crate::{AppState, BlockMat};
 
+/// The maximum light level.
+pub const MAX_LIGHT: u8 = 15;
+
 /// The world, consisting of smaller chunks
 #[derive(Default, Resource)]
 pub struct VoxelWorld {
     chunks: HashMap<IVec3, Entity>,
+    /// Block data of the generated chunks
+    data: HashMap<IVec3, Arc<Chunk>>,
+    /// Block changes that have not been queued yet, see [`BlockUpdateQueue`]
+    changes: Vec<(IVec3, BlockId, ChangeCause)>,
+    /// Chunks that have been changed since they were loaded or saved
+    modified: HashSet<IVec3>,
+    /// Content hashes of the chunks as they were generated,
+    /// missing for chunks that were loaded from or written to the storage
+    generated: HashMap<IVec3, u64>,
+    /// Chunks whose meshes have to be updated, see [`VoxelWorld::mark_dirty`]
+    dirty: HashSet<IVec3>,
+    /// Extents of the multi-voxel blocks, whose voxels are removed together
+    extents: HashMap<BlockId, UVec3>,
 }
 
 impl VoxelWorld {
+    /// Returns the position of the chunk containing the world position `p`.
+    ///
+    /// The block at `b` spans from `b` to `b + 1`, so this is consistent with
+    /// [`VoxelWorld::chunk_of_block`].
     pub fn chunk_pos(p: Vec3) -> IVec3 {
-        ((p - (Chunk::SIZE as f32 / 2.0)) / Chunk::SIZE as f32)
-            .round()
-            .as_ivec3()
+        Self::chunk_of_block(p.floor().as_ivec3())
     }
+    /// Returns the world position of the origin (lowest corner) of the chunk `p`.
     pub fn world_pos(p: IVec3) -> Vec3 {
         p.as_vec3() * Chunk::SIZE as f32
     }
 
+    /// Returns the chunk that something at `pos` reaches in `seconds` with its `velocity`.
+    pub fn predicted_chunk(pos: Vec3, velocity: Vec3, seconds: f32) -> IVec3 {
+        Self::chunk_pos(pos + velocity * seconds.max(0.0))
+    }
+
+    /// Returns the position of the chunk containing the `block`.
+    pub fn chunk_of_block(block: IVec3) -> IVec3 {
+        block.div_euclid(IVec3::splat(Chunk::SIZE as i32))
+    }
+    /// Returns the chunks whose meshes can change with the `block`: its own
+    /// chunk and the up to three neighbors whose faces it touches.
+    ///
+    /// The diagonal neighbors of edges and corners only share an edge with
+    /// the block, which does not affect their face culling.
+    pub fn affected_chunks(block: IVec3) -> Vec<IVec3> {
+        let chunk = Self::chunk_of_block(block);
+        let mut chunks = vec![chunk];
+        for d in Direction::all() {
+            let neighbor = Self::chunk_of_block(block + IVec3::from(d));
+            if neighbor != chunk {
+                chunks.push(neighbor);
+            }
+        }
+        chunks
+    }
+
+    /// Schedules the chunks affected by a change of the `block` for remeshing.
+    pub fn mark_dirty(&mut self, block: IVec3) {
+        self.dirty.extend(Self::affected_chunks(block));
+    }
+
+    /// Returns the position of the `block` within its chunk.
+    pub fn local_of_block(block: IVec3) -> UVec3 {
+        block
+            .rem_euclid(IVec3::splat(Chunk::SIZE as i32))
+            .as_uvec3()
+    }
+
+    /// Meshes the loaded chunk at `pos` like the chunk pipeline,
+    /// treating the faces towards unloaded neighbors as visible.
+    pub fn mesh_chunk(
+        &self,
+        pos: IVec3,
+        blocks: &HashMap<BlockId, Block>,
+        textures: &TileTextures,
+    ) -> Option<Mesh> {
+        let chunk = self.data.get(&pos)?;
+        let borders = Direction::all().map(|d| {
+            self.data
+                .get(&(pos + IVec3::from(d)))
+                .map_or(Border::new(), |c| c.border(d.inverse(), blocks))
+        });
+        Some(chunk.mesh(borders, blocks, textures))
+    }
+
+    /// Returns the y coordinate of the highest non-air block at the `local`
+    /// xz position of a column generated by [`WorldGen::generate_column`].
+    pub fn find_surface_y(column: &[(i32, Chunk)], local: UVec2) -> Option<i32> {
+        column.iter().rev().find_map(|(y, chunk)| {
+            (0..Chunk::SIZE as u32).rev().find_map(|ly| {
+                let block = chunk[UVec3::new(local.x, ly, local.y)];
+                (block != BlockId::AIR).then_some(y * Chunk::SIZE as i32 + ly as i32)
+            })
+        })
+    }
+
+    /// Returns the positions of all loaded chunks.
+    pub fn loaded(&self) -> impl Iterator<Item = IVec3> + '_ {
+        self.data.keys().copied()
+    }
+
+    /// Returns the block at `pos` or `None` if its chunk is not loaded.
+    pub fn get_block(&self, pos: IVec3) -> Option<BlockId> {
+        let chunk = self.data.get(&Self::chunk_of_block(pos))?;
+        Some(chunk[Self::local_of_block(pos)])
+    }
+    /// Returns the metadata of the block at `pos` or `None` if its chunk is not loaded.
+    pub fn get_meta(&self, pos: IVec3) -> Option<u8> {
+        let chunk = self.data.get(&Self::chunk_of_block(pos))?;
+        Some(chunk.meta(Self::local_of_block(pos)))
+    }
+
+    /// Returns the entity data of the block at `pos`.
+    pub fn block_entity(&self, pos: IVec3) -> Option<&BlockEntityData> {
+        let chunk = self.data.get(&Self::chunk_of_block(pos))?;
+        chunk.entity(Self::local_of_block(pos))
+    }
+
+    /// Sets or removes the entity data of the block at `pos`.
+    /// Returns `false` if the chunk is not loaded.
+    ///
+    /// Changing the block removes its entity data.
+    pub fn set_block_entity(&mut self, pos: IVec3, entity: Option<BlockEntityData>) -> bool {
+        let chunk_pos = Self::chunk_of_block(pos);
+        let local = Self::local_of_block(pos);
+        let Some(chunk) = self.data.get_mut(&chunk_pos) else {
+            return false;
+        };
+        if chunk.entity(local) != entity.as_ref() {
+            Arc::make_mut(chunk).set_entity(local, entity);
+            self.modified.insert(chunk_pos);
+        }
+        true
+    }
+
+    /// Returns the positions of the blocks with entity data in the loaded chunk `chunk_pos`.
+    pub fn block_entities_in(&self, chunk_pos: IVec3) -> impl Iterator<Item = IVec3> + '_ {
+        let origin = chunk_pos * Chunk::SIZE as i32;
+        self.data
+            .get(&chunk_pos)
+            .into_iter()
+            .flat_map(move |chunk| chunk.entities().map(move |(p, _)| origin + p.as_ivec3()))
+    }
+
+    /// Replaces the block at `pos`, resetting its metadata.
+    /// Returns the previous block or `None` if the chunk is not loaded.
+    pub fn set_block(&mut self, pos: IVec3, block: BlockId, cause: ChangeCause) -> Option<BlockId> {
+        self.set_block_meta(pos, block, 0, cause)
+    }
+    /// Replaces the block and metadata at `pos`.
+    /// Returns the previous block or `None` if the chunk is not loaded.
+    ///
+    /// Replacing a voxel of a multi-voxel block removes its other voxels.
+    pub fn set_block_meta(
+        &mut self,
+        pos: IVec3,
+        block: BlockId,
+        meta: u8,
+        cause: ChangeCause,
+    ) -> Option<BlockId> {
+        let (old, old_meta) = self.set_voxel(pos, block, meta, cause)?;
+        if old != block {
+            self.remove_parts(pos, old, old_meta, cause);
+        }
+        Some(old)
+    }
+
+    /// Replaces a single voxel, returning the previous block and metadata.
+    fn set_voxel(
+        &mut self,
+        pos: IVec3,
+        block: BlockId,
+        meta: u8,
+        cause: ChangeCause,
+    ) -> Option<(BlockId, u8)> {
+        let chunk_pos = Self::chunk_of_block(pos);
+        let chunk = Arc::make_mut(self.data.get_mut(&chunk_pos)?);
+        let local = Self::local_of_block(pos);
+        let old = std::mem::replace(&mut chunk[local], block);
+        let old_meta = chunk.meta(local);
+        chunk.set_meta(local, meta);
+        if old != block {
+            chunk.set_entity(local, None);
+        }
+        if old != block || old_meta != meta {
+            self.modified.insert(chunk_pos);
+        }
+        if old != block {
+            self.changes.push((pos, old, cause));
+        }
+        Some((old, old_meta))
+    }
+
+    /// Sets the multi-voxel blocks of the registry, whose voxels are
+    /// removed together when one of them is replaced.
+    pub fn set_multi_voxel_blocks(&mut self, blocks: &HashMap<BlockId, Block>) {
+        self.extents = blocks
+            .iter()
+            .filter(|(_, b)| b.is_multi_voxel())
+            .map(|(id, b)| (*id, b.extent))
+            .collect();
+    }
+
+    /// Removes the remaining voxels of the multi-voxel block `old`, whose
+    /// voxel at `pos` with the metadata `old_meta` has been replaced.
+    fn remove_parts(&mut self, pos: IVec3, old: BlockId, old_meta: u8, cause: ChangeCause) {
+        let Some(&extent) = self.extents.get(&old) else {
+            return;
+        };
+        let origin = pos - part_offset(old_meta).as_ivec3();
+        let mut parts = Vec::new();
+        for_uvec3(UVec3::ZERO, extent + 1, |offset| parts.push(offset));
+        for offset in parts {
+            let p = origin + offset.as_ivec3();
+            // Skip voxels that have been replaced in the meantime
+            if p != pos
+                && self.get_block(p) == Some(old)
+                && self.get_meta(p) == Some(part_meta(offset))
+            {
+                self.set_voxel(p, BlockId::AIR, 0, cause);
+            }
+        }
+    }
+
+    /// Returns the origin of the multi-voxel block at `pos`, or `pos` for all other blocks.
+    pub fn block_origin(&self, pos: IVec3, blocks: &HashMap<BlockId, Block>) -> IVec3 {
+        match self.get_block(pos).and_then(|b| blocks.get(&b)) {
+            Some(block) if block.is_multi_voxel() => {
+                pos - part_offset(self.get_meta(pos).unwrap_or_default()).as_ivec3()
+            }
+            _ => pos,
+        }
+    }
+
+    /// Places the block `id` with its origin at `pos`.
+    ///
+    /// Multi-voxel blocks are only placed if all their voxels are loaded air.
+    /// Returns if the block has been placed.
+    pub fn place_block(
+        &mut self,
+        pos: IVec3,
+        id: BlockId,
+        block: &Block,
+        cause: ChangeCause,
+    ) -> bool {
+        let voxels = block.voxels();
+        if !voxels
+            .iter()
+            .all(|p| self.get_block(pos + p.as_ivec3()) == Some(BlockId::AIR))
+        {
+            return false;
+        }
+        for offset in voxels {
+            self.set_block_meta(pos + offset.as_ivec3(), id, part_meta(offset), cause);
+        }
+        true
+    }
+
+    /// Breaks the block at `pos`, removing all voxels of a multi-voxel block.
+    /// Returns the broken block or `None` if the chunk is not loaded.
+    pub fn break_block(
+        &mut self,
+        pos: IVec3,
+        blocks: &HashMap<BlockId, Block>,
+        cause: ChangeCause,
+    ) -> Option<BlockId> {
+        let origin = self.block_origin(pos, blocks);
+        let id = self.get_block(origin)?;
+        let Some(block) = blocks.get(&id).filter(|b| b.is_multi_voxel()) else {
+            return self.set_block(pos, BlockId::AIR, cause);
+        };
+        for offset in block.voxels() {
+            let p = origin + offset.as_ivec3();
+            // Skip voxels that have been replaced in the meantime
+            if self.get_block(p) == Some(id) && self.get_meta(p) == Some(part_meta(offset)) {
+                self.set_block(p, BlockId::AIR, cause);
+            }
+        }
+        Some(id)
+    }
+
+    /// Fills the loaded blocks between `min` and `max` (inclusive) with `block`.
+    /// Returns the number of changed blocks.
+    pub fn fill_box(
+        &mut self,
+        min: IVec3,
+        max: IVec3,
+        block: BlockId,
+        cause: ChangeCause,
+    ) -> usize {
+        self.edit_box(min, max, cause, |_, _| Some(block))
+    }
+
+    /// Replaces the loaded `from` blocks between `min` and `max` (inclusive) with `to`.
+    /// Returns the number of changed blocks.
+    pub fn replace_in_box(
+        &mut self,
+        min: IVec3,
+        max: IVec3,
+        from: BlockId,
+        to: BlockId,
+        cause: ChangeCause,
+    ) -> usize {
+        self.edit_box(min, max, cause, |_, b| (b == from).then_some(to))
+    }
+
+    /// Replaces the blocks for which `f` returns a new block, given their
+    /// position and block, between `min` and `max` (inclusive).
+    /// Returns the number of changed blocks.
+    ///
+    /// The box is processed chunk by chunk, so each chunk is copied at most once.
+    /// Unloaded chunks are skipped.
+    pub fn edit_box(
+        &mut self,
+        min: IVec3,
+        max: IVec3,
+        cause: ChangeCause,
+        mut f: impl FnMut(IVec3, BlockId) -> Option<BlockId>,
+    ) -> usize {
+        let (min, max) = (min.min(max), min.max(max));
+        let Self {
+            data,
+            changes,
+            modified,
+            extents,
+            ..
+        } = self;
+
+        let mut changed = 0;
+        let mut parts = Vec::new();
+        let (chunk_min, chunk_max) = (Self::chunk_of_block(min), Self::chunk_of_block(max));
+        for cx in chunk_min.x..=chunk_max.x {
+            for cy in chunk_min.y..=chunk_max.y {
+                for cz in chunk_min.z..=chunk_max.z {
+                    let chunk_pos = IVec3::new(cx, cy, cz);
+                    let Some(chunk) = data.get_mut(&chunk_pos) else {
+                        continue;
+                    };
+                    let origin = chunk_pos * Chunk::SIZE as i32;
+                    let start = (min - origin).max(IVec3::ZERO).as_uvec3();
+                    let end = (max - origin).min(IVec3::splat(Chunk::SIZE as i32 - 1));
+                    let end = end.as_uvec3();
+
+                    for x in start.x..=end.x {
+                        for y in start.y..=end.y {
+                            for z in start.z..=end.z {
+                                let local = UVec3::new(x, y, z);
+                                let pos = origin + local.as_ivec3();
+                                let old = chunk[local];
+                                let Some(new) = f(pos, old).filter(|b| *b != old) else {
+                                    continue;
+                                };
+                                let chunk = Arc::make_mut(chunk);
+                                if extents.contains_key(&old) {
+                                    parts.push((pos, old, chunk.meta(local)));
+                                }
+                                chunk[local] = new;
+                                chunk.set_meta(local, 0);
+                                chunk.set_entity(local, None);
+                                changes.push((pos, old, cause));
+                                modified.insert(chunk_pos);
+                                changed += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // Remove the multi-voxel blocks that were only partially replaced
+        for (pos, old, meta) in parts {
+            self.remove_parts(pos, old, meta, cause);
+        }
+        changed
+    }
+
+    /// Replaces the blocks connected to `seed` that have the same id with `block`.
+    ///
+    /// At most `limit` blocks are replaced, starting with the ones closest to the seed.
+    /// Returns the number of changed blocks.
+    pub fn flood_fill(
+        &mut self,
+        seed: IVec3,
+        block: BlockId,
+        limit: usize,
+        cause: ChangeCause,
+    ) -> usize {
+        let Some(from) = self.get_block(seed) else {
+            return 0;
+        };
+        if from == block {
+            return 0;
+        }
+
+        let mut visited: HashSet<IVec3> = [seed].into_iter().collect();
+        let mut queue = VecDeque::from([seed]);
+        let mut filled = Vec::new();
+        while let Some(pos) = queue.pop_front() {
+            if filled.len() >= limit {
+                break;
+            }
+            filled.push(pos);
+            for d in Direction::all() {
+                let next = pos + IVec3::from(d);
+                if self.get_block(next) == Some(from) && visited.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        for &pos in &filled {
+            self.set_block(pos, block, cause);
+        }
+        filled.len()
+    }
+
+    /// Estimates the sky light reaching the top of the block at `pos`.
+    ///
+    /// Open sky gives full light, otherwise light leaks in from the sides,
+    /// decreasing with the distance to the opaque block above.
+    /// Unloaded chunks are treated as open sky.
+    pub fn sky_light(&self, pos: IVec3, blocks: &HashMap<BlockId, Block>) -> u8 {
+        for h in 1..=MAX_LIGHT as i32 {
+            match self.get_block(pos + h * IVec3::Y) {
+                Some(id) if blocks.get(&id).is_some_and(|b| b.opaque) => return h as u8 - 1,
+                Some(_) => {}
+                None => break,
+            }
+        }
+        MAX_LIGHT
+    }
+
+    /// Casts a ray from `origin` along `dir` and returns the first non-air block
+    /// together with the position in front of the face that was hit.
+    ///
+    /// Blocks are hit if the ray enters them within `max_distance`.
+    /// A ray passing exactly through an edge or corner hits the blocks beside
+    /// it, so it never slips between two diagonally adjacent blocks.
+    /// Stops at unloaded chunks.
+    pub fn raycast(&self, origin: Vec3, dir: Vec3, max_distance: f32) -> Option<(IVec3, IVec3)> {
+        let dir = dir.normalize_or_zero();
+        if dir == Vec3::ZERO {
+            return None;
+        }
+
+        // Voxel traversal (Amanatides & Woo)
+        let mut pos = origin.floor().as_ivec3();
+        let step = dir.signum().as_ivec3();
+        let delta = dir.recip().abs();
+        let next = Vec3::select(dir.cmpgt(Vec3::ZERO), pos.as_vec3() + 1.0, pos.as_vec3());
+        let mut t_max = Vec3::select(
+            dir.cmpeq(Vec3::ZERO),
+            Vec3::splat(f32::INFINITY),
+            (next - origin) / dir,
+        );
+
+        // Offset along the axes of the `mask` bits
+        let offset = |mask: u32| {
+            let axes = BVec3::new(mask & 1 != 0, mask & 2 != 0, mask & 4 != 0);
+            IVec3::select(axes, step, IVec3::ZERO)
+        };
+
+        let mut prev = pos;
+        let mut t = 0.0;
+        while t <= max_distance {
+            match self.get_block(pos)? {
+                BlockId::AIR => {}
+                _ => return Some((pos, prev)),
+            }
+
+            // Cross all faces at the same distance at once
+            t = t_max.min_element();
+            let crossed = t_max.cmple(Vec3::splat(t + 1e-5 * t.max(1.0)));
+            if t > max_distance {
+                break;
+            }
+            // Check the blocks beside the crossed edge or corner, the ones
+            // sharing a face with the current block first
+            let mask = crossed.bitmask();
+            for ones in 1..mask.count_ones() {
+                for side_mask in (1..mask).filter(|m| m & !mask == 0 && m.count_ones() == ones) {
+                    let side = pos + offset(side_mask);
+                    if self.get_block(side).is_some_and(|b| b != BlockId::AIR) {
+                        // The free block in front of one of its faces
+                        return Some((side, pos + offset(side_mask & (side_mask - 1))));
+                    }
+                }
+            }
+            prev = pos + offset(mask & (mask - 1));
+            pos += offset(mask);
+            t_max += Vec3::select(crossed, delta, Vec3::ZERO);
+        }
+        None
+    }
+
+    /// Returns the entities of the loaded and generating chunks.
+    pub fn chunk_entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.chunks.values().copied()
+    }
+
+    /// Returns the loaded chunks that have been modified since they were loaded
+    /// or taken, and marks them as unmodified.
+    ///
+    /// Chunks that still match their generated state are skipped.
+    pub fn take_modified(&mut self) -> Vec<(IVec3, Arc<Chunk>)> {
+        let modified = std::mem::take(&mut self.modified);
+        let mut chunks = Vec::with_capacity(modified.len());
+        for pos in modified {
+            if let Some(chunk) = self.data.get(&pos).cloned() {
+                if self.differs_from_generated(pos, &chunk) {
+                    chunks.push((pos, chunk));
+                }
+            }
+        }
+        chunks
+    }
+
+    /// Returns whether the `chunk` has to be saved, because it differs from
+    /// the generated chunk or was loaded from the storage.
+    ///
+    /// Chunks that differ are saved from now on, even if they are restored later,
+    /// so that their file is updated.
+    fn differs_from_generated(&mut self, pos: IVec3, chunk: &Chunk) -> bool {
+        match self.generated.get(&pos) {
+            Some(&hash) if hash == chunk.content_hash() => false,
+            Some(_) => {
+                self.generated.remove(&pos);
+                true
+            }
+            None => true,
+        }
+    }
+
     pub fn clear(&mut self) {
         self.chunks.clear();
+        self.data.clear();
+        self.changes.clear();
+        self.modified.clear();
+        self.generated.clear();
+        self.dirty.clear();
     }
 }
 
+/// The origin of a block change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeCause {
+    /// Placed or broken by the player
+    Player,
+    /// Updated by a simulation like fluids
+    Simulation,
+    /// Undone or redone by the player
+    History,
+    /// Bulk edits of the world edit tools
+    WorldEdit,
+}
+
+/// Queue of the changed blocks, filled with the changes of
+/// [`VoxelWorld::set_block`].
+///
+/// The changes are dispatched once per frame as [`BlockChangedEvent`]s,
+/// so that the simulations only have to look at the changed positions.
+#[derive(Resource, Default, Debug)]
+pub struct BlockUpdateQueue {
+    positions: Vec<IVec3>,
+    /// The block before the first change and the cause of the last change
+    changes: HashMap<IVec3, (BlockId, ChangeCause)>,
+}
+
+impl BlockUpdateQueue {
+    fn push(&mut self, pos: IVec3, old: BlockId, cause: ChangeCause) {
+        if let Some(change) = self.changes.get_mut(&pos) {
+            change.1 = cause;
+        } else {
+            self.positions.push(pos);
+            self.changes.insert(pos, (old, cause));
+        }
+    }
+}
+
+/// Sent for every block that has been modified with [`VoxelWorld::set_block`].
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BlockChangedEvent {
+    pub pos: IVec3,
+    pub old: BlockId,
+    pub new: BlockId,
+    pub cause: ChangeCause,
+}
+
+/// Sent when the chunk at `pos` has been generated or loaded and its
+/// blocks are available in the [`VoxelWorld`].
+///
+/// Sent by the [`WorldSet`], systems ordered after the set receive it in the
+/// same frame, others in the next one.
+#[allow(unused)]
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ChunkGenerated {
+    pub pos: IVec3,
+}
+
+/// Sent when the chunk at `pos` has received a new mesh, after the first
+/// meshing and every remeshing.
+///
+/// Sent by the [`WorldSet`], systems ordered after the set find the mesh on
+/// the `entity` in the same frame, others in the next one.
+#[allow(unused)]
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ChunkMeshed {
+    pub pos: IVec3,
+    pub entity: Entity,
+}
+
+/// Systems loading, generating, meshing and unloading the chunks,
+/// only running in [`AppState::Running`].
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorldSet;
+
+/// Stages of the chunk pipeline within the [`WorldSet`], running in this order.
+///
+/// Loading and unloading chunks around the player is part of the simulation
+/// and runs in [`FixedUpdate`] with the [`TickRate`], before the frame's
+/// [`Update`]. It reads the player transform of the previous frame, which is
+/// the latest one at this point. Handling the finished tasks and uploading
+/// the meshes runs every frame in [`Update`].
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WorldUpdateSet {
+    /// Spawns the chunk entities around the player and starts their tasks.
+    /// Chunks are only added to the world here. Runs in [`FixedUpdate`].
+    Generate,
+    /// Inserts the generated chunk data, which is readable afterwards.
+    HandleGeneration,
+    /// Applies the block changes and starts the mesh tasks of the dirty chunks.
+    /// Block changes made before are meshed in the same frame.
+    InitMesh,
+    /// Uploads the finished meshes and updates their visibility.
+    HandleMesh,
+    /// Unloads the chunks out of range after the new ones have been spawned.
+    /// Runs in [`FixedUpdate`], results of unloaded chunks' tasks are dropped
+    /// when handled.
+    Despawn,
+    /// Removes all chunks on a regeneration, after every other stage.
+    Regenerate,
+}
+
 #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
 struct ChunkPos(IVec3);
 
+impl ChunkPos {
+    /// Returns the bounds of the chunk in world space.
+    fn aabb(self) -> Aabb {
+        let min = VoxelWorld::world_pos(self.0);
+        Aabb::from_min_max(min, min + Vec3::splat(Chunk::SIZE as f32))
+    }
+
+    /// Returns the bounds of the chunk mesh relative to the chunk origin.
+    ///
+    /// They are attached to the chunk entities from their spawn on, so that
+    /// Bevy's frustum and shadow caster culling use them instead of computing
+    /// the bounds from the vertices of the first mesh, which it would not
+    /// update on remeshing.
+    /// They include multi-voxel blocks extending into the next chunks.
+    fn local_aabb() -> Aabb {
+        let max = (Chunk::SIZE as u32 + MAX_BLOCK_SIZE - 1) as f32;
+        Aabb::from_min_max(Vec3::ZERO, Vec3::splat(max))
+    }
+}
+
 #[derive(Component)]
 struct ChunkData(Arc<Chunk>);
 
+/// The chunk is generated by the task with the given id.
 #[derive(Component)]
-struct Generating(Task<Chunk>);
+struct Generating(u64);
 
 #[derive(Component, Debug)]
 struct MissingNeighbors(usize);
@@ -48,168 +711,855 @@ struct MissingNeighbors(usize);
 #[derive(Component)]
 struct RequiresMesh;
 
+/// The chunk is meshed by the task with the given id.
+#[derive(Component)]
+struct Meshing(u64);
+
+/// Progress of a chunk through the generation and meshing pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkState {
+    Generating,
+    /// Generated, waiting for its neighbors or a mesh task
+    Generated,
+    Meshing,
+    Meshed,
+}
+
+/// Read access to the pipeline states of the chunk entities.
+#[derive(SystemParam)]
+pub struct ChunkStates<'w, 's> {
+    query: Query<'w, 's, ChunkStateQuery>,
+}
+
+type ChunkStateQuery = (
+    &'static ChunkPos,
+    Has<Generating>,
+    Has<Meshing>,
+    Has<Handle<Mesh>>,
+);
+
+impl ChunkStates<'_, '_> {
+    /// Returns the positions and states of all chunks.
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, ChunkState)> + '_ {
+        self.query
+            .iter()
+            .map(|(ChunkPos(pos), generating, meshing, meshed)| {
+                let state = match () {
+                    _ if generating => ChunkState::Generating,
+                    _ if meshing => ChunkState::Meshing,
+                    _ if meshed => ChunkState::Meshed,
+                    _ => ChunkState::Generated,
+                };
+                (*pos, state)
+            })
+    }
+}
+
+/// Connected faces of a meshed chunk, recomputed with every mesh.
+#[derive(Component)]
+struct ChunkConnectivity(Connectivity);
+
+/// Hides the chunks that can't be seen through the connected faces of the
+/// chunks between them and the player.
+#[derive(Resource, Debug)]
+pub struct OcclusionCulling {
+    pub enabled: bool,
+    /// Number of chunks in view that have been hidden in the last frame
+    pub culled: usize,
+}
+
+impl Default for OcclusionCulling {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            culled: 0,
+        }
+    }
+}
+
+/// Draws the outlines of the chunks, colored by their pipeline state.
+#[derive(Resource, Debug, Default)]
+pub struct ShowChunkBoundaries(pub bool);
+
+/// Renders the chunk meshes as wireframes to inspect the meshing.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkWireframe {
+    pub enabled: bool,
+    /// Only the chunk of the targeted block is shown as wireframe
+    pub targeted: bool,
+}
+
+impl ChunkWireframe {
+    /// Key that toggles the wireframes.
+    pub const KEY: KeyCode = KeyCode::F4;
+
+    /// Returns whether the chunk at `pos` is shown as wireframe, given the
+    /// chunk of the targeted block.
+    pub fn shows(self, pos: IVec3, target: Option<IVec3>) -> bool {
+        self.enabled && (!self.targeted || target == Some(pos))
+    }
+}
+
+/// Tints the chunk meshes with their [`ChunkSeedColor`] to spot chunk seams
+/// and features that repeat across chunks.
+#[derive(Resource, Debug, Default)]
+pub struct ChunkSeedOverlay(pub bool);
+
+impl ChunkSeedOverlay {
+    /// Key that toggles the overlay.
+    pub const KEY: KeyCode = KeyCode::F7;
+}
+
+/// Debug color of a chunk, derived from the random generator of its
+/// generation passes.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct ChunkSeedColor(pub Color);
+
+impl ChunkSeedColor {
+    pub fn new(seed: u32, pos: IVec3) -> Self {
+        let hue = chunk_rng(seed, pos).gen_range(0..360);
+        Self(Color::hsl(hue as f32, 0.8, 0.6))
+    }
+
+    /// Returns the base color that blends the color with 50% alpha over
+    /// the block textures.
+    pub fn tint(self) -> Color {
+        let [r, g, b, _] = self.0.as_rgba_f32();
+        Color::rgb(0.5 + 0.5 * r, 0.5 + 0.5 * g, 0.5 + 0.5 * b)
+    }
+}
+
+/// Ticks per second of the world simulation in [`FixedUpdate`].
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct TickRate(pub f32);
+
+impl TickRate {
+    pub const MIN: f32 = 1.0;
+    pub const MAX: f32 = 60.0;
+}
+
+impl Default for TickRate {
+    fn default() -> Self {
+        Self(20.0)
+    }
+}
+
+/// Applies a changed tick rate to the fixed timestep.
+fn apply_tick_rate(rate: Res<TickRate>, mut time: ResMut<FixedTime>) {
+    let rate = rate.0.clamp(TickRate::MIN, TickRate::MAX);
+    time.period = Duration::from_secs_f32(1.0 / rate);
+}
+
+/// Maximum number of generated chunks that are inserted per frame.
+const MAX_GENERATED_PER_FRAME: usize = 64;
+
+/// Completion channels of the generation and mesh tasks.
+///
+/// The tasks send their result together with the chunk position and their id.
+/// Results of tasks that have been superseded or whose chunk has been despawned
+/// are dropped.
+#[derive(Resource)]
+struct ChunkTasks {
+    next_id: u64,
+    generated: TaskChannel<GenerateResult>,
+    meshed: TaskChannel<MeshResult>,
+    /// Content hashes of the chunks filled with a single block
+    trivial_hashes: HashMap<BlockId, u64>,
+}
+
+/// Result of a generation task.
+struct GenerateResult {
+    chunk: Chunk,
+    /// Time spent loading or generating, `None` for trivial chunks without a task
+    duration: Option<Duration>,
+    /// Content hash of generated chunks, `None` if loaded from the storage
+    generated: Option<u64>,
+}
+
+/// Result of a mesh task.
+struct MeshResult {
+    mesh: Mesh,
+    connectivity: Connectivity,
+    duration: Duration,
+    vertices: usize,
+}
+
+/// Channel of task results with their chunk position and task id.
+type TaskChannel<T> = (Sender<(IVec3, u64, T)>, Receiver<(IVec3, u64, T)>);
+
+impl Default for ChunkTasks {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            generated: crossbeam_channel::unbounded(),
+            meshed: crossbeam_channel::unbounded(),
+            trivial_hashes: HashMap::new(),
+        }
+    }
+}
+
+impl ChunkTasks {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Returns the content hash of a chunk filled with `block`, which is
+    /// only computed once per block.
+    fn trivial_hash(&mut self, block: BlockId) -> u64 {
+        *self
+            .trivial_hashes
+            .entry(block)
+            .or_insert_with(|| Chunk::new(block).content_hash())
+    }
+}
+
+/// Returns the duration after which chunk tasks are reported as slow.
+fn slow_task_threshold(settings: &PlayerSettings) -> Duration {
+    Duration::from_secs_f32(settings.slow_task_threshold.max(0.0) / 1000.0)
+}
+
+/// Logs a warning if the chunk task started at `start` took longer than `threshold`.
+fn warn_slow_task(task: &str, pos: IVec3, start: Instant, threshold: Duration) {
+    let elapsed = start.elapsed();
+    if elapsed > threshold {
+        warn!("{task} the chunk {pos} took {elapsed:?}");
+    }
+}
+
+/// Measurements of the chunk pipeline, shown in the world stats window.
+#[derive(Resource, Default)]
+pub struct WorldStats {
+    /// Chunks generated in the last frame
+    pub generated: usize,
+    /// Chunks meshed in the last frame
+    pub meshed: usize,
+    generate_times: VecDeque<Duration>,
+    mesh_times: VecDeque<Duration>,
+}
+
+impl WorldStats {
+    /// Number of tasks the average durations are computed over.
+    const HISTORY: usize = 128;
+
+    /// Average duration of the recent generation tasks.
+    pub fn generate_time(&self) -> Option<Duration> {
+        average(&self.generate_times)
+    }
+
+    /// Average duration of the recent mesh tasks.
+    pub fn mesh_time(&self) -> Option<Duration> {
+        average(&self.mesh_times)
+    }
+
+    fn record(times: &mut VecDeque<Duration>, duration: Duration) {
+        if times.len() >= Self::HISTORY {
+            times.pop_front();
+        }
+        times.push_back(duration);
+    }
+}
+
+fn average(times: &VecDeque<Duration>) -> Option<Duration> {
+    let sum = times.iter().sum::<Duration>();
+    (!times.is_empty()).then(|| sum / times.len() as u32)
+}
+
+/// Size of the chunk mesh, for the world stats window.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ChunkMeshSize {
+    pub vertices: usize,
+    /// Estimated size of the vertex and index buffers in bytes
+    pub bytes: usize,
+}
+
+/// Publishes the generated and meshed chunks per second.
+fn chunk_diagnostics(
+    mut diagnostics: Diagnostics,
+    mut stats: ResMut<WorldStats>,
+    time: Res<Time>,
+    mut generated: EventReader<ChunkGenerated>,
+    mut meshed: EventReader<ChunkMeshed>,
+) {
+    let generated = generated.iter().count();
+    let meshed = meshed.iter().count();
+    stats.generated = generated;
+    stats.meshed = meshed;
+    let delta = time.raw_delta_seconds_f64();
+    if delta == 0.0 {
+        return;
+    }
+    diagnostics.add_measurement(WorldPlugin::CHUNKS_GENERATED, || generated as f64 / delta);
+    diagnostics.add_measurement(WorldPlugin::CHUNKS_MESHED, || meshed as f64 / delta);
+}
+
+/// Newly meshed chunks rise from below into their final position.
 #[derive(Component)]
-struct Meshing(Task<Mesh>);
+struct ChunkFadeIn(Timer);
 
+/// Duration of the chunk fade in animation in seconds.
+const FADE_IN_DURATION: f32 = 0.3;
+/// Starting vertical offset of the chunk fade in animation.
+const FADE_IN_OFFSET: f32 = 4.0;
+
+#[allow(clippy::too_many_arguments)]
 fn init_generation(
     mut cmds: Commands,
     mut world: ResMut<VoxelWorld>,
     settings: Res<PlayerSettings>,
     noise: Res<WorldGen>,
-    query: Query<&Transform, With<PlayerController>>,
+    storage: Res<ChunkStorage>,
+    saves: Res<PendingSaves>,
+    mut tasks: ResMut<ChunkTasks>,
+    query: Query<(&Transform, &PlayerController)>,
+    query_generating: Query<(), With<Generating>>,
 ) {
-    let player_transform = query.single();
+    let (player_transform, controller) = query.single();
     let center = VoxelWorld::chunk_pos(player_transform.translation);
+    let predicted = VoxelWorld::predicted_chunk(
+        player_transform.translation,
+        controller.velocity,
+        settings.generation_lookahead,
+    );
 
-    let dist = settings.view_distance as i32 + 1;
+    let dist = settings.simulation_distance as i32 + 1;
 
     let thread_pool = AsyncComputeTaskPool::get();
+    let slow = slow_task_threshold(&settings);
+
+    // Limit the concurrent tasks, on the web they all run on the main thread
+    let mut available = settings
+        .max_generation_tasks
+        .saturating_sub(query_generating.iter().len());
+
+    for pos in generation_order(center, predicted, dist) {
+        if !noise.loaded_chunks().contains(&pos.y) {
+            continue;
+        }
+        // Wait until the chunk has been saved before loading it again
+        if saves.contains(pos) || world.chunks.contains_key(&pos) {
+            continue;
+        }
+        if available == 0 {
+            break;
+        }
+        available -= 1;
+        world.chunks.entry(pos).or_insert_with(|| {
+            let id = tasks.next_id();
+            // Skip the task for chunks above or far below the terrain
+            if let Some(block) = noise.trivial_chunk(pos) {
+                if !storage.contains(pos) {
+                    let result = GenerateResult {
+                        chunk: Chunk::new(block),
+                        duration: None,
+                        generated: Some(tasks.trivial_hash(block)),
+                    };
+                    let _ = tasks.generated.0.send((pos, id, result));
+                    return cmds
+                        .spawn((
+                            ChunkPos(pos),
+                            ChunkPos::local_aabb(),
+                            ChunkSeedColor::new(noise.seed, pos),
+                            Generating(id),
+                        ))
+                        .id();
+                }
+            }
+
+            let seed = noise.seed;
+            let noise = noise.clone();
+            let storage = storage.clone();
+            let sender = tasks.generated.0.clone();
+            thread_pool
+                .spawn(async move {
+                    let _span = info_span!("generate_chunk", %pos).entered();
+                    let start = Instant::now();
+                    let (chunk, generated) = match storage.load(pos) {
+                        Ok(Some(mut chunk)) => {
+                            let removed = chunk.remove_unknown(&blocks().read().unwrap());
+                            if removed > 0 {
+                                warn!("Removed {removed} unknown blocks from the chunk {pos}");
+                            }
+                            (chunk, None)
+                        }
+                        Ok(None) => {
+                            let chunk = generate_chunk(pos, &noise);
+                            let hash = chunk.content_hash();
+                            (chunk, Some(hash))
+                        }
+                        Err(e) => {
+                            warn!("Failed to load the chunk {pos}: {e}");
+                            let chunk = generate_chunk(pos, &noise);
+                            let hash = chunk.content_hash();
+                            (chunk, Some(hash))
+                        }
+                    };
+                    warn_slow_task("Generating", pos, start, slow);
+                    let result = GenerateResult {
+                        chunk,
+                        duration: Some(start.elapsed()),
+                        generated,
+                    };
+                    // The receiver lives as long as the app
+                    let _ = sender.send((pos, id, result));
+                })
+                .detach();
+            cmds.spawn((
+                ChunkPos(pos),
+                ChunkPos::local_aabb(),
+                ChunkSeedColor::new(seed, pos),
+                Generating(id),
+            ))
+            .id()
+        });
+    }
+}
 
-    for d in 0..dist {
-        for x in -dist..=dist {
+/// Returns the chunks within `dist` of the `center` in the order they are generated.
+///
+/// The chunks closest to either the center or the `predicted` chunk the player
+/// moves towards come first, ties are broken by the distance to the center.
+fn generation_order(center: IVec3, predicted: IVec3, dist: i32) -> Vec<IVec3> {
+    let mut order = Vec::new();
+    for x in -dist..=dist {
+        for y in -dist..=dist {
             for z in -dist..=dist {
-                for y in -dist..=dist {
-                    let off = IVec3::new(x, y, z);
-                    if distance(off) != d as u32 {
-                        continue;
-                    }
-                    let pos = center + off;
-                    world.chunks.entry(pos).or_insert_with(|| {
-                        let noise = noise.clone();
-                        let task = thread_pool.spawn(async move { generate_chunk(pos, &noise) });
-                        let entity = cmds.spawn((ChunkPos(pos), Generating(task))).id();
-                        entity
-                    });
+                let off = IVec3::new(x, y, z);
+                if distance(off) < dist as u32 {
+                    order.push(center + off);
                 }
             }
         }
     }
+    order.sort_by_key(|&pos| {
+        let from_center = distance(pos - center);
+        (from_center.min(distance(pos - predicted)), from_center)
+    });
+    order
 }
 
 fn handle_generation(
     mut cmds: Commands,
-    world: Res<VoxelWorld>,
-    mut query: Query<(Entity, &ChunkPos, &mut Generating)>,
+    mut world: ResMut<VoxelWorld>,
+    mut stats: ResMut<WorldStats>,
+    noise: Res<WorldGen>,
+    tasks: Res<ChunkTasks>,
+    query: Query<&Generating>,
     mut neighbors: Query<&mut MissingNeighbors>,
+    mut generated: EventWriter<ChunkGenerated>,
 ) {
-    for (entity, ChunkPos(pos), mut task) in query.iter_mut() {
-        if let Some(chunk) = future::block_on(future::poll_once(&mut task.0)) {
-            let mut surrounded = Vec::with_capacity(6);
-            if let Some(mut cmds) = cmds.get_entity(entity) {
-                let mut missing = 6;
-
-                for d in Direction::all() {
-                    if let Some(entity) = world.chunks.get(&(*pos + IVec3::from(d))) {
-                        missing -= 1;
-                        if let Ok(mut missing) = neighbors.get_mut(*entity) {
-                            if missing.0 > 1 {
-                                missing.0 -= 1;
-                            } else {
-                                surrounded.push(*entity);
-                            }
+    for (pos, id, result) in tasks.generated.1.try_iter().take(MAX_GENERATED_PER_FRAME) {
+        if let Some(duration) = result.duration {
+            WorldStats::record(&mut stats.generate_times, duration);
+        }
+        let Some(&entity) = world.chunks.get(&pos) else {
+            continue;
+        };
+        if !query.get(entity).is_ok_and(|g| g.0 == id) {
+            continue;
+        }
+        let _span = info_span!("insert_chunk", %pos).entered();
+
+        let mut surrounded = Vec::with_capacity(6);
+        if let Some(mut cmds) = cmds.get_entity(entity) {
+            let mut missing = 6;
+
+            for d in Direction::all() {
+                let neighbor = pos + IVec3::from(d);
+                // The chunks beyond the loaded height are never loaded
+                if !noise.loaded_chunks().contains(&neighbor.y) {
+                    missing -= 1;
+                } else if let Some(entity) = world.chunks.get(&neighbor) {
+                    missing -= 1;
+                    if let Ok(mut missing) = neighbors.get_mut(*entity) {
+                        if missing.0 > 1 {
+                            missing.0 -= 1;
+                        } else {
+                            surrounded.push(*entity);
                         }
                     }
                 }
+            }
 
-                if missing > 0 {
-                    cmds.insert((MissingNeighbors(missing), ChunkData(Arc::new(chunk))))
-                        .remove::<Generating>();
-                } else {
-                    cmds.insert((RequiresMesh, ChunkData(Arc::new(chunk))))
-                        .remove::<Generating>();
-                }
+            let chunk = Arc::new(result.chunk);
+            world.data.insert(pos, chunk.clone());
+            if let Some(hash) = result.generated {
+                world.generated.insert(pos, hash);
             }
-            for entity in surrounded {
-                cmds.get_entity(entity).map(|mut c| {
-                    c.insert(RequiresMesh).remove::<MissingNeighbors>();
-                });
+            generated.send(ChunkGenerated { pos });
+            if missing > 0 {
+                cmds.insert((MissingNeighbors(missing), ChunkData(chunk)))
+                    .remove::<Generating>();
+            } else {
+                cmds.insert((RequiresMesh, ChunkData(chunk)))
+                    .remove::<Generating>();
+            }
+        }
+        for entity in surrounded {
+            if let Some(mut cmds) = cmds.get_entity(entity) {
+                cmds.insert(RequiresMesh).remove::<MissingNeighbors>();
             }
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn init_mesh(
     mut cmds: Commands,
     world: Res<VoxelWorld>,
     settings: Res<PlayerSettings>,
+    noise: Res<WorldGen>,
+    mut tasks: ResMut<ChunkTasks>,
     player_query: Query<&Transform, With<PlayerController>>,
     query_mesh: Query<(Entity, &ChunkPos, &ChunkData, With<RequiresMesh>)>,
     query_data: Query<&ChunkData>,
+    query_meshing: Query<(), With<Meshing>>,
 ) {
     let player_transform = player_query.single();
     let center = VoxelWorld::chunk_pos(player_transform.translation);
-    let dist = settings.view_distance as u32;
+    let dist = settings.render_distance.min(settings.simulation_distance) as u32;
     let thread_pool = AsyncComputeTaskPool::get();
+    let slow = slow_task_threshold(&settings);
 
-    query_mesh.for_each(|(entity, ChunkPos(pos), ChunkData(chunk), _)| {
-        if distance(center - *pos) >= dist {
-            return;
-        }
+    // Limit the concurrent mesh tasks so that meshing can't monopolize the pool
+    let available = settings
+        .max_mesh_tasks
+        .saturating_sub(query_meshing.iter().len());
+    if available == 0 {
+        return;
+    }
 
-        let blocks = blocks().read().unwrap();
+    // Mesh the closest chunks first
+    let mut pending = query_mesh
+        .iter()
+        .filter(|(_, ChunkPos(pos), _, _)| distance(center - *pos) < dist)
+        .collect::<Vec<_>>();
+    pending.sort_unstable_by_key(|(_, ChunkPos(pos), _, _)| distance(center - *pos));
 
-        let mut borders = [Border::new(); 6];
-        for d in Direction::all() {
-            let Some(&entity) = world.chunks.get(&(*pos + IVec3::from(d))) else {
-                return;
-            };
+    let mut spawned = 0;
+    'chunks: for (entity, ChunkPos(pos), ChunkData(chunk), _) in pending {
+        if spawned >= available {
+            break;
+        }
+        let _span = info_span!("dispatch_mesh", %pos).entered();
 
-            if let Ok(ChunkData(chunk)) = query_data.get(entity) {
-                borders[d as usize] = chunk.border(d.inverse(), &blocks);
-            } else {
-                return;
+        // Only share the neighbors, their borders are extracted by the task
+        let mut neighbors = Vec::with_capacity(6);
+        for d in Direction::all() {
+            let neighbor = *pos + IVec3::from(d);
+            // The outermost loaded chunks are closed towards the unloaded ones
+            if !noise.loaded_chunks().contains(&neighbor.y) {
+                neighbors.push(None);
+                continue;
             }
+            let Some(&entity) = world.chunks.get(&neighbor) else {
+                continue 'chunks;
+            };
+            let Ok(ChunkData(chunk)) = query_data.get(entity) else {
+                continue 'chunks;
+            };
+            neighbors.push(Some(chunk.clone()));
         }
 
+        let Some(mut cmds) = cmds.get_entity(entity) else {
+            continue;
+        };
         let chunk = chunk.clone();
-        let task = thread_pool.spawn(async move { chunk.mesh(borders) });
+        let pos = *pos;
+        let id = tasks.next_id();
+        let sender = tasks.meshed.0.clone();
+        thread_pool
+            .spawn(async move {
+                let _span = info_span!("mesh_chunk", %pos).entered();
+                let start = Instant::now();
+                let blocks = blocks().read().unwrap();
+                let borders = Direction::all().map(|d| {
+                    neighbors[d as usize]
+                        .as_ref()
+                        .map_or(Border::full(), |c| c.border(d.inverse(), &blocks))
+                });
+                let mesh = chunk.mesh(borders, &blocks, TileTextures::get());
+                let connectivity = chunk.connectivity(&blocks);
+                warn_slow_task("Meshing", pos, start, slow);
+                let result = MeshResult {
+                    vertices: mesh.count_vertices(),
+                    mesh,
+                    connectivity,
+                    duration: start.elapsed(),
+                };
+                let _ = sender.send((pos, id, result));
+            })
+            .detach();
 
-        cmds.get_entity(entity).map(|mut cmds| {
-            cmds.insert(Meshing(task)).remove::<RequiresMesh>();
-        });
-    });
+        // A newer mesh task supersedes a running one
+        cmds.insert(Meshing(id)).remove::<RequiresMesh>();
+        spawned += 1;
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_mesh(
     mut cmds: Commands,
-    mut query: Query<(Entity, &ChunkPos, &mut Meshing)>,
+    settings: Res<PlayerSettings>,
+    world: Res<VoxelWorld>,
+    mut stats: ResMut<WorldStats>,
+    tasks: Res<ChunkTasks>,
+    query: Query<(&Meshing, Option<&Handle<Mesh>>)>,
     mut meshes: ResMut<Assets<Mesh>>,
     block_mat: Res<BlockMat>,
+    mut meshed: EventWriter<ChunkMeshed>,
 ) {
-    for (entity, ChunkPos(pos), mut task) in query.iter_mut() {
-        if let Some(mesh) = future::block_on(future::poll_once(&mut task.0)) {
-            cmds.entity(entity)
-                .insert((PbrBundle {
-                    mesh: meshes.add(mesh),
-                    material: block_mat.0.clone(),
-                    transform: Transform::from_translation(VoxelWorld::world_pos(*pos)),
-                    ..default()
-                },))
-                .remove::<Meshing>();
+    // Spread the mesh uploads over multiple frames
+    let mut uploads = 0;
+    while uploads < settings.max_mesh_uploads {
+        let Ok((pos, id, result)) = tasks.meshed.1.try_recv() else {
+            break;
+        };
+        WorldStats::record(&mut stats.mesh_times, result.duration);
+        let Some(&entity) = world.chunks.get(&pos) else {
+            continue;
+        };
+        let Ok((meshing, old_mesh)) = query.get(entity) else {
+            continue;
+        };
+        if meshing.0 != id {
+            continue;
+        }
+        let _span = info_span!("upload_mesh", %pos).entered();
+        let MeshResult {
+            mesh,
+            connectivity,
+            vertices,
+            ..
+        } = result;
+        let size = ChunkMeshSize {
+            vertices,
+            bytes: mesh.get_vertex_size() as usize * vertices
+                + mesh.get_index_buffer_bytes().map_or(0, <[u8]>::len),
+        };
+
+        let mut transform = Transform::from_translation(VoxelWorld::world_pos(pos));
+        let mut cmds = cmds.entity(entity);
+
+        // Only animate the first appearance, not remeshing after edits
+        if settings.chunk_fade_in && old_mesh.is_none() {
+            transform.translation.y -= FADE_IN_OFFSET;
+            cmds.insert(ChunkFadeIn(Timer::from_seconds(
+                FADE_IN_DURATION,
+                TimerMode::Once,
+            )));
+        }
+
+        cmds.insert((
+            PbrBundle {
+                mesh: meshes.add(mesh),
+                material: block_mat.0.clone(),
+                transform,
+                ..default()
+            },
+            ChunkConnectivity(connectivity),
+            size,
+        ))
+        .remove::<Meshing>();
+        meshed.send(ChunkMeshed { pos, entity });
+        uploads += 1;
+    }
+}
+
+/// Moves the newly meshed chunks into their final position.
+fn fade_in_chunks(
+    mut cmds: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &ChunkPos, &mut ChunkFadeIn, &mut Transform)>,
+) {
+    for (entity, ChunkPos(pos), mut fade, mut transform) in query.iter_mut() {
+        fade.0.tick(time.delta());
+        // Ease out, slowing down towards the end
+        let t = 1.0 - (1.0 - fade.0.percent()).powi(2);
+        transform.translation = VoxelWorld::world_pos(*pos) - Vec3::Y * FADE_IN_OFFSET * (1.0 - t);
+        if fade.0.finished() {
+            cmds.entity(entity).remove::<ChunkFadeIn>();
         }
     }
 }
 
+/// Hides the meshed chunks outside the render distance and those occluded
+/// by other chunks.
+fn cull_chunks(
+    settings: Res<PlayerSettings>,
+    world: Res<VoxelWorld>,
+    mut culling: ResMut<OcclusionCulling>,
+    player: Query<(&Transform, &Frustum), With<PlayerController>>,
+    connectivity: Query<&ChunkConnectivity>,
+    mut chunks: Query<(&ChunkPos, &mut Visibility), With<Handle<Mesh>>>,
+) {
+    let (transform, frustum) = player.single();
+    let center = VoxelWorld::chunk_pos(transform.translation);
+    let dist = settings.render_distance.min(settings.simulation_distance) as u32;
+
+    let in_view =
+        |pos: IVec3| frustum.intersects_obb(&ChunkPos(pos).aabb(), &Mat4::IDENTITY, true, false);
+    let visible = culling.enabled.then(|| {
+        visible_chunks(center, dist, in_view, |pos| {
+            world
+                .chunks
+                .get(&pos)
+                .and_then(|e| connectivity.get(*e).ok())
+                .map_or(Connectivity::ALL, |c| c.0)
+        })
+    });
+
+    let mut culled = 0;
+    for (ChunkPos(pos), mut visibility) in &mut chunks {
+        let mut shown = distance(center - *pos) < dist;
+        if shown && visible.as_ref().is_some_and(|v| !v.contains(pos)) {
+            shown = false;
+            if in_view(*pos) {
+                culled += 1;
+            }
+        }
+        let target = if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != target {
+            *visibility = target;
+        }
+    }
+    if culling.culled != culled {
+        culling.culled = culled;
+    }
+}
+
+/// Returns the chunks that are visible from the `center` chunk.
+///
+/// The chunks are traversed breadth first through their connected faces,
+/// only moving away from the center and staying within the view.
+fn visible_chunks(
+    center: IVec3,
+    dist: u32,
+    in_view: impl Fn(IVec3) -> bool,
+    connectivity: impl Fn(IVec3) -> Connectivity,
+) -> HashSet<IVec3> {
+    let mut visible = HashSet::new();
+    visible.insert(center);
+    // Chunk, face it was entered through and the directions of the path to it
+    let mut queue = VecDeque::new();
+    queue.push_back((center, None::<Direction>, 0_u8));
+    while let Some((pos, entry, traveled)) = queue.pop_front() {
+        let connectivity = connectivity(pos);
+        for d in Direction::all() {
+            if traveled & 1 << d.inverse() as usize != 0 {
+                continue;
+            }
+            if entry.is_some_and(|entry| !connectivity.connected(entry, d)) {
+                continue;
+            }
+            let next = pos + IVec3::from(d);
+            if distance(next - center) >= dist || !in_view(next) || !visible.insert(next) {
+                continue;
+            }
+            queue.push_back((next, Some(d.inverse()), traveled | 1 << d as usize));
+        }
+    }
+    visible
+}
+
+/// Despawns the chunks outside the simulation distance, saving the modified ones.
 fn despawn_chunks(
     mut cmds: Commands,
     mut world: ResMut<VoxelWorld>,
     settings: Res<PlayerSettings>,
+    storage: Res<ChunkStorage>,
+    mut saves: ResMut<PendingSaves>,
     player: Query<&Transform, With<PlayerController>>,
     chunks: Query<(Entity, &ChunkPos)>,
 ) {
     let player_transform = player.single();
     let center = VoxelWorld::chunk_pos(player_transform.translation);
 
-    let dist = settings.view_distance as u32;
+    let dist = settings.simulation_distance as u32;
 
     chunks.for_each(|(entity, ChunkPos(pos))| {
         if distance(center - *pos) > dist {
             cmds.entity(entity).despawn();
             world.chunks.remove(pos);
+            let chunk = world.data.remove(pos);
+            if let Some(chunk) = chunk.filter(|_| world.modified.remove(pos)) {
+                if world.differs_from_generated(*pos, &chunk) {
+                    saves.spawn(&storage, *pos, chunk);
+                }
+            }
+            world.generated.remove(pos);
         }
     });
 }
 
+/// Sends a single event for every changed position.
+///
+/// Positions that were changed multiple times are merged into one event,
+/// blocks that have been restored are skipped.
+fn dispatch_block_updates(
+    mut world: ResMut<VoxelWorld>,
+    mut updates: ResMut<BlockUpdateQueue>,
+    mut events: EventWriter<BlockChangedEvent>,
+) {
+    for (pos, old, cause) in world.changes.drain(..) {
+        updates.push(pos, old, cause);
+    }
+    let updates = &mut *updates;
+    for pos in updates.positions.drain(..) {
+        let (old, cause) = updates.changes[&pos];
+        let Some(new) = world.get_block(pos) else {
+            continue;
+        };
+        if old != new {
+            events.send(BlockChangedEvent {
+                pos,
+                old,
+                new,
+                cause,
+            });
+        }
+    }
+    updates.changes.clear();
+}
+
+/// Update the data of the dirty chunks and schedule them for remeshing.
+///
+/// Changed blocks mark their chunk and the touched neighbors as dirty,
+/// so that the faces along the chunk borders are culled correctly.
+fn remesh_changed_chunks(
+    mut cmds: Commands,
+    mut world: ResMut<VoxelWorld>,
+    mut events: EventReader<BlockChangedEvent>,
+    query: Query<Option<&MissingNeighbors>, With<ChunkData>>,
+) {
+    for event in events.iter() {
+        world.mark_dirty(event.pos);
+    }
+    // Avoid flagging the world as changed every frame
+    if world.dirty.is_empty() {
+        return;
+    }
+
+    let dirty = std::mem::take(&mut world.dirty);
+    for pos in dirty {
+        let (Some(&entity), Some(chunk)) = (world.chunks.get(&pos), world.data.get(&pos)) else {
+            continue;
+        };
+        // Chunks that miss neighbors are meshed as soon as they are complete
+        if let Ok(missing) = query.get(entity) {
+            let mut cmds = cmds.entity(entity);
+            cmds.insert(ChunkData(chunk.clone()));
+            if missing.is_none() {
+                cmds.insert(RequiresMesh);
+            }
+        }
+    }
+}
+
 fn distance(p: IVec3) -> u32 {
     p.max_element().abs().max(p.min_element().abs()) as _
 }
@@ -217,16 +1567,23 @@ fn distance(p: IVec3) -> u32 {
 #[derive(Event)]
 pub struct RegenerateEvent;
 
+/// Despawns all chunks to generate them again with the current settings.
+///
+/// The modified chunks are saved first, so that the edits are kept and
+/// the stored chunks are not outdated.
 fn regenerate_chunks(
     mut events: EventReader<RegenerateEvent>,
     mut cmds: Commands,
     mut world: ResMut<VoxelWorld>,
+    storage: Res<ChunkStorage>,
+    mut saves: ResMut<PendingSaves>,
     chunks: Query<Entity, With<ChunkPos>>,
 ) {
     if !events.is_empty() {
         events.clear();
 
         warn!("Regenerate!");
+        queue_saves(&mut world, &storage, &mut saves);
         chunks.for_each(|entity| cmds.entity(entity).despawn());
         world.clear();
     }
@@ -246,32 +1603,779 @@ fn move_chunk_center(
     });
 }
 
+/// Outline the chunks: red while generating, blue while waiting for the
+/// neighbors, yellow while meshing and green once meshed.
+fn draw_chunk_boundaries(show: Res<ShowChunkBoundaries>, chunks: ChunkStates, mut gizmos: Gizmos) {
+    if !show.0 {
+        return;
+    }
+    for (pos, state) in chunks.iter() {
+        let color = match state {
+            ChunkState::Generating => Color::rgba(1.0, 0.0, 0.0, 0.4),
+            ChunkState::Generated => Color::rgba(0.0, 0.0, 1.0, 0.4),
+            ChunkState::Meshing => Color::rgba(1.0, 1.0, 0.0, 0.4),
+            ChunkState::Meshed => Color::rgba(0.0, 1.0, 0.0, 0.4),
+        };
+        let aabb = ChunkPos(pos).aabb();
+        gizmos.cuboid(
+            Transform::from_translation(aabb.center.into())
+                .with_scale(Vec3::from(aabb.half_extents) * 2.0),
+            color,
+        );
+    }
+}
+
+fn toggle_wireframe(key: Res<Input<KeyCode>>, mut wireframe: ResMut<ChunkWireframe>) {
+    if key.just_pressed(ChunkWireframe::KEY) {
+        wireframe.enabled = !wireframe.enabled;
+    }
+}
+
+/// Adds or removes the [`Wireframe`] of the meshed chunks.
+///
+/// Wireframes need the polygon line mode, which is not supported by all
+/// graphics backends.
+fn apply_wireframe(
+    mut cmds: Commands,
+    mut wireframe: ResMut<ChunkWireframe>,
+    target: Res<TargetedBlock>,
+    device: Res<RenderDevice>,
+    chunks: Query<(Entity, &ChunkPos, Has<Wireframe>), With<Handle<Mesh>>>,
+) {
+    if wireframe.enabled && !device.features().contains(WgpuFeatures::POLYGON_MODE_LINE) {
+        warn!("Wireframes are not supported by the graphics backend");
+        wireframe.enabled = false;
+    }
+    let target = target.0.map(|t| VoxelWorld::chunk_of_block(t.hit));
+    for (entity, ChunkPos(pos), shown) in &chunks {
+        let show = wireframe.shows(*pos, target);
+        if show && !shown {
+            cmds.entity(entity).insert(Wireframe);
+        } else if !show && shown {
+            cmds.entity(entity).remove::<Wireframe>();
+        }
+    }
+}
+
+fn toggle_seed_overlay(key: Res<Input<KeyCode>>, mut overlay: ResMut<ChunkSeedOverlay>) {
+    if key.just_pressed(ChunkSeedOverlay::KEY) {
+        overlay.0 = !overlay.0;
+    }
+}
+
+/// Replaces the block material of the meshed chunks with a tinted copy
+/// while the [`ChunkSeedOverlay`] is enabled, and restores it afterwards.
+///
+/// Remeshed chunks get the block material again and are tinted in the next frame.
+/// The tinted copies are rebuilt when the block material is modified,
+/// e.g. by changing the texture filtering.
+fn apply_seed_overlay(
+    overlay: Res<ChunkSeedOverlay>,
+    block_mat: Res<BlockMat>,
+    mut events: EventReader<AssetEvent<StandardMaterial>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut chunks: Query<(&ChunkSeedColor, &mut Handle<StandardMaterial>), With<ChunkPos>>,
+) {
+    // Reads all events, so that they are not handled again in the next frame
+    let mut modified = false;
+    for event in events.iter() {
+        modified |= matches!(event, AssetEvent::Modified { handle } if *handle == block_mat.0);
+    }
+    for (color, mut material) in &mut chunks {
+        let tinted = *material != block_mat.0;
+        if overlay.0 && (!tinted || modified) {
+            let Some(base) = materials.get(&block_mat.0).cloned() else {
+                return;
+            };
+            let new = StandardMaterial {
+                base_color: color.tint(),
+                ..base
+            };
+            // Rebuilt copies keep their handle
+            match materials.get_mut(&*material).filter(|_| tinted) {
+                Some(old) => *old = new,
+                None => *material = materials.add(new),
+            }
+        } else if !overlay.0 && tinted {
+            // Drops the tinted material
+            *material = block_mat.0.clone();
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct WorldPlugin;
 
+impl WorldPlugin {
+    /// Chunks generated or loaded per second
+    pub const CHUNKS_GENERATED: DiagnosticId =
+        DiagnosticId::from_u128(208913876747464317886428389426625737397);
+    /// Chunks meshed per second
+    pub const CHUNKS_MESHED: DiagnosticId =
+        DiagnosticId::from_u128(158545116320791446605004017160348905317);
+}
+
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<VoxelWorld>()
+            .init_resource::<PendingSaves>()
+            .init_resource::<ChunkTasks>()
+            .init_resource::<WorldStats>()
+            .init_resource::<OcclusionCulling>()
+            .init_resource::<ShowChunkBoundaries>()
+            .init_resource::<ChunkWireframe>()
+            .init_resource::<ChunkSeedOverlay>()
+            .init_resource::<TickRate>()
+            .init_resource::<BlockUpdateQueue>()
             .add_event::<RegenerateEvent>()
-            .add_systems(
+            .add_event::<BlockChangedEvent>()
+            .add_event::<ChunkGenerated>()
+            .add_event::<ChunkMeshed>()
+            .register_diagnostic(Diagnostic::new(
+                Self::CHUNKS_GENERATED,
+                "chunks_generated",
+                20,
+            ))
+            .register_diagnostic(Diagnostic::new(Self::CHUNKS_MESHED, "chunks_meshed", 20))
+            .configure_set(FixedUpdate, WorldSet.run_if(in_state(AppState::Running)))
+            .configure_set(Update, WorldSet.run_if(in_state(AppState::Running)))
+            .configure_sets(
+                FixedUpdate,
+                (WorldUpdateSet::Generate, WorldUpdateSet::Despawn)
+                    .chain()
+                    .in_set(WorldSet),
+            )
+            .configure_sets(
                 Update,
                 (
-                    init_generation,
-                    handle_generation,
-                    init_mesh,
-                    handle_mesh,
-                    despawn_chunks
-                        .after(init_generation)
-                        .after(handle_generation)
-                        .after(init_mesh)
-                        .after(handle_mesh),
-                    regenerate_chunks.after(despawn_chunks),
+                    WorldUpdateSet::HandleGeneration,
+                    WorldUpdateSet::InitMesh,
+                    WorldUpdateSet::HandleMesh,
+                    WorldUpdateSet::Regenerate,
                 )
-                    .run_if(in_state(AppState::Running)),
+                    .chain()
+                    .in_set(WorldSet),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    init_generation.in_set(WorldUpdateSet::Generate),
+                    despawn_chunks.in_set(WorldUpdateSet::Despawn),
+                ),
             )
             .add_systems(
                 Update,
-                move_chunk_center.run_if(in_state(AppState::Running)),
-            );
+                (
+                    handle_generation.in_set(WorldUpdateSet::HandleGeneration),
+                    (dispatch_block_updates, remesh_changed_chunks, init_mesh)
+                        .chain()
+                        .in_set(WorldUpdateSet::InitMesh),
+                    (handle_mesh, (fade_in_chunks, cull_chunks))
+                        .chain()
+                        .in_set(WorldUpdateSet::HandleMesh),
+                    regenerate_chunks.in_set(WorldUpdateSet::Regenerate),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    apply_tick_rate.run_if(resource_changed::<TickRate>()),
+                    move_chunk_center,
+                    draw_chunk_boundaries,
+                    (toggle_wireframe, apply_wireframe).chain(),
+                    (toggle_seed_overlay, apply_seed_overlay).chain(),
+                    chunk_diagnostics.after(WorldSet),
+                )
+                    .run_if(in_state(AppState::Running)),
+            )
+            // The saves of a closed world finish on the world selection
+            .add_systems(Update, handle_saves)
+            .add_systems(Last, on_exit);
+    }
+}
+
+#[cfg(test)]
+impl VoxelWorld {
+    /// World with the air chunks in the given range loaded.
+    pub fn with_air_chunks(min: IVec3, max: IVec3) -> Self {
+        let mut world = Self::default();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let chunk = Arc::new(Chunk::new(BlockId::AIR));
+                    world.data.insert(IVec3::new(x, y, z), chunk);
+                }
+            }
+        }
+        world
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bevy::math::{IVec3, UVec3};
+    use bevy::utils::HashMap;
+
+    use bevy::prelude::*;
+    use bevy::render::mesh::PrimitiveTopology;
+    use bevy::render::primitives::Aabb;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{
+        dispatch_block_updates, distance, generation_order, handle_generation, handle_mesh,
+        regenerate_chunks, remesh_changed_chunks, visible_chunks, BlockChangedEvent,
+        BlockUpdateQueue, ChangeCause, ChunkData, ChunkGenerated, ChunkMeshSize, ChunkMeshed,
+        ChunkPos, ChunkSeedColor, ChunkTasks, ChunkWireframe, GenerateResult, Generating,
+        MeshResult, Meshing, MissingNeighbors, RegenerateEvent, RequiresMesh, VoxelWorld,
+        WorldStats,
+    };
+    use crate::block::{Block, BlockId};
+    use crate::chunk::{Chunk, Connectivity};
+    use crate::generation::WorldGen;
+    use crate::player::PlayerSettings;
+    use crate::storage::{ChunkStorage, PendingSaves};
+    use crate::BlockMat;
+
+    #[test]
+    fn fill_across_chunks() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::splat(-1), IVec3::ZERO);
+        let (min, max) = (IVec3::splat(-2), IVec3::splat(2));
+
+        let changed = world.fill_box(max, min, BlockId::STONE, ChangeCause::Player);
+        assert_eq!(changed, 5 * 5 * 5);
+        assert_eq!(world.changes.len(), changed);
+        assert_eq!(world.get_block(min), Some(BlockId::STONE));
+        assert_eq!(world.get_block(max), Some(BlockId::STONE));
+        assert_eq!(world.get_block(max + 1), Some(BlockId::AIR));
+
+        // Filling again changes nothing
+        assert_eq!(
+            world.fill_box(min, max, BlockId::STONE, ChangeCause::Player),
+            0
+        );
+
+        world.set_block(IVec3::ZERO, BlockId::DIRT, ChangeCause::Player);
+        let changed = world.replace_in_box(
+            min,
+            max,
+            BlockId::STONE,
+            BlockId::GRASS,
+            ChangeCause::Player,
+        );
+        assert_eq!(changed, 5 * 5 * 5 - 1);
+        assert_eq!(world.get_block(IVec3::ZERO), Some(BlockId::DIRT));
+        assert_eq!(world.get_block(min), Some(BlockId::GRASS));
+
+        // Unloaded chunks are skipped
+        let far = IVec3::splat(Chunk::SIZE as i32);
+        let changed = world.fill_box(max, far, BlockId::STONE, ChangeCause::Player);
+        assert_eq!(changed, (Chunk::SIZE - 2).pow(3));
+    }
+
+    #[test]
+    fn skip_unchanged_saves() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::X);
+        let hash = Chunk::new(BlockId::AIR).content_hash();
+        world.generated.insert(IVec3::ZERO, hash);
+        world.generated.insert(IVec3::X, hash);
+
+        // Restored blocks match the generated chunk again
+        let pos = IVec3::new(1, 2, 3);
+        world.set_block(pos, BlockId::STONE, ChangeCause::Player);
+        world.set_block(pos, BlockId::AIR, ChangeCause::Player);
+        assert!(world.take_modified().is_empty());
+
+        world.set_block(pos, BlockId::STONE, ChangeCause::Player);
+        let far = IVec3::new(Chunk::SIZE as i32, 0, 0);
+        world.set_block(far, BlockId::STONE, ChangeCause::Player);
+        world.set_block(far, BlockId::AIR, ChangeCause::Player);
+        let modified = world.take_modified();
+        assert_eq!(modified.len(), 1);
+        assert_eq!(modified[0].0, IVec3::ZERO);
+
+        // Once saved, the file has to be updated as well
+        world.set_block(pos, BlockId::AIR, ChangeCause::Player);
+        assert_eq!(world.take_modified().len(), 1);
+    }
+
+    #[test]
+    fn dispatch_updates() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::ZERO);
+        let (pos, restored) = (IVec3::new(1, 2, 3), IVec3::ONE);
+        world.set_block(pos, BlockId::STONE, ChangeCause::Player);
+        world.set_block(pos, BlockId::DIRT, ChangeCause::Simulation);
+        world.set_block(restored, BlockId::STONE, ChangeCause::Player);
+        world.set_block(restored, BlockId::AIR, ChangeCause::Player);
+
+        let mut app = App::new();
+        app.add_event::<BlockChangedEvent>()
+            .init_resource::<BlockUpdateQueue>()
+            .insert_resource(world)
+            .add_systems(Update, dispatch_block_updates);
+        app.update();
+
+        // One event per position, from the first to the last block
+        let events = app.world.resource::<Events<BlockChangedEvent>>();
+        let events: Vec<_> = events.get_reader().iter(events).copied().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].pos, pos);
+        assert_eq!(events[0].old, BlockId::AIR);
+        assert_eq!(events[0].new, BlockId::DIRT);
+        assert_eq!(events[0].cause, ChangeCause::Simulation);
+        assert!(app.world.resource::<BlockUpdateQueue>().changes.is_empty());
+    }
+
+    #[test]
+    fn regenerate_keeps_edits() {
+        let dir = std::env::temp_dir().join("bevy-voxel-regenerate");
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage = ChunkStorage::new(dir.clone());
+
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::ZERO);
+        world.set_block(IVec3::ONE, BlockId::STONE, ChangeCause::Player);
+
+        let mut app = App::new();
+        app.add_plugins(TaskPoolPlugin::default())
+            .add_event::<RegenerateEvent>()
+            .insert_resource(world)
+            .insert_resource(storage.clone())
+            .init_resource::<PendingSaves>()
+            .add_systems(Update, regenerate_chunks);
+        app.world.send_event(RegenerateEvent);
+        app.update();
+
+        // The edited chunk is loaded from the storage instead of generated
+        app.world.resource_mut::<PendingSaves>().flush();
+        assert!(app
+            .world
+            .resource::<VoxelWorld>()
+            .get_block(IVec3::ONE)
+            .is_none());
+        let chunk = storage.load(IVec3::ZERO).unwrap().unwrap();
+        assert_eq!(chunk[UVec3::ONE], BlockId::STONE);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flood_fill_limit() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::X);
+        // Wall separating the chunks
+        let size = Chunk::SIZE as i32;
+        world.fill_box(
+            IVec3::new(size - 1, 0, 0),
+            IVec3::new(size - 1, size - 1, size - 1),
+            BlockId::STONE,
+            ChangeCause::Player,
+        );
+
+        let changed = world.flood_fill(IVec3::ZERO, BlockId::WATER, 100, ChangeCause::Player);
+        assert_eq!(changed, 100);
+
+        let changed = world.flood_fill(IVec3::ZERO, BlockId::DIRT, usize::MAX, ChangeCause::Player);
+        assert_eq!(changed, 100);
+
+        let changed = world.flood_fill(
+            IVec3::splat(10),
+            BlockId::DIRT,
+            usize::MAX,
+            ChangeCause::Player,
+        );
+        assert_eq!(changed, (Chunk::SIZE - 1) * Chunk::SIZE * Chunk::SIZE - 100);
+        assert_eq!(world.get_block(IVec3::new(size, 0, 0)), Some(BlockId::AIR));
+    }
+
+    #[test]
+    fn multi_voxel_blocks() {
+        let door = BlockId(20);
+        let mut blocks = HashMap::new();
+        blocks.insert(BlockId::AIR, Block::default());
+        blocks.insert(BlockId::STONE, Block::default());
+        let block = Block {
+            extent: UVec3::new(0, 1, 0),
+            ..Default::default()
+        };
+        blocks.insert(door, block.clone());
+
+        let mut world = VoxelWorld::with_air_chunks(IVec3::ZERO, IVec3::Y);
+        world.set_multi_voxel_blocks(&blocks);
+        // The upper part crosses into the chunk above
+        let origin = IVec3::new(1, Chunk::SIZE as i32 - 1, 1);
+        let top = origin + IVec3::Y;
+        assert!(world.place_block(origin, door, &block, ChangeCause::Player));
+        assert_eq!(world.get_block(top), Some(door));
+        assert_eq!(world.block_origin(top, &blocks), origin);
+        assert_eq!(world.block_origin(origin, &blocks), origin);
+
+        // Occupied voxels prevent the placement
+        world.set_block(IVec3::new(2, 1, 1), BlockId::STONE, ChangeCause::Player);
+        let blocked = IVec3::new(2, 0, 1);
+        assert!(!world.place_block(blocked, door, &block, ChangeCause::Player));
+        assert_eq!(world.get_block(blocked), Some(BlockId::AIR));
+
+        // Breaking either voxel removes the whole block
+        assert_eq!(
+            world.break_block(top, &blocks, ChangeCause::Player),
+            Some(door)
+        );
+        assert_eq!(world.get_block(origin), Some(BlockId::AIR));
+        assert_eq!(world.get_block(top), Some(BlockId::AIR));
+        assert_eq!(world.get_meta(top), Some(0));
+
+        // So does replacing either voxel, also in bulk
+        assert!(world.place_block(origin, door, &block, ChangeCause::Player));
+        world.set_block(origin, BlockId::STONE, ChangeCause::Simulation);
+        assert_eq!(world.get_block(top), Some(BlockId::AIR));
+        let (lower, upper) = (IVec3::new(1, 0, 1), IVec3::new(1, 1, 1));
+        assert!(world.place_block(lower, door, &block, ChangeCause::Player));
+        world.fill_box(upper, upper, BlockId::STONE, ChangeCause::WorldEdit);
+        assert_eq!(world.get_block(lower), Some(BlockId::AIR));
+        assert_eq!(world.get_block(upper), Some(BlockId::STONE));
+    }
+
+    #[test]
+    fn occlusion_culling() {
+        let all = |_| Connectivity::ALL;
+        let visible = visible_chunks(IVec3::ZERO, 3, |_| true, all);
+        assert_eq!(visible.len(), 5 * 5 * 5);
+
+        // Only the chunks in front of the player are in view
+        let visible = visible_chunks(IVec3::ZERO, 3, |p| p.x >= 0, all);
+        assert_eq!(visible.len(), 3 * 5 * 5);
+
+        // Solid chunks in +x are visible but hide the chunks behind them
+        let solid = |p: IVec3| {
+            if p.x == 1 {
+                Connectivity::NONE
+            } else {
+                Connectivity::ALL
+            }
+        };
+        let visible = visible_chunks(IVec3::ZERO, 3, |p| p.x >= 0, solid);
+        assert!(visible.contains(&IVec3::new(1, 0, 0)));
+        assert!(!visible.contains(&IVec3::new(2, 0, 0)));
+        assert!(!visible.contains(&IVec3::new(2, 2, 2)));
+        assert_eq!(visible.len(), 2 * 5 * 5);
+    }
+
+    #[test]
+    fn chunk_events() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_asset::<Mesh>()
+            .init_resource::<VoxelWorld>()
+            .init_resource::<ChunkTasks>()
+            .init_resource::<WorldStats>()
+            .init_resource::<PlayerSettings>()
+            .init_resource::<WorldGen>()
+            .init_resource::<BlockMat>()
+            .add_event::<ChunkGenerated>()
+            .add_event::<ChunkMeshed>()
+            .add_systems(Update, (handle_generation, handle_mesh));
+
+        let pos = IVec3::new(1, 2, 3);
+        let entity = app
+            .world
+            .spawn((ChunkPos(pos), ChunkPos::local_aabb(), Generating(1)))
+            .id();
+        let mut world = app.world.resource_mut::<VoxelWorld>();
+        world.chunks.insert(pos, entity);
+        let tasks = app.world.resource::<ChunkTasks>();
+        let chunk = Chunk::new(BlockId::AIR);
+        let result = GenerateResult {
+            chunk,
+            duration: Some(Duration::from_millis(2)),
+            generated: None,
+        };
+        tasks.generated.0.send((pos, 1, result)).unwrap();
+        app.update();
+
+        let events = app.world.resource::<Events<ChunkGenerated>>();
+        let generated = events
+            .get_reader()
+            .iter(events)
+            .map(|e| e.pos)
+            .collect::<Vec<_>>();
+        assert_eq!(generated, [pos]);
+        assert!(app
+            .world
+            .resource::<VoxelWorld>()
+            .get_block(pos * 32)
+            .is_some());
+        assert_eq!(app.world.get::<MissingNeighbors>(entity).unwrap().0, 6);
+
+        // The chunks above the loaded height are never waited for
+        let top = IVec3::new(0, WorldGen::default().vertical_chunks.end, 0);
+        let top_entity = app.world.spawn((ChunkPos(top), Generating(3))).id();
+        let mut world = app.world.resource_mut::<VoxelWorld>();
+        world.chunks.insert(top, top_entity);
+        let tasks = app.world.resource::<ChunkTasks>();
+        let result = GenerateResult {
+            chunk: Chunk::new(BlockId::AIR),
+            duration: None,
+            generated: None,
+        };
+        tasks.generated.0.send((top, 3, result)).unwrap();
+        app.update();
+        assert_eq!(app.world.get::<MissingNeighbors>(top_entity).unwrap().0, 5);
+
+        app.world.entity_mut(entity).insert(Meshing(2));
+        let tasks = app.world.resource::<ChunkTasks>();
+        let result = MeshResult {
+            mesh: Mesh::new(PrimitiveTopology::TriangleList),
+            connectivity: Connectivity::ALL,
+            duration: Duration::from_millis(4),
+            vertices: 0,
+        };
+        tasks.meshed.0.send((pos, 2, result)).unwrap();
+        app.update();
+
+        let events = app.world.resource::<Events<ChunkMeshed>>();
+        let meshed = events
+            .get_reader()
+            .iter(events)
+            .map(|e| (e.pos, e.entity))
+            .collect::<Vec<_>>();
+        assert_eq!(meshed, [(pos, entity)]);
+        assert!(app.world.get::<Handle<Mesh>>(entity).is_some());
+        assert_eq!(app.world.get::<ChunkMeshSize>(entity).unwrap().vertices, 0);
+        // The mesh keeps the precomputed bounds
+        let aabb = app.world.get::<Aabb>(entity).unwrap();
+        assert_eq!(aabb.min(), ChunkPos::local_aabb().min());
+        assert_eq!(aabb.max(), ChunkPos::local_aabb().max());
+
+        let stats = app.world.resource::<WorldStats>();
+        assert_eq!(stats.generate_time(), Some(Duration::from_millis(2)));
+        assert_eq!(stats.mesh_time(), Some(Duration::from_millis(4)));
+    }
+
+    #[test]
+    fn remesh_neighbors() {
+        let size = Chunk::SIZE as i32;
+        let sorted = |mut chunks: Vec<IVec3>| {
+            chunks.sort_unstable_by_key(|p| p.to_array());
+            chunks
+        };
+        let affected = |block| sorted(VoxelWorld::affected_chunks(block));
+
+        assert_eq!(affected(IVec3::new(5, 6, 7)), [IVec3::ZERO]);
+        assert_eq!(
+            affected(IVec3::new(5, size - 1, 7)),
+            sorted(vec![IVec3::ZERO, IVec3::Y])
+        );
+        assert_eq!(
+            affected(IVec3::new(0, 6, size - 1)),
+            sorted(vec![IVec3::ZERO, IVec3::NEG_X, IVec3::Z])
+        );
+
+        // A corner edit flags the three chunks sharing a face, not the diagonals
+        let mut app = App::new();
+        app.add_event::<BlockChangedEvent>()
+            .add_systems(Update, remesh_changed_chunks);
+        let mut world = VoxelWorld::with_air_chunks(IVec3::NEG_ONE, IVec3::ONE);
+        for pos in world.data.keys().copied().collect::<Vec<_>>() {
+            let chunk = world.data[&pos].clone();
+            let entity = app.world.spawn((ChunkPos(pos), ChunkData(chunk))).id();
+            world.chunks.insert(pos, entity);
+        }
+        let corner = IVec3::new(size - 1, 0, size - 1);
+        world.set_block(corner, BlockId::STONE, ChangeCause::Player);
+        app.insert_resource(world);
+        app.world.send_event(BlockChangedEvent {
+            pos: corner,
+            old: BlockId::AIR,
+            new: BlockId::STONE,
+            cause: ChangeCause::Player,
+        });
+        app.update();
+
+        let mut query = app.world.query_filtered::<&ChunkPos, With<RequiresMesh>>();
+        let flagged = sorted(query.iter(&app.world).map(|p| p.0).collect());
+        let expected = sorted(vec![IVec3::ZERO, IVec3::X, IVec3::NEG_Y, IVec3::Z]);
+        assert_eq!(flagged, expected);
+        assert_eq!(affected(corner), expected);
+        assert!(app.world.resource::<VoxelWorld>().dirty.is_empty());
+    }
+
+    #[test]
+    fn raycast() {
+        let mut world = VoxelWorld::with_air_chunks(IVec3::NEG_ONE, IVec3::ONE);
+        let stone = BlockId::STONE;
+        let origin = Vec3::new(0.5, 0.5, 0.5);
+
+        // The hit block is entered at exactly the reach distance
+        world.set_block(IVec3::new(4, 0, 0), stone, ChangeCause::Player);
+        assert_eq!(
+            world.raycast(origin, Vec3::X, 3.5),
+            Some((IVec3::new(4, 0, 0), IVec3::new(3, 0, 0)))
+        );
+        assert_eq!(world.raycast(origin, Vec3::X, 3.49), None);
+
+        // Diagonal through the edge between two blocks
+        let dir = Vec3::new(1.0, 0.0, 1.0);
+        world.set_block(IVec3::new(2, 0, 2), stone, ChangeCause::Player);
+        assert_eq!(
+            world.raycast(origin, dir, 8.0).map(|(hit, _)| hit),
+            Some(IVec3::new(2, 0, 2))
+        );
+        world.set_block(IVec3::new(1, 0, 0), stone, ChangeCause::Player);
+        world.set_block(IVec3::new(0, 0, 1), stone, ChangeCause::Player);
+        let (hit, front) = world.raycast(origin, dir, 8.0).unwrap();
+        assert!(
+            hit == IVec3::new(1, 0, 0) || hit == IVec3::new(0, 0, 1),
+            "{hit}"
+        );
+        assert_eq!(front, IVec3::ZERO);
+
+        // A single block beside the edge blocks the ray as well
+        world.set_block(IVec3::new(1, 0, 0), BlockId::AIR, ChangeCause::Player);
+        assert_eq!(
+            world.raycast(origin, dir, 8.0),
+            Some((IVec3::new(0, 0, 1), IVec3::ZERO))
+        );
+
+        // Through a corner, also from negative positions
+        let origin = Vec3::new(-1.5, -1.5, -1.5);
+        world.set_block(IVec3::new(-1, -2, -2), stone, ChangeCause::Player);
+        assert_eq!(
+            world.raycast(origin, Vec3::ONE, 8.0),
+            Some((IVec3::new(-1, -2, -2), IVec3::splat(-2)))
+        );
+        // The blocks beside the edges of the corner are hit as well
+        world.set_block(IVec3::new(-1, -2, -2), BlockId::AIR, ChangeCause::Player);
+        world.set_block(IVec3::new(-1, -1, -2), stone, ChangeCause::Player);
+        assert_eq!(
+            world.raycast(origin, Vec3::ONE, 8.0),
+            Some((IVec3::new(-1, -1, -2), IVec3::new(-2, -1, -2)))
+        );
+        // The block diagonally behind the corner is hit from a face-adjacent block
+        world.set_block(IVec3::new(-1, -1, -2), BlockId::AIR, ChangeCause::Player);
+        world.set_block(IVec3::NEG_ONE, stone, ChangeCause::Player);
+        assert_eq!(
+            world.raycast(origin, Vec3::ONE, 8.0),
+            Some((IVec3::NEG_ONE, IVec3::new(-2, -1, -1)))
+        );
+    }
+
+    #[test]
+    fn block_to_chunk() {
+        let size = Chunk::SIZE as i32;
+        let mut rng = StdRng::seed_from_u64(7);
+        let boundaries = [-size - 1, -size, -1, 0, 1, size - 1, size];
+        let samples = (0..1000)
+            .map(|_| IVec3::from_array([(); 3].map(|_| rng.gen_range(-1_000_000..1_000_000))))
+            .chain(boundaries.map(IVec3::splat));
+        for b in samples {
+            let chunk = VoxelWorld::chunk_of_block(b);
+            let local = VoxelWorld::local_of_block(b);
+            assert_eq!(chunk * size + local.as_ivec3(), b);
+            assert!(local.cmplt(Chunk::MAX).all());
+
+            // Every position within the block maps to the same chunk
+            let p = b.as_vec3();
+            assert_eq!(VoxelWorld::chunk_pos(p), chunk, "{p}");
+            assert_eq!(VoxelWorld::chunk_pos(p + 0.5), chunk, "{p}");
+            assert_eq!(VoxelWorld::chunk_pos(p + 0.9), chunk, "{p}");
+            assert_eq!(VoxelWorld::chunk_pos(VoxelWorld::world_pos(chunk)), chunk);
+
+            let aabb = ChunkPos(chunk).aabb();
+            let offset = (p + 0.5) - Vec3::from(aabb.center);
+            assert!(offset.abs().cmplt(aabb.half_extents.into()).all(), "{p}");
+        }
+        assert_eq!(VoxelWorld::chunk_pos(Vec3::splat(-0.001)), IVec3::NEG_ONE);
+        assert_eq!(VoxelWorld::chunk_pos(Vec3::ZERO), IVec3::ZERO);
+    }
+
+    #[test]
+    fn chunk_bounds() {
+        let pos = IVec3::new(-2, 1, 3);
+        let world = ChunkPos(pos).aabb();
+        let local = ChunkPos::local_aabb();
+        let origin = VoxelWorld::world_pos(pos);
+        // The local bounds cover the whole chunk and the overhang of multi-voxel blocks
+        assert_eq!(Vec3::from(local.min()) + origin, Vec3::from(world.min()));
+        assert!((Vec3::from(local.max()) + origin)
+            .cmpgt(world.max().into())
+            .all());
+    }
+
+    #[test]
+    fn generation_priority() {
+        let pos = Vec3::new(16.0, 8.0, 16.0);
+        let center = VoxelWorld::chunk_pos(pos);
+
+        // Without movement the chunks are ordered by their distance
+        let predicted = VoxelWorld::predicted_chunk(pos, Vec3::ZERO, 1.5);
+        assert_eq!(predicted, center);
+        let order = generation_order(center, predicted, 4);
+        assert_eq!(order.len(), 7 * 7 * 7);
+        assert_eq!(order[0], center);
+        assert!(order
+            .windows(2)
+            .all(|w| distance(w[0] - center) <= distance(w[1] - center)));
+
+        // Moving fast along +x, the predicted chunk comes right after the center
+        let predicted = VoxelWorld::predicted_chunk(pos, Vec3::new(64.0, 0.0, 0.0), 1.5);
+        assert_eq!(predicted, center + IVec3::new(3, 0, 0));
+        let order = generation_order(center, predicted, 4);
+        assert_eq!(order[..2], [center, predicted]);
+        let index = |p: IVec3| order.iter().position(|o| *o == center + p).unwrap();
+        assert!(index(IVec3::new(2, 0, 0)) < index(IVec3::new(-2, 0, 0)));
+        assert!(index(IVec3::new(3, 1, 0)) < index(IVec3::new(0, 2, 0)));
+
+        // Falling, the column below is preferred
+        let predicted = VoxelWorld::predicted_chunk(pos, Vec3::new(0.0, -60.0, 0.0), 1.0);
+        assert_eq!(predicted, center - IVec3::new(0, 2, 0));
+        let order = generation_order(center, predicted, 4);
+        assert!(
+            order.iter().position(|p| *p == predicted)
+                < order.iter().position(|p| *p == center + IVec3::Y)
+        );
+    }
+
+    #[test]
+    fn wireframe_targeting() {
+        let pos = IVec3::new(1, -2, 3);
+        let off = ChunkWireframe::default();
+        assert!(!off.shows(pos, Some(pos)));
+
+        let all = ChunkWireframe {
+            enabled: true,
+            targeted: false,
+        };
+        assert!(all.shows(pos, None));
+        assert!(all.shows(pos, Some(IVec3::ZERO)));
+
+        let targeted = ChunkWireframe {
+            enabled: true,
+            targeted: true,
+        };
+        assert!(targeted.shows(pos, Some(pos)));
+        assert!(!targeted.shows(pos, Some(pos + IVec3::X)));
+        assert!(!targeted.shows(pos, None));
+    }
+
+    #[test]
+    fn seed_colors() {
+        let pos = IVec3::new(3, -1, 7);
+        assert_eq!(ChunkSeedColor::new(1, pos), ChunkSeedColor::new(1, pos));
+
+        // Neighbors rarely share a hue
+        let mut hues = Vec::new();
+        for d in [IVec3::ZERO, IVec3::X, IVec3::Y, IVec3::Z] {
+            let color = ChunkSeedColor::new(1, pos + d);
+            let [r, g, b, a] = color.tint().as_rgba_f32();
+            assert!([r, g, b].iter().all(|c| (0.5..=1.0).contains(c)));
+            assert_eq!(a, 1.0);
+            let Color::Hsla { hue, .. } = color.0 else {
+                panic!("{color:?}");
+            };
+            hues.push(hue);
+        }
+        hues.sort_by(f32::total_cmp);
+        hues.dedup();
+        assert!(hues.len() > 1);
     }
 }